@@ -18,64 +18,80 @@ fn navigation_benchmark(c: &mut Criterion) {
     });
 
     let lua = mlua::Lua::new();
-    let mut app = app::App::create(PWD.into(), &lua, None, [].into())
-        .expect("failed to create app");
+    let mut app =
+        app::App::create("xplr".into(), None, PWD.into(), &lua, None, [].into())
+            .expect("failed to create app");
 
     app = app
-        .clone()
         .handle_task(app::Task::new(
             app::MsgIn::External(app::ExternalMsg::ChangeDirectory(PWD.into())),
             None,
         ))
         .unwrap();
 
+    let mut app = Some(app);
+
     c.bench_function("focus next item", |b| {
         b.iter(|| {
-            app.clone()
+            let next = app
+                .take()
+                .unwrap()
                 .handle_task(app::Task::new(
                     app::MsgIn::External(app::ExternalMsg::FocusNext),
                     None,
                 ))
-                .unwrap()
+                .unwrap();
+            app = Some(next);
         })
     });
 
     c.bench_function("focus previous item", |b| {
         b.iter(|| {
-            app.clone()
+            let next = app
+                .take()
+                .unwrap()
                 .handle_task(app::Task::new(
                     app::MsgIn::External(app::ExternalMsg::FocusPrevious),
                     None,
                 ))
-                .unwrap()
+                .unwrap();
+            app = Some(next);
         })
     });
 
     c.bench_function("focus first item", |b| {
         b.iter(|| {
-            app.clone()
+            let next = app
+                .take()
+                .unwrap()
                 .handle_task(app::Task::new(
                     app::MsgIn::External(app::ExternalMsg::FocusFirst),
                     None,
                 ))
-                .unwrap()
+                .unwrap();
+            app = Some(next);
         })
     });
 
     c.bench_function("focus last item", |b| {
         b.iter(|| {
-            app.clone()
+            let next = app
+                .take()
+                .unwrap()
                 .handle_task(app::Task::new(
                     app::MsgIn::External(app::ExternalMsg::FocusLast),
                     None,
                 ))
-                .unwrap()
+                .unwrap();
+            app = Some(next);
         })
     });
 
     c.bench_function("leave and enter directory", |b| {
         b.iter(|| {
-            app.clone()
+            let next = app
+                .take()
+                .unwrap()
                 .handle_task(app::Task::new(
                     app::MsgIn::External(app::ExternalMsg::Back),
                     None,
@@ -85,7 +101,8 @@ fn navigation_benchmark(c: &mut Criterion) {
                     app::MsgIn::External(app::ExternalMsg::Enter),
                     None,
                 ))
-                .unwrap()
+                .unwrap();
+            app = Some(next);
         })
     });
 }
@@ -97,11 +114,11 @@ fn draw_benchmark(c: &mut Criterion) {
     });
 
     let lua = mlua::Lua::new();
-    let mut app = app::App::create(PWD.into(), &lua, None, [].into())
-        .expect("failed to create app");
+    let mut app =
+        app::App::create("xplr".into(), None, PWD.into(), &lua, None, [].into())
+            .expect("failed to create app");
 
     app = app
-        .clone()
         .handle_task(app::Task::new(
             app::MsgIn::External(app::ExternalMsg::ChangeDirectory(PWD.into())),
             None,
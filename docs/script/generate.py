@@ -247,7 +247,8 @@ def gen_xplr_util():
                 reading = Function(doc=[line[4:]], name="")
 
         if line.startswith("pub fn") and reading:
-            reading.name = "\n### xplr.util." + line.split("<")[0].split()[-1] + "\n"
+            name = line.split("<")[0].split()[-1].removeprefix("r#")
+            reading.name = "\n### xplr.util." + name + "\n"
             functions.append(reading)
             reading = None
             continue
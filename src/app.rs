@@ -3,7 +3,9 @@ use crate::config::Hooks;
 use crate::config::Mode;
 pub use crate::directory_buffer::DirectoryBuffer;
 use crate::explorer;
+use crate::fs_watcher::{self, FsEvent};
 use crate::input::{InputOperation, Key};
+use crate::logs;
 use crate::lua;
 pub use crate::msg::in_::external::Command;
 pub use crate::msg::in_::external::ExplorerConfig;
@@ -19,6 +21,8 @@ pub use crate::msg::out::MsgOut;
 pub use crate::node::Node;
 pub use crate::node::ResolvedNode;
 pub use crate::pipe::Pipe;
+use crate::progress::{self, Progress};
+use crate::store::{self, Store};
 use crate::ui::Layout;
 use anyhow::{bail, Result};
 use chrono::{DateTime, Local};
@@ -31,6 +35,7 @@ use std::collections::VecDeque;
 use std::env;
 use std::fs;
 use std::path::PathBuf;
+use std::time::Instant;
 use tui_input::{Input, InputRequest};
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -56,6 +61,7 @@ pub enum LogLevel {
     Warning,
     Success,
     Error,
+    Debug,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -82,6 +88,7 @@ impl std::fmt::Display for Log {
             LogLevel::Warning => "WARNING",
             LogLevel::Success => "SUCCESS",
             LogLevel::Error => "ERROR  ",
+            LogLevel::Debug => "DEBUG  ",
         };
         write!(f, "[{}] {} {}", &self.created_at, level_str, &self.message)
     }
@@ -141,6 +148,7 @@ pub struct LuaContextHeavy {
     pub explorer_config: ExplorerConfig,
     pub history: History,
     pub last_modes: Vec<Mode>,
+    pub fs_event: Option<FsEvent>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -191,6 +199,22 @@ pub struct App {
     pub history: History,
     pub last_modes: Vec<Mode>,
     pub hostname: String,
+
+    /// Reported from Lua with `xplr.util.set_progress`.
+    #[serde(skip)]
+    pub progress: Option<Progress>,
+
+    /// Set from Lua with `xplr.util.store_set`, for the session only.
+    #[serde(default)]
+    pub store: Store,
+
+    /// The last times allowed by `xplr.util.throttle`, by key.
+    #[serde(skip)]
+    pub throttled: HashMap<String, Instant>,
+
+    /// The last changes reported by a watcher registered with `xplr.util.watch`.
+    #[serde(default)]
+    pub fs_event: Option<FsEvent>,
 }
 
 impl App {
@@ -223,9 +247,7 @@ impl App {
             }
         };
 
-        let config_files = config_file
-            .into_iter()
-            .chain(extra_config_files.into_iter());
+        let config_files = config_file.into_iter().chain(extra_config_files);
 
         let mut load_errs = vec![];
         for config_file in config_files {
@@ -276,7 +298,7 @@ impl App {
             .unwrap_or_else(env::temp_dir)
             .join("xplr")
             .join("session")
-            .join(&pid.to_string())
+            .join(pid.to_string())
             .to_string_lossy()
             .to_string();
 
@@ -284,7 +306,7 @@ impl App {
             session_path = env::temp_dir()
                 .join("xplr")
                 .join("session")
-                .join(&pid.to_string())
+                .join(pid.to_string())
                 .to_string_lossy()
                 .to_string();
             fs::create_dir_all(&session_path)?;
@@ -305,7 +327,7 @@ impl App {
         let hostname = gethostname().to_string_lossy().to_string();
 
         if let Some(vroot) = vroot.as_ref() {
-            if !pwd.starts_with(&vroot) {
+            if !pwd.starts_with(vroot) {
                 bail!(
                     "{:?} is outside of virtual root {:?}",
                     pwd.to_string_lossy(),
@@ -350,6 +372,10 @@ impl App {
             history: Default::default(),
             last_modes: Default::default(),
             hostname,
+            progress: None,
+            store: Default::default(),
+            throttled: Default::default(),
+            fs_event: Default::default(),
             hooks,
         };
 
@@ -396,11 +422,13 @@ impl App {
     }
 
     pub fn handle_task(self, task: Task) -> Result<Self> {
-        let app = match task.msg {
+        let mut app = match task.msg {
             MsgIn::Internal(msg) => self.handle_internal(msg)?,
             MsgIn::External(msg) => self.handle_external(msg, task.key)?,
         };
-        app.refresh()
+        app.progress = progress::current();
+        store::sync(&mut app.store, &mut app.throttled);
+        app.add_logs(logs::take())?.refresh()
     }
 
     fn handle_internal(self, msg: InternalMsg) -> Result<Self> {
@@ -410,6 +438,7 @@ impl App {
                 self.add_last_focus(parent, focus_path)
             }
             InternalMsg::HandleKey(key) => self.handle_key(key),
+            InternalMsg::FsEvent(event) => self.handle_fs_event(event),
         }
     }
 
@@ -765,7 +794,9 @@ impl App {
     fn follow_symlink(self) -> Result<Self> {
         if let Some(pth) = self
             .focused_node()
-            .and_then(|n| n.symlink.to_owned().map(|s| s.absolute_path))
+            .and_then(|n| n.symlink.to_owned())
+            .filter(|s| !s.is_broken)
+            .map(|s| s.absolute_path)
         {
             self.focus_path(&pth, true)
         } else {
@@ -822,7 +853,7 @@ impl App {
         let dir = PathBuf::from(dir).absolutize()?.to_path_buf();
 
         if let Some(vroot) = &self.vroot.clone() {
-            if !dir.starts_with(&vroot) {
+            if !dir.starts_with(vroot) {
                 return self.log_error(format!(
                     "{:?} is outside of virtual root {:?}",
                     dir.to_string_lossy(),
@@ -1288,6 +1319,16 @@ impl App {
         Ok(self)
     }
 
+    /// Events can still arrive for a watcher that was just removed.
+    fn handle_fs_event(mut self, event: FsEvent) -> Result<Self> {
+        if let Some(msgs) = fs_watcher::messages(event.handle) {
+            // Handled right away, so that the Lua calls see their own event.
+            self.fs_event = Some(event);
+            self = self.handle_batch_external_msgs(msgs)?;
+        }
+        Ok(self)
+    }
+
     pub fn select(mut self) -> Result<Self> {
         if let Some(n) = self.focused_node().map(|n| n.to_owned()) {
             self.selection.insert(n);
@@ -1324,8 +1365,7 @@ impl App {
 
     pub fn un_select_path(mut self, path: String) -> Result<Self> {
         let pathbuf = PathBuf::from(path).absolutize()?.to_path_buf();
-        self.selection
-            .retain(|n| PathBuf::from(&n.absolute_path) != pathbuf);
+        self.selection.retain(|n| n.absolute_path != pathbuf);
         Ok(self)
     }
 
@@ -1361,11 +1401,7 @@ impl App {
 
     fn toggle_selection_by_path(self, path: String) -> Result<Self> {
         let pathbuf = PathBuf::from(&path).absolutize()?.to_path_buf();
-        if self
-            .selection
-            .iter()
-            .any(|n| PathBuf::from(&n.absolute_path) == pathbuf)
-        {
+        if self.selection.iter().any(|n| n.absolute_path == pathbuf) {
             self.un_select_path(path)
         } else {
             self.select_path(path)
@@ -1598,6 +1634,15 @@ impl App {
         Ok(self)
     }
 
+    /// Adds the logs added from Lua with `xplr.util.log`.
+    pub fn add_logs(mut self, logs: Vec<Log>) -> Result<Self> {
+        if !logs.is_empty() {
+            self.logs_hidden = false;
+            self.logs.extend(logs);
+        }
+        Ok(self)
+    }
+
     fn quit(mut self) -> Result<Self> {
         self.msg_out.push_back(MsgOut::Quit);
         Ok(self)
@@ -1704,7 +1749,7 @@ impl App {
         let global_kb = &self.config.general.global_key_bindings;
 
         builtin.into_iter()
-        .chain(custom.into_iter())
+        .chain(custom)
         .map(|(name, mode)| {
             (name, mode.sanitized(read_only, global_kb.clone()))
         })
@@ -1742,7 +1787,7 @@ impl App {
 
     pub fn write_pipes(&self, delimiter: char) -> Result<()> {
         fs::create_dir_all(self.pipe.path.clone())?;
-        fs::write(&self.pipe.msg_in, &[delimiter as u8])?;
+        fs::write(&self.pipe.msg_in, [delimiter as u8])?;
 
         let selection_str = self.selection_str(delimiter);
         fs::write(&self.pipe.selection_out, selection_str)?;
@@ -1799,6 +1844,7 @@ impl App {
             explorer_config: self.explorer_config.clone(),
             history: self.history.clone(),
             last_modes: self.last_modes.clone(),
+            fs_event: self.fs_event.clone(),
         }
     }
 
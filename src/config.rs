@@ -160,6 +160,9 @@ pub struct LogsConfig {
 
     #[serde(default)]
     pub error: UiElement,
+
+    #[serde(default)]
+    pub debug: UiElement,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -4,30 +4,110 @@ use crate::app::{
 use anyhow::{Error, Result};
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
 use lazy_static::lazy_static;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
 use std::fs;
+use std::path::Path;
 use std::path::PathBuf;
 use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, SystemTime};
 
 lazy_static! {
     static ref FUZZY_MATCHER: SkimMatcherV2 = SkimMatcherV2::default();
+    static ref DIR_CACHE: Mutex<DirCache> = Mutex::new(DirCache::default());
+    static ref THREAD_POOLS: Mutex<HashMap<usize, Arc<ThreadPool>>> =
+        Mutex::new(HashMap::new());
 }
 
-pub fn explore(parent: &PathBuf, config: &ExplorerConfig) -> Result<Vec<Node>> {
-    let dirs = fs::read_dir(&parent)?;
-    let mut nodes = dirs
-        .filter_map(|d| {
-            d.ok().map(|e| {
-                e.path()
-                    .file_name()
-                    .map(|n| n.to_string_lossy().to_string())
-                    .unwrap_or_default()
-            })
-        })
-        .map(|name| Node::new(parent.to_string_lossy().to_string(), name))
-        .filter(|n| config.filter(n))
-        .collect::<Vec<Node>>();
+/// The number of directory listings kept in [`DIR_CACHE`].
+const DIR_CACHE_SIZE: usize = 64;
+
+/// Directory listings, keyed by the path and the modification time of the
+/// directory, most recently used first.
+///
+/// Only the names are cached, as the modification time of a directory only
+/// changes when entries are added, removed or renamed. The metadata of the
+/// entries is always read again.
+#[derive(Default)]
+struct DirCache {
+    entries: Vec<(PathBuf, SystemTime, Vec<OsString>)>,
+}
+
+impl DirCache {
+    fn get(&mut self, dir: &Path, mtime: SystemTime) -> Option<Vec<OsString>> {
+        let idx = self.entries.iter().position(|(p, _, _)| p == dir)?;
+        let entry = self.entries.remove(idx);
+        if entry.1 != mtime {
+            return None;
+        }
+        let names = entry.2.clone();
+        self.entries.insert(0, entry);
+        Some(names)
+    }
+
+    fn insert(&mut self, dir: &Path, mtime: SystemTime, names: Vec<OsString>) {
+        self.entries.retain(|(p, _, _)| p != dir);
+        self.entries.insert(0, (dir.into(), mtime, names));
+        self.entries.truncate(DIR_CACHE_SIZE);
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static READ_DIR_CALLS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// An entry that couldn't be read while exploring.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExploreError {
+    pub path: String,
+    pub message: String,
+}
+
+impl ExploreError {
+    fn new(path: &Path, err: impl ToString) -> Self {
+        Self {
+            path: path.to_string_lossy().to_string(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// The result of [`explore_with_errors`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Explored {
+    /// The nodes in the `offset` and `limit` window.
+    pub nodes: Vec<Node>,
+    pub errors: Vec<ExploreError>,
+    /// The number of nodes before windowing.
+    pub total_count: usize,
+}
+
+/// Explore the directory, skipping the entries that can't be read. Fails only
+/// if the directory itself can't be read.
+pub fn explore(parent: &Path, config: &ExplorerConfig) -> Result<Vec<Node>> {
+    explore_with_errors(parent, config).map(|e| e.nodes)
+}
+
+/// Same as [`explore`], but also returns the errors for the skipped entries
+/// and sub directories, and the total number of nodes.
+pub fn explore_with_errors(parent: &Path, config: &ExplorerConfig) -> Result<Explored> {
+    let mut visited = HashSet::new();
+    if let Ok(path) = parent.canonicalize() {
+        visited.insert(path.to_string_lossy().to_string());
+    }
+
+    let context = Context::new(config)?;
+    let mut nodes =
+        read_nodes(parent, Path::new(""), 1, config, &mut visited, &context)?;
 
     nodes = if let Some(pattern) = config.searcher.as_ref().map(|s| &s.pattern) {
         let mut nodes = nodes
@@ -42,13 +122,378 @@ pub fn explore(parent: &PathBuf, config: &ExplorerConfig) -> Result<Vec<Node>> {
         nodes.sort_by(|(_, s1), (_, s2)| s2.cmp(s1));
         nodes.into_iter().map(|(n, _)| n).collect::<Vec<_>>()
     } else {
-        nodes.sort_by(|a, b| config.sort(a, b));
         nodes
     };
 
+    let total_count = nodes.len();
+    let nodes = nodes
+        .into_iter()
+        .skip(config.offset)
+        .take(config.limit.unwrap_or(usize::MAX))
+        .collect::<Vec<_>>();
+
+    // Nothing filters or sorts by these, so only the window needs them.
+    let populate = |node: Node| {
+        let node = if config.add_mime {
+            node.with_mime()
+        } else {
+            node
+        };
+        let node = if config.add_owner {
+            node.with_owner()
+        } else {
+            node
+        };
+        if config.add_binary_human_size {
+            node.with_binary_human_size()
+        } else {
+            node
+        }
+    };
+
+    let nodes = if !config.add_mime && !config.add_owner && !config.add_binary_human_size
+    {
+        nodes
+    } else {
+        match (config.parallelism, &context.pool) {
+            (Some(1), _) => nodes.into_iter().map(populate).collect(),
+            (_, Some(pool)) => {
+                pool.install(|| nodes.into_par_iter().map(populate).collect())
+            }
+            (_, None) => nodes.into_par_iter().map(populate).collect(),
+        }
+    };
+
+    let errors = context.errors.into_inner().unwrap_or_default();
+    Ok(Explored {
+        nodes,
+        errors,
+        total_count,
+    })
+}
+
+/// The type of the entries to [`find`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FindType {
+    File,
+    Dir,
+}
+
+/// The depth limit of a walk, given the `max_depth` option. Like in the
+/// [`ExplorerConfig`], `0` means no limit, the same as not setting it.
+pub fn depth_limit(max_depth: Option<usize>) -> Option<usize> {
+    max_depth.filter(|d| *d != 0)
+}
+
+/// What to [`find`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FindQuery {
+    /// The glob pattern the names must match. Patterns containing a `/` are
+    /// matched against the path relative to the root instead.
+    pub pattern: Option<String>,
+    /// See [`depth_limit`].
+    pub max_depth: Option<usize>,
+    #[serde(rename = "type")]
+    pub type_: Option<FindType>,
+    pub respect_gitignore: bool,
+    pub limit: Option<usize>,
+}
+
+/// Walk the directory recursively, in the order of the names, and return the
+/// nodes matching the query. Symlinks are not followed, and the entries that
+/// can't be read are skipped.
+pub fn find(root: &Path, query: &FindQuery) -> Result<Vec<Node>> {
+    fs::read_dir(root)?;
+
+    let matcher = query
+        .pattern
+        .as_ref()
+        .map(|p| {
+            GlobBuilder::new(p)
+                .literal_separator(true)
+                .build()
+                .map(|g| (g.compile_matcher(), p.contains('/')))
+        })
+        .transpose()?;
+
+    let root_str = root.to_string_lossy().to_string();
+    let walker = WalkBuilder::new(root)
+        .max_depth(depth_limit(query.max_depth))
+        .standard_filters(false)
+        .parents(query.respect_gitignore)
+        .git_ignore(query.respect_gitignore)
+        .git_global(query.respect_gitignore)
+        .git_exclude(query.respect_gitignore)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    let nodes = walker
+        .filter_map(|e| e.ok())
+        .filter(|e| e.depth() > 0)
+        .filter_map(|e| {
+            let relative = e.path().strip_prefix(root).ok()?;
+            let matches = match &matcher {
+                Some((m, true)) => m.is_match(relative),
+                Some((m, false)) => m.is_match(e.file_name()),
+                None => true,
+            };
+            if !matches {
+                return None;
+            }
+            let node =
+                Node::try_new(root_str.clone(), relative.to_string_lossy().to_string())
+                    .ok()?;
+            match query.type_ {
+                Some(FindType::File) if !node.is_file => None,
+                Some(FindType::Dir) if !node.is_dir => None,
+                _ => Some(node),
+            }
+        })
+        .take(query.limit.unwrap_or(usize::MAX))
+        .collect();
+
     Ok(nodes)
 }
 
+fn read_dir_names(dir: &Path, config: &ExplorerConfig) -> Result<Vec<OsString>> {
+    if !config.respect_gitignore {
+        return read_dir_names_cached(dir);
+    }
+
+    fs::read_dir(dir)?;
+
+    // The walker also reads the ignore files in the parent directories, so
+    // nested `.gitignore` files are honored at any depth.
+    let names = WalkBuilder::new(dir)
+        .max_depth(Some(1))
+        .standard_filters(false)
+        .parents(true)
+        .git_ignore(true)
+        .git_global(true)
+        .git_exclude(true)
+        .build()
+        .filter_map(|d| d.ok())
+        .filter(|e| e.depth() == 1)
+        .map(|e| e.file_name().to_os_string())
+        .collect();
+
+    Ok(names)
+}
+
+/// Read the names in the directory, reusing the previous listing if the
+/// modification time of the directory hasn't changed.
+fn read_dir_names_cached(dir: &Path) -> Result<Vec<OsString>> {
+    let mtime = fs::metadata(dir)?.modified().ok();
+
+    if let Some(mtime) = mtime {
+        if let Some(names) = DIR_CACHE.lock().ok().and_then(|mut c| c.get(dir, mtime)) {
+            return Ok(names);
+        }
+    }
+
+    #[cfg(test)]
+    READ_DIR_CALLS.with(|c| c.set(c.get() + 1));
+
+    let names = fs::read_dir(dir)?
+        .filter_map(|d| d.ok().map(|e| e.file_name()))
+        .collect::<Vec<_>>();
+
+    // The directory might still be modified within the same timestamp
+    // granularity, so recently modified directories aren't cached.
+    let settled = |mtime: SystemTime| {
+        mtime
+            .elapsed()
+            .map(|age| age > Duration::from_secs(2))
+            .unwrap_or(false)
+    };
+
+    if let Some(mtime) = mtime.filter(|m| settled(*m)) {
+        if let Ok(mut cache) = DIR_CACHE.lock() {
+            cache.insert(dir, mtime, names.clone());
+        }
+    }
+
+    Ok(names)
+}
+
+/// The pool with the given number of threads, built on first use and shared by
+/// the explorations after that.
+fn thread_pool(num_threads: usize) -> Result<Arc<ThreadPool>> {
+    let mut pools = THREAD_POOLS.lock().map_err(|e| Error::msg(e.to_string()))?;
+    if let Some(pool) = pools.get(&num_threads) {
+        return Ok(pool.clone());
+    }
+    let pool = Arc::new(ThreadPoolBuilder::new().num_threads(num_threads).build()?);
+    pools.insert(num_threads, pool.clone());
+    Ok(pool)
+}
+
+/// What's prepared once per exploration.
+struct Context {
+    pool: Option<Arc<ThreadPool>>,
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    errors: Mutex<Vec<ExploreError>>,
+}
+
+impl Context {
+    fn new(config: &ExplorerConfig) -> Result<Self> {
+        // Only build a dedicated pool to cap the number of threads.
+        let pool = match config.parallelism {
+            Some(n) if n > 1 => Some(thread_pool(n)?),
+            _ => None,
+        };
+
+        Ok(Self {
+            pool,
+            include: build_globset(&config.include_patterns)?,
+            exclude: build_globset(&config.exclude_patterns)?,
+            errors: Default::default(),
+        })
+    }
+
+    fn add_error(&self, error: ExploreError) {
+        if let Ok(mut errors) = self.errors.lock() {
+            errors.push(error);
+        }
+    }
+
+    fn is_included(&self, name: &OsString) -> bool {
+        let path = Path::new(name);
+        self.include
+            .as_ref()
+            .map(|g| g.is_match(path))
+            .unwrap_or(true)
+            && !self
+                .exclude
+                .as_ref()
+                .map(|g| g.is_match(path))
+                .unwrap_or(false)
+    }
+}
+
+fn build_globset(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(Some(builder.build()?))
+}
+
+/// Read the nodes in `root/relative`, sorted per directory, followed by the
+/// nodes of the sub directories, if `max_depth` allows it.
+fn read_nodes(
+    root: &Path,
+    relative: &Path,
+    depth: usize,
+    config: &ExplorerConfig,
+    visited: &mut HashSet<String>,
+    context: &Context,
+) -> Result<Vec<Node>> {
+    let root_str = root.to_string_lossy().to_string();
+    let dir = root.join(relative);
+    let names = read_dir_names(&dir, config)?
+        .into_iter()
+        .filter(|name| context.is_included(name))
+        .collect::<Vec<_>>();
+
+    // Following a symlink to the directory itself or to any of its parents
+    // would loop, so such symlinks are reported as is.
+    let dir_canonical = if config.follow_symlinks {
+        dir.canonicalize().ok()
+    } else {
+        None
+    };
+    let follow = |node: &Node| match (&dir_canonical, &node.canonical) {
+        (Some(dir), Some(target)) => !dir.starts_with(&target.absolute_path),
+        _ => false,
+    };
+
+    let new_node = |name: &OsString| {
+        let relative_path = relative.join(name);
+        let mut node = match Node::try_new(
+            root_str.clone(),
+            relative_path.to_string_lossy().to_string(),
+        ) {
+            Ok(node) => node,
+            Err(err) => {
+                context.add_error(ExploreError::new(&root.join(relative_path), err));
+                return None;
+            }
+        };
+        if node.is_symlink && follow(&node) {
+            node = node.followed();
+        }
+        Some(node)
+    };
+
+    // The order is preserved, so the result is the same either way.
+    let nodes = match (config.parallelism, &context.pool) {
+        (Some(1), _) => names.iter().map(new_node).collect::<Vec<Option<_>>>(),
+        (_, Some(pool)) => pool.install(|| names.par_iter().map(new_node).collect()),
+        (_, None) => names.par_iter().map(new_node).collect(),
+    };
+
+    let mut nodes = nodes
+        .into_iter()
+        .flatten()
+        .filter(|n| config.filter(n))
+        .collect::<Vec<Node>>();
+
+    if config.searcher.is_none() {
+        nodes.sort_by(|a, b| config.sort(a, b));
+    }
+
+    let descend = match config.max_depth {
+        Some(0) => true,
+        Some(max) => depth < max,
+        None => false,
+    };
+
+    if !descend {
+        return Ok(nodes);
+    }
+
+    let mut all = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let children = if node.is_dir
+            && node
+                .canonical
+                .as_ref()
+                .map(|c| visited.insert(c.absolute_path.clone()))
+                .unwrap_or(false)
+        {
+            // Unreadable sub directories are skipped.
+            read_nodes(
+                root,
+                Path::new(&node.relative_path),
+                depth + 1,
+                config,
+                visited,
+                context,
+            )
+            .unwrap_or_else(|err| {
+                context
+                    .add_error(ExploreError::new(Path::new(&node.absolute_path), err));
+                vec![]
+            })
+        } else {
+            vec![]
+        };
+
+        all.push(node);
+        all.extend(children);
+    }
+
+    Ok(all)
+}
+
 pub(crate) fn explore_sync(
     config: ExplorerConfig,
     parent: PathBuf,
@@ -133,6 +578,8 @@ pub(crate) fn explore_recursive_async(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::app::{NodeSorter, NodeSorterApplicable};
+    use tempfile::TempDir;
 
     #[test]
     fn test_explore_sync() {
@@ -154,6 +601,685 @@ mod tests {
         assert!(r.is_err());
     }
 
+    fn fixture() -> TempDir {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        fs::write(root.join("x"), "").unwrap();
+        fs::write(root.join("a/y"), "").unwrap();
+        fs::write(root.join("a/b/z"), "").unwrap();
+        fs::write(root.join("a/b/c/w"), "").unwrap();
+        tmp
+    }
+
+    fn sorted_config() -> ExplorerConfig {
+        let mut config = ExplorerConfig::default();
+        config.sorters.insert(NodeSorterApplicable {
+            sorter: NodeSorter::ByRelativePath,
+            reverse: false,
+        });
+        config
+    }
+
+    fn relative_paths(nodes: &[Node]) -> Vec<&str> {
+        nodes.iter().map(|n| n.relative_path.as_str()).collect()
+    }
+
+    #[test]
+    fn test_explore_max_depth() {
+        let tmp = fixture();
+        let root = tmp.path();
+        let mut config = sorted_config();
+
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(relative_paths(&nodes), vec!["a", "x"]);
+
+        config.max_depth = Some(1);
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(relative_paths(&nodes), vec!["a", "x"]);
+
+        config.max_depth = Some(2);
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(relative_paths(&nodes), vec!["a", "a/b", "a/y", "x"]);
+        assert_eq!(nodes[1].absolute_path, root.join("a/b").to_string_lossy());
+        assert_eq!(nodes[1].parent, root.to_string_lossy());
+
+        config.max_depth = Some(0);
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(
+            relative_paths(&nodes),
+            vec!["a", "a/b", "a/b/c", "a/b/c/w", "a/b/z", "a/y", "x"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_explore_max_depth_symlink_cycle() {
+        let tmp = fixture();
+        let root = tmp.path();
+        std::os::unix::fs::symlink(root, root.join("a/loop")).unwrap();
+
+        let config = ExplorerConfig {
+            max_depth: Some(0),
+            ..Default::default()
+        };
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(nodes.len(), 8);
+        assert!(nodes.iter().any(|n| n.relative_path == "a/loop"));
+    }
+
+    #[test]
+    fn test_explore_respect_gitignore() {
+        let tmp = fixture();
+        let root = tmp.path();
+        fs::write(root.join(".gitignore"), "*.log\n/x\n").unwrap();
+        fs::write(root.join("a/b/.gitignore"), "z\n").unwrap();
+        fs::write(root.join("foo.log"), "").unwrap();
+        fs::write(root.join("a/b/bar.log"), "").unwrap();
+
+        let mut config = sorted_config();
+        config.max_depth = Some(0);
+
+        // Not a git repository.
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(nodes.len(), 11);
+
+        config.respect_gitignore = true;
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(nodes.len(), 11);
+
+        fs::create_dir(root.join(".git")).unwrap();
+
+        config.respect_gitignore = false;
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(nodes.len(), 12);
+
+        config.respect_gitignore = true;
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(
+            relative_paths(&nodes),
+            vec![
+                ".git",
+                ".gitignore",
+                "a",
+                "a/b",
+                "a/b/.gitignore",
+                "a/b/c",
+                "a/b/c/w",
+                "a/y",
+            ]
+        );
+
+        // Exploring a sub directory still honors the parent ignore files.
+        let nodes = explore(&root.join("a/b"), &config).unwrap();
+        assert_eq!(relative_paths(&nodes), vec![".gitignore", "c", "c/w"]);
+    }
+
+    #[test]
+    fn test_explore_add_mime() {
+        let tmp = fixture();
+        let root = tmp.path();
+        fs::write(root.join("foo.png"), "").unwrap();
+
+        let mut config = sorted_config();
+        let nodes = explore(root, &config).unwrap();
+        assert!(nodes.iter().all(|n| n.mime.is_none()));
+
+        config.add_mime = true;
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(relative_paths(&nodes), vec!["a", "foo.png", "x"]);
+        assert_eq!(nodes[0].mime, None);
+        assert_eq!(nodes[1].mime.as_deref(), Some("image/png"));
+        assert_eq!(nodes[2].mime, None);
+    }
+
+    #[test]
+    fn test_explore_window() {
+        let tmp = fixture();
+        let root = tmp.path();
+        for i in 0..25 {
+            fs::write(root.join(format!("f{:02}.png", i)), "").unwrap();
+        }
+
+        let mut config = sorted_config();
+        let all = explore(root, &config).unwrap();
+        assert_eq!(all.len(), 27);
+
+        // The pages cover the sorted listing, in order.
+        config.limit = Some(10);
+        let mut paged = vec![];
+        for offset in [0, 10, 20] {
+            config.offset = offset;
+            let explored = explore_with_errors(root, &config).unwrap();
+            assert_eq!(explored.total_count, 27);
+            assert!(explored.nodes.len() <= 10);
+            paged.extend(explored.nodes);
+        }
+        assert_eq!(paged, all);
+
+        config.offset = 21;
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(
+            relative_paths(&nodes),
+            vec!["f20.png", "f21.png", "f22.png", "f23.png", "f24.png", "x"]
+        );
+
+        // Sorting applies before windowing.
+        config.sorters.clear();
+        config.sorters.insert(NodeSorterApplicable {
+            sorter: NodeSorter::ByRelativePath,
+            reverse: true,
+        });
+        config.offset = 1;
+        config.limit = Some(2);
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(relative_paths(&nodes), vec!["f24.png", "f23.png"]);
+
+        // Past the end.
+        config.offset = 30;
+        let explored = explore_with_errors(root, &config).unwrap();
+        assert!(explored.nodes.is_empty());
+        assert_eq!(explored.total_count, 27);
+
+        // The window is populated too.
+        config.offset = 1;
+        config.add_mime = true;
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(nodes[0].mime.as_deref(), Some("image/png"));
+        assert_eq!(nodes[1].mime.as_deref(), Some("image/png"));
+    }
+
+    #[test]
+    fn test_explore_size() {
+        let tmp = fixture();
+        let root = tmp.path();
+        fs::write(root.join("small"), "foo").unwrap();
+        fs::write(root.join("big"), vec![0; 1_234_567]).unwrap();
+
+        let nodes = explore(root, &sorted_config()).unwrap();
+        assert_eq!(relative_paths(&nodes), vec!["a", "big", "small", "x"]);
+
+        // Directories always report 0.
+        assert_eq!(nodes[0].size, 0);
+        assert_eq!(nodes[0].human_size, "0 B");
+
+        assert_eq!(nodes[1].size, 1_234_567);
+        assert_eq!(nodes[1].human_size, "1.23 MB");
+
+        assert_eq!(nodes[2].size, 3);
+        assert_eq!(nodes[2].human_size, "3 B");
+
+        assert_eq!(nodes[3].size, 0);
+        assert_eq!(nodes[3].human_size, "0 B");
+
+        assert!(nodes.iter().all(|n| n.binary_human_size.is_none()));
+    }
+
+    #[test]
+    fn test_explore_add_binary_human_size() {
+        let tmp = fixture();
+        let root = tmp.path();
+        fs::write(root.join("small"), "foo").unwrap();
+        fs::write(root.join("big"), vec![0; 1_234_567]).unwrap();
+        fs::write(root.join("kib"), vec![0; 1024]).unwrap();
+
+        let mut config = sorted_config();
+        config.add_binary_human_size = true;
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(
+            relative_paths(&nodes),
+            vec!["a", "big", "kib", "small", "x"]
+        );
+
+        let sizes = nodes
+            .iter()
+            .map(|n| n.binary_human_size.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec!["0 B", "1.2 MiB", "1 KiB", "3 B", "0 B"]);
+    }
+
+    #[test]
+    fn test_explore_dir_cache() {
+        let tmp = fixture();
+        let root = tmp.path();
+        let config = sorted_config();
+        let calls = || READ_DIR_CALLS.with(|c| c.get());
+        let set_mtime = |dir: &Path, secs: u64| {
+            let mtime = SystemTime::now() - Duration::from_secs(secs);
+            fs::File::open(dir).unwrap().set_modified(mtime).unwrap();
+        };
+
+        // Recently modified directories aren't cached.
+        let before = calls();
+        explore(root, &config).unwrap();
+        explore(root, &config).unwrap();
+        assert_eq!(calls(), before + 2);
+
+        set_mtime(root, 60);
+        let before = calls();
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(calls(), before + 1);
+        assert_eq!(relative_paths(&nodes), vec!["a", "x"]);
+
+        // Unchanged mtime, the listing is reused, but not the metadata.
+        fs::write(root.join("x"), "foo").unwrap();
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(calls(), before + 1);
+        assert_eq!(relative_paths(&nodes), vec!["a", "x"]);
+        assert_eq!(nodes[1].size, 3);
+
+        // Changed mtime, the listing is invalidated.
+        fs::write(root.join("y"), "").unwrap();
+        set_mtime(root, 30);
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(calls(), before + 2);
+        assert_eq!(relative_paths(&nodes), vec!["a", "x", "y"]);
+    }
+
+    #[test]
+    fn test_dir_cache_lru() {
+        let mtime = SystemTime::UNIX_EPOCH;
+        let mut cache = DirCache::default();
+        for i in 0..DIR_CACHE_SIZE + 1 {
+            cache.insert(Path::new(&i.to_string()), mtime, vec![]);
+            if i > 0 {
+                // Keep the first one in use.
+                assert!(cache.get(Path::new("0"), mtime).is_some());
+            }
+        }
+        assert_eq!(cache.entries.len(), DIR_CACHE_SIZE);
+        assert!(cache.get(Path::new("0"), mtime).is_some());
+        assert!(cache.get(Path::new("1"), mtime).is_none());
+        assert!(cache.get(Path::new("2"), mtime).is_some());
+
+        let later = mtime + Duration::from_secs(1);
+        assert!(cache.get(Path::new("2"), later).is_none());
+        assert!(cache.get(Path::new("2"), mtime).is_none());
+    }
+
+    #[test]
+    fn test_explore_parallelism() {
+        let tmp = fixture();
+        let root = tmp.path();
+        for i in 0..50 {
+            let dir = root.join(format!("d{}", i));
+            fs::create_dir(&dir).unwrap();
+            for j in 0..20 {
+                fs::write(dir.join(format!("f{}.txt", j)), "x".repeat(i * j)).unwrap();
+            }
+        }
+
+        let mut config = sorted_config();
+        config.max_depth = Some(0);
+        config.add_mime = true;
+
+        config.parallelism = Some(1);
+        let serial = explore(root, &config).unwrap();
+        assert_eq!(serial.len(), 7 + 50 * 21);
+
+        for parallelism in [None, Some(0), Some(2), Some(8)] {
+            config.parallelism = parallelism;
+            let parallel = explore(root, &config).unwrap();
+            assert_eq!(parallel.len(), serial.len());
+            for (p, s) in parallel.iter().zip(serial.iter()) {
+                assert_eq!(p, s);
+            }
+        }
+    }
+
+    #[test]
+    fn test_thread_pool_reused() {
+        let pool = thread_pool(3).unwrap();
+        assert_eq!(pool.current_num_threads(), 3);
+        assert!(Arc::ptr_eq(&pool, &thread_pool(3).unwrap()));
+        assert!(!Arc::ptr_eq(&pool, &thread_pool(4).unwrap()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_explore_follow_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = fixture();
+        let root = tmp.path();
+        symlink(root.join("a/b"), root.join("linked")).unwrap();
+        symlink(root.join("nothing"), root.join("broken")).unwrap();
+        symlink(root, root.join("a/loop")).unwrap();
+        symlink(".", root.join("a/b/c/self")).unwrap();
+
+        let mut config = sorted_config();
+        config.max_depth = Some(0);
+
+        let nodes = explore(root, &config).unwrap();
+        let linked = nodes.iter().find(|n| n.relative_path == "linked").unwrap();
+        assert!(linked.is_symlink);
+        assert!(!linked.is_dir);
+        assert!(!nodes.iter().any(|n| n.relative_path.starts_with("linked/")));
+
+        config.follow_symlinks = true;
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(
+            relative_paths(&nodes),
+            vec![
+                "a",
+                "a/b",
+                "a/b/c",
+                "a/b/c/self",
+                "a/b/c/w",
+                "a/b/z",
+                "a/loop",
+                "a/y",
+                "broken",
+                "linked",
+                "x",
+            ]
+        );
+
+        let node = |path: &str| nodes.iter().find(|n| n.relative_path == path).unwrap();
+
+        // Already explored as `a/b`.
+        assert!(node("linked").is_dir);
+        assert!(node("linked").is_symlink);
+        assert_eq!(node("linked").mime_essence, "inode/directory");
+
+        // Loops are reported as is.
+        assert!(!node("a/loop").is_dir);
+        assert!(node("a/loop").is_symlink);
+        assert!(!node("a/b/c/self").is_dir);
+
+        assert!(node("broken").is_broken);
+        assert!(!node("broken").is_dir);
+
+        // Recurses into the target when it's not explored otherwise.
+        let nodes = explore(&root.join("a/b/c"), &config).unwrap();
+        assert_eq!(relative_paths(&nodes), vec!["self", "w"]);
+
+        let other_tmp = fixture();
+        let other = other_tmp.path();
+        symlink(root.join("a/b"), other.join("linked")).unwrap();
+        let nodes = explore(other, &config).unwrap();
+        assert!(relative_paths(&nodes).contains(&"linked/c/w"));
+        assert!(relative_paths(&nodes).contains(&"linked/z"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_explore_symlink_metadata() {
+        use std::os::unix::fs::{symlink, MetadataExt};
+
+        let tmp = fixture();
+        let root = tmp.path();
+        symlink(root.join("a/b"), root.join("to_dir")).unwrap();
+        symlink("a/y", root.join("to_file")).unwrap();
+        symlink("a/missing.txt", root.join("broken")).unwrap();
+
+        let nodes = explore(root, &sorted_config()).unwrap();
+        let node = |path: &str| nodes.iter().find(|n| n.relative_path == path).unwrap();
+
+        let to_dir = node("to_dir").symlink.as_ref().unwrap();
+        assert_eq!(to_dir.absolute_path, root.join("a/b").to_string_lossy());
+        assert!(to_dir.is_dir);
+        assert!(!to_dir.is_broken);
+
+        let to_file = node("to_file").symlink.as_ref().unwrap();
+        assert_eq!(to_file.absolute_path, root.join("a/y").to_string_lossy());
+        assert!(to_file.is_file);
+        assert!(!to_file.is_broken);
+
+        let broken = node("broken");
+        assert!(broken.is_broken);
+        assert!(broken.canonical.is_none());
+        let target = broken.symlink.as_ref().unwrap();
+        assert_eq!(
+            target.absolute_path,
+            root.join("a/missing.txt").to_string_lossy()
+        );
+        assert_eq!(target.extension, "txt");
+        assert!(target.is_broken);
+        assert!(!target.is_dir);
+        let meta = fs::symlink_metadata(root.join("broken")).unwrap();
+        assert_eq!((target.uid, target.gid), (meta.uid(), meta.gid()));
+
+        assert!(node("x").symlink.is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_explore_add_owner() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp = fixture();
+        let root = tmp.path();
+        let meta = fs::metadata(root.join("x")).unwrap();
+
+        let mut config = sorted_config();
+        let nodes = explore(root, &config).unwrap();
+        assert!(nodes.iter().all(|n| n.owner.is_none() && n.group.is_none()));
+
+        config.add_owner = true;
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(nodes[1].relative_path, "x");
+        assert_eq!(nodes[1].owner, crate::node::uid_to_name(meta.uid()));
+        assert_eq!(nodes[1].group, crate::node::gid_to_name(meta.gid()));
+    }
+
+    #[test]
+    fn test_explore_natural_sort() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        for name in [
+            "file10", "file2", "file1", "v1.10.0", "v1.9.2", "a10b2", "a10b10", "a9b",
+            "é10", "é2", "File3",
+        ] {
+            fs::write(root.join(name), "").unwrap();
+        }
+        fs::create_dir(root.join("dir10")).unwrap();
+        fs::create_dir(root.join("dir9")).unwrap();
+
+        let mut config = ExplorerConfig::default();
+        config.sorters.insert(NodeSorterApplicable {
+            sorter: NodeSorter::ByIsDir,
+            reverse: true,
+        });
+        config.sorters.insert(NodeSorterApplicable {
+            sorter: NodeSorter::ByRelativePath,
+            reverse: false,
+        });
+
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(
+            relative_paths(&nodes),
+            vec![
+                "dir9", "dir10", "File3", "a9b", "a10b2", "a10b10", "file1", "file2",
+                "file10", "v1.9.2", "v1.10.0", "é2", "é10",
+            ]
+        );
+
+        config.sorters.clear();
+        config.sorters.insert(NodeSorterApplicable {
+            sorter: NodeSorter::ByIRelativePath,
+            reverse: false,
+        });
+
+        let nodes = explore(root, &config).unwrap();
+        let files = relative_paths(&nodes)
+            .into_iter()
+            .filter(|p| p.to_lowercase().starts_with("file"))
+            .collect::<Vec<_>>();
+        assert_eq!(files, vec!["file1", "file2", "File3", "file10"]);
+    }
+
+    #[test]
+    fn test_explore_patterns() {
+        let tmp = fixture();
+        let root = tmp.path();
+        fs::write(root.join("foo.rs"), "").unwrap();
+        fs::write(root.join("bar.rs"), "").unwrap();
+        fs::write(root.join("foo.toml"), "").unwrap();
+        fs::write(root.join("a/baz.rs"), "").unwrap();
+
+        let mut config = sorted_config();
+        let patterns = |p: &[&str]| p.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+        config.include_patterns = patterns(&["*.{rs,toml}"]);
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(relative_paths(&nodes), vec!["bar.rs", "foo.rs", "foo.toml"]);
+
+        config.include_patterns = patterns(&[]);
+        config.exclude_patterns = patterns(&["*.rs", "x"]);
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(relative_paths(&nodes), vec!["a", "foo.toml"]);
+
+        // Matched against the file name, at any depth.
+        config.max_depth = Some(0);
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(
+            relative_paths(&nodes),
+            vec!["a", "a/b", "a/b/c", "a/b/c/w", "a/b/z", "a/y", "foo.toml"]
+        );
+
+        // Exclude wins over include.
+        config.max_depth = None;
+        config.include_patterns = patterns(&["foo.*", "bar.*"]);
+        config.exclude_patterns = patterns(&["*.rs"]);
+        let nodes = explore(root, &config).unwrap();
+        assert_eq!(relative_paths(&nodes), vec!["foo.toml"]);
+
+        config.include_patterns = patterns(&["[a"]);
+        assert!(explore(root, &config).is_err());
+    }
+
+    #[test]
+    fn test_find() {
+        let tmp = fixture();
+        let root = tmp.path();
+        fs::write(root.join("a/y.txt"), "").unwrap();
+        fs::write(root.join("a/b/c/w.txt"), "").unwrap();
+        fs::write(root.join("x.txt"), "").unwrap();
+        fs::write(root.join(".gitignore"), "w.txt\n").unwrap();
+        fs::create_dir(root.join(".git")).unwrap();
+
+        let find = |query: FindQuery| find(root, &query).unwrap();
+
+        let query = FindQuery {
+            pattern: Some("*.txt".into()),
+            ..Default::default()
+        };
+        let nodes = find(query.clone());
+        assert_eq!(
+            relative_paths(&nodes),
+            vec!["a/b/c/w.txt", "a/y.txt", "x.txt"]
+        );
+        assert_eq!(
+            nodes[0].absolute_path,
+            root.join("a/b/c/w.txt").to_string_lossy()
+        );
+
+        let nodes = find(FindQuery {
+            max_depth: Some(2),
+            ..query.clone()
+        });
+        assert_eq!(relative_paths(&nodes), vec!["a/y.txt", "x.txt"]);
+
+        let nodes = find(FindQuery {
+            max_depth: Some(0),
+            ..query.clone()
+        });
+        assert_eq!(
+            relative_paths(&nodes),
+            vec!["a/b/c/w.txt", "a/y.txt", "x.txt"]
+        );
+
+        let nodes = find(FindQuery {
+            limit: Some(2),
+            ..query.clone()
+        });
+        assert_eq!(relative_paths(&nodes), vec!["a/b/c/w.txt", "a/y.txt"]);
+
+        let nodes = find(FindQuery {
+            respect_gitignore: true,
+            ..query
+        });
+        assert_eq!(relative_paths(&nodes), vec!["a/y.txt", "x.txt"]);
+
+        let nodes = find(FindQuery {
+            type_: Some(FindType::Dir),
+            ..Default::default()
+        });
+        assert_eq!(relative_paths(&nodes), vec![".git", "a", "a/b", "a/b/c"]);
+
+        let nodes = find(FindQuery {
+            pattern: Some("a/*".into()),
+            type_: Some(FindType::File),
+            ..Default::default()
+        });
+        assert_eq!(relative_paths(&nodes), vec!["a/y", "a/y.txt"]);
+
+        assert!(super::find(&root.join("nothing"), &FindQuery::default()).is_err());
+        assert!(super::find(
+            root,
+            &FindQuery {
+                pattern: Some("[".into()),
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[ignore = "permissions don't apply to root, run it as a regular user"]
+    fn test_explore_skips_unreadable_entries() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = fixture();
+        let root = tmp.path();
+        fs::create_dir(root.join("locked")).unwrap();
+        fs::write(root.join("locked/secret"), "").unwrap();
+
+        // The entry is listed, but its metadata can't be read.
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o444))
+            .unwrap();
+
+        let mut config = sorted_config();
+        config.max_depth = Some(2);
+        let res = explore_with_errors(root, &config);
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o755))
+            .unwrap();
+
+        let Explored { nodes, errors, .. } = res.unwrap();
+        assert_eq!(
+            relative_paths(&nodes),
+            vec!["a", "a/b", "a/y", "locked", "x"]
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, root.join("locked/secret").to_string_lossy());
+        assert!(errors[0].message.contains("ermission denied"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[ignore = "permissions don't apply to root, run it as a regular user"]
+    fn test_explore_unreadable_sub_directory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = fixture();
+        let root = tmp.path();
+        fs::set_permissions(root.join("a/b"), fs::Permissions::from_mode(0o000))
+            .unwrap();
+
+        let mut config = sorted_config();
+        config.max_depth = Some(0);
+        let res = explore_with_errors(root, &config);
+        fs::set_permissions(root.join("a/b"), fs::Permissions::from_mode(0o755))
+            .unwrap();
+
+        let Explored { nodes, errors, .. } = res.unwrap();
+        assert_eq!(relative_paths(&nodes), vec!["a", "a/b", "a/y", "x"]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, root.join("a/b").to_string_lossy());
+    }
+
     fn extract_dirbuf_from_msg(msg: MsgIn) -> DirectoryBuffer {
         assert!(matches!(msg, MsgIn::Internal(_)));
 
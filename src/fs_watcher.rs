@@ -0,0 +1,240 @@
+use crate::app::{ExternalMsg, InternalMsg, MsgIn, Task};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{
+    new_debouncer, DebounceEventResult, DebouncedEventKind, Debouncer,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+lazy_static! {
+    static ref FS_WATCHERS: Mutex<FsWatchers> = Mutex::new(FsWatchers::default());
+}
+
+/// Coalesced changes reported by a watcher registered with `xplr.util.watch`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FsEvent {
+    pub handle: u64,
+    pub paths: Vec<String>,
+}
+
+struct FsWatcher {
+    messages: Vec<ExternalMsg>,
+    _debouncer: Debouncer<RecommendedWatcher>,
+}
+
+#[derive(Default)]
+pub struct FsWatchers {
+    tx_msg_in: Arc<Mutex<Option<Sender<Task>>>>,
+    last_handle: u64,
+    watchers: HashMap<u64, FsWatcher>,
+}
+
+impl FsWatchers {
+    /// Events are sent to the given channel. Until connected, they are dropped.
+    pub fn connect(&mut self, tx_msg_in: Sender<Task>) {
+        if let Ok(mut tx) = self.tx_msg_in.lock() {
+            *tx = Some(tx_msg_in);
+        }
+    }
+
+    pub fn watch(
+        &mut self,
+        path: &Path,
+        recursive: bool,
+        debounce: Duration,
+        messages: Vec<ExternalMsg>,
+    ) -> Result<u64> {
+        let handle = self.last_handle + 1;
+        let tx_msg_in = self.tx_msg_in.clone();
+
+        let mut debouncer = new_debouncer(debounce, move |res: DebounceEventResult| {
+            let msg = match res {
+                Ok(events) => {
+                    // A path that keeps changing is reported once it settles.
+                    let paths: Vec<String> = events
+                        .into_iter()
+                        .filter(|e| e.kind == DebouncedEventKind::Any)
+                        .map(|e| e.path.to_string_lossy().to_string())
+                        .collect();
+                    if paths.is_empty() {
+                        return;
+                    }
+                    MsgIn::Internal(InternalMsg::FsEvent(FsEvent { handle, paths }))
+                }
+                Err(e) => MsgIn::External(ExternalMsg::LogError(e.to_string())),
+            };
+
+            if let Some(tx) = tx_msg_in.lock().ok().and_then(|tx| tx.clone()) {
+                tx.send(Task::new(msg, None)).unwrap_or_default();
+            }
+        })?;
+
+        let mode = if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        debouncer.watcher().watch(path, mode)?;
+
+        self.last_handle = handle;
+        self.watchers.insert(
+            handle,
+            FsWatcher {
+                messages,
+                _debouncer: debouncer,
+            },
+        );
+        Ok(handle)
+    }
+
+    /// Returns false if the handle wasn't being watched.
+    pub fn unwatch(&mut self, handle: u64) -> bool {
+        self.watchers.remove(&handle).is_some()
+    }
+
+    /// The messages to handle for the given watcher, if it's still active.
+    pub fn messages(&self, handle: u64) -> Option<Vec<ExternalMsg>> {
+        self.watchers.get(&handle).map(|w| w.messages.clone())
+    }
+}
+
+pub fn connect(tx_msg_in: Sender<Task>) {
+    if let Ok(mut watchers) = FS_WATCHERS.lock() {
+        watchers.connect(tx_msg_in);
+    }
+}
+
+pub fn watch(
+    path: &Path,
+    recursive: bool,
+    debounce: Duration,
+    messages: Vec<ExternalMsg>,
+) -> Result<u64> {
+    FS_WATCHERS
+        .lock()
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?
+        .watch(path, recursive, debounce, messages)
+}
+
+pub fn unwatch(handle: u64) -> bool {
+    FS_WATCHERS
+        .lock()
+        .map(|mut w| w.unwatch(handle))
+        .unwrap_or(false)
+}
+
+pub fn messages(handle: u64) -> Option<Vec<ExternalMsg>> {
+    FS_WATCHERS.lock().ok().and_then(|w| w.messages(handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::mpsc;
+    use std::time::Instant;
+    use tempfile::TempDir;
+
+    const DEBOUNCE: Duration = Duration::from_millis(200);
+
+    // The events report the canonical paths.
+    fn fixture() -> (TempDir, std::path::PathBuf) {
+        let tmp = TempDir::new().unwrap();
+        fs::create_dir_all(tmp.path().join("sub")).unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        (tmp, root)
+    }
+
+    fn next_event(rx: &mpsc::Receiver<Task>) -> FsEvent {
+        let task = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        match task.msg {
+            MsgIn::Internal(InternalMsg::FsEvent(event)) => event,
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+    }
+
+    fn assert_quiet(rx: &mpsc::Receiver<Task>) {
+        assert!(rx.recv_timeout(DEBOUNCE * 3).is_err());
+    }
+
+    #[test]
+    fn test_fs_watcher_events() {
+        let (_tmp, root) = fixture();
+        let (tx, rx) = mpsc::channel();
+
+        let mut watchers = FsWatchers::default();
+        watchers.connect(tx);
+        let handle = watchers
+            .watch(&root, false, DEBOUNCE, vec![ExternalMsg::ExplorePwdAsync])
+            .unwrap();
+        assert_eq!(
+            watchers.messages(handle),
+            Some(vec![ExternalMsg::ExplorePwdAsync])
+        );
+
+        let file = root.join("file");
+        let file_str = file.to_string_lossy().to_string();
+
+        // Rapid changes are coalesced, and arrive after the debounce interval.
+        let start = Instant::now();
+        fs::write(&file, "a").unwrap();
+        fs::write(&file, "ab").unwrap();
+        fs::write(&file, "abc").unwrap();
+        let event = next_event(&rx);
+        assert!(start.elapsed() >= DEBOUNCE);
+        assert_eq!(
+            event,
+            FsEvent {
+                handle,
+                paths: vec![file_str.clone()]
+            }
+        );
+        assert_quiet(&rx);
+
+        fs::write(&file, "modified").unwrap();
+        assert_eq!(next_event(&rx).paths, vec![file_str.clone()]);
+
+        fs::remove_file(&file).unwrap();
+        assert_eq!(next_event(&rx).paths, vec![file_str]);
+
+        // Not recursive.
+        fs::write(root.join("sub/file"), "").unwrap();
+        assert_quiet(&rx);
+
+        assert!(watchers.unwatch(handle));
+        assert!(!watchers.unwatch(handle));
+        assert_eq!(watchers.messages(handle), None);
+
+        fs::write(root.join("other"), "").unwrap();
+        assert_quiet(&rx);
+    }
+
+    #[test]
+    fn test_fs_watcher_recursive() {
+        let (_tmp, root) = fixture();
+        let (tx, rx) = mpsc::channel();
+
+        let mut watchers = FsWatchers::default();
+        watchers.connect(tx);
+        let handle = watchers.watch(&root, true, DEBOUNCE, vec![]).unwrap();
+
+        let file = root.join("sub/file");
+        fs::write(&file, "").unwrap();
+        let event = next_event(&rx);
+        assert_eq!(event.handle, handle);
+        assert_eq!(event.paths, vec![file.to_string_lossy().to_string()]);
+    }
+
+    #[test]
+    fn test_fs_watcher_missing_path() {
+        let mut watchers = FsWatchers::default();
+        let path = Path::new("/there/is/no/such/path");
+        assert!(watchers.watch(path, false, DEBOUNCE, vec![]).is_err());
+    }
+}
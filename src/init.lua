@@ -150,6 +150,16 @@ xplr.config.general.logs.error.format = "ERROR"
 -- Type: [Style](https://xplr.dev/en/style)
 xplr.config.general.logs.error.style = { fg = "Red" }
 
+-- The string to indicate a debug message in logs.
+--
+-- Type: nullable string
+xplr.config.general.logs.debug.format = "DEBUG"
+
+-- The style for the debug logs.
+--
+-- Type: [Style](https://xplr.dev/en/style)
+xplr.config.general.logs.debug.style = { fg = "DarkGray" }
+
 -- Columns to display in the table header.
 --
 -- Type: nullable list of tables with the following fields:
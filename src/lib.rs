@@ -8,14 +8,19 @@ pub mod config;
 pub mod directory_buffer;
 pub mod event_reader;
 pub mod explorer;
+pub mod fs_watcher;
 pub mod input;
+pub mod logs;
 pub mod lua;
 pub mod msg;
 pub mod node;
 pub mod permissions;
 pub mod pipe;
+pub mod progress;
 pub mod pwd_watcher;
 pub mod runner;
+pub mod store;
+pub mod temp;
 pub mod ui;
 pub mod yaml;
 
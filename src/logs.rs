@@ -0,0 +1,78 @@
+use crate::app::{ExternalMsg, Log, LogLevel, MsgIn, Task};
+use lazy_static::lazy_static;
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref PENDING_LOGS: Mutex<PendingLogs> = Mutex::new(PendingLogs::default());
+}
+
+/// The logs added with `xplr.util.log`, until the app takes them.
+#[derive(Debug, Default)]
+pub struct PendingLogs {
+    logs: Vec<Log>,
+    tx_msg_in: Option<Sender<Task>>,
+}
+
+impl PendingLogs {
+    /// Returns true if it's the first log since they were last taken, i.e. if
+    /// the app needs to be woken up.
+    pub fn push(&mut self, log: Log) -> bool {
+        self.logs.push(log);
+        self.logs.len() == 1
+    }
+
+    pub fn take(&mut self) -> Vec<Log> {
+        std::mem::take(&mut self.logs)
+    }
+}
+
+/// Until connected, the logs are kept until the app handles its first task.
+pub fn connect(tx_msg_in: Sender<Task>) {
+    if let Ok(mut pending) = PENDING_LOGS.lock() {
+        pending.tx_msg_in = Some(tx_msg_in);
+    }
+}
+
+pub fn push(level: LogLevel, message: String) {
+    if let Ok(mut pending) = PENDING_LOGS.lock() {
+        if pending.push(Log::new(level, message)) {
+            if let Some(tx) = pending.tx_msg_in.as_ref() {
+                let msg = MsgIn::External(ExternalMsg::Refresh);
+                tx.send(Task::new(msg, None)).unwrap_or_default();
+            }
+        }
+    }
+}
+
+pub fn take() -> Vec<Log> {
+    PENDING_LOGS
+        .lock()
+        .map(|mut pending| pending.take())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_logs() {
+        let mut pending = PendingLogs::default();
+        assert!(pending.take().is_empty());
+
+        assert!(pending.push(Log::new(LogLevel::Debug, "foo".into())));
+        assert!(!pending.push(Log::new(LogLevel::Error, "bar".into())));
+
+        let logs = pending.take();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].level, LogLevel::Debug);
+        assert_eq!(logs[0].message, "foo");
+        assert_eq!(logs[1].level, LogLevel::Error);
+        assert!(logs[0].created_at <= logs[1].created_at);
+        assert!(pending.take().is_empty());
+
+        // Woken up again.
+        assert!(pending.push(Log::new(LogLevel::Info, "baz".into())));
+    }
+}
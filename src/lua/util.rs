@@ -1,31 +1,228 @@
+use crate::app;
+use crate::app::ExternalMsg;
 use crate::explorer;
+use crate::fs_watcher;
+use crate::logs;
 use crate::lua;
 use crate::msg::in_::external::ExplorerConfig;
+use crate::node;
+use crate::progress;
+use crate::runner;
+use crate::store;
+use crate::temp;
+use crate::ui::{Modifier, Style};
 use anyhow::Result;
+use arboard::Clipboard;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Local, TimeZone, Utc};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use globset::GlobBuilder;
+use humansize::{format_size, BINARY, DECIMAL};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::WalkBuilder;
+use indexmap::IndexSet;
+use lazy_static::lazy_static;
+use lscolors::LsColors;
+use md5::Md5;
 use mlua::Error as LuaError;
 use mlua::Lua;
 use mlua::LuaSerdeExt;
+use mlua::SerializeOptions;
 use mlua::Table;
+use mlua::UserData;
+use mlua::UserDataMethods;
 use mlua::Value;
 use path_absolutize::*;
+use percent_encoding::{percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
+use semver::{Version, VersionReq};
+use serde::de::DeserializeOwned;
 use serde::de::Error;
+use serde::Deserialize;
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Component;
+use std::path::Path;
 use std::path::PathBuf;
+use std::process::Child;
 use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use tui::style::Color;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+use xxhash_rust::xxh64::Xxh64;
 
-pub(crate) fn create_table(lua: &Lua) -> Result<Table> {
+lazy_static! {
+    static ref FUZZY_MATCHER: SkimMatcherV2 = SkimMatcherV2::default().smart_case();
+    static ref CLIPBOARD: Mutex<Option<Clipboard>> = Mutex::new(None);
+    static ref ICONS_BY_FILENAME: HashMap<&'static str, &'static str> =
+        ICONS_BY_FILENAME_DATA.iter().cloned().collect();
+    static ref ICONS_BY_EXTENSION: HashMap<&'static str, &'static str> =
+        ICONS_BY_EXTENSION_DATA.iter().cloned().collect();
+    static ref ENV_LSCOLORS: Mutex<Option<(Option<String>, LsColors)>> =
+        Mutex::new(None);
+}
+
+pub(crate) fn create_table(lua: &Lua) -> Result<Table<'_>> {
     let mut util = lua.create_table()?;
 
     util = dirname(util, lua)?;
     util = basename(util, lua)?;
     util = absolute(util, lua)?;
     util = explore(util, lua)?;
+    util = find(util, lua)?;
     util = shell_execute(util, lua)?;
     util = shell_quote(util, lua)?;
+    util = shell_quote_all(util, lua)?;
+    util = relative_to(util, lua)?;
+    util = path_split(util, lua)?;
+    util = join(util, lua)?;
+    util = extension(util, lua)?;
+    util = stem(util, lua)?;
+    util = shorten(util, lua)?;
+    util = shell_spawn(util, lua)?;
+    util = stat(util, lua)?;
+    util = read_file(util, lua)?;
+    util = write_file(util, lua)?;
+    util = json_decode(util, lua)?;
+    util = json_encode(util, lua)?;
+    util = yaml_decode(util, lua)?;
+    util = yaml_encode(util, lua)?;
+    util = toml_decode(util, lua)?;
+    util = toml_encode(util, lua)?;
+    util = base64_encode(util, lua)?;
+    util = base64_decode(util, lua)?;
+    util = hash(util, lua)?;
+    util = glob_match(util, lua)?;
+    util = fuzzy_match(util, lua)?;
+    util = clipboard_write(util, lua)?;
+    util = clipboard_read(util, lua)?;
+    util = osc52_copy(util, lua)?;
+    util = hexdump(util, lua)?;
+    util = realpath(util, lua)?;
+    util = exists(util, lua)?;
+    util = is_dir(util, lua)?;
+    util = is_file(util, lua)?;
+    util = is_symlink(util, lua)?;
+    util = uid_to_name(util, lua)?;
+    util = gid_to_name(util, lua)?;
+    util = which(util, lua)?;
+    util = expand(util, lua)?;
+    util = parse_size(util, lua)?;
+    util = human_size(util, lua)?;
+    util = format_time(util, lua)?;
+    util = relative_time(util, lua)?;
+    util = regex_match(util, lua)?;
+    util = regex_replace(util, lua)?;
+    util = truncate(util, lua)?;
+    util = wrap(util, lua)?;
+    util = parse_lscolors(util, lua)?;
+    util = lscolor_for(util, lua)?;
+    util = icon(util, lua)?;
+    util = copy(util, lua)?;
+    util = r#move(util, lua)?;
+    util = mkdir(util, lua)?;
+    util = remove(util, lua)?;
+    util = symlink(util, lua)?;
+    util = readlink(util, lua)?;
+    util = dir_size(util, lua)?;
+    util = url_encode(util, lua)?;
+    util = url_decode(util, lua)?;
+    util = path_to_uri(util, lua)?;
+    util = uri_to_path(util, lua)?;
+    util = notify(util, lua)?;
+    util = open(util, lua)?;
+    util = open_with(util, lua)?;
+    util = deep_merge(util, lua)?;
+    util = clone(util, lua)?;
+    util = version_compare(util, lua)?;
+    util = version_matches(util, lua)?;
+    util = diff_dirs(util, lua)?;
+    util = read_lines(util, lua)?;
+    util = watch(util, lua)?;
+    util = unwatch(util, lua)?;
+    util = getenv(util, lua)?;
+    util = setenv(util, lua)?;
+    util = environ(util, lua)?;
+    util = lines(util, lua)?;
+    util = unlines(util, lua)?;
+    util = sniff(util, lua)?;
+    util = archive_list(util, lua)?;
+    util = archive_extract(util, lua)?;
+    util = throttle(util, lua)?;
+    util = tabulate(util, lua)?;
+    util = set_progress(util, lua)?;
+    util = clear_progress(util, lua)?;
+    util = editor(util, lua)?;
+    util = pager(util, lua)?;
+    util = trash(util, lua)?;
+    util = store_set(util, lua)?;
+    util = store_get(util, lua)?;
+    util = store_delete(util, lua)?;
+    util = persist_set(util, lua)?;
+    util = persist_get(util, lua)?;
+    util = persist_keys(util, lua)?;
+    util = common_path(util, lua)?;
+    util = highlight(util, lua)?;
+    util = image_kitty(util, lua)?;
+    util = image_sixel(util, lua)?;
+    util = detect_graphics(util, lua)?;
+    util = render_markdown(util, lua)?;
+    util = ansi_strip(util, lua)?;
+    util = ansi_width(util, lua)?;
+    util = tempfile(util, lua)?;
+    util = tempdir(util, lua)?;
+    util = chmod(util, lua)?;
+    util = chown(util, lua)?;
+    util = sort_nodes(util, lua)?;
+    util = filter_nodes(util, lua)?;
+    util = index_of(util, lua)?;
+    util = is_tty(util, lua)?;
+    util = terminal_size(util, lua)?;
+    util = color(util, lua)?;
+    util = blend(util, lua)?;
+    util = lighten(util, lua)?;
+    util = darken(util, lua)?;
+    util = to_hex(util, lua)?;
+    util = to_ansi256(util, lua)?;
+    util = supports_truecolor(util, lua)?;
+    util = sanitize(util, lua)?;
+    util = find_duplicates(util, lua)?;
+    util = disk_usage(util, lua)?;
+    util = batch_rename(util, lua)?;
+    util = tree_prefix(util, lua)?;
+    util = gitignore_matcher(util, lua)?;
+    util = find_root(util, lua)?;
+    util = log(util, lua)?;
 
     Ok(util)
 }
 
-/// Get the directory name of a given path.
+/// Get the directory name of a given path. The trailing separators are
+/// ignored, and the path is not normalized, so `"foo/.."` gives `"foo"`.
+/// Returns nil if the path has no parent, e.g. for `"/"`, `"foo"`, `"."` and
+/// `".."`.
 ///
 /// Type: function( path:string ) -> path:string|nil
 ///
@@ -34,19 +231,28 @@ pub(crate) fn create_table(lua: &Lua) -> Result<Table> {
 /// ```lua
 /// xplr.util.dirname("/foo/bar")
 /// -- "/foo"
+///
+/// xplr.util.dirname("/foo/bar/")
+/// -- "/foo"
+///
+/// xplr.util.dirname("/")
+/// -- nil
 /// ```
 pub fn dirname<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
     let func = lua.create_function(|_, path: String| {
-        let parent = PathBuf::from(path)
+        let parent = Path::new(trim_trailing_separators(&path))
             .parent()
-            .map(|p| p.to_string_lossy().to_string());
+            .map(|p| p.to_string_lossy().to_string())
+            .filter(|p| !p.is_empty());
         Ok(parent)
     })?;
     util.set("dirname", func)?;
     Ok(util)
 }
 
-/// Get the base name of a given path.
+/// Get the base name of a given path. The trailing separators are ignored.
+/// Returns nil if the path has no name, e.g. for `"/"`, `"."`, `".."` and the
+/// paths ending with `".."`.
 ///
 /// Type: function( path:string ) -> path:string|nil
 ///
@@ -55,10 +261,16 @@ pub fn dirname<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
 /// ```lua
 /// xplr.util.basename("/foo/bar")
 /// -- "bar"
+///
+/// xplr.util.basename("/foo/bar/")
+/// -- "bar"
+///
+/// xplr.util.basename("/")
+/// -- nil
 /// ```
 pub fn basename<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
     let func = lua.create_function(|_, path: String| {
-        let parent = PathBuf::from(path)
+        let parent = Path::new(trim_trailing_separators(&path))
             .file_name()
             .map(|p| p.to_string_lossy().to_string());
         Ok(parent)
@@ -67,8 +279,20 @@ pub fn basename<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
     Ok(util)
 }
 
-/// Get the absolute path of the given path by prepending $PWD.
-/// It doesn't check if the path exists.
+// Strips the trailing separators, but keeps the root.
+fn trim_trailing_separators(path: &str) -> &str {
+    let trimmed = path.trim_end_matches(std::path::is_separator);
+    if trimmed.is_empty() && !path.is_empty() {
+        &path[..1]
+    } else {
+        trimmed
+    }
+}
+
+/// Get the absolute path of the given path by prepending $PWD, with the `.`
+/// and `..` components normalized. It's purely lexical, so it doesn't check
+/// if the path exists, and doesn't resolve the symlinks like
+/// `xplr.util.realpath` does.
 ///
 /// Type: function( path:string ) -> path:string
 ///
@@ -77,6 +301,9 @@ pub fn basename<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
 /// ```lua
 /// xplr.util.absolute("foo/bar")
 /// -- "/tmp/foo/bar"
+///
+/// xplr.util.absolute("foo/../bar/.")
+/// -- "/tmp/bar"
 /// ```
 pub fn absolute<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
     let func = lua.create_function(|_, path: String| {
@@ -91,17 +318,35 @@ pub fn absolute<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
 }
 
 /// Explore directories with the given explorer config.
+/// Set `max_depth` in the config to also list the entries of the sub
+/// directories. The nodes are sorted per directory, and the nodes of a sub
+/// directory are placed right after it.
+///
+/// The entries that can't be read are skipped, and returned as the second
+/// value, as a list of `{ path, message }`. Raises an error only if the given
+/// directory can't be read.
+///
+/// Set `offset` and `limit` in the config to only get a page of the nodes, for
+/// the huge directories. The total number of nodes is returned as the third
+/// value.
 ///
 /// Type: function( path:string, config:[Explorer Config][1]|nil )
-///         -> { node:[Node][2]... }
+///         -> { node:[Node][2]... }, { { path:string, message:string }... },
+///            total_count:integer
 ///
 /// Example:
 ///
 /// ```lua
 ///
 /// xplr.util.explore("/tmp")
-/// xplr.util.explore("/tmp", app.extra_config)
-/// -- { { absolute_path = "/tmp/a", ... }, ... }
+/// xplr.util.explore("/tmp", app.explorer_config)
+/// -- { { absolute_path = "/tmp/a", ... }, ... }, {}, 3
+///
+/// xplr.util.explore("/tmp", { max_depth = 2, filters = {}, sorters = {} })
+/// -- { { relative_path = "a", ... }, { relative_path = "a/b", ... }, ... }
+///
+/// xplr.util.explore("/tmp", { offset = 100, limit = 50, filters = {}, sorters = {} })
+/// -- { { relative_path = "x", ... }, ... }, {}, 1000
 /// ```
 ///
 /// [1]: https://xplr.dev/en/lua-function-calls#explorer-config
@@ -114,61 +359,13812 @@ pub fn explore<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
             ExplorerConfig::default()
         };
 
-        let nodes = explorer::explore(&PathBuf::from(path), &config)
+        let explored = explorer::explore_with_errors(&PathBuf::from(path), &config)
             .map_err(LuaError::custom)?;
-        let res = lua::serialize(lua, &nodes).map_err(LuaError::custom)?;
-        Ok(res)
+        let nodes = lua::serialize(lua, &explored.nodes).map_err(LuaError::custom)?;
+        let errors = lua::serialize(lua, &explored.errors).map_err(LuaError::custom)?;
+        Ok((nodes, errors, explored.total_count))
     })?;
     util.set("explore", func)?;
     Ok(util)
 }
 
+/// Sort the given nodes, e.g. as returned by [xplr.util.explore][1], with the
+/// same logic as the explorer. The sorters are the same as the
+/// [explorer config][2]'s, i.e. a list of `{ sorter, reverse }`, applied in
+/// order, each breaking the ties of the previous ones. See [Sorting][3] for
+/// the available sorters.
+///
+/// The sort is stable, i.e. the nodes that every sorter considers equal keep
+/// their order. The nodes themselves are returned as they were given, in a
+/// new list.
+///
+/// Type: function( nodes:{ node:[Node][4]... }, sorters:{ sorter:table... } ) -> { node:[Node][4]... }
+///
+/// Example:
+///
+/// ```lua
+/// local nodes = xplr.util.explore("/tmp")
+/// xplr.util.sort_nodes(nodes, {
+///   { sorter = "ByIsDir", reverse = true },
+///   { sorter = "ByIRelativePath" },
+/// })
+/// -- { { relative_path = "dir", ... }, { relative_path = "file", ... } }
+/// ```
+///
+/// [1]: #xplrutilexplore
+/// [2]: https://xplr.dev/en/lua-function-calls#explorer-config
+/// [3]: https://xplr.dev/en/sorting
+/// [4]: https://xplr.dev/en/lua-function-calls#node
+pub fn sort_nodes<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, (nodes, sorters): (Vec<Value>, Vec<Value>)| {
+            let config = ExplorerConfig {
+                sorters: sorters
+                    .into_iter()
+                    .map(|sorter| lua.from_value(sorter))
+                    .collect::<mlua::Result<_>>()?,
+                ..Default::default()
+            };
+            let parsed: Vec<node::Node> = nodes
+                .iter()
+                .map(|node| lua.from_value(node.clone()))
+                .collect::<mlua::Result<_>>()?;
+
+            let mut order: Vec<usize> = (0..nodes.len()).collect();
+            order.sort_by(|a, b| config.sort(&parsed[*a], &parsed[*b]));
+            lua.create_sequence_from(order.into_iter().map(|i| nodes[i].clone()))
+        })?;
+    util.set("sort_nodes", func)?;
+    Ok(util)
+}
+
+/// Filter the given nodes, e.g. as returned by [xplr.util.explore][1] or
+/// [xplr.util.find][2], with the same logic as the explorer. The filters are
+/// the same as the [explorer config][3]'s, i.e. a list of `{ filter, input }`,
+/// and a node is kept only if it passes all of them. See [Filtering][4] for
+/// the available filters, each of which has a negated variant, e.g.
+/// `RelativePathDoesNotStartWith`.
+///
+/// The nodes themselves are returned as they were given, in order, in a new
+/// list.
+///
+/// Type: function( nodes:{ node:[Node][5]... }, filters:{ filter:table... } ) -> { node:[Node][5]... }
+///
+/// Example:
+///
+/// ```lua
+/// local nodes = xplr.util.explore("/tmp")
+/// xplr.util.filter_nodes(nodes, {
+///   { filter = "RelativePathDoesNotStartWith", input = "." },
+///   { filter = "IRelativePathDoesMatchRegex", input = "\\.md$" },
+/// })
+/// -- { { relative_path = "README.md", ... } }
+/// ```
+///
+/// [1]: #xplrutilexplore
+/// [2]: #xplrutilfind
+/// [3]: https://xplr.dev/en/lua-function-calls#explorer-config
+/// [4]: https://xplr.dev/en/filtering
+/// [5]: https://xplr.dev/en/lua-function-calls#node
+pub fn filter_nodes<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, (nodes, filters): (Vec<Value>, Vec<Value>)| {
+            let config = ExplorerConfig {
+                filters: filters
+                    .into_iter()
+                    .map(|filter| lua.from_value(filter))
+                    .collect::<mlua::Result<_>>()?,
+                ..Default::default()
+            };
+
+            let mut filtered = vec![];
+            for node in nodes {
+                let parsed: node::Node = lua.from_value(node.clone())?;
+                if config.filter(&parsed) {
+                    filtered.push(node);
+                }
+            }
+            lua.create_sequence_from(filtered)
+        })?;
+    util.set("filter_nodes", func)?;
+    Ok(util)
+}
+
+/// Get the position of the node with the given relative path in the given
+/// nodes, e.g. to keep the focus on the same file after exploring again, even
+/// if the entries were added, removed or sorted differently. Returns nil if
+/// there's no such node.
+///
+/// The returned index is 1-based, like Lua lists. Subtract 1 for the
+/// [FocusByIndex][1] message.
+///
+/// Type: function( nodes:{ node:[Node][2]... }, relative_path:string ) -> index:integer|nil
+///
+/// Example:
+///
+/// ```lua
+/// local focused = app.focused_node.relative_path
+/// local nodes = xplr.util.explore(app.pwd, app.explorer_config)
+/// local index = xplr.util.index_of(nodes, focused)
+/// -- 3
+///
+/// if index then
+///   return { { FocusByIndex = index - 1 } }
+/// end
+/// ```
+///
+/// [1]: https://xplr.dev/en/messages#focusbyindex
+/// [2]: https://xplr.dev/en/lua-function-calls#node
+pub fn index_of<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, (nodes, relative_path): (Table, String)| {
+        for (i, node) in nodes.sequence_values::<Table>().enumerate() {
+            let path: Option<String> = node?.get("relative_path")?;
+            if path.as_deref() == Some(relative_path.as_str()) {
+                return Ok(Some(i + 1));
+            }
+        }
+        Ok(None)
+    })?;
+    util.set("index_of", func)?;
+    Ok(util)
+}
+
+/// Find the entries in the given directory, recursively, and return them as
+/// nodes, in the order of their paths. Symlinks are not followed.
+///
+/// Options:
+///
+/// - pattern: Glob pattern the names must match (e.g. `*.txt`). Patterns
+///   containing a `/` are matched against the path relative to the root.
+/// - max_depth: Only look up to this depth, 1 being the direct children, and
+///   0 meaning no limit, like in the explorer config.
+/// - type: "file" or "dir", the type of the entries to find.
+/// - respect_gitignore: Skip the entries ignored by git.
+/// - limit: Stop after finding this many entries.
+///
+/// Type: function( root:string, options:table|nil ) -> { node:[Node][1]... }
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.find("/tmp", { pattern = "*.txt", max_depth = 2, limit = 100 })
+/// -- { { relative_path = "foo.txt", ... }, { relative_path = "bar/baz.txt", ... } }
+/// ```
+///
+/// [1]: https://xplr.dev/en/lua-function-calls#node
+pub fn find<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, (root, options): (String, Option<Table>)| {
+            let query: explorer::FindQuery = from_options(lua, options)?;
+            let nodes =
+                explorer::find(Path::new(&root), &query).map_err(LuaError::custom)?;
+            lua::serialize(lua, &nodes).map_err(LuaError::custom)
+        })?;
+    util.set("find", func)?;
+    Ok(util)
+}
+
 /// Execute shell commands safely.
 ///
-/// Type: function( program:string, args:{ arg:string... }|nil )
+/// Options:
+///
+/// - timeout_ms: Kill the command if it doesn't exit within the given
+///   number of milliseconds. The output produced until then is returned with
+///   `returncode = nil` and a note appended to `stderr`.
+/// - stdin: Feed the given string to the command's standard input.
+/// - cwd: Run the command in the given directory instead of $PWD.
+/// - env: Extra environment variables to set for the command. These are
+///   added on top of the inherited environment.
+/// - clear_env: Don't inherit xplr's environment. Only the variables in
+///   `env` (if any) will be visible to the command.
+/// - on_output: Function called with each chunk of the output as soon as it's
+///   produced, and the name of the stream ("stdout" or "stderr"). It's called
+///   from within `shell_execute`, one chunk at a time, before it returns.
+///   Errors raised by it kill the command and are raised again.
+///
+/// Type: function( program:string, args:{ arg:string... }|nil, options:table|nil )
 ///         -> { stdout = string, stderr = string, returncode = number|nil }
 ///
 /// Example:
 ///
 /// ```lua
-/// xplr.util.shell_execute("pwd"})
+/// xplr.util.shell_execute("pwd")
 /// xplr.util.shell_execute("bash", {"-c", "xplr --help"})
 /// -- { stdout = "xplr...", stderr = "", returncode = 0 }
+///
+/// xplr.util.shell_execute("sleep", {"10"}, { timeout_ms = 100 })
+/// -- { stdout = "", stderr = "timed out after 100ms", returncode = nil }
+///
+/// xplr.util.shell_execute("sort", {}, { stdin = "b\na\n" })
+/// -- { stdout = "a\nb\n", stderr = "", returncode = 0 }
+///
+/// xplr.util.shell_execute("sh", {"-c", "echo $FOO"}, { cwd = "/tmp", env = { FOO = "bar" } })
+/// -- { stdout = "bar\n", stderr = "", returncode = 0 }
+///
+/// xplr.util.shell_execute("make", {}, {
+///   on_output = function(chunk, stream)
+///     xplr.util.log("debug", stream .. ": " .. chunk)
+///   end,
+/// })
+/// -- { stdout = "...", stderr = "", returncode = 0 }
 /// ```
 pub fn shell_execute<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
-    let func =
-        lua.create_function(|lua, (program, args): (String, Option<Vec<String>>)| {
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        timeout_ms: Option<u64>,
+        stdin: Option<String>,
+        cwd: Option<String>,
+        env: HashMap<String, String>,
+        clear_env: bool,
+    }
+
+    let func = lua.create_function(
+        |lua, (program, args, options): (String, Option<Vec<String>>, Option<Table>)| {
+            let (options, on_output): (Options, Option<mlua::Function>) =
+                from_options_with_callback(lua, options, "on_output")?;
+
             let mut cmd = Command::new(program);
-            let mut cmd_ref = &mut cmd;
             if let Some(args) = args {
-                cmd_ref = cmd_ref.args(args)
+                cmd.args(args);
+            };
+
+            if let Some(cwd) = options.cwd {
+                cmd.current_dir(cwd);
+            }
+
+            if options.clear_env {
+                cmd.env_clear();
+            }
+            cmd.envs(options.env);
+
+            #[cfg(unix)]
+            if options.timeout_ms.is_some() || on_output.is_some() {
+                // So that we can kill the whole process tree on timeout or on errors.
+                std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+            }
+
+            let stdin = if options.stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            };
+
+            let mut child = cmd
+                .stdin(stdin)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+
+            // Write on a separate thread so that we don't deadlock when the
+            // child fills up its output pipes before consuming all the input.
+            let writer =
+                child
+                    .stdin
+                    .take()
+                    .zip(options.stdin)
+                    .map(|(mut pipe, input)| {
+                        thread::spawn(move || pipe.write_all(input.as_bytes()))
+                    });
+
+            let timeout = options.timeout_ms.map(Duration::from_millis);
+            let (status, stdout, stderr) = match on_output {
+                Some(on_output) => stream_output(lua, &mut child, timeout, on_output)?,
+                None => {
+                    let stdout = read_in_background(child.stdout.take());
+                    let stderr = read_in_background(child.stderr.take());
+
+                    let status = match timeout {
+                        Some(timeout) => wait_timeout(&mut child, timeout)?,
+                        None => Some(child.wait()?),
+                    };
+
+                    let stdout = stdout.join().unwrap_or_default();
+                    let stderr = stderr.join().unwrap_or_default();
+                    (status, stdout, stderr)
+                }
             };
-            let output = cmd_ref.output()?;
+
+            // The child may exit without reading all the input.
+            if let Some(writer) = writer {
+                writer.join().ok();
+            }
+
+            let mut stderr = String::from_utf8_lossy(&stderr).to_string();
+
+            if let (None, Some(ms)) = (status, options.timeout_ms) {
+                if !stderr.is_empty() && !stderr.ends_with('\n') {
+                    stderr.push('\n');
+                }
+                stderr.push_str(&format!("timed out after {}ms", ms));
+            }
 
             let res = lua.create_table()?;
-            res.set("stdout", String::from_utf8_lossy(&output.stdout))?;
-            res.set("stderr", String::from_utf8_lossy(&output.stderr))?;
-            res.set("returncode", output.status.code())?;
+            res.set("stdout", String::from_utf8_lossy(&stdout))?;
+            res.set("stderr", stderr)?;
+            res.set("returncode", status.and_then(|s| s.code()))?;
             Ok(res)
-        })?;
+        },
+    )?;
     util.set("shell_execute", func)?;
     Ok(util)
 }
 
-/// Quote commands and paths safely.
+fn read_in_background<R: Read + Send + 'static>(
+    pipe: Option<R>,
+) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = vec![];
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut buf).unwrap_or_default();
+        }
+        buf
+    })
+}
+
+// Reads the output on background threads, and passes each chunk to the
+// callback on the current thread, as they arrive. Returns `None` for the
+// status if the child had to be killed.
+fn stream_output<'lua>(
+    lua: &'lua Lua,
+    child: &mut Child,
+    timeout: Option<Duration>,
+    on_output: mlua::Function<'lua>,
+) -> mlua::Result<(Option<ExitStatus>, Vec<u8>, Vec<u8>)> {
+    fn forward<R: Read + Send + 'static>(
+        pipe: Option<R>,
+        stream: &'static str,
+        tx: mpsc::Sender<(&'static str, Vec<u8>)>,
+    ) {
+        if let Some(mut pipe) = pipe {
+            thread::spawn(move || {
+                let mut buf = vec![0; 8 * 1024];
+                while let Ok(n) = pipe.read(&mut buf) {
+                    if n == 0 || tx.send((stream, buf[..n].to_vec())).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+
+    let (tx, rx) = mpsc::channel();
+    forward(child.stdout.take(), "stdout", tx.clone());
+    forward(child.stderr.take(), "stderr", tx);
+
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let mut killed = false;
+    let (mut stdout, mut stderr) = (vec![], vec![]);
+
+    loop {
+        let wait = deadline
+            .filter(|_| !killed)
+            .map(|d| d.saturating_duration_since(Instant::now()))
+            .unwrap_or(Duration::from_millis(100))
+            .min(Duration::from_millis(100));
+
+        match rx.recv_timeout(wait) {
+            Ok((stream, chunk)) => {
+                if stream == "stdout" {
+                    stdout.extend_from_slice(&chunk);
+                } else {
+                    stderr.extend_from_slice(&chunk);
+                }
+                let res = lua
+                    .create_string(&chunk)
+                    .and_then(|c| on_output.call::<_, ()>((c, stream)));
+                if let Err(e) = res {
+                    kill(child).ok();
+                    return Err(e);
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if !killed && deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+                    kill(child)?;
+                    killed = true;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    let status = if killed {
+        None
+    } else {
+        match deadline {
+            Some(d) => wait_timeout(child, d.saturating_duration_since(Instant::now()))?,
+            None => Some(child.wait()?),
+        }
+    };
+    Ok((status, stdout, stderr))
+}
+
+// Returns `None` if the child had to be killed.
+fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            kill(child)?;
+            return Ok(None);
+        }
+
+        thread::sleep((timeout - elapsed).min(Duration::from_millis(10)));
+    }
+}
+
+// Kills the child along with its process group, if it has one.
+fn kill(child: &mut Child) -> io::Result<()> {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    child.kill().or_else(|e| {
+        if e.kind() == io::ErrorKind::InvalidInput {
+            Ok(()) // Already exited
+        } else {
+            Err(e)
+        }
+    })?;
+    child.wait()?;
+    Ok(())
+}
+
+/// Quote commands and paths safely, for the given shell.
+///
+/// Options:
+///
+/// - shell: "posix", "powershell" or "cmd". Defaults to "cmd" on Windows, and
+///   "posix" elsewhere.
 ///
-/// Type: function( string ) -> string
+/// Type: function( string, options:table|nil ) -> string
 ///
 /// Example:
 ///
 /// ```lua
 /// xplr.util.shell_quote("a'b\"c")
 /// -- 'a'"'"'b"c'
+///
+/// xplr.util.shell_quote("a'b c", { shell = "powershell" })
+/// -- 'a''b c'
+///
+/// xplr.util.shell_quote('a"b c', { shell = "cmd" })
+/// -- ^"a\^"b c^"
 /// ```
 pub fn shell_quote<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
-    let func = lua.create_function(|_, string: String| {
-        Ok(format!("'{}'", string.replace('\'', r#"'"'"'"#)))
-    })?;
+    let func =
+        lua.create_function(|lua, (string, options): (String, Option<Table>)| {
+            let options: QuoteOptions = from_options(lua, options)?;
+            Ok(quote(&string, options.shell))
+        })?;
     util.set("shell_quote", func)?;
     Ok(util)
 }
+
+/// Quote each of the given arguments like `xplr.util.shell_quote`, and join
+/// them with spaces, e.g. to build a command for `sh -c`. Empty arguments are
+/// quoted as `''`, so they are kept. It takes the same options as
+/// `xplr.util.shell_quote`.
+///
+/// Prefer passing the arguments to `xplr.util.shell_execute` directly when
+/// the shell features (e.g. pipes, redirections, globs) are not needed, as no
+/// quoting is needed then.
+///
+/// Type: function( args:{ arg:string... }, options:table|nil ) -> string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.shell_quote_all({ "echo", "a b", "" })
+/// -- "'echo' 'a b' ''"
+/// ```
+pub fn shell_quote_all<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, (args, options): (Vec<String>, Option<Table>)| {
+            let options: QuoteOptions = from_options(lua, options)?;
+            Ok(args
+                .iter()
+                .map(|a| quote(a, options.shell))
+                .collect::<Vec<_>>()
+                .join(" "))
+        })?;
+    util.set("shell_quote_all", func)?;
+    Ok(util)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum QuoteShell {
+    Posix,
+    Powershell,
+    Cmd,
+}
+
+impl Default for QuoteShell {
+    fn default() -> Self {
+        if cfg!(windows) {
+            Self::Cmd
+        } else {
+            Self::Posix
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct QuoteOptions {
+    shell: QuoteShell,
+}
+
+fn quote(string: &str, shell: QuoteShell) -> String {
+    match shell {
+        QuoteShell::Posix => format!("'{}'", string.replace('\'', r#"'"'"'"#)),
+
+        // PowerShell treats the typographic single quotes like `'` too.
+        QuoteShell::Powershell => {
+            let mut quoted = String::with_capacity(string.len() + 2);
+            quoted.push('\'');
+            for c in string.chars() {
+                if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}')
+                {
+                    quoted.push(c);
+                }
+                quoted.push(c);
+            }
+            quoted.push('\'');
+            quoted
+        }
+
+        // Quote for the programs parsing their command line like the C
+        // runtime does, then escape the characters special to cmd.exe itself
+        // with `^`, including the quotes, so it never toggles its own quoting.
+        QuoteShell::Cmd => {
+            let mut quoted = String::with_capacity(string.len() + 2);
+            quoted.push('"');
+            let mut backslashes = 0;
+            for c in string.chars() {
+                match c {
+                    '\\' => backslashes += 1,
+                    '"' => {
+                        // Double the preceding backslashes, and escape the quote.
+                        quoted.push_str(&"\\".repeat(backslashes + 1));
+                        backslashes = 0;
+                    }
+                    _ => backslashes = 0,
+                }
+                quoted.push(c);
+            }
+            quoted.push_str(&"\\".repeat(backslashes));
+            quoted.push('"');
+
+            let mut escaped = String::with_capacity(quoted.len() * 2);
+            for c in quoted.chars() {
+                if matches!(c, '(' | ')' | '%' | '!' | '^' | '"' | '<' | '>' | '&' | '|')
+                {
+                    escaped.push('^');
+                }
+                escaped.push(c);
+            }
+            escaped
+        }
+    }
+}
+
+/// Get the relative path of the given path with respect to a base path.
+/// Both the paths are made absolute (prepending $PWD) and normalized before
+/// comparison. If the paths are on different roots (e.g. different drive
+/// letters on Windows), the path is returned unchanged.
+///
+/// Type: function( path:string, base:string|nil ) -> path:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.relative_to("/a/b/c", "/a/x")
+/// -- "../b/c"
+///
+/// xplr.util.relative_to("/a/b", "/a/b")
+/// -- "."
+///
+/// xplr.util.relative_to("/tmp/foo")
+/// -- "foo"
+/// ```
+pub fn relative_to<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, (path, base): (String, Option<String>)| {
+        let base = match base {
+            Some(base) => PathBuf::from(base),
+            None => std::env::current_dir()?,
+        };
+        let rel = relative_path(&PathBuf::from(path).absolutize()?, &base.absolutize()?);
+        Ok(rel.to_string_lossy().to_string())
+    })?;
+    util.set("relative_to", func)?;
+    Ok(util)
+}
+
+// Both the paths are expected to be absolute and normalized.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let mut path_iter = path.components().peekable();
+    let mut base_iter = base.components().peekable();
+
+    match (path_iter.peek(), base_iter.peek()) {
+        (Some(Component::Prefix(p)), Some(Component::Prefix(b))) if p != b => {
+            return path.to_path_buf();
+        }
+        _ => {}
+    }
+
+    while let (Some(p), Some(b)) = (path_iter.peek(), base_iter.peek()) {
+        if p != b {
+            break;
+        }
+        path_iter.next();
+        base_iter.next();
+    }
+
+    let rel: PathBuf = base_iter
+        .map(|_| Component::ParentDir)
+        .chain(path_iter)
+        .collect();
+
+    if rel.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        rel
+    }
+}
+
+/// Get the longest common ancestor directory of the given paths. The paths
+/// are made absolute (prepending $PWD) and normalized, and then compared
+/// component by component, so `/a/b` and `/a/bc` only share `/a`. Relative
+/// and absolute paths can be mixed.
+///
+/// If one of the paths is the common ancestor itself, but isn't a directory
+/// (e.g. when a single file is given), its parent is returned instead.
+///
+/// Returns nil if no path is given, or if they share no common root (e.g.
+/// different drive letters on Windows).
+///
+/// Type: function( { path:string... } ) -> path:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.common_path({ "/a/b/c", "/a/b/d/e", "/a/bc" })
+/// -- "/a"
+///
+/// xplr.util.common_path({ "/etc/hosts" })
+/// -- "/etc"
+///
+/// xplr.util.common_path({ "C:\\foo", "D:\\foo" })
+/// -- nil
+/// ```
+pub fn common_path<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, paths: Vec<String>| {
+        let paths = paths
+            .iter()
+            .map(|p| Path::new(p).absolutize().map(|p| p.to_path_buf()))
+            .collect::<io::Result<Vec<PathBuf>>>()?;
+        Ok(common_ancestor(&paths).map(|p| p.to_string_lossy().to_string()))
+    })?;
+    util.set("common_path", func)?;
+    Ok(util)
+}
+
+// The paths are expected to be absolute and normalized.
+fn common_ancestor(paths: &[PathBuf]) -> Option<PathBuf> {
+    let (first, rest) = paths.split_first()?;
+    let mut common: Vec<Component> = first.components().collect();
+    for path in rest {
+        let len = common
+            .iter()
+            .zip(path.components())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        common.truncate(len);
+    }
+
+    if common.is_empty() {
+        return None;
+    }
+
+    let common: PathBuf = common.into_iter().collect();
+    if !common.is_dir() && paths.iter().any(|p| p == &common) {
+        common.parent().map(Path::to_path_buf).or(Some(common))
+    } else {
+        Some(common)
+    }
+}
+
+/// Split a path into its components. If the path is absolute, the root
+/// (`/` on Unix, the drive or UNC prefix on Windows) is the first component.
+/// `.` components and redundant separators are dropped, but `..` is
+/// preserved.
+///
+/// Type: function( path:string ) -> { component:string... }
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.path_split("/foo/bar/baz")
+/// -- { "/", "foo", "bar", "baz" }
+///
+/// xplr.util.path_split("./a//b/../c")
+/// -- { "a", "b", "..", "c" }
+/// ```
+pub fn path_split<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|_, path: String| Ok(split_path(Path::new(&path))))?;
+    util.set("path_split", func)?;
+    Ok(util)
+}
+
+fn split_path(path: &Path) -> Vec<String> {
+    let mut parts: Vec<String> = vec![];
+    let mut has_prefix = false;
+    for comp in path.components() {
+        match comp {
+            Component::CurDir => {}
+            Component::Prefix(p) => {
+                has_prefix = true;
+                parts.push(p.as_os_str().to_string_lossy().to_string());
+            }
+            Component::RootDir if has_prefix => {
+                if let Some(prefix) = parts.last_mut() {
+                    prefix.push(std::path::MAIN_SEPARATOR);
+                }
+            }
+            comp => parts.push(comp.as_os_str().to_string_lossy().to_string()),
+        }
+    }
+    parts
+}
+
+/// Join the given path components using the platform path separator. Empty
+/// components are skipped. Same as Rust's `PathBuf::push`, an absolute
+/// component replaces everything joined before it.
+///
+/// Type: function( { component:string... } ) -> path:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.join({ "/foo", "bar", "baz" })
+/// -- "/foo/bar/baz"
+///
+/// xplr.util.join({ "foo", "/bar", "baz" })
+/// -- "/bar/baz"
+/// ```
+pub fn join<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, parts: Vec<String>| {
+        let path = parts.iter().filter(|p| !p.is_empty()).fold(
+            PathBuf::new(),
+            |mut path, part| {
+                path.push(part);
+                path
+            },
+        );
+        Ok(path.to_string_lossy().to_string())
+    })?;
+    util.set("join", func)?;
+    Ok(util)
+}
+
+/// Get the extension of the given path, without the leading dot.
+/// Only the last extension is returned, and dotfiles like `.bashrc` have no
+/// extension.
+///
+/// Type: function( path:string ) -> extension:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.extension("foo.tar.gz")
+/// -- "gz"
+///
+/// xplr.util.extension(".bashrc")
+/// -- nil
+/// ```
+pub fn extension<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, path: String| {
+        let ext = PathBuf::from(path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_string());
+        Ok(ext)
+    })?;
+    util.set("extension", func)?;
+    Ok(util)
+}
+
+/// Get the file name of the given path without its extension.
+///
+/// Type: function( path:string ) -> stem:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.stem("/foo/bar.tar.gz")
+/// -- "bar.tar"
+///
+/// xplr.util.stem(".bashrc")
+/// -- ".bashrc"
+/// ```
+pub fn stem<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, path: String| {
+        let stem = PathBuf::from(path)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string());
+        Ok(stem)
+    })?;
+    util.set("stem", func)?;
+    Ok(util)
+}
+
+/// Shorten the given path by abbreviating all but the last component to
+/// their first few characters (leading dots are preserved, e.g. `.config`
+/// becomes `.c`).
+///
+/// Options:
+///
+/// - length: The number of characters to keep per component (default: 1).
+/// - with_home: Replace the home directory with `~` before shortening
+///   (default: false).
+///
+/// Type: function( path:string, options:{ length:number|nil, with_home:boolean|nil }|nil )
+///         -> path:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.shorten("/home/user/projects/xplr")
+/// -- "/h/u/p/xplr"
+///
+/// xplr.util.shorten("/home/user/projects/xplr", { with_home = true, length = 2 })
+/// -- "~/pr/xplr"
+/// ```
+pub fn shorten<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        length: Option<usize>,
+        with_home: bool,
+    }
+
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let home = if options.with_home {
+                dirs::home_dir()
+            } else {
+                None
+            };
+            Ok(shorten_path(
+                &path,
+                options.length.unwrap_or(1),
+                home.as_deref(),
+            ))
+        })?;
+    util.set("shorten", func)?;
+    Ok(util)
+}
+
+fn shorten_path(path: &str, length: usize, home: Option<&Path>) -> String {
+    let mut path = PathBuf::from(path);
+    if let Some(rest) = home.and_then(|h| path.strip_prefix(h).ok()) {
+        path = PathBuf::from("~").join(rest);
+    }
+
+    let mut comps = path.components().collect::<Vec<_>>();
+    let last = comps.pop();
+
+    comps
+        .into_iter()
+        .map(|comp| match comp {
+            Component::Normal(name) => {
+                let name = name.to_string_lossy();
+                let (dot, name) = match name.strip_prefix('.') {
+                    Some(rest) => (".", rest),
+                    None => ("", name.as_ref()),
+                };
+                let short: String = name.chars().take(length).collect();
+                PathBuf::from(format!("{}{}", dot, short))
+            }
+            comp => PathBuf::from(comp.as_os_str()),
+        })
+        .chain(last.map(|l| PathBuf::from(l.as_os_str())))
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .to_string()
+}
+
+fn from_options<'lua, T: DeserializeOwned + Default>(
+    lua: &'lua Lua,
+    options: Option<Table<'lua>>,
+) -> mlua::Result<T> {
+    match options {
+        Some(options) => lua.from_value(Value::Table(options)),
+        None => Ok(T::default()),
+    }
+}
+
+// Like `from_options`, but also takes out the callback with the given name,
+// which can't be deserialized.
+fn from_options_with_callback<'lua, T: DeserializeOwned + Default>(
+    lua: &'lua Lua,
+    options: Option<Table<'lua>>,
+    name: &str,
+) -> mlua::Result<(T, Option<mlua::Function<'lua>>)> {
+    match options {
+        Some(options) => {
+            let callback = options.get(name)?;
+            let options = lua.from_value_with(
+                Value::Table(options),
+                mlua::DeserializeOptions::new().deny_unsupported_types(false),
+            )?;
+            Ok((options, callback))
+        }
+        None => Ok((T::default(), None)),
+    }
+}
+
+/// Spawn a command in the background without waiting for it to exit, and
+/// without capturing its output. Unlike `xplr.util.shell_execute`, this
+/// returns immediately with the process ID. The process is reaped once it
+/// exits, so it doesn't become a zombie.
+///
+/// Type: function( program:string, args:{ arg:string... }|nil ) -> pid:number
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.shell_spawn("firefox", {"https://xplr.dev"})
+/// -- 12345
+/// ```
+pub fn shell_spawn<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|_, (program, args): (String, Option<Vec<String>>)| {
+            let mut cmd = Command::new(program);
+            if let Some(args) = args {
+                cmd.args(args);
+            };
+
+            let mut child = cmd
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()?;
+
+            let pid = child.id();
+            thread::spawn(move || child.wait());
+            Ok(pid)
+        })?;
+    util.set("shell_spawn", func)?;
+    Ok(util)
+}
+
+/// Get the file system metadata of the given path, without following
+/// symlinks. The timestamps are in seconds since UNIX epoch. `mode`, `uid`,
+/// `gid` and `nlink` are only available on Unix.
+///
+/// Returns nil and the error message if the path can't be read.
+///
+/// Type: function( path:string )
+///         -> { size = number, is_dir = boolean, is_file = boolean,
+///              is_symlink = boolean, readonly = boolean, mtime = number|nil,
+///              atime = number|nil, ctime = number|nil, mode = number|nil,
+///              uid = number|nil, gid = number|nil, nlink = number|nil }|nil,
+///            error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.stat("/tmp/foo.txt")
+/// -- { size = 42, is_file = true, mtime = 1666666666, mode = 33188, ... }
+///
+/// xplr.util.stat("/there/is/no/path")
+/// -- nil, "No such file or directory (os error 2)"
+/// ```
+pub fn stat<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Serialize)]
+    struct Stat {
+        size: u64,
+        is_dir: bool,
+        is_file: bool,
+        is_symlink: bool,
+        readonly: bool,
+        mtime: Option<u64>,
+        atime: Option<u64>,
+        ctime: Option<u64>,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        nlink: Option<u64>,
+    }
+
+    fn secs(time: io::Result<SystemTime>) -> Option<u64> {
+        time.ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+    }
+
+    let func = lua.create_function(|lua, path: String| {
+        let meta = match fs::symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(err) => return Ok((None, Some(err.to_string()))),
+        };
+
+        #[cfg(unix)]
+        let (ctime, mode, uid, gid, nlink) = {
+            use std::os::unix::fs::MetadataExt;
+            (
+                u64::try_from(meta.ctime()).ok(),
+                Some(meta.mode()),
+                Some(meta.uid()),
+                Some(meta.gid()),
+                Some(meta.nlink()),
+            )
+        };
+
+        #[cfg(not(unix))]
+        let (ctime, mode, uid, gid, nlink) =
+            (secs(meta.created()), None, None, None, None);
+
+        let stat = Stat {
+            size: meta.len(),
+            is_dir: meta.is_dir(),
+            is_file: meta.is_file(),
+            is_symlink: meta.file_type().is_symlink(),
+            readonly: meta.permissions().readonly(),
+            mtime: secs(meta.modified()),
+            atime: secs(meta.accessed()),
+            ctime,
+            mode,
+            uid,
+            gid,
+            nlink,
+        };
+
+        let res = lua::serialize(lua, &stat).map_err(LuaError::custom)?;
+        Ok((Some(res), None))
+    })?;
+    util.set("stat", func)?;
+    Ok(util)
+}
+
+/// Read the content of the given file, up to `max_bytes` bytes (default: 1
+/// MiB). Invalid UTF-8 sequences are replaced, so binary files can be read
+/// too. The second value tells if the content was truncated.
+///
+/// Returns nil and the error message if the file can't be read.
+///
+/// Type: function( path:string, max_bytes:number|nil )
+///         -> content:string|nil, truncated:boolean|string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.read_file("/tmp/foo.txt")
+/// -- "foo\n", false
+///
+/// xplr.util.read_file("/var/log/huge.log", 1024)
+/// -- "...", true
+///
+/// xplr.util.read_file("/there/is/no/file")
+/// -- nil, "No such file or directory (os error 2)"
+/// ```
+pub fn read_file<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    const DEFAULT_MAX_BYTES: u64 = 1024 * 1024;
+
+    let func =
+        lua.create_function(|lua, (path, max_bytes): (String, Option<u64>)| {
+            let max_bytes = max_bytes.unwrap_or(DEFAULT_MAX_BYTES);
+            let mut buf = vec![];
+            let res = fs::File::open(path)
+                .and_then(|f| f.take(max_bytes.saturating_add(1)).read_to_end(&mut buf));
+
+            if let Err(err) = res {
+                return Ok((
+                    Value::Nil,
+                    Value::String(lua.create_string(err.to_string())?),
+                ));
+            }
+
+            let truncated = buf.len() as u64 > max_bytes;
+            buf.truncate(max_bytes as usize);
+
+            let content = String::from_utf8_lossy(&buf);
+            Ok((
+                Value::String(lua.create_string(content.as_ref())?),
+                Value::Boolean(truncated),
+            ))
+        })?;
+    util.set("read_file", func)?;
+    Ok(util)
+}
+
+/// Write the given content to a file.
+///
+/// Options:
+///
+/// - append: Append to the file instead of overwriting it.
+/// - mode: Set the Unix permissions of the file (e.g. `tonumber("644", 8)`).
+/// - create_dirs: Create the missing parent directories.
+/// - atomic: Write to a temporary file in the same directory first, and then
+///   rename it to the target path, so that readers never see a partially
+///   written file. The permissions of an existing file are kept, unless
+///   `mode` is given.
+///
+/// Returns nil on success, or the error message on failure.
+///
+/// Type: function( path:string, content:string, options:table|nil ) -> error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.write_file("/tmp/foo.txt", "foo\n")
+/// -- nil
+///
+/// xplr.util.write_file("/tmp/foo.txt", "bar\n", { append = true, atomic = true })
+/// -- nil
+/// ```
+pub fn write_file<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        append: bool,
+        mode: Option<u32>,
+        create_dirs: bool,
+        atomic: bool,
+    }
+
+    fn write(path: &Path, content: &[u8], options: &Options) -> io::Result<()> {
+        if options.create_dirs {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        if options.atomic {
+            let tmp = temp::sibling(path);
+            let res = (|| {
+                if options.append && path.exists() {
+                    fs::copy(path, &tmp)?;
+                }
+                write(
+                    &tmp,
+                    content,
+                    &Options {
+                        atomic: false,
+                        create_dirs: false,
+                        ..*options
+                    },
+                )?;
+                if options.mode.is_none() {
+                    if let Ok(metadata) = fs::metadata(path) {
+                        fs::set_permissions(&tmp, metadata.permissions())?;
+                    }
+                }
+                fs::rename(&tmp, path)
+            })();
+            if res.is_err() {
+                fs::remove_file(&tmp).ok();
+            }
+            return res;
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(options.append)
+            .truncate(!options.append)
+            .open(path)?;
+        file.write_all(content)?;
+
+        #[cfg(unix)]
+        if let Some(mode) = options.mode {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+
+        Ok(())
+    }
+
+    let func = lua.create_function(
+        |lua, (path, content, options): (String, mlua::String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let res = write(Path::new(&path), content.as_bytes(), &options);
+            Ok(res.err().map(|e| e.to_string()))
+        },
+    )?;
+    util.set("write_file", func)?;
+    Ok(util)
+}
+
+/// Decode the given JSON string into a Lua value. Raises an error if the
+/// string is not valid JSON.
+///
+/// JSON null decodes to nil, so the keys set to null are missing in the
+/// decoded table. In arrays, it leaves holes, e.g. `[1, null, 3]` decodes to
+/// `{ 1, nil, 3 }`, where `#` and `ipairs` can't be relied on to reach the
+/// last item. Iterate such arrays with `pairs`, or check for null in the JSON
+/// itself.
+///
+/// Type: function( json:string ) -> any
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.json_decode('{"foo": [1, 2, {"bar": true}], "baz": null}')
+/// -- { foo = { 1, 2, { bar = true } } }
+///
+/// xplr.util.json_decode('[1, null, 3]')
+/// -- { [1] = 1, [3] = 3 }
+/// ```
+pub fn json_decode<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, json: String| {
+        let value: serde_json::Value =
+            serde_json::from_str(&json).map_err(LuaError::custom)?;
+        lua.to_value_with(
+            &value,
+            SerializeOptions::new().serialize_unit_to_null(false),
+        )
+    })?;
+    util.set("json_decode", func)?;
+    Ok(util)
+}
+
+/// Encode the given Lua value into a JSON string. Raises an error if the
+/// value can't be represented in JSON (e.g. a function).
+///
+/// Options:
+///
+/// - pretty: Indent the output over multiple lines.
+///
+/// Type: function( value:any, options:table|nil ) -> json:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.json_encode({ foo = { 1, 2, { bar = true } } })
+/// -- '{"foo":[1,2,{"bar":true}]}'
+///
+/// xplr.util.json_encode({ foo = "bar" }, { pretty = true })
+/// -- '{\n  "foo": "bar"\n}'
+/// ```
+pub fn json_encode<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        pretty: bool,
+    }
+
+    let func =
+        lua.create_function(|lua, (value, options): (Value, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let value: serde_json::Value = lua.from_value(value)?;
+            let res = if options.pretty {
+                serde_json::to_string_pretty(&value)
+            } else {
+                serde_json::to_string(&value)
+            };
+            res.map_err(LuaError::custom)
+        })?;
+    util.set("json_encode", func)?;
+    Ok(util)
+}
+
+/// Decode the given YAML string into a Lua value. Raises an error if the
+/// string is not valid YAML.
+///
+/// If the string contains multiple documents (separated by `---`), only the
+/// first one is decoded. An empty string decodes to nil.
+///
+/// Type: function( yaml:string ) -> any
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.yaml_decode("foo: [1, 2, { bar: true }]")
+/// -- { foo = { 1, 2, { bar = true } } }
+/// ```
+pub fn yaml_decode<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, yaml: String| {
+        if yaml.trim().is_empty() {
+            return Ok(Value::Nil);
+        }
+        match serde_yaml::Deserializer::from_str(&yaml).next() {
+            Some(document) => {
+                match serde_yaml::Value::deserialize(document)
+                    .map_err(LuaError::custom)?
+                {
+                    serde_yaml::Value::Null => Ok(Value::Nil),
+                    value => lua.to_value(&value),
+                }
+            }
+            None => Ok(Value::Nil),
+        }
+    })?;
+    util.set("yaml_decode", func)?;
+    Ok(util)
+}
+
+/// Encode the given Lua value into a YAML string. Raises an error if the
+/// value can't be represented in YAML (e.g. a function).
+///
+/// Type: function( value:any ) -> yaml:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.yaml_encode({ foo = { 1, 2 } })
+/// -- "foo:\n- 1\n- 2\n"
+/// ```
+pub fn yaml_encode<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, value: Value| {
+        let value: serde_yaml::Value = lua.from_value(value)?;
+        serde_yaml::to_string(&value).map_err(LuaError::custom)
+    })?;
+    util.set("yaml_encode", func)?;
+    Ok(util)
+}
+
+/// Decode the given TOML string into a Lua table. Datetimes are decoded as
+/// strings. Raises an error if the string is not valid TOML.
+///
+/// Type: function( toml:string ) -> table
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.toml_decode('[foo]\nbar = 1\ndate = 1979-05-27')
+/// -- { foo = { bar = 1, date = "1979-05-27" } }
+/// ```
+pub fn toml_decode<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    fn stringify_datetimes(value: toml::Value) -> toml::Value {
+        match value {
+            toml::Value::Datetime(dt) => toml::Value::String(dt.to_string()),
+            toml::Value::Array(arr) => {
+                toml::Value::Array(arr.into_iter().map(stringify_datetimes).collect())
+            }
+            toml::Value::Table(tbl) => toml::Value::Table(
+                tbl.into_iter()
+                    .map(|(k, v)| (k, stringify_datetimes(v)))
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+
+    let func = lua.create_function(|lua, toml: String| {
+        let value: toml::Value = toml::from_str(&toml).map_err(LuaError::custom)?;
+        lua.to_value(&stringify_datetimes(value))
+    })?;
+    util.set("toml_decode", func)?;
+    Ok(util)
+}
+
+/// Encode the given Lua table into a TOML string. Raises an error if the
+/// value can't be represented in TOML, e.g. when a table has non-string keys.
+///
+/// Type: function( value:table ) -> toml:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.toml_encode({ foo = { bar = 1 } })
+/// -- "[foo]\nbar = 1\n"
+/// ```
+pub fn toml_encode<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    fn check_keys(table: &Table, path: &mut Vec<String>) -> mlua::Result<()> {
+        let pairs = table
+            .clone()
+            .pairs::<Value, Value>()
+            .collect::<mlua::Result<Vec<_>>>()?;
+
+        // A sequence becomes a TOML array, anything else a TOML table.
+        let is_array = !pairs.is_empty() && pairs.len() == table.raw_len();
+
+        for (key, value) in pairs {
+            let key = match key {
+                Value::String(s) => s.to_string_lossy().to_string(),
+                Value::Integer(i) if is_array => i.to_string(),
+                Value::Number(n) if is_array => n.to_string(),
+                key => {
+                    let parent = if path.is_empty() {
+                        "root".into()
+                    } else {
+                        path.join(".")
+                    };
+                    return Err(LuaError::RuntimeError(format!(
+                        "TOML keys must be strings, found {} key in {}",
+                        key.type_name(),
+                        parent,
+                    )));
+                }
+            };
+            if let Value::Table(t) = value {
+                if path.len() > 128 {
+                    return Err(LuaError::RuntimeError(
+                        "TOML value is nested too deeply".into(),
+                    ));
+                }
+                path.push(key);
+                check_keys(&t, path)?;
+                path.pop();
+            }
+        }
+        Ok(())
+    }
+
+    let func = lua.create_function(|lua, table: Table| {
+        check_keys(&table, &mut vec![])?;
+        let value: toml::Value = lua.from_value(Value::Table(table))?;
+        toml::to_string(&value).map_err(LuaError::custom)
+    })?;
+    util.set("toml_encode", func)?;
+    Ok(util)
+}
+
+/// Encode the given string (which may contain binary data) into base64,
+/// using the standard alphabet.
+///
+/// Options:
+///
+/// - url_safe: Use the URL and filename safe alphabet instead.
+///
+/// Type: function( data:string, options:table|nil ) -> base64:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.base64_encode("foo?")
+/// -- "Zm9vPw=="
+///
+/// xplr.util.base64_encode("\255\254\253")
+/// -- "//79"
+///
+/// xplr.util.base64_encode("\255\254\253", { url_safe = true })
+/// -- "__79"
+/// ```
+pub fn base64_encode<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, (data, options): (mlua::String, Option<Table>)| {
+            let options: Base64Options = from_options(lua, options)?;
+            Ok(base64::encode_config(data.as_bytes(), options.config()))
+        })?;
+    util.set("base64_encode", func)?;
+    Ok(util)
+}
+
+/// Decode the given base64 string, using the standard alphabet. The result
+/// is returned as a Lua string, which may contain binary data. Raises an error
+/// if the input is not valid base64.
+///
+/// Options:
+///
+/// - url_safe: Use the URL and filename safe alphabet instead.
+///
+/// Type: function( base64:string, options:table|nil ) -> data:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.base64_decode("Zm9vPw==")
+/// -- "foo?"
+/// ```
+pub fn base64_decode<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, (data, options): (mlua::String, Option<Table>)| {
+            let options: Base64Options = from_options(lua, options)?;
+            let bytes = base64::decode_config(data.as_bytes(), options.config())
+                .map_err(LuaError::custom)?;
+            lua.create_string(&bytes)
+        })?;
+    util.set("base64_decode", func)?;
+    Ok(util)
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Base64Options {
+    url_safe: bool,
+}
+
+impl Base64Options {
+    fn config(&self) -> base64::Config {
+        if self.url_safe {
+            base64::URL_SAFE
+        } else {
+            base64::STANDARD
+        }
+    }
+}
+
+// Streams the reader, so that large files aren't loaded into memory.
+fn digest<D: Digest>(mut reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut hasher = D::new();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => return Ok(hasher.finalize().to_vec()),
+            n => hasher.update(&buf[..n]),
+        }
+    }
+}
+
+/// Hash the given string, or the content of the given file, and return the
+/// lowercase hex digest. Files are streamed from disk, so that large files
+/// aren't loaded into memory.
+///
+/// Options:
+///
+/// - algo: One of "sha256" (default), "sha1", "md5" or "xxhash" (XXH64 with
+///   seed 0).
+/// - path: Treat the input as a file path, and hash the content of the file.
+///
+/// Returns `nil` and the error message if the file can't be read.
+///
+/// Type: function( input:string, options:table|nil ) -> digest:string|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.hash("foo")
+/// -- "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"
+///
+/// xplr.util.hash("/path/to/file", { algo = "md5", path = true })
+/// -- "d3b07384d113edec49eaa6238ad5ff00"
+/// ```
+pub fn hash<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Default, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Algo {
+        #[default]
+        Sha256,
+        Sha1,
+        Md5,
+        Xxhash,
+    }
+
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        algo: Algo,
+        path: bool,
+    }
+
+    fn xxhash(mut reader: impl Read) -> io::Result<Vec<u8>> {
+        let mut hasher = Xxh64::new(0);
+        let mut buf = [0; 64 * 1024];
+        loop {
+            match reader.read(&mut buf)? {
+                0 => return Ok(hasher.digest().to_be_bytes().to_vec()),
+                n => hasher.update(&buf[..n]),
+            }
+        }
+    }
+
+    fn hash_reader(algo: &Algo, reader: impl Read) -> io::Result<String> {
+        let bytes = match algo {
+            Algo::Sha256 => digest::<Sha256>(reader),
+            Algo::Sha1 => digest::<Sha1>(reader),
+            Algo::Md5 => digest::<Md5>(reader),
+            Algo::Xxhash => xxhash(reader),
+        }?;
+        Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    let func =
+        lua.create_function(|lua, (input, options): (mlua::String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let res = if options.path {
+                fs::File::open(input.to_str()?)
+                    .and_then(|f| hash_reader(&options.algo, f))
+            } else {
+                hash_reader(&options.algo, input.as_bytes())
+            };
+
+            match res {
+                Ok(digest) => Ok((Some(digest), None)),
+                Err(err) => Ok((None, Some(err.to_string()))),
+            }
+        })?;
+    util.set("hash", func)?;
+    Ok(util)
+}
+
+/// Check if the given path matches the given shell-style glob pattern.
+///
+/// Supports `?` (any single character), `*` (anything except `/`), `**`
+/// (anything, including `/`), character classes like `[a-z]` or `[!0-9]`, and
+/// alternatives like `{rs,toml}`. Raises an error if the pattern is invalid.
+///
+/// Options:
+///
+/// - case_insensitive: Match regardless of the case.
+///
+/// Type: function( pattern:string, path:string, options:table|nil ) -> boolean
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.glob_match("*.{rs,toml}", "Cargo.toml")
+/// -- true
+///
+/// xplr.util.glob_match("src/**/*.rs", "src/lua/util.rs")
+/// -- true
+///
+/// xplr.util.glob_match("*.md", "README.MD", { case_insensitive = true })
+/// -- true
+/// ```
+pub fn glob_match<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        case_insensitive: bool,
+    }
+
+    let func = lua.create_function(
+        |lua, (pattern, path, options): (String, String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let glob = GlobBuilder::new(&pattern)
+                .case_insensitive(options.case_insensitive)
+                .literal_separator(true)
+                .backslash_escape(true)
+                .build()
+                .map_err(LuaError::custom)?;
+            Ok(glob.compile_matcher().is_match(path))
+        },
+    )?;
+    util.set("glob_match", func)?;
+    Ok(util)
+}
+
+/// Fuzzy match the given query against the given candidate string, using the
+/// same algorithm as the search mode. Returns nil if the query doesn't match,
+/// or the match score (higher is better) with the 1-based positions of the
+/// matched characters.
+///
+/// Matching is smart case: a lowercase query matches regardless of the case,
+/// whereas a query containing uppercase letters is case sensitive.
+///
+/// Type: function( query:string, candidate:string ) -> { score:number, indices:{ number... } }|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.fuzzy_match("src", "source.rs")
+/// -- { score = 62, indices = { 1, 4, 5 } }
+///
+/// xplr.util.fuzzy_match("Src", "source.rs")
+/// -- nil
+/// ```
+pub fn fuzzy_match<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Serialize)]
+    struct Match {
+        score: i64,
+        indices: Vec<usize>,
+    }
+
+    let func = lua.create_function(|lua, (query, candidate): (String, String)| {
+        match FUZZY_MATCHER.fuzzy_indices(&candidate, &query) {
+            Some((score, indices)) => {
+                let indices = indices.into_iter().map(|i| i + 1).collect();
+                lua::serialize(lua, &Match { score, indices }).map_err(LuaError::custom)
+            }
+            None => Ok(Value::Nil),
+        }
+    })?;
+    util.set("fuzzy_match", func)?;
+    Ok(util)
+}
+
+/// Copy the given text to the system clipboard.
+///
+/// The clipboard is connected to on first use. On Linux, the Wayland data
+/// control protocol is used when available, falling back to X11. Returns nil
+/// on success, or the error message if no clipboard is available (e.g. in a
+/// headless environment). Over SSH, see `xplr.util.osc52_copy`.
+///
+/// Type: function( text:string ) -> error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.clipboard_write(app.focused_node.absolute_path)
+/// -- nil
+/// ```
+pub fn clipboard_write<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, text: String| {
+        let res = with_clipboard(|clipboard| clipboard.set_text(text));
+        Ok(res.err())
+    })?;
+    util.set("clipboard_write", func)?;
+    Ok(util)
+}
+
+/// Read the text content of the system clipboard.
+///
+/// Returns nil and the error message if no clipboard is available, or if it
+/// doesn't contain text.
+///
+/// Type: function() -> text:string|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.clipboard_read()
+/// -- "/path/to/file"
+/// ```
+pub fn clipboard_read<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, ()| {
+        match with_clipboard(|clipboard| clipboard.get_text()) {
+            Ok(text) => Ok((Some(text), None)),
+            Err(err) => Ok((None, Some(err))),
+        }
+    })?;
+    util.set("clipboard_read", func)?;
+    Ok(util)
+}
+
+fn with_clipboard<T>(
+    f: impl FnOnce(&mut Clipboard) -> std::result::Result<T, arboard::Error>,
+) -> std::result::Result<T, String> {
+    // The handle is kept around so that, on X11, the copied content stays
+    // available after the function returns.
+    let mut clipboard = CLIPBOARD.lock().map_err(|e| e.to_string())?;
+    if clipboard.is_none() {
+        *clipboard = Some(Clipboard::new().map_err(|e| e.to_string())?);
+    }
+
+    match clipboard.as_mut() {
+        Some(c) => f(c).map_err(|e| e.to_string()),
+        None => Err("clipboard is not available".into()),
+    }
+}
+
+/// Copy the given text to the clipboard of the terminal emulator using the
+/// OSC 52 escape sequence. Unlike `xplr.util.clipboard_write`, this also works
+/// over SSH, as long as the terminal supports it.
+///
+/// The sequence is written directly to the terminal (`/dev/tty`), so it's safe
+/// to call while the TUI is active. Since many terminals limit the size of the
+/// sequence, the text is truncated to fit 74994 bytes of base64 data.
+///
+/// Returns whether the text was truncated, or nil and the error message if the
+/// terminal can't be written to.
+///
+/// Type: function( text:string ) -> truncated:boolean|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.osc52_copy(app.focused_node.absolute_path)
+/// -- false
+/// ```
+pub fn osc52_copy<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, text: String| {
+        let (seq, truncated) = osc52_sequence(&text);
+        let res = runner::get_tty().and_then(|mut tty| {
+            tty.write_all(seq.as_bytes())?;
+            tty.flush()?;
+            Ok(())
+        });
+
+        match res {
+            Ok(()) => Ok((Some(truncated), None)),
+            Err(err) => Ok((None, Some(err.to_string()))),
+        }
+    })?;
+    util.set("osc52_copy", func)?;
+    Ok(util)
+}
+
+fn osc52_sequence(text: &str) -> (String, bool) {
+    const MAX_BASE64_LEN: usize = 74994;
+    const MAX_TEXT_LEN: usize = MAX_BASE64_LEN / 4 * 3;
+
+    let mut end = text.len().min(MAX_TEXT_LEN);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let encoded = base64::encode(&text[..end]);
+    (format!("\x1b]52;c;{}\x07", encoded), end < text.len())
+}
+
+/// Get the hex dump of the given file, with the offset, the hex bytes and the
+/// ASCII representation of each row. Non-printable bytes are shown as `.` in
+/// the ASCII column. Only the requested bytes are read, so it's cheap even
+/// for huge files.
+///
+/// Options:
+///
+/// - length: The maximum number of bytes to dump. Defaults to 4096.
+/// - width: The number of bytes per row. Defaults to 16.
+/// - offset: The position in the file to start from. Defaults to 0.
+///
+/// Returns `nil` and the error message if the file can't be read.
+///
+/// Type: function( path:string, options:table|nil ) -> dump:string|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.hexdump("/path/to/file", { length = 20 })
+/// -- "00000000: 48 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 0a 00 01  Hello, world!...\n"
+/// -- .. "00000010: 02 03 04 05                                      ....\n"
+/// ```
+pub fn hexdump<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Deserialize)]
+    #[serde(default)]
+    struct Options {
+        length: u64,
+        width: usize,
+        offset: u64,
+    }
+
+    impl Default for Options {
+        fn default() -> Self {
+            Self {
+                length: 4096,
+                width: 16,
+                offset: 0,
+            }
+        }
+    }
+
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let mut buf = vec![];
+            let res = fs::File::open(path).and_then(|mut f| {
+                f.seek(SeekFrom::Start(options.offset))?;
+                f.take(options.length).read_to_end(&mut buf)
+            });
+
+            match res {
+                Ok(_) => Ok((Some(hex_dump(&buf, options.offset, options.width)), None)),
+                Err(err) => Ok((None, Some(err.to_string()))),
+            }
+        })?;
+    util.set("hexdump", func)?;
+    Ok(util)
+}
+
+fn hex_dump(bytes: &[u8], offset: u64, width: usize) -> String {
+    let width = width.max(1);
+    let mut dump = String::new();
+
+    for (i, row) in bytes.chunks(width).enumerate() {
+        let hex = row
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let ascii = row
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+
+        dump.push_str(&format!(
+            "{:08x}: {:hex_width$}  {}\n",
+            offset + (i * width) as u64,
+            hex,
+            ascii,
+            hex_width = width * 3 - 1,
+        ));
+    }
+
+    dump
+}
+
+/// Get the canonical absolute path of the given path, with all the symlinks
+/// resolved. Unlike `xplr.util.absolute`, the path must exist. On Windows, the
+/// `\\?\` prefix is stripped.
+///
+/// Returns `nil` and the error message if the path can't be resolved.
+///
+/// Type: function( path:string ) -> path:string|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.realpath("/tmp/link-to-foo")
+/// -- "/tmp/foo"
+///
+/// xplr.util.realpath("/there/is/no/path")
+/// -- nil, "No such file or directory (os error 2)"
+/// ```
+pub fn realpath<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, path: String| match fs::canonicalize(path) {
+        Ok(path) => {
+            let path = path.to_string_lossy().to_string();
+            let path = match path.strip_prefix(r"\\?\") {
+                Some(p) if cfg!(windows) => match p.strip_prefix(r"UNC\") {
+                    Some(unc) => format!(r"\\{}", unc),
+                    None => p.to_string(),
+                },
+                _ => path,
+            };
+            Ok((Some(path), None))
+        }
+        Err(err) => Ok((None, Some(err.to_string()))),
+    })?;
+    util.set("realpath", func)?;
+    Ok(util)
+}
+
+/// Check if the given path exists. Symlinks are followed by default, so that
+/// a broken symlink doesn't exist, unless `follow` is set to `false`.
+///
+/// Options:
+///
+/// - follow: Follow the symlinks. Defaults to `true`.
+///
+/// Type: function( path:string, options:table|nil ) -> boolean
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.exists("/tmp")
+/// -- true
+///
+/// xplr.util.exists("/path/to/broken/symlink", { follow = false })
+/// -- true
+/// ```
+pub fn exists<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: FollowOptions = from_options(lua, options).unwrap_or_default();
+            Ok(metadata(&path, options.follow).is_some())
+        })?;
+    util.set("exists", func)?;
+    Ok(util)
+}
+
+/// Check if the given path is a directory. Symlinks are followed by default.
+///
+/// Options:
+///
+/// - follow: Follow the symlinks. Defaults to `true`.
+///
+/// Type: function( path:string, options:table|nil ) -> boolean
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.is_dir("/tmp")
+/// -- true
+///
+/// xplr.util.is_dir("/path/to/symlink/to/dir", { follow = false })
+/// -- false
+/// ```
+pub fn is_dir<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: FollowOptions = from_options(lua, options).unwrap_or_default();
+            Ok(metadata(&path, options.follow)
+                .map(|m| m.is_dir())
+                .unwrap_or(false))
+        })?;
+    util.set("is_dir", func)?;
+    Ok(util)
+}
+
+/// Check if the given path is a regular file. Symlinks are followed by
+/// default.
+///
+/// Options:
+///
+/// - follow: Follow the symlinks. Defaults to `true`.
+///
+/// Type: function( path:string, options:table|nil ) -> boolean
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.is_file("/etc/hosts")
+/// -- true
+///
+/// xplr.util.is_file("/tmp")
+/// -- false
+/// ```
+pub fn is_file<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: FollowOptions = from_options(lua, options).unwrap_or_default();
+            Ok(metadata(&path, options.follow)
+                .map(|m| m.is_file())
+                .unwrap_or(false))
+        })?;
+    util.set("is_file", func)?;
+    Ok(util)
+}
+
+/// Check if the given path is a symlink, without following it. Broken
+/// symlinks are symlinks too.
+///
+/// Type: function( path:string ) -> boolean
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.is_symlink("/path/to/symlink")
+/// -- true
+///
+/// xplr.util.is_symlink("/tmp")
+/// -- false
+/// ```
+pub fn is_symlink<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, path: String| {
+        Ok(metadata(&path, false)
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(false))
+    })?;
+    util.set("is_symlink", func)?;
+    Ok(util)
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct FollowOptions {
+    follow: bool,
+}
+
+impl Default for FollowOptions {
+    fn default() -> Self {
+        Self { follow: true }
+    }
+}
+
+fn metadata(path: &str, follow: bool) -> Option<fs::Metadata> {
+    if follow {
+        fs::metadata(path).ok()
+    } else {
+        fs::symlink_metadata(path).ok()
+    }
+}
+
+/// Get the name of the user with the given ID. Returns nil if the user can't
+/// be found, or on Windows.
+///
+/// Type: function( uid:number ) -> name:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.uid_to_name(app.focused_node.uid)
+/// -- "root"
+/// ```
+pub fn uid_to_name<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, uid: u32| Ok(node::uid_to_name(uid)))?;
+    util.set("uid_to_name", func)?;
+    Ok(util)
+}
+
+/// Get the name of the group with the given ID. Returns nil if the group
+/// can't be found, or on Windows.
+///
+/// Type: function( gid:number ) -> name:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.gid_to_name(app.focused_node.gid)
+/// -- "root"
+/// ```
+pub fn gid_to_name<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, gid: u32| Ok(node::gid_to_name(gid)))?;
+    util.set("gid_to_name", func)?;
+    Ok(util)
+}
+
+/// Find the given program in `$PATH` (honoring `$PATHEXT` on Windows), using
+/// the current environment of the process. Returns the absolute path of the
+/// executable, or nil if it's not found.
+///
+/// Type: function( program:string ) -> path:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.which("sh")
+/// -- "/bin/sh"
+///
+/// xplr.util.which("there-is-no-such-program")
+/// -- nil
+/// ```
+pub fn which<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, program: String| {
+        let path = which::which(program).ok().map(|path| {
+            path.absolutize()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_else(|_| path.to_string_lossy().to_string())
+        });
+        Ok(path)
+    })?;
+    util.set("which", func)?;
+    Ok(util)
+}
+
+/// Expand a leading `~` or `~user` to the home directory, and the environment
+/// variables like `$VAR` or `${VAR}`, without touching the filesystem.
+///
+/// Unset variables expand to an empty string, and `~user` is left as is if
+/// the user doesn't exist.
+///
+/// Type: function( path:string ) -> path:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.expand("~/.config/${APP}/$FILE")
+/// -- "/home/user/.config/xplr/init.lua"
+///
+/// xplr.util.expand("~root/foo")
+/// -- "/root/foo"
+/// ```
+pub fn expand<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, path: String| {
+        Ok(expand_vars(&expand_tilde(&path), |name| {
+            std::env::var(name).ok()
+        }))
+    })?;
+    util.set("expand", func)?;
+    Ok(util)
+}
+
+fn expand_tilde(path: &str) -> String {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) => rest,
+        None => return path.into(),
+    };
+
+    let (user, rest) = rest
+        .find('/')
+        .map(|i| rest.split_at(i))
+        .unwrap_or((rest, ""));
+    let home = if user.is_empty() {
+        dirs::home_dir()
+    } else {
+        home_dir_of(user)
+    };
+
+    match home {
+        Some(home) => format!("{}{}", home.to_string_lossy(), rest),
+        None => path.into(),
+    }
+}
+
+#[cfg(unix)]
+fn home_dir_of(user: &str) -> Option<PathBuf> {
+    use users::os::unix::UserExt;
+    users::get_user_by_name(user).map(|u| u.home_dir().to_path_buf())
+}
+
+#[cfg(not(unix))]
+fn home_dir_of(_user: &str) -> Option<PathBuf> {
+    None
+}
+
+fn expand_vars(text: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let is_name_char = |c: char| c == '_' || c.is_ascii_alphanumeric();
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(i) = rest.find('$') {
+        res.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+
+        let name = if let Some(braced) = rest.strip_prefix('{') {
+            braced
+                .find('}')
+                .map(|end| &braced[..end])
+                .filter(|name| !name.is_empty() && name.chars().all(is_name_char))
+                .map(|name| (name, name.len() + 2))
+        } else {
+            let len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+            Some((&rest[..len], len)).filter(|(name, _)| {
+                name.chars()
+                    .next()
+                    .map(|c| !c.is_ascii_digit())
+                    .unwrap_or(false)
+            })
+        };
+
+        match name {
+            Some((name, len)) => {
+                res.push_str(&lookup(name).unwrap_or_default());
+                rest = &rest[len..];
+            }
+            None => res.push('$'),
+        }
+    }
+
+    res.push_str(rest);
+    res
+}
+
+/// Parse the given human readable size into the number of bytes. Accepts bare
+/// numbers, decimal units (`K`, `KB`, `M`, `MB`, ... up to `PB`, multiples of
+/// 1000), and binary units (`KiB`, `MiB`, ... up to `PiB`, multiples of 1024),
+/// case insensitively, with optional fractions and spaces. Raises an error if
+/// the size can't be parsed.
+///
+/// Type: function( size:string ) -> bytes:number
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.parse_size("10MiB")
+/// -- 10485760
+///
+/// xplr.util.parse_size("1.5 kb")
+/// -- 1500
+/// ```
+pub fn parse_size<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, size: String| {
+        parse_human_size(&size)
+            .ok_or_else(|| LuaError::RuntimeError(format!("invalid size: {:?}", size)))
+    })?;
+    util.set("parse_size", func)?;
+    Ok(util)
+}
+
+fn parse_human_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number.parse().ok()?;
+
+    let unit = unit.trim().to_lowercase();
+    let unit = unit.strip_suffix('b').unwrap_or(&unit);
+    let (prefix, base) = match unit.strip_suffix('i') {
+        Some(prefix) if !prefix.is_empty() => (prefix, 1024_f64),
+        Some(_) => return None,
+        None => (unit, 1000_f64),
+    };
+
+    let exp = match prefix {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        "p" => 5,
+        _ => return None,
+    };
+
+    Some((number * base.powi(exp)).round() as u64)
+}
+
+/// Format the given number of bytes as a human readable size, the same way as
+/// the `human_size` field of the nodes.
+///
+/// Options:
+///
+/// - binary: Use the binary units (KiB, MiB, ...), multiples of 1024, instead
+///   of the decimal ones (kB, MB, ...), multiples of 1000.
+///
+/// Type: function( bytes:number, options:table|nil ) -> size:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.human_size(10485760)
+/// -- "10.49 MB"
+///
+/// xplr.util.human_size(10485760, { binary = true })
+/// -- "10 MiB"
+/// ```
+pub fn human_size<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        binary: bool,
+    }
+
+    let func = lua.create_function(|lua, (bytes, options): (u64, Option<Table>)| {
+        let options: Options = from_options(lua, options)?;
+        let format = if options.binary { BINARY } else { DECIMAL };
+        Ok(format_size(bytes, format))
+    })?;
+    util.set("human_size", func)?;
+    Ok(util)
+}
+
+/// Format the given unix timestamp (in seconds) using the given strftime
+/// format, in the local time zone. The default format is ISO 8601 like
+/// `2022-11-29T21:13:37+05:30`. See the [chrono documentation][1] for the
+/// supported format specifiers.
+///
+/// Since the `created` and `last_modified` fields of the nodes are in
+/// nanoseconds, they need to be divided by 1000000000 first.
+///
+/// Returns nil if the timestamp is out of range. Raises an error if the
+/// format is invalid.
+///
+/// Options:
+///
+/// - utc: Use UTC instead of the local time zone.
+///
+/// Type: function( timestamp:number, format:string|nil, options:table|nil ) -> string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.format_time(0, nil, { utc = true })
+/// -- "1970-01-01T00:00:00+00:00"
+///
+/// xplr.util.format_time(app.focused_node.last_modified / 1000000000, "%b %d %H:%M")
+/// -- "Nov 29 21:13"
+/// ```
+///
+/// [1]: https://docs.rs/chrono/latest/chrono/format/strftime/index.html
+pub fn format_time<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        utc: bool,
+    }
+
+    let func = lua.create_function(
+        |lua, (timestamp, format, options): (f64, Option<String>, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let format = format.as_deref().unwrap_or("%Y-%m-%dT%H:%M:%S%:z");
+            let res = if options.utc {
+                format_timestamp(timestamp, format, &Utc)
+            } else {
+                format_timestamp(timestamp, format, &Local)
+            };
+            res.map_err(LuaError::RuntimeError)
+        },
+    )?;
+    util.set("format_time", func)?;
+    Ok(util)
+}
+
+fn format_timestamp<Tz: TimeZone>(
+    timestamp: f64,
+    format: &str,
+    tz: &Tz,
+) -> std::result::Result<Option<String>, String>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let items = StrftimeItems::new(format).collect::<Vec<_>>();
+    if items.contains(&Item::Error) {
+        return Err(format!("invalid time format: {:?}", format));
+    }
+
+    if !timestamp.is_finite() || timestamp.abs() >= i64::MAX as f64 {
+        return Ok(None);
+    }
+
+    let secs = timestamp.floor();
+    let nanos = ((timestamp - secs) * 1e9) as u32;
+    let time = tz
+        .timestamp_opt(secs as i64, nanos.min(999_999_999))
+        .single();
+    Ok(time.map(|t| t.format_with_items(items.into_iter()).to_string()))
+}
+
+/// Describe the given unix timestamp (in seconds) relative to `now`, which
+/// defaults to the current time, e.g. "5 days ago" or "in 2 hours".
+///
+/// The largest unit that fits is used, rounded down:
+///
+/// - less than 10 seconds: "just now"
+/// - less than a minute: seconds
+/// - less than an hour: minutes
+/// - less than a day: hours
+/// - less than 30 days: days
+/// - less than 365 days: months (of 30 days)
+/// - otherwise: years (of 365 days)
+///
+/// Type: function( timestamp:number, now:number|nil ) -> string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.relative_time(app.focused_node.last_modified / 1000000000)
+/// -- "3 minutes ago"
+///
+/// xplr.util.relative_time(7200, 0)
+/// -- "in 2 hours"
+/// ```
+pub fn relative_time<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, (timestamp, now): (f64, Option<f64>)| {
+        let now = now.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or_default()
+        });
+        Ok(describe_relative_time(timestamp - now))
+    })?;
+    util.set("relative_time", func)?;
+    Ok(util)
+}
+
+fn describe_relative_time(diff: f64) -> String {
+    const UNITS: [(&str, f64); 6] = [
+        ("year", 365.0 * 86400.0),
+        ("month", 30.0 * 86400.0),
+        ("day", 86400.0),
+        ("hour", 3600.0),
+        ("minute", 60.0),
+        ("second", 1.0),
+    ];
+
+    let secs = diff.abs();
+    if secs.is_nan() || secs < 10.0 {
+        return "just now".into();
+    }
+
+    let (unit, count) = UNITS
+        .iter()
+        .find(|(_, size)| secs >= *size)
+        .map(|(unit, size)| (*unit, (secs / size).floor()))
+        .unwrap_or(("second", secs.floor()));
+
+    let plural = if count == 1.0 { "" } else { "s" };
+    if diff < 0.0 {
+        format!("{} {}{} ago", count, unit, plural)
+    } else {
+        format!("in {} {}{}", count, unit, plural)
+    }
+}
+
+/// Match the given text against the given regex pattern. Returns nil if it
+/// doesn't match, or a list with the whole match followed by the capture
+/// groups. Capture groups that didn't participate in the match are nil. Raises
+/// an error if the pattern is invalid.
+///
+/// Flags can be set inline, e.g. `(?i)` for case insensitive matching. See the
+/// [regex crate documentation][1] for the syntax.
+///
+/// Type: function( pattern:string, text:string ) -> { match:string|nil... }|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.regex_match([[(\w+)\.(\w+)$]], "/tmp/foo.txt")
+/// -- { "foo.txt", "foo", "txt" }
+///
+/// xplr.util.regex_match("(?i)^readme", "README.md")
+/// -- { "README" }
+/// ```
+///
+/// [1]: https://docs.rs/regex/latest/regex/#syntax
+pub fn regex_match<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, (pattern, text): (String, String)| {
+        let re = Regex::new(&pattern).map_err(LuaError::custom)?;
+        match re.captures(&text) {
+            Some(caps) => {
+                let res = lua.create_table()?;
+                for (i, cap) in caps.iter().enumerate() {
+                    if let Some(cap) = cap {
+                        res.raw_set(i + 1, cap.as_str())?;
+                    }
+                }
+                Ok(Some(res))
+            }
+            None => Ok(None),
+        }
+    })?;
+    util.set("regex_match", func)?;
+    Ok(util)
+}
+
+/// Replace the first match of the given regex pattern in the given text with
+/// the replacement, which can refer to the capture groups with `$1`, `$2`
+/// or `${name}`. Use `$$` for a literal `$`. Raises an error if the pattern
+/// is invalid.
+///
+/// Options:
+///
+/// - all: Replace all the matches instead of only the first one.
+///
+/// Type: function( pattern:string, replacement:string, text:string, options:table|nil ) -> string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.regex_replace([[(\w+)\.txt]], "$1.md", "foo.txt bar.txt")
+/// -- "foo.md bar.txt"
+///
+/// xplr.util.regex_replace([[(\w+)\.txt]], "$1.md", "foo.txt bar.txt", { all = true })
+/// -- "foo.md bar.md"
+/// ```
+pub fn regex_replace<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        all: bool,
+    }
+
+    let func = lua.create_function(
+        |lua,
+         (pattern, replacement, text, options): (
+            String,
+            String,
+            String,
+            Option<Table>,
+        )| {
+            let options: Options = from_options(lua, options)?;
+            let re = Regex::new(&pattern).map_err(LuaError::custom)?;
+            let res = if options.all {
+                re.replace_all(&text, replacement.as_str())
+            } else {
+                re.replace(&text, replacement.as_str())
+            };
+            Ok(res.to_string())
+        },
+    )?;
+    util.set("regex_replace", func)?;
+    Ok(util)
+}
+
+/// Truncate the given text to fit the given terminal display width, taking
+/// wide (e.g. CJK and emoji), zero width and combining characters into
+/// account. If truncated, an ellipsis is added, and the result, including the
+/// ellipsis, never exceeds the width.
+///
+/// Options:
+///
+/// - ellipsis: The string to mark the truncation with. Defaults to "…".
+/// - from: Truncate from the "right" (default), or from the "left", which
+///   keeps the end of the text, useful for paths.
+///
+/// Type: function( text:string, width:number, options:table|nil ) -> string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.truncate("日本語のテキスト", 7)
+/// -- "日本語…"
+///
+/// xplr.util.truncate("/home/user/projects/xplr", 12, { from = "left" })
+/// -- "…ojects/xplr"
+/// ```
+pub fn truncate<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Default, Deserialize, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum From {
+        Left,
+        #[default]
+        Right,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(default)]
+    struct Options {
+        ellipsis: String,
+        from: From,
+    }
+
+    impl Default for Options {
+        fn default() -> Self {
+            Self {
+                ellipsis: "…".into(),
+                from: From::default(),
+            }
+        }
+    }
+
+    let func = lua.create_function(
+        |lua, (text, width, options): (String, usize, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let res = truncate_to_width(
+                &text,
+                width,
+                &options.ellipsis,
+                options.from == From::Left,
+            );
+            Ok(res)
+        },
+    )?;
+    util.set("truncate", func)?;
+    Ok(util)
+}
+
+fn truncate_to_width(text: &str, width: usize, ellipsis: &str, left: bool) -> String {
+    if text.width() <= width {
+        return text.into();
+    }
+
+    let ellipsis = take_width(ellipsis, width, false);
+    let budget = width - ellipsis.width();
+    let kept = take_width(text, budget, left);
+
+    if left {
+        format!("{}{}", ellipsis, kept)
+    } else {
+        format!("{}{}", kept, ellipsis)
+    }
+}
+
+/// Take the graphemes from the start (or the end) of the text, as long as
+/// they fit the width.
+fn take_width(text: &str, width: usize, from_end: bool) -> String {
+    let mut total = 0;
+    let fits = |g: &&str| {
+        total += g.width();
+        total <= width
+    };
+
+    if from_end {
+        let mut graphemes = text
+            .graphemes(true)
+            .rev()
+            .take_while(fits)
+            .collect::<Vec<_>>();
+        graphemes.reverse();
+        graphemes.concat()
+    } else {
+        text.graphemes(true).take_while(fits).collect()
+    }
+}
+
+/// Wrap the given text at the word boundaries to fit the given terminal
+/// display width, and return the lines. The existing newlines are preserved.
+/// Words longer than the width are kept whole unless `break_words` is set.
+///
+/// Options:
+///
+/// - break_words: Break the words that don't fit the width.
+///
+/// Type: function( text:string, width:number, options:table|nil ) -> { line:string... }
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.wrap("The quick brown fox jumps", 10)
+/// -- { "The quick", "brown fox", "jumps" }
+///
+/// xplr.util.wrap("Supercalifragilistic", 10, { break_words = true })
+/// -- { "Supercalif", "ragilistic" }
+/// ```
+pub fn wrap<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        break_words: bool,
+    }
+
+    let func = lua.create_function(
+        |lua, (text, width, options): (String, usize, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let opts =
+                textwrap::Options::new(width.max(1)).break_words(options.break_words);
+            let lines = textwrap::wrap(&text, opts)
+                .into_iter()
+                .map(|l| l.to_string())
+                .collect::<Vec<_>>();
+            Ok(lines)
+        },
+    )?;
+    util.set("wrap", func)?;
+    Ok(util)
+}
+
+/// Parse the given `LS_COLORS` specification, or the `LS_COLORS` environment
+/// variable by default, on top of the defaults of `dircolors`. Returns an
+/// object with the `style_for(path_or_node)` method, behaving like
+/// `xplr.util.lscolor_for`.
+///
+/// Type: function( spec:string|nil ) -> { style_for:function( path:string|[Node][1] ) -> [Style][2] }
+///
+/// Example:
+///
+/// ```lua
+/// local lscolors = xplr.util.parse_lscolors("di=1;34:*.tar=31")
+/// lscolors:style_for("/tmp/foo.tar")
+/// -- { fg = "Red" }
+/// ```
+///
+/// [1]: https://xplr.dev/en/lua-function-calls#node
+/// [2]: https://xplr.dev/en/style
+pub fn parse_lscolors<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, spec: Option<String>| {
+        let lscolors = match spec.or_else(|| std::env::var("LS_COLORS").ok()) {
+            Some(spec) => LsColors::from_string(&spec),
+            None => LsColors::default(),
+        };
+        Ok(LuaLsColors(lscolors))
+    })?;
+    util.set("parse_lscolors", func)?;
+    Ok(util)
+}
+
+/// Get the style that `ls` would use for the given path or node, according to
+/// the `LS_COLORS` environment variable, or the defaults of `dircolors`. The
+/// file type rules (e.g. `di`, `ln`, `ex`, `or`) are checked for the path on
+/// the disk, and the extension rules (e.g. `*.tar`) for the regular files.
+/// Returns an empty style if no rule applies.
+///
+/// Type: function( path:string|[Node][1] ) -> [Style][2]
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.lscolor_for(app.focused_node)
+/// -- { fg = "Blue", add_modifiers = { "Bold" } }
+/// ```
+///
+/// [1]: https://xplr.dev/en/lua-function-calls#node
+/// [2]: https://xplr.dev/en/style
+pub fn lscolor_for<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, target: Value| {
+        let path = target_path(&target)?;
+        let env = std::env::var("LS_COLORS").ok();
+        let mut cache = ENV_LSCOLORS.lock().map_err(LuaError::custom)?;
+        let lscolors = match cache.take() {
+            Some((spec, lscolors)) if spec == env => lscolors,
+            _ => env
+                .as_deref()
+                .map(LsColors::from_string)
+                .unwrap_or_default(),
+        };
+        let style = lscolors_style(&lscolors, &path);
+        *cache = Some((env, lscolors));
+        lua::serialize(lua, &style).map_err(LuaError::custom)
+    })?;
+    util.set("lscolor_for", func)?;
+    Ok(util)
+}
+
+// Wraps the parsed specification for Lua.
+struct LuaLsColors(LsColors);
+
+impl UserData for LuaLsColors {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("style_for", |lua, this, target: Value| {
+            let style = lscolors_style(&this.0, &target_path(&target)?);
+            lua::serialize(lua, &style).map_err(LuaError::custom)
+        });
+    }
+}
+
+fn target_path(target: &Value) -> mlua::Result<String> {
+    match target {
+        Value::String(s) => Ok(s.to_str()?.to_string()),
+        Value::Table(t) => t.get("absolute_path"),
+        v => Err(LuaError::RuntimeError(format!(
+            "expected a path or a node, got {}",
+            v.type_name()
+        ))),
+    }
+}
+
+fn lscolors_style(lscolors: &LsColors, path: &str) -> Style {
+    fn color(color: &lscolors::Color) -> Color {
+        use lscolors::Color as C;
+        match *color {
+            C::Black => Color::Black,
+            C::Red => Color::Red,
+            C::Green => Color::Green,
+            C::Yellow => Color::Yellow,
+            C::Blue => Color::Blue,
+            C::Magenta => Color::Magenta,
+            C::Cyan => Color::Cyan,
+            C::White => Color::Gray,
+            C::BrightBlack => Color::DarkGray,
+            C::BrightRed => Color::LightRed,
+            C::BrightGreen => Color::LightGreen,
+            C::BrightYellow => Color::LightYellow,
+            C::BrightBlue => Color::LightBlue,
+            C::BrightMagenta => Color::LightMagenta,
+            C::BrightCyan => Color::LightCyan,
+            C::BrightWhite => Color::White,
+            C::Fixed(i) => Color::Indexed(i),
+            C::RGB(r, g, b) => Color::Rgb(r, g, b),
+        }
+    }
+
+    let style = match lscolors.style_for_path(path) {
+        Some(style) => style,
+        None => return Style::default(),
+    };
+
+    let font = &style.font_style;
+    let modifiers = [
+        (font.bold, Modifier::Bold),
+        (font.dimmed, Modifier::Dim),
+        (font.italic, Modifier::Italic),
+        (font.underline, Modifier::Underlined),
+        (font.slow_blink, Modifier::SlowBlink),
+        (font.rapid_blink, Modifier::RapidBlink),
+        (font.reverse, Modifier::Reversed),
+        (font.hidden, Modifier::Hidden),
+        (font.strikethrough, Modifier::CrossedOut),
+    ]
+    .into_iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, m)| m)
+    .collect::<IndexSet<_>>();
+
+    Style {
+        fg: style.foreground.as_ref().map(color),
+        bg: style.background.as_ref().map(color),
+        add_modifiers: Some(modifiers).filter(|m| !m.is_empty()),
+        sub_modifiers: None,
+    }
+}
+
+/// Get the [Nerd Font][1] glyph for the given path or node, based on its file
+/// type, name and extension. Paths are checked on the disk, while nodes are
+/// checked using their fields. Unknown file types get a generic file glyph.
+///
+/// The optional overrides take priority over the builtin glyphs:
+///
+/// - directory: glyph for directories.
+/// - symlink: glyph for symlinks.
+/// - executable: glyph for executable files without a known extension.
+/// - default: glyph for unknown file types.
+/// - filenames: table mapping exact file names to glyphs.
+/// - extensions: table mapping (case insensitive) extensions to glyphs.
+///
+/// Type: function( path:string|[Node][2], overrides:table|nil ) -> glyph:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.icon("main.rs")
+/// -- "\u{e7a8}"
+///
+/// xplr.util.icon(app.focused_node, { directory = "D", extensions = { rs = "R" } })
+/// -- "R"
+/// ```
+///
+/// [1]: https://www.nerdfonts.com
+/// [2]: https://xplr.dev/en/lua-function-calls#node
+pub fn icon<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Overrides {
+        directory: Option<String>,
+        symlink: Option<String>,
+        executable: Option<String>,
+        default: Option<String>,
+        filenames: HashMap<String, String>,
+        extensions: HashMap<String, String>,
+    }
+
+    let func =
+        lua.create_function(|lua, (target, overrides): (Value, Option<Table>)| {
+            let overrides: Overrides = from_options(lua, overrides)?;
+            let target = IconTarget::from_value(&target)?;
+            let extension = target.extension.to_lowercase();
+
+            let custom = if target.is_symlink {
+                overrides.symlink
+            } else if target.is_dir {
+                overrides.directory
+            } else {
+                overrides
+                    .filenames
+                    .get(&target.name)
+                    .or_else(|| {
+                        overrides
+                            .extensions
+                            .iter()
+                            .find(|(k, _)| k.to_lowercase() == extension)
+                            .map(|(_, v)| v)
+                    })
+                    .cloned()
+                    .or_else(|| {
+                        if ICONS_BY_FILENAME.contains_key(target.name.as_str())
+                            || ICONS_BY_EXTENSION.contains_key(extension.as_str())
+                        {
+                            None
+                        } else if target.is_executable {
+                            overrides.executable
+                        } else {
+                            overrides.default
+                        }
+                    })
+            };
+
+            Ok(custom.unwrap_or_else(|| builtin_icon(&target, &extension).into()))
+        })?;
+    util.set("icon", func)?;
+    Ok(util)
+}
+
+const ICON_DIRECTORY: &str = "\u{f115}";
+const ICON_SYMLINK: &str = "\u{f481}";
+const ICON_EXECUTABLE: &str = "\u{f489}";
+const ICON_DEFAULT: &str = "\u{f15b}";
+
+const ICON_ARCHIVE: &str = "\u{f410}";
+const ICON_AUDIO: &str = "\u{f1c7}";
+const ICON_CONFIG: &str = "\u{e615}";
+const ICON_GIT: &str = "\u{e702}";
+const ICON_IMAGE: &str = "\u{f1c5}";
+const ICON_SHELL: &str = "\u{f489}";
+const ICON_VIDEO: &str = "\u{f1c8}";
+
+const ICONS_BY_FILENAME_DATA: &[(&str, &str)] = &[
+    (".bashrc", ICON_SHELL),
+    (".gitattributes", ICON_GIT),
+    (".gitconfig", ICON_GIT),
+    (".gitignore", ICON_GIT),
+    (".gitmodules", ICON_GIT),
+    (".zshrc", ICON_SHELL),
+    ("Cargo.lock", "\u{e7a8}"),
+    ("Cargo.toml", "\u{e7a8}"),
+    ("Dockerfile", "\u{f308}"),
+    ("LICENSE", "\u{f0219}"),
+    ("Makefile", "\u{e779}"),
+];
+
+const ICONS_BY_EXTENSION_DATA: &[(&str, &str)] = &[
+    ("7z", ICON_ARCHIVE),
+    ("avi", ICON_VIDEO),
+    ("bash", ICON_SHELL),
+    ("bmp", ICON_IMAGE),
+    ("bz2", ICON_ARCHIVE),
+    ("c", "\u{e61e}"),
+    ("conf", ICON_CONFIG),
+    ("cpp", "\u{e61d}"),
+    ("css", "\u{e749}"),
+    ("csv", "\u{f1c3}"),
+    ("deb", ICON_ARCHIVE),
+    ("diff", "\u{f440}"),
+    ("doc", "\u{f1c2}"),
+    ("docx", "\u{f1c2}"),
+    ("fish", ICON_SHELL),
+    ("flac", ICON_AUDIO),
+    ("gif", ICON_IMAGE),
+    ("go", "\u{e626}"),
+    ("gz", ICON_ARCHIVE),
+    ("h", "\u{e61e}"),
+    ("hpp", "\u{e61d}"),
+    ("hs", "\u{e777}"),
+    ("htm", "\u{e736}"),
+    ("html", "\u{e736}"),
+    ("ico", ICON_IMAGE),
+    ("ini", ICON_CONFIG),
+    ("java", "\u{e738}"),
+    ("jpeg", ICON_IMAGE),
+    ("jpg", ICON_IMAGE),
+    ("js", "\u{e74e}"),
+    ("json", "\u{e60b}"),
+    ("lock", "\u{f023}"),
+    ("log", "\u{f18d}"),
+    ("lua", "\u{e620}"),
+    ("md", "\u{e73e}"),
+    ("mkv", ICON_VIDEO),
+    ("mov", ICON_VIDEO),
+    ("mp3", ICON_AUDIO),
+    ("mp4", ICON_VIDEO),
+    ("ogg", ICON_AUDIO),
+    ("patch", "\u{f440}"),
+    ("pdf", "\u{f1c1}"),
+    ("php", "\u{e73d}"),
+    ("png", ICON_IMAGE),
+    ("ppt", "\u{f1c4}"),
+    ("pptx", "\u{f1c4}"),
+    ("py", "\u{e606}"),
+    ("rar", ICON_ARCHIVE),
+    ("rb", "\u{e739}"),
+    ("rs", "\u{e7a8}"),
+    ("scss", "\u{e749}"),
+    ("sh", ICON_SHELL),
+    ("sql", "\u{e706}"),
+    ("svg", ICON_IMAGE),
+    ("tar", ICON_ARCHIVE),
+    ("toml", ICON_CONFIG),
+    ("ts", "\u{e628}"),
+    ("txt", "\u{f15c}"),
+    ("vim", "\u{e62b}"),
+    ("wav", ICON_AUDIO),
+    ("webm", ICON_VIDEO),
+    ("webp", ICON_IMAGE),
+    ("xls", "\u{f1c3}"),
+    ("xlsx", "\u{f1c3}"),
+    ("xml", "\u{e619}"),
+    ("xz", ICON_ARCHIVE),
+    ("yaml", ICON_CONFIG),
+    ("yml", ICON_CONFIG),
+    ("zip", ICON_ARCHIVE),
+    ("zsh", ICON_SHELL),
+    ("zst", ICON_ARCHIVE),
+];
+
+// What the icon of a path or a node depends on.
+struct IconTarget {
+    name: String,
+    extension: String,
+    is_dir: bool,
+    is_symlink: bool,
+    is_executable: bool,
+}
+
+impl IconTarget {
+    fn from_value(value: &Value) -> mlua::Result<Self> {
+        match value {
+            Value::Table(node) => {
+                let permissions: Option<Table> = node.get("permissions")?;
+                let is_executable = match permissions {
+                    Some(p) => {
+                        p.get::<_, Option<bool>>("user_execute")?.unwrap_or(false)
+                            || p.get::<_, Option<bool>>("group_execute")?
+                                .unwrap_or(false)
+                            || p.get::<_, Option<bool>>("other_execute")?
+                                .unwrap_or(false)
+                    }
+                    None => false,
+                };
+                // The relative path has the sub directories when exploring
+                // with max_depth.
+                let relative_path = node
+                    .get::<_, Option<String>>("relative_path")?
+                    .unwrap_or_default();
+                Ok(Self {
+                    name: Path::new(&relative_path)
+                        .file_name()
+                        .map(|n| n.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    extension: node
+                        .get::<_, Option<String>>("extension")?
+                        .unwrap_or_default(),
+                    is_dir: node.get::<_, Option<bool>>("is_dir")?.unwrap_or(false),
+                    is_symlink: node
+                        .get::<_, Option<bool>>("is_symlink")?
+                        .unwrap_or(false),
+                    is_executable,
+                })
+            }
+            _ => {
+                let path = PathBuf::from(target_path(value)?);
+                let metadata = fs::symlink_metadata(&path).ok();
+                Ok(Self {
+                    name: path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    extension: path
+                        .extension()
+                        .map(|e| e.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    is_dir: metadata.as_ref().map(|m| m.is_dir()).unwrap_or(false),
+                    is_symlink: metadata
+                        .as_ref()
+                        .map(|m| m.file_type().is_symlink())
+                        .unwrap_or(false),
+                    is_executable: metadata
+                        .as_ref()
+                        .map(|m| m.is_file() && is_executable(m))
+                        .unwrap_or(false),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_: &fs::Metadata) -> bool {
+    false
+}
+
+fn builtin_icon(target: &IconTarget, extension: &str) -> &'static str {
+    if target.is_symlink {
+        ICON_SYMLINK
+    } else if target.is_dir {
+        ICON_DIRECTORY
+    } else if let Some(icon) = ICONS_BY_FILENAME.get(target.name.as_str()) {
+        icon
+    } else if let Some(icon) = ICONS_BY_EXTENSION.get(extension) {
+        icon
+    } else if target.is_executable {
+        ICON_EXECUTABLE
+    } else {
+        ICON_DEFAULT
+    }
+}
+
+/// Copy a file, a symlink or a directory recursively. `dst` is the path of the
+/// copy, not the directory to copy into. Directories are merged into the
+/// existing ones.
+///
+/// Options:
+///
+/// - overwrite: Overwrite the existing files.
+/// - follow_symlinks: Copy the files the symlinks point to, instead of
+///   recreating the symlinks. The copy fails on a symlink to a directory that's
+///   being copied, instead of looping.
+/// - preserve_permissions: Copy the permissions too (default: true).
+/// - on_progress: Function called with the copied and the total number of
+///   bytes, after each file and at least every MiB.
+///
+/// Returns the number of files and bytes copied, and the error message if the
+/// copy failed partway.
+///
+/// Type: function( src:string, dst:string, options:table|nil ) -> { files:number, bytes:number }, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.copy("/tmp/foo", "/tmp/bar", {
+///   on_progress = function(copied, total)
+///     xplr.util.log("debug", copied .. "/" .. total)
+///   end,
+/// })
+/// -- { files = 3, bytes = 8 }, nil
+/// ```
+pub fn copy<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(
+        |lua, (src, dst, options): (String, String, Option<Table>)| {
+            let (options, on_progress): (CopyOptions, _) =
+                from_options_with_callback(lua, options, "on_progress")?;
+
+            let mut copier = Copier::new(options);
+            if let Some(f) = on_progress {
+                copier.on_progress = Some(Box::new(move |copied, total| {
+                    f.call((copied, total)).map_err(|e| e.to_string())
+                }));
+            }
+
+            let err = copier.copy(Path::new(&src), Path::new(&dst)).err();
+            let summary =
+                lua::serialize(lua, &copier.summary).map_err(LuaError::custom)?;
+            Ok((summary, err))
+        },
+    )?;
+    util.set("copy", func)?;
+    Ok(util)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct CopyOptions {
+    overwrite: bool,
+    follow_symlinks: bool,
+    preserve_permissions: bool,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            overwrite: false,
+            follow_symlinks: false,
+            preserve_permissions: true,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct CopySummary {
+    files: u64,
+    bytes: u64,
+}
+
+const COPY_PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
+type ProgressCallback<'a> = Box<dyn FnMut(u64, u64) -> Result<(), String> + 'a>;
+
+struct Copier<'a> {
+    options: CopyOptions,
+    on_progress: Option<ProgressCallback<'a>>,
+    summary: CopySummary,
+    total: u64,
+    reported: u64,
+
+    /// The canonical paths of the directories being copied, when following
+    /// the symlinks.
+    ancestors: Vec<PathBuf>,
+}
+
+impl<'a> Copier<'a> {
+    fn new(options: CopyOptions) -> Self {
+        Self {
+            options,
+            on_progress: None,
+            summary: Default::default(),
+            total: 0,
+            reported: 0,
+            ancestors: vec![],
+        }
+    }
+
+    fn copy(&mut self, src: &Path, dst: &Path) -> Result<(), String> {
+        let fail = |p: &Path, e: io::Error| format!("{}: {}", p.display(), e);
+
+        if src.is_dir() {
+            let src_abs = fs::canonicalize(src).map_err(|e| fail(src, e))?;
+            let dst_abs = dst.absolutize().map_err(|e| fail(dst, e))?;
+            let dst_abs = dst_abs
+                .parent()
+                .and_then(|p| fs::canonicalize(p).ok())
+                .zip(dst_abs.file_name())
+                .map(|(p, n)| p.join(n))
+                .unwrap_or_else(|| dst_abs.to_path_buf());
+            if dst_abs.starts_with(&src_abs) {
+                return Err(format!(
+                    "{}: cannot copy a directory into itself",
+                    src.display()
+                ));
+            }
+        }
+
+        if self.on_progress.is_some() {
+            self.total = total_size(src, self.options.follow_symlinks, &mut vec![]);
+        }
+        self.copy_entry(src, dst)
+    }
+
+    fn copy_entry(&mut self, src: &Path, dst: &Path) -> Result<(), String> {
+        let fail = |p: &Path, e: io::Error| format!("{}: {}", p.display(), e);
+
+        let metadata = if self.options.follow_symlinks {
+            fs::metadata(src)
+        } else {
+            fs::symlink_metadata(src)
+        }
+        .map_err(|e| fail(src, e))?;
+
+        if metadata.file_type().is_symlink() {
+            self.copy_symlink(src, dst)
+        } else if metadata.is_dir() {
+            if self.options.follow_symlinks {
+                let canonical = fs::canonicalize(src).map_err(|e| fail(src, e))?;
+                if self.ancestors.contains(&canonical) {
+                    return Err(format!("{}: symlink loop", src.display()));
+                }
+                self.ancestors.push(canonical);
+            }
+            if let Err(e) = fs::create_dir(dst) {
+                if e.kind() != io::ErrorKind::AlreadyExists || !dst.is_dir() {
+                    return Err(fail(dst, e));
+                }
+            }
+            for entry in fs::read_dir(src).map_err(|e| fail(src, e))? {
+                let entry = entry.map_err(|e| fail(src, e))?;
+                self.copy_entry(&entry.path(), &dst.join(entry.file_name()))?;
+            }
+            self.ancestors.pop();
+            if self.options.preserve_permissions {
+                fs::set_permissions(dst, metadata.permissions())
+                    .map_err(|e| fail(dst, e))?;
+            }
+            Ok(())
+        } else {
+            self.copy_file(src, dst, &metadata)
+        }
+    }
+
+    fn copy_file(
+        &mut self,
+        src: &Path,
+        dst: &Path,
+        metadata: &fs::Metadata,
+    ) -> Result<(), String> {
+        let fail = |p: &Path, e: io::Error| format!("{}: {}", p.display(), e);
+
+        let mut reader = fs::File::open(src).map_err(|e| fail(src, e))?;
+        let mut open = fs::OpenOptions::new();
+        open.write(true);
+        if self.options.overwrite {
+            // Don't write through an existing symlink.
+            if fs::symlink_metadata(dst)
+                .map(|m| m.file_type().is_symlink())
+                .unwrap_or(false)
+            {
+                fs::remove_file(dst).map_err(|e| fail(dst, e))?;
+            }
+            open.create(true).truncate(true);
+        } else {
+            open.create_new(true);
+        }
+        let mut writer = open.open(dst).map_err(|e| fail(dst, e))?;
+
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buf).map_err(|e| fail(src, e))?;
+            if n == 0 {
+                break;
+            }
+            writer.write_all(&buf[..n]).map_err(|e| fail(dst, e))?;
+            self.summary.bytes += n as u64;
+            if self.summary.bytes - self.reported >= COPY_PROGRESS_INTERVAL {
+                self.report()?;
+            }
+        }
+
+        if self.options.preserve_permissions {
+            fs::set_permissions(dst, metadata.permissions())
+                .map_err(|e| fail(dst, e))?;
+        }
+        self.summary.files += 1;
+        self.report()
+    }
+
+    #[cfg(unix)]
+    fn copy_symlink(&mut self, src: &Path, dst: &Path) -> Result<(), String> {
+        let fail = |p: &Path, e: io::Error| format!("{}: {}", p.display(), e);
+
+        let target = fs::read_link(src).map_err(|e| fail(src, e))?;
+        if let Ok(m) = fs::symlink_metadata(dst) {
+            if !self.options.overwrite || m.is_dir() {
+                return Err(fail(dst, io::ErrorKind::AlreadyExists.into()));
+            }
+            fs::remove_file(dst).map_err(|e| fail(dst, e))?;
+        }
+        std::os::unix::fs::symlink(target, dst).map_err(|e| fail(dst, e))?;
+        self.summary.files += 1;
+        self.report()
+    }
+
+    #[cfg(not(unix))]
+    fn copy_symlink(&mut self, src: &Path, dst: &Path) -> Result<(), String> {
+        let metadata =
+            fs::metadata(src).map_err(|e| format!("{}: {}", src.display(), e))?;
+        self.copy_file(src, dst, &metadata)
+    }
+
+    fn report(&mut self) -> Result<(), String> {
+        self.reported = self.summary.bytes;
+        match self.on_progress.as_mut() {
+            Some(f) => f(self.summary.bytes, self.total),
+            None => Ok(()),
+        }
+    }
+}
+
+// Like the copy, the symlinks to the directories in `ancestors` aren't
+// followed.
+fn total_size(path: &Path, follow_symlinks: bool, ancestors: &mut Vec<PathBuf>) -> u64 {
+    let metadata = if follow_symlinks {
+        fs::metadata(path)
+    } else {
+        fs::symlink_metadata(path)
+    };
+    match metadata {
+        Ok(m) if m.is_dir() => {
+            if follow_symlinks {
+                match fs::canonicalize(path) {
+                    Ok(c) if !ancestors.contains(&c) => ancestors.push(c),
+                    _ => return 0,
+                }
+            }
+            let size = fs::read_dir(path)
+                .map(|entries| {
+                    entries
+                        .filter_map(|e| e.ok())
+                        .map(|e| total_size(&e.path(), follow_symlinks, ancestors))
+                        .sum()
+                })
+                .unwrap_or(0);
+            if follow_symlinks {
+                ancestors.pop();
+            }
+            size
+        }
+        Ok(m) if m.is_file() => m.len(),
+        _ => 0,
+    }
+}
+
+/// Move or rename a file, a symlink or a directory. When the destination is on
+/// another filesystem, it falls back to copying the source recursively and
+/// then deleting it.
+///
+/// Options:
+///
+/// - overwrite: Replace the existing destination.
+/// - create_dirs: Create the missing parent directories of the destination.
+///
+/// Returns nil on success, or the error message on failure.
+///
+/// Type: function( src:string, dst:string, options:table|nil ) -> error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.move("/tmp/foo", "/tmp/bar/foo", { create_dirs = true })
+/// -- nil
+/// ```
+pub fn r#move<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(
+        |lua, (src, dst, options): (String, String, Option<Table>)| {
+            let options: MoveOptions = from_options(lua, options)?;
+            Ok(
+                move_path(Path::new(&src), Path::new(&dst), &options, |s, d| {
+                    fs::rename(s, d)
+                })
+                .err(),
+            )
+        },
+    )?;
+    util.set("move", func)?;
+    Ok(util)
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct MoveOptions {
+    overwrite: bool,
+    create_dirs: bool,
+}
+
+fn move_path<F>(
+    src: &Path,
+    dst: &Path,
+    options: &MoveOptions,
+    rename: F,
+) -> Result<(), String>
+where
+    F: Fn(&Path, &Path) -> io::Result<()>,
+{
+    let fail = |p: &Path, e: io::Error| format!("{}: {}", p.display(), e);
+
+    let src_metadata = fs::symlink_metadata(src).map_err(|e| fail(src, e))?;
+    if !options.overwrite && fs::symlink_metadata(dst).is_ok() {
+        return Err(fail(dst, io::ErrorKind::AlreadyExists.into()));
+    }
+
+    if options.create_dirs {
+        if let Some(parent) = dst.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| fail(parent, e))?;
+        }
+    }
+
+    match rename(src, dst) {
+        Ok(()) => Ok(()),
+        Err(e) if is_cross_device(&e) => {
+            let mut copier = Copier::new(CopyOptions {
+                overwrite: options.overwrite,
+                ..Default::default()
+            });
+            copier
+                .copy(src, dst)
+                .map_err(|e| format!("{}; the source was left in place", e))?;
+            if src_metadata.is_dir() {
+                fs::remove_dir_all(src)
+            } else {
+                fs::remove_file(src)
+            }
+            .map_err(|e| fail(src, e))
+        }
+        Err(e) => Err(fail(src, e)),
+    }
+}
+
+#[cfg(unix)]
+fn is_cross_device(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EXDEV)
+}
+
+#[cfg(windows)]
+fn is_cross_device(err: &io::Error) -> bool {
+    // ERROR_NOT_SAME_DEVICE
+    err.raw_os_error() == Some(17)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_cross_device(_: &io::Error) -> bool {
+    false
+}
+
+/// Create a directory.
+///
+/// Options:
+///
+/// - parents: Create the missing parent directories too, and don't fail if
+///   the directory already exists (like `mkdir -p`).
+/// - mode: Set the Unix permissions of the directory (e.g. `tonumber("755", 8)`).
+///
+/// Returns nil on success, or the error message on failure.
+///
+/// Type: function( path:string, options:table|nil ) -> error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.mkdir("/tmp/foo/bar", { parents = true })
+/// -- nil
+/// ```
+pub fn mkdir<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        parents: bool,
+        mode: Option<u32>,
+    }
+
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+
+            let res = fs::DirBuilder::new()
+                .recursive(options.parents)
+                .create(&path)
+                .and_then(|()| {
+                    #[cfg(unix)]
+                    if let Some(mode) = options.mode {
+                        use std::os::unix::fs::PermissionsExt;
+                        fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+                    }
+                    Ok(())
+                });
+
+            Ok(res.err().map(|e| format!("{}: {}", path, e)))
+        })?;
+    util.set("mkdir", func)?;
+    Ok(util)
+}
+
+/// Remove a file, a symlink or a directory. Symlinks are removed themselves,
+/// never the files they point to.
+///
+/// Options:
+///
+/// - recursive: Remove the directories with their contents (like `rm -r`).
+/// - force: Don't fail if the path doesn't exist.
+///
+/// Returns nil on success, or the error message on failure.
+///
+/// Type: function( path:string, options:table|nil ) -> error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.remove("/tmp/foo", { recursive = true, force = true })
+/// -- nil
+/// ```
+pub fn remove<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        recursive: bool,
+        force: bool,
+    }
+
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+
+            let res = fs::symlink_metadata(&path).and_then(|metadata| {
+                let file_type = metadata.file_type();
+                if file_type.is_symlink() {
+                    // Symlinks to directories are directories on Windows.
+                    fs::remove_file(&path).or_else(|e| {
+                        if cfg!(windows) {
+                            fs::remove_dir(&path)
+                        } else {
+                            Err(e)
+                        }
+                    })
+                } else if file_type.is_dir() {
+                    if options.recursive {
+                        fs::remove_dir_all(&path)
+                    } else {
+                        fs::remove_dir(&path)
+                    }
+                } else {
+                    fs::remove_file(&path)
+                }
+            });
+
+            Ok(match res {
+                Err(e) if options.force && e.kind() == io::ErrorKind::NotFound => None,
+                res => res.err().map(|e| format!("{}: {}", path, e)),
+            })
+        })?;
+    util.set("remove", func)?;
+    Ok(util)
+}
+
+/// Change the permissions of a file or a directory, like `chmod`. The mode
+/// is either a number, e.g. `tonumber("755", 8)`, a string of octal digits,
+/// e.g. "755", or symbolic, e.g. "u+x", "go-w" or "u=rwX,g=rX,o=". When the
+/// users are omitted in the symbolic mode, e.g. "+x", it applies to all of
+/// them, regardless of the umask.
+///
+/// Options:
+///
+/// - recursive: Also change the contents of the directories. The symlinks
+///   found inside are skipped.
+///
+/// Returns nil on success, or the error message on failure, e.g. on Windows
+/// where it's not supported. Raises an error if the mode is invalid, or if a
+/// numeric mode is outside `0` to `0o7777`.
+///
+/// Type: function( path:string, mode:number|string, options:table|nil ) -> error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.chmod("/tmp/script.sh", "u+x")
+/// -- nil
+///
+/// xplr.util.chmod("/tmp/dir", "755", { recursive = true })
+/// -- nil
+/// ```
+pub fn chmod<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        recursive: bool,
+    }
+
+    let func = lua.create_function(
+        |lua, (path, mode, options): (String, Value, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let mode = match mode {
+                Value::Integer(bits) if (0..=0o7777).contains(&bits) => {
+                    FileMode::Absolute(bits as u32)
+                }
+                Value::Number(bits)
+                    if bits.fract() == 0.0 && (0.0..=4095.0).contains(&bits) =>
+                {
+                    FileMode::Absolute(bits as u32)
+                }
+                Value::String(mode) => {
+                    FileMode::parse(mode.to_str()?).map_err(LuaError::RuntimeError)?
+                }
+                mode => {
+                    return Err(LuaError::RuntimeError(format!(
+                        "invalid mode: {:?}",
+                        mode
+                    )))
+                }
+            };
+
+            #[cfg(unix)]
+            let res = {
+                use std::os::unix::fs::PermissionsExt;
+
+                walk_tree(
+                    Path::new(&path),
+                    options.recursive,
+                    &mut |path, metadata| {
+                        if metadata.file_type().is_symlink() {
+                            return Ok(());
+                        }
+                        let current = metadata.permissions().mode() & 0o7777;
+                        let new = mode.apply(current, metadata.is_dir());
+                        if new == current {
+                            return Ok(());
+                        }
+                        fs::set_permissions(path, fs::Permissions::from_mode(new))
+                    },
+                )
+            };
+
+            #[cfg(not(unix))]
+            let res = {
+                let _ = (mode, options.recursive);
+                Err((
+                    PathBuf::from(&path),
+                    io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "not supported on this platform",
+                    ),
+                ))
+            };
+
+            Ok(res
+                .err()
+                .map(|(path, e)| format!("{}: {}", path.to_string_lossy(), e)))
+        },
+    )?;
+    util.set("chmod", func)?;
+    Ok(util)
+}
+
+/// Change the owner and the group of a file or a directory, like `chown`.
+/// The user and the group can be given by their names or by their ids. The
+/// ones not given are left unchanged.
+///
+/// Options:
+///
+/// - user: The name of the new owner.
+/// - uid: The id of the new owner.
+/// - group: The name of the new group.
+/// - gid: The id of the new group.
+/// - recursive: Also change the contents of the directories. The symlinks
+///   found inside are changed themselves, and not followed.
+///
+/// Returns nil on success, or the error message on failure, e.g. when the
+/// user doesn't exist or on Windows where it's not supported.
+///
+/// Type: function( path:string, options:table ) -> error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.chown("/tmp/dir", { user = "alice", group = "staff", recursive = true })
+/// -- nil
+///
+/// xplr.util.chown("/tmp/file", { gid = 100 })
+/// -- nil
+/// ```
+pub fn chown<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        user: Option<String>,
+        uid: Option<u32>,
+        group: Option<String>,
+        gid: Option<u32>,
+        recursive: bool,
+    }
+
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            if options.user.is_some() && options.uid.is_some() {
+                return Err(LuaError::RuntimeError("set either user or uid".into()));
+            }
+            if options.group.is_some() && options.gid.is_some() {
+                return Err(LuaError::RuntimeError("set either group or gid".into()));
+            }
+
+            #[cfg(unix)]
+            let res = {
+                let uid = match options.user.as_ref() {
+                    Some(user) => match users::get_user_by_name(user) {
+                        Some(user) => Some(user.uid()),
+                        None => return Ok(Some(format!("no such user: {user}"))),
+                    },
+                    None => options.uid,
+                };
+                let gid = match options.group.as_ref() {
+                    Some(group) => match users::get_group_by_name(group) {
+                        Some(group) => Some(group.gid()),
+                        None => return Ok(Some(format!("no such group: {group}"))),
+                    },
+                    None => options.gid,
+                };
+
+                walk_tree(
+                    Path::new(&path),
+                    options.recursive,
+                    &mut |path, metadata| {
+                        if metadata.file_type().is_symlink() {
+                            std::os::unix::fs::lchown(path, uid, gid)
+                        } else {
+                            std::os::unix::fs::chown(path, uid, gid)
+                        }
+                    },
+                )
+            };
+
+            #[cfg(not(unix))]
+            let res = {
+                let _ = options.recursive;
+                Err((
+                    PathBuf::from(&path),
+                    io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "not supported on this platform",
+                    ),
+                ))
+            };
+
+            Ok(res
+                .err()
+                .map(|(path, e)| format!("{}: {}", path.to_string_lossy(), e)))
+        })?;
+    util.set("chown", func)?;
+    Ok(util)
+}
+
+// Call `f` with the path, following it if it's a symlink, and then, if
+// recursive, with everything inside it, without following the symlinks.
+#[cfg(unix)]
+fn walk_tree(
+    path: &Path,
+    recursive: bool,
+    f: &mut dyn FnMut(&Path, &fs::Metadata) -> io::Result<()>,
+) -> std::result::Result<(), (PathBuf, io::Error)> {
+    fn walk_dir(
+        dir: &Path,
+        f: &mut dyn FnMut(&Path, &fs::Metadata) -> io::Result<()>,
+    ) -> std::result::Result<(), (PathBuf, io::Error)> {
+        let err = |e| (dir.to_path_buf(), e);
+        for entry in fs::read_dir(dir).map_err(err)? {
+            let path = entry.map_err(err)?.path();
+            let err = |e| (path.clone(), e);
+            let metadata = fs::symlink_metadata(&path).map_err(err)?;
+            f(&path, &metadata).map_err(err)?;
+            if metadata.is_dir() {
+                walk_dir(&path, f)?;
+            }
+        }
+        Ok(())
+    }
+
+    let err = |e| (path.to_path_buf(), e);
+    let metadata = fs::metadata(path).map_err(err)?;
+    f(path, &metadata).map_err(err)?;
+    if recursive && metadata.is_dir() {
+        walk_dir(path, f)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(not(unix), allow(dead_code))]
+enum FileMode {
+    Absolute(u32),
+    Symbolic(String),
+}
+
+impl FileMode {
+    fn parse(mode: &str) -> std::result::Result<Self, String> {
+        if !mode.is_empty() && mode.len() <= 4 && mode.chars().all(|c| c.is_digit(8)) {
+            u32::from_str_radix(mode, 8)
+                .map(Self::Absolute)
+                .map_err(|e| e.to_string())
+        } else {
+            apply_symbolic_mode(0, mode, false)?;
+            Ok(Self::Symbolic(mode.to_string()))
+        }
+    }
+
+    #[cfg(unix)]
+    fn apply(&self, mode: u32, is_dir: bool) -> u32 {
+        match self {
+            Self::Absolute(bits) => bits & 0o7777,
+            Self::Symbolic(spec) => {
+                apply_symbolic_mode(mode, spec, is_dir).unwrap_or(mode)
+            }
+        }
+    }
+}
+
+// Apply the comma separated clauses of a symbolic mode, like `chmod` does,
+// e.g. "u+x,go=rX" or "g=u".
+fn apply_symbolic_mode(
+    mode: u32,
+    spec: &str,
+    is_dir: bool,
+) -> std::result::Result<u32, String> {
+    let invalid = || format!("invalid mode: {spec:?}");
+    let mut mode = mode & 0o7777;
+
+    for clause in spec.split(',') {
+        let mut chars = clause.chars().peekable();
+
+        let mut who = 0;
+        while let Some(&c) = chars.peek() {
+            who |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => break,
+            };
+            chars.next();
+        }
+        if who == 0 {
+            who = 0o7777;
+        }
+
+        let mut has_op = false;
+        while let Some(op) = chars.next() {
+            if !matches!(op, '+' | '-' | '=') {
+                return Err(invalid());
+            }
+            has_op = true;
+
+            let mut perms = 0;
+            while let Some(&c) = chars.peek() {
+                perms |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    'X' if is_dir || mode & 0o111 != 0 => 0o111,
+                    'X' => 0,
+                    's' => 0o6000,
+                    't' => 0o1000,
+                    'u' => ((mode >> 6) & 0o7) * 0o111,
+                    'g' => ((mode >> 3) & 0o7) * 0o111,
+                    'o' => (mode & 0o7) * 0o111,
+                    '+' | '-' | '=' => break,
+                    _ => return Err(invalid()),
+                };
+                chars.next();
+            }
+
+            let bits = perms & who;
+            match op {
+                '+' => mode |= bits,
+                '-' => mode &= !bits,
+                _ => mode = (mode & !who) | bits,
+            }
+        }
+
+        if !has_op {
+            return Err(invalid());
+        }
+    }
+
+    Ok(mode)
+}
+
+/// Create a symlink at `link` pointing to `target`. The target is stored as
+/// is, so relative targets are relative to the directory of the link.
+///
+/// Options:
+///
+/// - kind: "file" or "dir". Windows needs to know what the link points to. It
+///   is guessed from the target when not specified, and ignored elsewhere.
+/// - force: Replace the existing file or symlink at `link`.
+///
+/// Returns nil on success, or the error message on failure.
+///
+/// Type: function( target:string, link:string, options:table|nil ) -> error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.symlink("dotfiles/vimrc", "/home/user/.vimrc", { force = true })
+/// -- nil
+/// ```
+pub fn symlink<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Kind {
+        File,
+        Dir,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        kind: Option<Kind>,
+        force: bool,
+    }
+
+    let func = lua.create_function(
+        |lua, (target, link, options): (String, String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let link_path = Path::new(&link);
+
+            if options.force {
+                if let Ok(metadata) = fs::symlink_metadata(link_path) {
+                    if metadata.is_dir() {
+                        return Ok(Some(format!("{}: is a directory", link)));
+                    } else if let Err(e) = fs::remove_file(link_path) {
+                        return Ok(Some(format!("{}: {}", link, e)));
+                    }
+                }
+            }
+
+            #[cfg(unix)]
+            let res = {
+                let _ = options.kind;
+                std::os::unix::fs::symlink(&target, link_path)
+            };
+
+            #[cfg(windows)]
+            let res = {
+                let kind = options.kind.unwrap_or_else(|| {
+                    let resolved = link_path
+                        .parent()
+                        .map(|p| p.join(&target))
+                        .unwrap_or_else(|| PathBuf::from(&target));
+                    if resolved.is_dir() {
+                        Kind::Dir
+                    } else {
+                        Kind::File
+                    }
+                });
+                match kind {
+                    Kind::Dir => std::os::windows::fs::symlink_dir(&target, link_path),
+                    Kind::File => std::os::windows::fs::symlink_file(&target, link_path),
+                }
+            };
+
+            #[cfg(not(any(unix, windows)))]
+            let res: io::Result<()> = {
+                let _ = options.kind;
+                Err(io::ErrorKind::Unsupported.into())
+            };
+
+            Ok(res.err().map(|e| format!("{}: {}", link, e)))
+        },
+    )?;
+    util.set("symlink", func)?;
+    Ok(util)
+}
+
+/// Get the target of the given symlink, exactly as it is stored, without
+/// resolving it. Returns nil if the path is not a symlink, or nil and the
+/// error message if it can't be read.
+///
+/// Type: function( path:string ) -> target:string|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.readlink("/home/user/.vimrc")
+/// -- "dotfiles/vimrc"
+///
+/// xplr.util.readlink("/home/user")
+/// -- nil
+/// ```
+pub fn readlink<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, path: String| {
+        let res = fs::symlink_metadata(&path).and_then(|metadata| {
+            if metadata.file_type().is_symlink() {
+                fs::read_link(&path).map(Some)
+            } else {
+                Ok(None)
+            }
+        });
+        Ok(match res {
+            Ok(target) => (target.map(|t| t.to_string_lossy().to_string()), None),
+            Err(e) => (None, Some(format!("{}: {}", path, e))),
+        })
+    })?;
+    util.set("readlink", func)?;
+    Ok(util)
+}
+
+/// Get the total size of the given directory, and the number of files and
+/// sub directories in it, recursively. Symlinks count as files unless
+/// followed, and unreadable directories are skipped.
+///
+/// Options:
+///
+/// - follow_symlinks: Follow the symlinks. Symlink loops are only walked once.
+/// - max_depth: Only count the entries up to this depth, 1 being the direct
+///   children of the directory, and 0 meaning no limit, like in the explorer
+///   config.
+/// - dedup_hardlinks: Count the files with multiple hard links only once
+///   (Unix only).
+/// - on_progress: Function called with the bytes, files and dirs counted so
+///   far, every 1000 entries.
+///
+/// Returns the totals, and the error message if the directory can't be read or
+/// the callback failed. The totals counted so far are returned on error.
+///
+/// Type: function( path:string, options:table|nil ) -> { bytes:number, files:number, dirs:number }, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.dir_size("/tmp/foo", { dedup_hardlinks = true })
+/// -- { bytes = 8, files = 3, dirs = 2 }, nil
+/// ```
+pub fn dir_size<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        follow_symlinks: bool,
+        max_depth: Option<usize>,
+        dedup_hardlinks: bool,
+    }
+
+    #[derive(Debug, Default, Serialize)]
+    struct Summary {
+        bytes: u64,
+        files: u64,
+        dirs: u64,
+    }
+
+    struct Walker<'lua> {
+        options: Options,
+        on_progress: Option<mlua::Function<'lua>>,
+        summary: Summary,
+        entries: u64,
+        visited: HashSet<PathBuf>,
+        inodes: HashSet<(u64, u64)>,
+    }
+
+    impl<'lua> Walker<'lua> {
+        fn walk(&mut self, dir: &Path, depth: usize) -> Result<(), String> {
+            if explorer::depth_limit(self.options.max_depth)
+                .map(|m| depth > m)
+                .unwrap_or(false)
+            {
+                return Ok(());
+            }
+
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(e) if depth == 1 => return Err(format!("{}: {}", dir.display(), e)),
+                Err(_) => return Ok(()),
+            };
+
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                let metadata = if self.options.follow_symlinks {
+                    fs::metadata(&path).or_else(|_| fs::symlink_metadata(&path))
+                } else {
+                    fs::symlink_metadata(&path)
+                };
+                let metadata = match metadata {
+                    Ok(m) => m,
+                    Err(_) => continue,
+                };
+
+                if metadata.is_dir() {
+                    if !self.is_new_dir(&path) {
+                        continue;
+                    }
+                    self.summary.dirs += 1;
+                    self.walk(&path, depth + 1)?;
+                } else if self.is_new(&metadata) {
+                    self.summary.files += 1;
+                    self.summary.bytes += metadata.len();
+                }
+
+                self.entries += 1;
+                if self.entries.is_multiple_of(1000) {
+                    self.report()?;
+                }
+            }
+            Ok(())
+        }
+
+        fn is_new_dir(&mut self, dir: &Path) -> bool {
+            !self.options.follow_symlinks
+                || fs::canonicalize(dir)
+                    .map(|c| self.visited.insert(c))
+                    .unwrap_or(true)
+        }
+
+        #[cfg(unix)]
+        fn is_new(&mut self, metadata: &fs::Metadata) -> bool {
+            use std::os::unix::fs::MetadataExt;
+            !self.options.dedup_hardlinks
+                || metadata.nlink() <= 1
+                || self.inodes.insert((metadata.dev(), metadata.ino()))
+        }
+
+        #[cfg(not(unix))]
+        fn is_new(&mut self, _: &fs::Metadata) -> bool {
+            let _ = &self.inodes;
+            true
+        }
+
+        fn report(&self) -> Result<(), String> {
+            match &self.on_progress {
+                Some(f) => f
+                    .call::<_, ()>((
+                        self.summary.bytes,
+                        self.summary.files,
+                        self.summary.dirs,
+                    ))
+                    .map_err(|e| e.to_string()),
+                None => Ok(()),
+            }
+        }
+    }
+
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let (options, on_progress) =
+                from_options_with_callback(lua, options, "on_progress")?;
+            let mut walker = Walker {
+                options,
+                on_progress,
+                summary: Default::default(),
+                entries: 0,
+                visited: Default::default(),
+                inodes: Default::default(),
+            };
+            walker.is_new_dir(Path::new(&path));
+            let err = walker.walk(Path::new(&path), 1).err();
+            let summary =
+                lua::serialize(lua, &walker.summary).map_err(LuaError::custom)?;
+            Ok((summary, err))
+        })?;
+    util.set("dir_size", func)?;
+    Ok(util)
+}
+
+/// Percent-encode the given string, like JavaScript's `encodeURI`, keeping the
+/// characters with a special meaning in URLs (e.g. `/`, `?`, `&`).
+///
+/// Options:
+///
+/// - component: Also encode the characters with a special meaning, like
+///   JavaScript's `encodeURIComponent`, to use the string as a part of a URL.
+///
+/// Type: function( string:string, options:table|nil ) -> string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.url_encode("/tmp/a b?c=d")
+/// -- "/tmp/a%20b?c=d"
+///
+/// xplr.util.url_encode("/tmp/a b?c=d", { component = true })
+/// -- "%2Ftmp%2Fa%20b%3Fc%3Dd"
+/// ```
+pub fn url_encode<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        component: bool,
+    }
+
+    let func =
+        lua.create_function(|lua, (string, options): (mlua::String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let set = if options.component {
+                URL_COMPONENT
+            } else {
+                URL
+            };
+            Ok(percent_encode(string.as_bytes(), set).to_string())
+        })?;
+    util.set("url_encode", func)?;
+    Ok(util)
+}
+
+/// Decode the percent-encoded string. Raises an error if the string contains
+/// an invalid percent sequence.
+///
+/// Type: function( string:string ) -> string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.url_decode("/tmp/a%20b")
+/// -- "/tmp/a b"
+/// ```
+pub fn url_decode<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, string: mlua::String| {
+        let bytes = percent_decode(string.as_bytes()).map_err(LuaError::RuntimeError)?;
+        lua.create_string(&bytes)
+    })?;
+    util.set("url_decode", func)?;
+    Ok(util)
+}
+
+/// Get the `file://` URI of the given path, made absolute, with the special
+/// and the non-ASCII characters percent-encoded.
+///
+/// Type: function( path:string ) -> uri:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.path_to_uri("/tmp/a b/café")
+/// -- "file:///tmp/a%20b/caf%C3%A9"
+/// ```
+pub fn path_to_uri<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, path: String| {
+        let path = PathBuf::from(path)
+            .absolutize()?
+            .to_string_lossy()
+            .to_string();
+
+        // Windows paths need to look like "/C:/foo/bar".
+        let path = if cfg!(windows) {
+            format!("/{}", path.replace('\\', "/"))
+        } else {
+            path
+        };
+
+        Ok(format!(
+            "file://{}",
+            percent_encode(path.as_bytes(), URL_PATH)
+        ))
+    })?;
+    util.set("path_to_uri", func)?;
+    Ok(util)
+}
+
+/// Get the path of the given `file://` URI, decoding the percent-encoded
+/// characters. Raises an error if it's not a valid `file://` URI.
+///
+/// Type: function( uri:string ) -> path:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.uri_to_path("file:///tmp/a%20b/caf%C3%A9")
+/// -- "/tmp/a b/café"
+/// ```
+pub fn uri_to_path<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, uri: String| {
+        let invalid = || LuaError::RuntimeError(format!("invalid file URI: {}", uri));
+
+        // Only local files are supported, with an empty or "localhost" host.
+        let path = uri.strip_prefix("file://").ok_or_else(invalid)?;
+        let path = path.strip_prefix("localhost").unwrap_or(path);
+        if !path.starts_with('/') {
+            return Err(invalid());
+        }
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        let bytes = percent_decode(path.as_bytes()).map_err(|_| invalid())?;
+
+        if cfg!(windows) {
+            let path = String::from_utf8(bytes).map_err(|_| invalid())?;
+            lua.create_string(path.trim_start_matches('/').replace('/', "\\"))
+        } else {
+            lua.create_string(&bytes)
+        }
+    })?;
+    util.set("uri_to_path", func)?;
+    Ok(util)
+}
+
+// The characters kept by `encodeURIComponent`, i.e. the alphanumerics and
+// `-_.!~*'()`.
+const URL_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'!')
+    .remove(b'~')
+    .remove(b'*')
+    .remove(b'\'')
+    .remove(b'(')
+    .remove(b')');
+
+// The characters kept by `encodeURI`.
+const URL: &AsciiSet = &URL_COMPONENT
+    .remove(b';')
+    .remove(b',')
+    .remove(b'/')
+    .remove(b'?')
+    .remove(b':')
+    .remove(b'@')
+    .remove(b'&')
+    .remove(b'=')
+    .remove(b'+')
+    .remove(b'$')
+    .remove(b'#');
+
+// The characters kept in the paths of `file://` URIs.
+const URL_PATH: &AsciiSet = &URL_COMPONENT.remove(b'/').remove(b':');
+
+fn percent_decode(bytes: &[u8]) -> std::result::Result<Vec<u8>, String> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter().enumerate();
+    while let Some((i, &b)) = iter.next() {
+        if b != b'%' {
+            decoded.push(b);
+            continue;
+        }
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .filter(|h| h.bytes().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("invalid percent sequence at position {}", i + 1))?;
+        decoded.push(u8::from_str_radix(hex, 16).unwrap_or_default());
+        iter.nth(1);
+    }
+    Ok(decoded)
+}
+
+/// Show a desktop notification, unlike the logs shown inside xplr.
+///
+/// Options:
+///
+/// - summary: The title of the notification.
+/// - body: The text of the notification.
+/// - icon: The name or the path of the icon.
+/// - timeout_ms: Close the notification after the given number of
+///   milliseconds, if supported by the notification server.
+/// - urgency: "low", "normal" or "critical" (Linux and BSD only).
+///
+/// Returns nil on success, or the error message if the notification can't be
+/// shown, e.g. without a notification server, or if xplr was built without the
+/// `notify` feature.
+///
+/// Type: function( options:table ) -> error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.notify({ summary = "xplr", body = "Copied 3 files", timeout_ms = 3000 })
+/// -- nil
+/// ```
+pub fn notify<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, options: Table| {
+        let options: NotifyOptions = from_options(lua, Some(options))?;
+
+        #[cfg(feature = "notify")]
+        let err = notification(&options).show().err().map(|e| e.to_string());
+
+        #[cfg(not(feature = "notify"))]
+        let err = {
+            let _ = options;
+            Some("xplr was built without the notify feature".to_string())
+        };
+
+        Ok(err)
+    })?;
+    util.set("notify", func)?;
+    Ok(util)
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum NotifyUrgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct NotifyOptions {
+    summary: String,
+    body: String,
+    icon: Option<String>,
+    timeout_ms: Option<u32>,
+    urgency: Option<NotifyUrgency>,
+}
+
+#[cfg(feature = "notify")]
+fn notification(options: &NotifyOptions) -> notify_rust::Notification {
+    let mut notification = notify_rust::Notification::new();
+    notification
+        .appname("xplr")
+        .summary(&options.summary)
+        .body(&options.body);
+
+    if let Some(icon) = &options.icon {
+        notification.icon(icon);
+    }
+    if let Some(ms) = options.timeout_ms {
+        notification.timeout(notify_rust::Timeout::Milliseconds(ms));
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if let Some(urgency) = options.urgency {
+        notification.urgency(match urgency {
+            NotifyUrgency::Low => notify_rust::Urgency::Low,
+            NotifyUrgency::Normal => notify_rust::Urgency::Normal,
+            NotifyUrgency::Critical => notify_rust::Urgency::Critical,
+        });
+    }
+
+    notification
+}
+
+/// Open the file, the directory or the URL with the default application, using
+/// `xdg-open` or an equivalent on Linux and BSD, `open` on macOS, and `start`
+/// on Windows. The application is launched in the background, without
+/// capturing its output, so it never blocks xplr.
+///
+/// Returns nil if the application was launched, or the error message if none of
+/// the launchers could be started.
+///
+/// Type: function( path:string ) -> error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.open(app.focused_node.absolute_path)
+/// -- nil
+/// ```
+pub fn open<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|_, path: String| Ok(spawn_detached(open::commands(path))))?;
+    util.set("open", func)?;
+    Ok(util)
+}
+
+/// Like `xplr.util.open`, but open the path with the given application.
+///
+/// Type: function( path:string, app:string ) -> error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.open_with("https://xplr.dev", "firefox")
+/// -- nil
+/// ```
+pub fn open_with<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, (path, app): (String, String)| {
+        Ok(spawn_detached(vec![open::with_command(path, app)]))
+    })?;
+    util.set("open_with", func)?;
+    Ok(util)
+}
+
+// Spawn the first command that can be started, in the background, and return
+// the last error if none could.
+fn spawn_detached(commands: Vec<Command>) -> Option<String> {
+    let mut err = None;
+    for mut cmd in commands {
+        let child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+
+        match child {
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+                return None;
+            }
+            Err(e) => {
+                err = Some(format!("{}: {}", cmd.get_program().to_string_lossy(), e))
+            }
+        }
+    }
+    Some(err.unwrap_or_else(|| "no launcher found".into()))
+}
+
+/// Merge the override table into the base table recursively, and return the
+/// result as a new table. The nested maps are merged, while the arrays and the
+/// other values in the override table replace the ones in the base table. An
+/// empty table is merged into a map, but replaces an array. Raises an error if
+/// the override table contains itself.
+///
+/// Type: function( base:table, override:table ) -> table
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.deep_merge(
+///   { style = { fg = "Red", add_modifiers = { "Bold" } }, width = 10 },
+///   { style = { bg = "Blue", add_modifiers = { "Italic" } } }
+/// )
+/// -- { style = { fg = "Red", bg = "Blue", add_modifiers = { "Italic" } }, width = 10 }
+/// ```
+pub fn deep_merge<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    fn merge<'lua>(
+        lua: &'lua Lua,
+        base: &Table<'lua>,
+        over: &Table<'lua>,
+        stack: &mut Vec<*const std::ffi::c_void>,
+        seen: &mut ClonedTables<'lua>,
+    ) -> mlua::Result<Table<'lua>> {
+        if stack.contains(&over.to_pointer()) {
+            return Err(LuaError::RuntimeError(
+                "cannot merge a table containing itself".into(),
+            ));
+        }
+        stack.push(over.to_pointer());
+
+        let merged = match deep_clone(lua, Value::Table(base.clone()), seen)? {
+            Value::Table(t) => t,
+            _ => unreachable!(),
+        };
+
+        for pair in over.clone().pairs::<Value, Value>() {
+            let (key, value) = pair?;
+            let value = match (base.raw_get::<_, Value>(key.clone())?, value) {
+                (Value::Table(b), Value::Table(o))
+                    if !is_array(&b)? && !is_array(&o)? =>
+                {
+                    Value::Table(merge(lua, &b, &o, stack, seen)?)
+                }
+                (_, value) => deep_clone(lua, value, seen)?,
+            };
+            merged.raw_set(key, value)?;
+        }
+
+        stack.pop();
+        Ok(merged)
+    }
+
+    let func = lua.create_function(|lua, (base, over): (Table, Table)| {
+        merge(lua, &base, &over, &mut vec![], &mut Default::default())
+    })?;
+    util.set("deep_merge", func)?;
+    Ok(util)
+}
+
+/// Copy the given value deeply, so that the copy doesn't share any nested
+/// table with the original. Tables referring to themselves are copied as such.
+/// Metatables, functions and userdata are not copied.
+///
+/// Type: function( value:any ) -> any
+///
+/// Example:
+///
+/// ```lua
+/// local copy = xplr.util.clone(xplr.config.general.table)
+/// copy.header.height = 2
+/// -- xplr.config.general.table.header.height is unchanged
+/// ```
+pub fn clone<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, value: Value| {
+        deep_clone(lua, value, &mut Default::default())
+    })?;
+    util.set("clone", func)?;
+    Ok(util)
+}
+
+type ClonedTables<'lua> = HashMap<*const std::ffi::c_void, Table<'lua>>;
+
+fn deep_clone<'lua>(
+    lua: &'lua Lua,
+    value: Value<'lua>,
+    seen: &mut ClonedTables<'lua>,
+) -> mlua::Result<Value<'lua>> {
+    let table = match value {
+        Value::Table(t) => t,
+        value => return Ok(value),
+    };
+
+    if let Some(copy) = seen.get(&table.to_pointer()) {
+        return Ok(Value::Table(copy.clone()));
+    }
+
+    let copy = lua.create_table()?;
+    seen.insert(table.to_pointer(), copy.clone());
+    for pair in table.clone().pairs::<Value, Value>() {
+        let (key, value) = pair?;
+        copy.raw_set(deep_clone(lua, key, seen)?, deep_clone(lua, value, seen)?)?;
+    }
+    copy.set_metatable(table.get_metatable());
+    Ok(Value::Table(copy))
+}
+
+// Whether the table is a non-empty sequence.
+fn is_array(table: &Table) -> mlua::Result<bool> {
+    let len = table.raw_len();
+    if len == 0 {
+        return Ok(false);
+    }
+    let mut count = 0;
+    for pair in table.clone().pairs::<Value, Value>() {
+        pair?;
+        count += 1;
+    }
+    Ok(count == len)
+}
+
+/// Compare the given versions following the [semantic versioning][1] rules,
+/// returning -1, 0 or 1. The "v" prefix and the missing minor and patch
+/// numbers are tolerated, and the build metadata is ignored. Raises an error
+/// if a version can't be parsed.
+///
+/// Type: function( a:string, b:string ) -> -1|0|1
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.version_compare("v1.2.0", "1.10")
+/// -- -1
+///
+/// xplr.util.version_compare("1.0.0", "1.0.0-rc.1")
+/// -- 1
+/// ```
+///
+/// [1]: https://semver.org
+pub fn version_compare<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, (a, b): (String, String)| {
+        let ord = parse_version(&a)?.cmp_precedence(&parse_version(&b)?);
+        Ok(ord as i8)
+    })?;
+    util.set("version_compare", func)?;
+    Ok(util)
+}
+
+/// Check if the given version satisfies the requirement, e.g. `>=1.2, <2.0`
+/// or `^1.2`, following the [rules of Cargo][1]. The version is parsed like
+/// in `xplr.util.version_compare`. Raises an error if the version or the
+/// requirement can't be parsed.
+///
+/// Type: function( version:string, requirement:string ) -> boolean
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.version_matches("v1.4.2", ">=1.2, <2.0")
+/// -- true
+/// ```
+///
+/// [1]: https://doc.rust-lang.org/cargo/reference/specifying-dependencies.html
+pub fn version_matches<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, (version, req): (String, String)| {
+        let version = parse_version(&version)?;
+        let req = VersionReq::parse(&req).map_err(|e| {
+            LuaError::RuntimeError(format!(
+                "invalid version requirement: {}: {}",
+                req, e
+            ))
+        })?;
+        Ok(req.matches(&version))
+    })?;
+    util.set("version_matches", func)?;
+    Ok(util)
+}
+
+fn parse_version(version: &str) -> mlua::Result<Version> {
+    let trimmed = version.trim();
+    let trimmed = trimmed.strip_prefix(['v', 'V']).unwrap_or(trimmed);
+
+    // Complete "1" and "1.2" into "1.0.0" and "1.2.0".
+    let end = trimmed.find(['-', '+']).unwrap_or(trimmed.len());
+    let (core, rest) = trimmed.split_at(end);
+    let missing = 2usize.saturating_sub(core.matches('.').count());
+    let full = format!("{}{}{}", core, ".0".repeat(missing), rest);
+
+    Version::parse(&full).map_err(|e| {
+        LuaError::RuntimeError(format!("invalid version: {}: {}", version, e))
+    })
+}
+
+/// Compare the entries of two directories by name, without descending into
+/// the sub directories. The entries that only exist on one side are listed in
+/// `only_left` and `only_right`. The entries that exist on both sides but
+/// differ are listed in `differing`, with the reasons:
+///
+/// - "type": One is a directory (or a symlink with `compare_links`) and the
+///   other isn't.
+/// - "size": The sizes differ.
+/// - "mtime": The modification times differ (unless comparing by content).
+/// - "content": The contents differ (only when comparing by content).
+/// - "target": The symlinks point to different paths (only with
+///   `compare_links`).
+/// - "unreadable": The contents couldn't be read to be compared.
+///
+/// Options:
+///
+/// - by_content: Compare the contents of the files of the same size, instead
+///   of the modification times.
+/// - compare_links: Compare the symlinks by their target paths, instead of
+///   the files they point to.
+///
+/// Raises an error if a directory can't be read.
+///
+/// Type: function( left:string, right:string, options:table|nil ) -> { only_left:{ name:string... }, only_right:{ name:string... }, differing:{ { name:string, reasons:{ reason:string... } }... } }
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.diff_dirs("/tmp/a", "/tmp/b", { by_content = true })
+/// -- {
+/// --   only_left = { "foo" },
+/// --   only_right = { "bar" },
+/// --   differing = { { name = "baz", reasons = { "content" } } },
+/// -- }
+/// ```
+pub fn diff_dirs<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        by_content: bool,
+        compare_links: bool,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct Differing {
+        name: String,
+        reasons: Vec<&'static str>,
+    }
+
+    #[derive(Debug, Default, Serialize)]
+    struct Diff {
+        only_left: Vec<String>,
+        only_right: Vec<String>,
+        differing: Vec<Differing>,
+    }
+
+    fn list(dir: &str) -> mlua::Result<BTreeMap<String, node::Node>> {
+        let nodes = explorer::explore(Path::new(dir), &ExplorerConfig::default())
+            .map_err(LuaError::custom)?;
+        Ok(nodes
+            .into_iter()
+            .map(|n| (n.relative_path.clone(), n))
+            .collect())
+    }
+
+    fn compare(l: &node::Node, r: &node::Node, options: &Options) -> Vec<&'static str> {
+        if options.compare_links && (l.is_symlink || r.is_symlink) {
+            if l.is_symlink != r.is_symlink {
+                return vec!["type"];
+            }
+            let target = |n: &node::Node| fs::read_link(&n.absolute_path).ok();
+            return if target(l) != target(r) {
+                vec!["target"]
+            } else {
+                vec![]
+            };
+        }
+
+        // Symlinks are compared by the files they point to, if not broken.
+        let resolve = |n: &node::Node| match &n.canonical {
+            Some(c) => (c.is_dir, c.size, c.last_modified),
+            None => (n.is_dir, n.size, n.last_modified),
+        };
+        let (l_dir, l_size, l_mtime) = resolve(l);
+        let (r_dir, r_size, r_mtime) = resolve(r);
+
+        if l_dir != r_dir {
+            vec!["type"]
+        } else if l_dir {
+            vec![]
+        } else if l_size != r_size {
+            vec!["size"]
+        } else if options.by_content {
+            match same_content(Path::new(&l.absolute_path), Path::new(&r.absolute_path))
+            {
+                Ok(true) => vec![],
+                Ok(false) => vec!["content"],
+                Err(_) => vec!["unreadable"],
+            }
+        } else if l_mtime != r_mtime {
+            vec!["mtime"]
+        } else {
+            vec![]
+        }
+    }
+
+    let func = lua.create_function(
+        |lua, (left, right, options): (String, String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let left = list(&left)?;
+            let mut right = list(&right)?;
+
+            let mut diff = Diff::default();
+            for (name, l) in left {
+                match right.remove(&name) {
+                    Some(r) => {
+                        let reasons = compare(&l, &r, &options);
+                        if !reasons.is_empty() {
+                            diff.differing.push(Differing { name, reasons });
+                        }
+                    }
+                    None => diff.only_left.push(name),
+                }
+            }
+            diff.only_right = right.into_keys().collect();
+
+            lua::serialize(lua, &diff).map_err(LuaError::custom)
+        },
+    )?;
+    util.set("diff_dirs", func)?;
+    Ok(util)
+}
+
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    let mut a = io::BufReader::new(fs::File::open(a)?);
+    let mut b = io::BufReader::new(fs::File::open(b)?);
+    loop {
+        let (a_buf, b_buf) = (a.fill_buf()?, b.fill_buf()?);
+        if a_buf.is_empty() || b_buf.is_empty() {
+            return Ok(a_buf.is_empty() && b_buf.is_empty());
+        }
+        let n = a_buf.len().min(b_buf.len());
+        if a_buf[..n] != b_buf[..n] {
+            return Ok(false);
+        }
+        a.consume(n);
+        b.consume(n);
+    }
+}
+
+/// Read a range of lines from a file, without reading the rest of it. The
+/// lines are returned without the line endings, and the invalid UTF-8
+/// sequences are replaced with `�`.
+///
+/// Options:
+///
+/// - start: The number of the first line to read, starting from 1 (default: 1).
+/// - count: The maximum number of lines to read (default: all).
+///
+/// Returns the lines, with `total_read`, the number of lines read including
+/// the skipped ones, and `reached_eof`, whether there are no more lines after
+/// them. Returns nil and the error message on failure.
+///
+/// Type: function( path:string, options:table|nil ) -> { lines:{ line:string... }, total_read:number, reached_eof:boolean }|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.read_lines("/var/log/messages", { start = 10, count = 2 })
+/// -- { lines = { "foo", "bar" }, total_read = 11, reached_eof = false }
+/// ```
+pub fn read_lines<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        start: usize,
+        count: Option<usize>,
+    }
+
+    impl Default for Options {
+        fn default() -> Self {
+            Self {
+                start: 1,
+                count: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Default, Serialize)]
+    struct Lines {
+        lines: Vec<String>,
+        total_read: usize,
+        reached_eof: bool,
+    }
+
+    fn read(path: &str, options: &Options) -> io::Result<Lines> {
+        let mut reader = io::BufReader::new(fs::File::open(path)?);
+        let mut res = Lines::default();
+        let mut buf = vec![];
+
+        let end = options
+            .count
+            .map(|c| options.start.max(1).saturating_add(c))
+            .unwrap_or(usize::MAX);
+
+        while res.total_read + 1 < end {
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                res.reached_eof = true;
+                return Ok(res);
+            }
+            res.total_read += 1;
+            if res.total_read < options.start {
+                continue;
+            }
+            if buf.ends_with(b"\n") {
+                buf.pop();
+                if buf.ends_with(b"\r") {
+                    buf.pop();
+                }
+            }
+            res.lines.push(String::from_utf8_lossy(&buf).to_string());
+        }
+
+        res.reached_eof = reader.fill_buf()?.is_empty();
+        Ok(res)
+    }
+
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            match read(&path, &options) {
+                Ok(lines) => {
+                    let lines = lua::serialize(lua, &lines).map_err(LuaError::custom)?;
+                    Ok((lines, None))
+                }
+                Err(e) => Ok((Value::Nil, Some(format!("{}: {}", path, e)))),
+            }
+        })?;
+    util.set("read_lines", func)?;
+    Ok(util)
+}
+
+/// Watch a file or a directory for changes, instead of re-exploring on a
+/// timer. The rapid changes are coalesced, and once they settle, xplr
+/// receives an internal `FsEvent` message, and handles the given messages.
+/// The changed paths are passed to the Lua functions they call, as
+/// [`fs_event`][3] in the context.
+///
+/// Options:
+///
+/// - recursive: Watch the sub-directories too.
+/// - debounce_ms: The interval to coalesce the changes in, 200 by default.
+/// - messages: The [messages][1] to handle on changes,
+///   `{ "ExplorePwdAsync" }` by default.
+///
+/// Returns a handle to pass to [xplr.util.unwatch][2], or nil and the error
+/// message on failure.
+///
+/// Type: function( path:string, options:table|nil ) -> handle:number|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.watch("/tmp/downloads", {
+///   recursive = true,
+///   debounce_ms = 500,
+///   messages = { "ExplorePwdAsync" },
+/// })
+/// -- 1, nil
+/// ```
+///
+/// [1]: https://xplr.dev/en/message
+/// [2]: #xplrutilunwatch
+/// [3]: https://xplr.dev/en/lua-function-calls#fs_event
+pub fn watch<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        recursive: bool,
+        debounce_ms: u64,
+        messages: Vec<ExternalMsg>,
+    }
+
+    impl Default for Options {
+        fn default() -> Self {
+            Self {
+                recursive: false,
+                debounce_ms: 200,
+                messages: vec![ExternalMsg::ExplorePwdAsync],
+            }
+        }
+    }
+
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let res = fs_watcher::watch(
+                Path::new(&path),
+                options.recursive,
+                Duration::from_millis(options.debounce_ms),
+                options.messages,
+            );
+
+            match res {
+                Ok(handle) => Ok((Some(handle), None)),
+                Err(e) => Ok((None, Some(format!("{}: {}", path, e)))),
+            }
+        })?;
+    util.set("watch", func)?;
+    Ok(util)
+}
+
+/// Stop a watcher registered with [xplr.util.watch][1].
+///
+/// Returns false if the handle wasn't being watched.
+///
+/// Type: function( handle:number ) -> boolean
+///
+/// Example:
+///
+/// ```lua
+/// local handle = xplr.util.watch("/tmp/downloads")
+/// xplr.util.unwatch(handle)
+/// -- true
+/// ```
+///
+/// [1]: #xplrutilwatch
+pub fn unwatch<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, handle: u64| Ok(fs_watcher::unwatch(handle)))?;
+    util.set("unwatch", func)?;
+    Ok(util)
+}
+
+/// Get the value of an environment variable of the xplr process.
+///
+/// Returns nil if the variable is not set.
+///
+/// Type: function( name:string ) -> value:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.getenv("HOME")
+/// -- "/home/user"
+///
+/// xplr.util.getenv("NOT_SET")
+/// -- nil
+/// ```
+pub fn getenv<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, name: String| {
+        Ok(env::var_os(name).map(|v| v.to_string_lossy().to_string()))
+    })?;
+    util.set("getenv", func)?;
+    Ok(util)
+}
+
+/// Set an environment variable, or unset it when the value is nil.
+///
+/// Note that this mutates the environment of the xplr process itself, so it
+/// affects all the commands spawned afterwards, not just the ones spawned by
+/// the calling plugin.
+///
+/// Type: function( name:string, value:string|nil )
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.setenv("EDITOR", "vim")
+/// xplr.util.setenv("EDITOR", nil)
+/// ```
+pub fn setenv<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, (name, value): (String, Option<String>)| {
+        if name.is_empty() || name.contains(['=', '\0']) {
+            return Err(LuaError::RuntimeError(format!(
+                "invalid environment variable name: {:?}",
+                name
+            )));
+        }
+
+        match value {
+            Some(value) if value.contains('\0') => Err(LuaError::RuntimeError(format!(
+                "invalid value for environment variable {}: {:?}",
+                name, value
+            ))),
+            Some(value) => {
+                env::set_var(name, value);
+                Ok(())
+            }
+            None => {
+                env::remove_var(name);
+                Ok(())
+            }
+        }
+    })?;
+    util.set("setenv", func)?;
+    Ok(util)
+}
+
+/// Get the whole environment of the xplr process as a table.
+///
+/// Type: function() -> { [name:string]:string }
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.environ()
+/// -- { HOME = "/home/user", SHELL = "/bin/bash", ... }
+/// ```
+pub fn environ<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, ()| {
+        let vars: HashMap<String, String> = env::vars_os()
+            .map(|(k, v)| {
+                (
+                    k.to_string_lossy().to_string(),
+                    v.to_string_lossy().to_string(),
+                )
+            })
+            .collect();
+        lua::serialize(lua, &vars).map_err(LuaError::custom)
+    })?;
+    util.set("environ", func)?;
+    Ok(util)
+}
+
+/// Split a text into lines. Both `\n` and `\r\n` line endings are supported,
+/// so the stray `\r` of the Windows authored files is stripped.
+///
+/// An empty text gives no lines, and a trailing newline doesn't give a
+/// trailing empty line, so `"a\nb\n"` and `"a\nb"` give the same lines.
+///
+/// Type: function( text:string ) -> lines:string[]
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.lines("foo\r\nbar\n")
+/// -- { "foo", "bar" }
+///
+/// xplr.util.lines("")
+/// -- {}
+/// ```
+pub fn lines<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, text: String| {
+        Ok(text.lines().map(String::from).collect::<Vec<_>>())
+    })?;
+    util.set("lines", func)?;
+    Ok(util)
+}
+
+/// Join lines into a text.
+///
+/// Options:
+///
+/// - separator: The separator to join the lines with, `"\n"` by default.
+///
+/// No trailing separator is added, so it reverses [xplr.util.lines][1] for the
+/// texts without a trailing newline.
+///
+/// Type: function( lines:string[], options:table|nil ) -> text:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.unlines({ "foo", "bar" })
+/// -- "foo\nbar"
+///
+/// xplr.util.unlines({ "foo", "bar" }, { separator = "\r\n" })
+/// -- "foo\r\nbar"
+/// ```
+///
+/// [1]: #xplrutillines
+pub fn unlines<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        separator: String,
+    }
+
+    impl Default for Options {
+        fn default() -> Self {
+            Self {
+                separator: "\n".into(),
+            }
+        }
+    }
+
+    let func =
+        lua.create_function(|lua, (lines, options): (Vec<String>, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            Ok(lines.join(&options.separator))
+        })?;
+    util.set("unlines", func)?;
+    Ok(util)
+}
+
+/// Detect the type of a file from its content, using the magic bytes at its
+/// start, unlike the extension based [mime][1] of the nodes. So it also works
+/// for the extensionless and the mislabeled files.
+///
+/// Options:
+///
+/// - max_bytes: How many bytes to read from the start of the file, 8192 by
+///   default.
+///
+/// Returns `{ mime, extension, is_text }`, or nil and the error message on
+/// failure. `is_text` is true if the read bytes are valid UTF-8 without any
+/// null byte. When the type isn't recognized, `mime` is `"text/plain"` or
+/// `"application/octet-stream"`, based on `is_text`, and `extension` is nil.
+///
+/// Type: function( path:string, options:table|nil ) -> { mime:string, extension:string|nil, is_text:boolean }|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.sniff("/tmp/picture")
+/// -- { mime = "image/png", extension = "png", is_text = false }
+///
+/// xplr.util.sniff("/tmp/notes", { max_bytes = 1024 })
+/// -- { mime = "text/plain", is_text = true }
+/// ```
+///
+/// [1]: https://xplr.dev/en/lua-function-calls#mime
+pub fn sniff<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        max_bytes: u64,
+    }
+
+    impl Default for Options {
+        fn default() -> Self {
+            Self { max_bytes: 8192 }
+        }
+    }
+
+    #[derive(Debug, Serialize)]
+    struct Sniffed {
+        mime: String,
+        extension: Option<String>,
+        is_text: bool,
+    }
+
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+
+            let mut buf = vec![];
+            let res = fs::File::open(&path)
+                .and_then(|f| f.take(options.max_bytes).read_to_end(&mut buf));
+            if let Err(e) = res {
+                return Ok((Value::Nil, Some(format!("{}: {}", path, e))));
+            }
+
+            let is_text = is_text(&buf);
+            let sniffed = match infer::get(&buf) {
+                Some(t) => Sniffed {
+                    mime: t.mime_type().into(),
+                    extension: Some(t.extension().into()),
+                    is_text,
+                },
+                None => Sniffed {
+                    mime: if is_text {
+                        "text/plain".into()
+                    } else {
+                        "application/octet-stream".into()
+                    },
+                    extension: None,
+                    is_text,
+                },
+            };
+
+            let sniffed = lua::serialize(lua, &sniffed).map_err(LuaError::custom)?;
+            Ok((sniffed, None))
+        })?;
+    util.set("sniff", func)?;
+    Ok(util)
+}
+
+// Valid UTF-8 without null bytes. A character cut at the end of the sample
+// doesn't count as invalid.
+fn is_text(buf: &[u8]) -> bool {
+    if buf.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(buf) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+/// List the contents of a zip or a tar archive, without extracting it.
+///
+/// Options:
+///
+/// - format: "zip", "tar", "tar.gz" or "tar.bz2". By default, it's detected
+///   from the extension, or else from the content.
+///
+/// Returns a list of `{ relative_path, is_dir, size }`, in the order of the
+/// paths, or nil and the error message on failure. The directories that
+/// aren't in the archive themselves, but contain its entries, are listed too,
+/// so that a tree can be built from the list.
+///
+/// Type: function( path:string, options:table|nil ) -> { { relative_path:string, is_dir:boolean, size:integer }... }|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.archive_list("/tmp/foo.zip")
+/// -- {
+/// --   { relative_path = "foo", is_dir = true, size = 0 },
+/// --   { relative_path = "foo/bar.txt", is_dir = false, size = 42 },
+/// -- }, nil
+/// ```
+pub fn archive_list<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        format: Option<ArchiveFormat>,
+    }
+
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+
+            let format = match options.format {
+                Some(format) => Ok(format),
+                None => ArchiveFormat::detect(Path::new(&path)),
+            };
+
+            match format.and_then(|f| list_archive(Path::new(&path), f)) {
+                Ok(entries) => {
+                    let entries =
+                        lua::serialize(lua, &entries).map_err(LuaError::custom)?;
+                    Ok((entries, None))
+                }
+                Err(e) => Ok((Value::Nil, Some(format!("{}: {}", path, e)))),
+            }
+        })?;
+    util.set("archive_list", func)?;
+    Ok(util)
+}
+
+/// Extract a single member of a zip or a tar archive, as listed by
+/// [xplr.util.archive_list][1], to the memory, or to a directory.
+///
+/// Options:
+///
+/// - dest: Extract the member into this directory, keeping its path inside
+///   the archive, instead of returning its content. Members with paths
+///   escaping the directory (e.g. `../foo`), or leading through a symlink
+///   inside it, are rejected.
+/// - overwrite: Replace the existing file in `dest`.
+/// - max_bytes: Only return this many bytes of the content, when extracting to
+///   the memory.
+/// - format: Same as in [xplr.util.archive_list][1].
+///
+/// Returns the content, or the path of the extracted file when `dest` is
+/// given, or nil and the error message on failure, e.g. when the member
+/// doesn't exist.
+///
+/// Type: function( archive:string, member:string, options:table|nil ) -> content_or_path:string|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.archive_extract("/tmp/foo.zip", "foo/bar.txt", { max_bytes = 1024 })
+/// -- "content of bar.txt", nil
+///
+/// xplr.util.archive_extract("/tmp/foo.zip", "foo/bar.txt", { dest = "/tmp/out" })
+/// -- "/tmp/out/foo/bar.txt", nil
+/// ```
+///
+/// [1]: #xplrutilarchive_list
+pub fn archive_extract<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        dest: Option<String>,
+        overwrite: bool,
+        max_bytes: Option<u64>,
+        format: Option<ArchiveFormat>,
+    }
+
+    let func = lua.create_function(
+        |lua, (path, member, options): (String, String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+
+            let format = match options.format {
+                Some(format) => Ok(format),
+                None => ArchiveFormat::detect(Path::new(&path)),
+            };
+
+            let res = format.and_then(|format| match &options.dest {
+                Some(dest) => {
+                    let target = Path::new(&archive_path(&member))
+                        .components()
+                        .all(|c| matches!(c, Component::Normal(_)))
+                        .then(|| Path::new(dest).join(archive_path(&member)))
+                        .ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                format!("{}: unsafe member path", member),
+                            )
+                        })?;
+
+                    with_archive_member(
+                        Path::new(&path),
+                        format,
+                        &member,
+                        |r, is_dir| {
+                            reject_symlinks(Path::new(dest), &target)?;
+                            if is_dir {
+                                fs::create_dir_all(&target)?;
+                            } else {
+                                if let Some(parent) = target.parent() {
+                                    fs::create_dir_all(parent)?;
+                                }
+                                let mut file = fs::OpenOptions::new()
+                                    .write(true)
+                                    .create(true)
+                                    .truncate(true)
+                                    .create_new(!options.overwrite)
+                                    .open(&target)?;
+                                io::copy(r, &mut file)?;
+                            }
+                            Ok(lua.create_string(target.to_string_lossy().as_bytes()))
+                        },
+                    )
+                }
+                None => {
+                    with_archive_member(Path::new(&path), format, &member, |r, _| {
+                        let mut buf = vec![];
+                        r.take(options.max_bytes.unwrap_or(u64::MAX))
+                            .read_to_end(&mut buf)?;
+                        Ok(lua.create_string(&buf))
+                    })
+                }
+            });
+
+            match res {
+                Ok(content) => Ok((Some(content?), None)),
+                Err(e) => Ok((None, Some(format!("{}: {}", path, e)))),
+            }
+        },
+    )?;
+    util.set("archive_extract", func)?;
+    Ok(util)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+enum ArchiveFormat {
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "tar.bz2")]
+    TarBz2,
+}
+
+impl ArchiveFormat {
+    fn detect(path: &Path) -> io::Result<Self> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        let by_name = [
+            (".zip", Self::Zip),
+            (".tar", Self::Tar),
+            (".tar.gz", Self::TarGz),
+            (".tgz", Self::TarGz),
+            (".tar.bz2", Self::TarBz2),
+            (".tbz2", Self::TarBz2),
+        ];
+        if let Some((_, format)) = by_name.iter().find(|(ext, _)| name.ends_with(ext)) {
+            return Ok(*format);
+        }
+
+        // The tar magic is at the offset 257.
+        let mut buf = vec![];
+        fs::File::open(path)?.take(512).read_to_end(&mut buf)?;
+        match infer::get(&buf).map(|t| t.mime_type()) {
+            Some("application/zip") => Ok(Self::Zip),
+            Some("application/x-tar") => Ok(Self::Tar),
+            Some("application/gzip") => Ok(Self::TarGz),
+            Some("application/x-bzip2") => Ok(Self::TarBz2),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported archive format",
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ArchiveEntry {
+    relative_path: String,
+    is_dir: bool,
+    size: u64,
+}
+
+#[cfg(feature = "archive")]
+fn list_archive(path: &Path, format: ArchiveFormat) -> io::Result<Vec<ArchiveEntry>> {
+    fn tar_entries(reader: impl Read) -> io::Result<Vec<(String, bool, u64)>> {
+        let mut archive = tar::Archive::new(reader);
+        let mut entries = vec![];
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = entry.path()?.to_string_lossy().to_string();
+            let is_dir = entry.header().entry_type().is_dir();
+            entries.push((path, is_dir, entry.size()));
+        }
+        Ok(entries)
+    }
+
+    let file = io::BufReader::new(fs::File::open(path)?);
+    let entries = match format {
+        ArchiveFormat::Zip => {
+            let mut archive = zip::ZipArchive::new(file)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let mut entries = vec![];
+            for i in 0..archive.len() {
+                let entry = archive
+                    .by_index_raw(i)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                entries.push((entry.name().to_string(), entry.is_dir(), entry.size()));
+            }
+            entries
+        }
+        ArchiveFormat::Tar => tar_entries(file)?,
+        ArchiveFormat::TarGz => tar_entries(flate2::read::GzDecoder::new(file))?,
+        ArchiveFormat::TarBz2 => tar_entries(bzip2::read::BzDecoder::new(file))?,
+    };
+
+    // Also list the implied parent directories.
+    let mut listed: BTreeMap<String, ArchiveEntry> = BTreeMap::new();
+    for (path, is_dir, size) in entries {
+        let path = archive_path(&path);
+        if path.is_empty() || path == "." {
+            continue;
+        }
+
+        for dir in Path::new(&path).ancestors().skip(1) {
+            if dir.as_os_str().is_empty() {
+                break;
+            }
+            let dir = dir.to_string_lossy().to_string();
+            listed.entry(dir.clone()).or_insert(ArchiveEntry {
+                relative_path: dir,
+                is_dir: true,
+                size: 0,
+            });
+        }
+
+        listed.insert(
+            path.clone(),
+            ArchiveEntry {
+                relative_path: path,
+                is_dir,
+                size: if is_dir { 0 } else { size },
+            },
+        );
+    }
+
+    Ok(listed.into_values().collect())
+}
+
+#[cfg(not(feature = "archive"))]
+fn list_archive(_: &Path, _: ArchiveFormat) -> io::Result<Vec<ArchiveEntry>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "xplr was built without the archive feature",
+    ))
+}
+
+// Fail if any existing path from `dest` (excluded) down to `target` is a
+// symlink, as writing through it could escape `dest`.
+fn reject_symlinks(dest: &Path, target: &Path) -> io::Result<()> {
+    let relative = target.strip_prefix(dest).unwrap_or(target);
+    let mut path = dest.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(m) if m.file_type().is_symlink() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{}: symlink in the destination path", path.display()),
+                ));
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    Ok(())
+}
+
+// The paths as listed by `archive_list`.
+fn archive_path(name: &str) -> String {
+    name.trim_start_matches("./")
+        .trim_start_matches('/')
+        .trim_end_matches('/')
+        .to_string()
+}
+
+// Pass the reader of the given member, and whether it's a directory, to `f`.
+#[cfg(feature = "archive")]
+fn with_archive_member<T>(
+    path: &Path,
+    format: ArchiveFormat,
+    member: &str,
+    f: impl FnOnce(&mut dyn Read, bool) -> io::Result<T>,
+) -> io::Result<T> {
+    fn tar_member<T>(
+        reader: impl Read,
+        member: &str,
+        f: impl FnOnce(&mut dyn Read, bool) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if archive_path(&entry.path()?.to_string_lossy()) != member {
+                continue;
+            }
+            let kind = entry.header().entry_type();
+            if !kind.is_file() && !kind.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{}: not a regular file", member),
+                ));
+            }
+            return f(&mut entry, kind.is_dir());
+        }
+        Err(no_member(member))
+    }
+
+    fn no_member(member: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no such member: {}", member),
+        )
+    }
+
+    let member = archive_path(member);
+    let file = io::BufReader::new(fs::File::open(path)?);
+    match format {
+        ArchiveFormat::Zip => {
+            let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+            let mut archive = zip::ZipArchive::new(file).map_err(invalid)?;
+            for i in 0..archive.len() {
+                if archive_path(archive.by_index_raw(i).map_err(invalid)?.name())
+                    != member
+                {
+                    continue;
+                }
+                let mut entry = archive.by_index(i).map_err(invalid)?;
+                let is_dir = entry.is_dir();
+                return f(&mut entry, is_dir);
+            }
+            Err(no_member(&member))
+        }
+        ArchiveFormat::Tar => tar_member(file, &member, f),
+        ArchiveFormat::TarGz => {
+            tar_member(flate2::read::GzDecoder::new(file), &member, f)
+        }
+        ArchiveFormat::TarBz2 => {
+            tar_member(bzip2::read::BzDecoder::new(file), &member, f)
+        }
+    }
+}
+
+#[cfg(not(feature = "archive"))]
+fn with_archive_member<T>(
+    _: &Path,
+    _: ArchiveFormat,
+    _: &str,
+    _: impl FnOnce(&mut dyn Read, bool) -> io::Result<T>,
+) -> io::Result<T> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "xplr was built without the archive feature",
+    ))
+}
+
+/// Check if the work identified by the given key can run again, i.e. if at
+/// least the given interval has passed since the last time it was allowed.
+/// Useful to skip the redundant expensive work in the hooks that fire too
+/// often, e.g. during rapid navigation.
+///
+/// The first call for a key is always allowed. The last allowed times are
+/// kept in the app state with the [session store][1], using a monotonic
+/// clock, so they are shared by all the callers of the same key, and persist
+/// across the calls.
+///
+/// Type: function( key:string, interval_ms:number ) -> boolean
+///
+/// Example:
+///
+/// ```lua
+/// xplr.fn.custom.preview = function(app)
+///   if not xplr.util.throttle("preview", 200) then
+///     return
+///   end
+///   -- expensive work
+/// end
+/// ```
+///
+/// [1]: #xplrutilstore_set
+pub fn throttle<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, (key, interval_ms): (String, u64)| {
+        Ok(store::throttle(
+            key,
+            Duration::from_millis(interval_ms),
+            Instant::now(),
+        ))
+    })?;
+    util.set("throttle", func)?;
+    Ok(util)
+}
+
+/// Align the columns of the given rows, taking the terminal display width of
+/// the wide (e.g. CJK and emoji) characters into account, and return the
+/// lines. The cells can be strings or numbers, and nil cells or missing cells
+/// at the end of the shorter rows are left empty.
+///
+/// Options:
+///
+/// - separator: The string to put between the columns, " " by default.
+/// - columns: A list of the column options, each a table with:
+///   - align: "left" (default) or "right".
+///   - max_width: Truncate the cells wider than this, with an ellipsis, as
+///     done by [xplr.util.truncate][1].
+///
+/// The lines have no trailing spaces, the last cell isn't padded when it's
+/// left aligned, and the empty cells at the end are left out.
+///
+/// Type: function( rows:{ { cell:string|number... }... }, options:table|nil ) -> { line:string... }
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.tabulate(
+///   { { "foo", 1 }, { "日本語", 200 } },
+///   { separator = " | ", columns = { {}, { align = "right" } } }
+/// )
+/// -- { "foo    |   1", "日本語 | 200" }
+/// ```
+///
+/// [1]: #xplrutiltruncate
+pub fn tabulate<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Align {
+        #[default]
+        Left,
+        Right,
+    }
+
+    #[derive(Debug, Default, Clone, Deserialize)]
+    #[serde(default)]
+    struct Column {
+        align: Align,
+        max_width: Option<usize>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        separator: String,
+        columns: Vec<Column>,
+    }
+
+    impl Default for Options {
+        fn default() -> Self {
+            Self {
+                separator: " ".into(),
+                columns: vec![],
+            }
+        }
+    }
+
+    let func = lua.create_function(|lua, (rows, options): (Table, Option<Table>)| {
+        let options: Options = from_options(lua, options)?;
+
+        let mut cells: Vec<Vec<String>> = vec![];
+        for row in rows.sequence_values::<Table>() {
+            let row = row?;
+            let mut values = vec![];
+            for i in 1..=row.raw_len() {
+                let value = match row.raw_get::<_, Value>(i)? {
+                    Value::Nil => String::new(),
+                    v => lua
+                        .coerce_string(v.clone())?
+                        .ok_or_else(|| {
+                            LuaError::RuntimeError(format!(
+                                "invalid cell: {}",
+                                v.type_name()
+                            ))
+                        })?
+                        .to_str()?
+                        .to_string(),
+                };
+                values.push(value);
+            }
+            cells.push(values);
+        }
+
+        let ncols = cells.iter().map(|r| r.len()).max().unwrap_or(0);
+        let column = |i: usize| options.columns.get(i).cloned().unwrap_or_default();
+
+        for row in cells.iter_mut() {
+            row.resize(ncols, String::new());
+            for (i, cell) in row.iter_mut().enumerate() {
+                if let Some(max) = column(i).max_width {
+                    *cell = truncate_to_width(cell, max, "…", false);
+                }
+            }
+        }
+
+        let widths: Vec<usize> = (0..ncols)
+            .map(|i| cells.iter().map(|r| r[i].width()).max().unwrap_or(0))
+            .collect();
+
+        let lines: Vec<String> = cells
+            .into_iter()
+            .map(|mut row| {
+                // The empty cells at the end would only add spaces.
+                let last = row.iter().rposition(|c| !c.is_empty()).unwrap_or(0);
+                row.truncate(last + 1);
+                row.into_iter()
+                    .enumerate()
+                    .map(|(i, cell)| {
+                        let pad = " ".repeat(widths[i] - cell.width());
+                        match column(i).align {
+                            Align::Right => format!("{}{}", pad, cell),
+                            Align::Left if i == last => cell,
+                            Align::Left => format!("{}{}", cell, pad),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(&options.separator)
+            })
+            .collect();
+
+        Ok(lines)
+    })?;
+    util.set("tabulate", func)?;
+    Ok(util)
+}
+
+/// Report the progress of a long operation, to show it in the title of the
+/// logs panel, e.g. "copying 42%". The fraction is between 0 and 1, or nil if
+/// it's unknown. The progress is cleared automatically if it's not updated
+/// within 5 seconds.
+///
+/// Note that xplr can't redraw the screen while a Lua function is running, so
+/// the progress is shown once the control comes back to xplr, e.g. while
+/// waiting for a command running in the background.
+///
+/// Type: function( label:string, fraction:number|nil )
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.set_progress("copying", 0.42)
+/// xplr.util.set_progress("searching")
+/// ```
+pub fn set_progress<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, (label, fraction): (String, Option<f64>)| {
+        progress::set(label, fraction);
+        Ok(())
+    })?;
+    util.set("set_progress", func)?;
+    Ok(util)
+}
+
+/// Clear the progress reported with [xplr.util.set_progress][1].
+///
+/// Type: function()
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.clear_progress()
+/// ```
+///
+/// [1]: #xplrutilset_progress
+pub fn clear_progress<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, ()| {
+        progress::clear();
+        Ok(())
+    })?;
+    util.set("clear_progress", func)?;
+    Ok(util)
+}
+
+/// Get the editor to use, from `$VISUAL` or `$EDITOR`, falling back to `vim`,
+/// `vi` or `nano`. The first one found in `$PATH` is returned as a
+/// [Command][1], with the arguments in the variable split like a shell would,
+/// e.g. `EDITOR="code -w"` gives `{ command = "code", args = { "-w" } }`.
+///
+/// Returns nil if none is found.
+///
+/// Type: function() -> [Command][1]|nil
+///
+/// Example:
+///
+/// ```lua
+/// local editor = xplr.util.editor()
+/// -- { command = "code", args = { "-w" } }
+///
+/// table.insert(editor.args, app.focused_node.absolute_path)
+/// return { { Call = editor } }
+/// ```
+///
+/// [1]: https://xplr.dev/en/messages#call
+pub fn editor<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, ()| {
+        let cmd = resolve_program(
+            |var| env::var(var).ok(),
+            &["VISUAL", "EDITOR"],
+            &["vim", "vi", "nano"],
+        );
+        lua::serialize(lua, &cmd).map_err(LuaError::custom)
+    })?;
+    util.set("editor", func)?;
+    Ok(util)
+}
+
+/// Get the pager to use, from `$PAGER`, falling back to `less` or `more`,
+/// the same way as [xplr.util.editor][1].
+///
+/// Returns nil if none is found.
+///
+/// Type: function() -> [Command][2]|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.pager()
+/// -- { command = "less", args = { "-R" } }
+/// ```
+///
+/// [1]: #xplrutileditor
+/// [2]: https://xplr.dev/en/messages#call
+pub fn pager<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, ()| {
+        let cmd =
+            resolve_program(|var| env::var(var).ok(), &["PAGER"], &["less", "more"]);
+        lua::serialize(lua, &cmd).map_err(LuaError::custom)
+    })?;
+    util.set("pager", func)?;
+    Ok(util)
+}
+
+// The first of the commands in the variables, or of the fallback programs,
+// that can be found in `$PATH`.
+fn resolve_program(
+    getenv: impl Fn(&str) -> Option<String>,
+    vars: &[&str],
+    fallbacks: &[&str],
+) -> Option<app::Command> {
+    let from_vars = vars.iter().filter_map(|var| {
+        let mut words = shell_words::split(&getenv(var)?).ok()?.into_iter();
+        let command = words.next()?;
+        Some(app::Command {
+            command,
+            args: words.collect(),
+        })
+    });
+
+    let from_fallbacks = fallbacks.iter().map(|program| app::Command {
+        command: program.to_string(),
+        args: vec![],
+    });
+
+    from_vars
+        .chain(from_fallbacks)
+        .find(|cmd| which::which(&cmd.command).is_ok())
+}
+
+/// Move the given files or directories to the trash of the platform, instead
+/// of deleting them permanently. The directories are moved with their
+/// contents.
+///
+/// Returns a list of `{ path, ok, error }`, one for each of the given paths,
+/// in the same order. If the trash isn't supported, e.g. when xplr is built
+/// without the trash feature, an error is returned for every path, and
+/// nothing is deleted.
+///
+/// Type: function( paths:string[] ) -> { { path:string, ok:boolean, error:string|nil }... }
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.trash({ "/tmp/foo", "/tmp/nothing" })
+/// -- {
+/// --   { path = "/tmp/foo", ok = true },
+/// --   { path = "/tmp/nothing", ok = false, error = "..." },
+/// -- }
+/// ```
+pub fn trash<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Serialize)]
+    struct Trashed {
+        path: String,
+        ok: bool,
+        error: Option<String>,
+    }
+
+    let func = lua.create_function(|lua, paths: Vec<String>| {
+        let res: Vec<Trashed> = paths
+            .into_iter()
+            .map(|path| {
+                #[cfg(feature = "trash")]
+                let error = trash::delete(&path).err().map(|e| e.to_string());
+
+                #[cfg(not(feature = "trash"))]
+                let error = Some("xplr was built without the trash feature".to_string());
+
+                Trashed {
+                    path,
+                    ok: error.is_none(),
+                    error,
+                }
+            })
+            .collect();
+        lua::serialize(lua, &res).map_err(LuaError::custom)
+    })?;
+    util.set("trash", func)?;
+    Ok(util)
+}
+
+/// Store a value for the rest of the session, so that it can be shared
+/// between message handlers and plugins without using globals. The value is
+/// serialized, so it's copied, and can't contain functions or userdata.
+/// Storing nil deletes the key.
+///
+/// The values are kept in memory only, in the app state, and are lost when
+/// xplr exits.
+///
+/// Type: function( key:string, value:any ) -> nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.store_set("my_plugin", { last_visited = { "/tmp", "/etc" } })
+/// ```
+pub fn store_set<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, (key, value): (String, Value)| {
+        if value.is_nil() {
+            store::delete(&key);
+        } else {
+            let value: serde_json::Value = lua.from_value(value)?;
+            store::set(key, value);
+        }
+        Ok(())
+    })?;
+    util.set("store_set", func)?;
+    Ok(util)
+}
+
+/// Get a copy of the value stored with [xplr.util.store_set][1], or nil if
+/// the key doesn't exist.
+///
+/// Type: function( key:string ) -> any
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.store_get("my_plugin")
+/// -- { last_visited = { "/tmp", "/etc" } }
+///
+/// xplr.util.store_get("no_such_key")
+/// -- nil
+/// ```
+///
+/// [1]: #xplrutilstore_set
+pub fn store_get<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, key: String| {
+        lua::serialize(lua, &store::get(&key)).map_err(LuaError::custom)
+    })?;
+    util.set("store_get", func)?;
+    Ok(util)
+}
+
+/// Delete the value stored with [xplr.util.store_set][1]. Returns true if the
+/// key existed.
+///
+/// Type: function( key:string ) -> boolean
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.store_delete("my_plugin")
+/// -- true
+/// ```
+///
+/// [1]: #xplrutilstore_set
+pub fn store_delete<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|_, key: String| Ok(store::delete(&key).is_some()))?;
+    util.set("store_delete", func)?;
+    Ok(util)
+}
+
+/// Like [xplr.util.store_set][1], but the value is saved to a JSON file in the
+/// user's data directory (e.g. `~/.local/share/xplr/store.json`), so that it
+/// survives restarts. Useful for bookmarks and preferences. Storing nil
+/// deletes the key.
+///
+/// The file is shared by all the xplr instances. It's locked and read again
+/// before every write so that the keys set by the other instances aren't
+/// lost, and written atomically so that it's never left half written.
+///
+/// Returns nil on success, or the error message on failure, e.g. when there's
+/// no data directory.
+///
+/// Type: function( key:string, value:any ) -> error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.persist_set("bookmarks", { "/etc", "/tmp" })
+/// -- nil
+/// ```
+///
+/// [1]: #xplrutilstore_set
+pub fn persist_set<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, (key, value): (String, Value)| {
+        let value: Option<serde_json::Value> = if value.is_nil() {
+            None
+        } else {
+            Some(lua.from_value(value)?)
+        };
+        Ok(store::persistent_set(key, value)
+            .err()
+            .map(|e| e.to_string()))
+    })?;
+    util.set("persist_set", func)?;
+    Ok(util)
+}
+
+/// Get a copy of the value saved with [xplr.util.persist_set][1], or nil if
+/// the key doesn't exist. The file is read on first use only.
+///
+/// Type: function( key:string ) -> any, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.persist_get("bookmarks")
+/// -- { "/etc", "/tmp" }
+/// ```
+///
+/// [1]: #xplrutilpersist_set
+pub fn persist_get<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, key: String| match store::persistent_get(&key) {
+            Ok(value) => {
+                Ok((lua::serialize(lua, &value).map_err(LuaError::custom)?, None))
+            }
+            Err(e) => Ok((Value::Nil, Some(e.to_string()))),
+        })?;
+    util.set("persist_get", func)?;
+    Ok(util)
+}
+
+/// List the keys saved with [xplr.util.persist_set][1], in sorted order.
+///
+/// Type: function() -> { key:string... }|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.persist_keys()
+/// -- { "bookmarks", "theme" }
+/// ```
+///
+/// [1]: #xplrutilpersist_set
+pub fn persist_keys<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, ()| match store::persistent_keys() {
+        Ok(keys) => Ok((Some(keys), None)),
+        Err(e) => Ok((None, Some(e.to_string()))),
+    })?;
+    util.set("persist_keys", func)?;
+    Ok(util)
+}
+
+/// Highlight the syntax of the given text, or file, for previewing it in the
+/// terminal. Returns the text with the 24-bit ANSI color codes, or unchanged
+/// if the language is unknown.
+///
+/// Options:
+///
+/// - file: The first argument is the path of the file to highlight.
+/// - language: The name (e.g. "Rust") or the file extension (e.g. "rs") of
+///   the language. By default, it's detected from the file name, or from the
+///   first line (e.g. a shebang).
+/// - theme: One of "base16-ocean.dark" (default), "base16-eighties.dark",
+///   "base16-mocha.dark", "base16-ocean.light", "InspiredGitHub",
+///   "Solarized (dark)" and "Solarized (light)". Raises an error if the
+///   theme doesn't exist.
+///
+/// The syntaxes and the themes are loaded once, on first use. Returns nil
+/// and the error message if the file can't be read, or if xplr was built
+/// without the highlight feature.
+///
+/// Type: function( text_or_path:string, options:table|nil ) -> highlighted:string|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.highlight('fn main() {}', { language = "rs" })
+/// -- "\x1b[38;2;180;142;173mfn\x1b[38;2;192;197;206m ..."
+///
+/// xplr.util.highlight("/etc/hosts", { file = true, theme = "InspiredGitHub" })
+/// -- "127.0.0.1 localhost\n"
+/// ```
+pub fn highlight<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        file: bool,
+        language: Option<String>,
+        theme: String,
+    }
+
+    impl Default for Options {
+        fn default() -> Self {
+            Self {
+                file: false,
+                language: None,
+                theme: "base16-ocean.dark".into(),
+            }
+        }
+    }
+
+    let func =
+        lua.create_function(|lua, (text_or_path, options): (String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let (text, path) = if options.file {
+                match fs::read(&text_or_path) {
+                    Ok(bytes) => (
+                        String::from_utf8_lossy(&bytes).to_string(),
+                        Some(PathBuf::from(&text_or_path)),
+                    ),
+                    Err(e) => return Ok((None, Some(format!("{text_or_path}: {e}")))),
+                }
+            } else {
+                (text_or_path, None)
+            };
+
+            match highlight_text(
+                &text,
+                options.language.as_deref(),
+                path.as_deref(),
+                &options.theme,
+            ) {
+                Ok(highlighted) => Ok((Some(highlighted), None)),
+                Err(HighlightError::NoSuchTheme) => Err(LuaError::RuntimeError(
+                    format!("no such theme: {}", options.theme),
+                )),
+                Err(HighlightError::Other(e)) => Ok((None, Some(e))),
+            }
+        })?;
+    util.set("highlight", func)?;
+    Ok(util)
+}
+
+#[derive(Debug)]
+enum HighlightError {
+    #[cfg_attr(not(feature = "highlight"), allow(dead_code))]
+    NoSuchTheme,
+    Other(String),
+}
+
+#[cfg(feature = "highlight")]
+lazy_static! {
+    static ref SYNTAXES: syntect::parsing::SyntaxSet =
+        syntect::parsing::SyntaxSet::load_defaults_newlines();
+    static ref THEMES: syntect::highlighting::ThemeSet =
+        syntect::highlighting::ThemeSet::load_defaults();
+}
+
+#[cfg(feature = "highlight")]
+fn highlight_text(
+    text: &str,
+    language: Option<&str>,
+    path: Option<&Path>,
+    theme: &str,
+) -> std::result::Result<String, HighlightError> {
+    use syntect::easy::HighlightLines;
+    use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+    let theme = THEMES
+        .themes
+        .get(theme)
+        .ok_or(HighlightError::NoSuchTheme)?;
+
+    let by_file_name = || {
+        let path = path?;
+        let name = path.file_name()?.to_string_lossy();
+        let ext = path.extension().map(|e| e.to_string_lossy());
+        SYNTAXES
+            .find_syntax_by_extension(&name)
+            .or_else(|| ext.and_then(|e| SYNTAXES.find_syntax_by_extension(&e)))
+    };
+    let syntax = match language {
+        Some(language) => SYNTAXES.find_syntax_by_token(language),
+        None => by_file_name().or_else(|| {
+            SYNTAXES.find_syntax_by_first_line(text.lines().next().unwrap_or_default())
+        }),
+    };
+    let Some(syntax) = syntax else {
+        return Ok(text.to_string());
+    };
+
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut highlighted = String::with_capacity(text.len() * 2);
+    for line in LinesWithEndings::from(text) {
+        let ranges = highlighter
+            .highlight_line(line, &SYNTAXES)
+            .map_err(|e| HighlightError::Other(e.to_string()))?;
+        highlighted.push_str(&as_24_bit_terminal_escaped(&ranges, false));
+    }
+    if !highlighted.is_empty() {
+        highlighted.push_str("\x1b[0m");
+    }
+    Ok(highlighted)
+}
+
+#[cfg(not(feature = "highlight"))]
+fn highlight_text(
+    _: &str,
+    _: Option<&str>,
+    _: Option<&Path>,
+    _: &str,
+) -> std::result::Result<String, HighlightError> {
+    Err(HighlightError::Other(
+        "xplr was built without the highlight feature".into(),
+    ))
+}
+
+/// Render the given image with the [Kitty graphics protocol][1], for
+/// previewing it in the capable terminals. Returns the escape sequence that
+/// displays the image at the cursor position when printed.
+///
+/// Options:
+///
+/// - cols: Fit the image in this many columns.
+/// - rows: Fit the image in this many rows.
+/// - cell_width: The width of a terminal cell in pixels (default: 10).
+/// - cell_height: The height of a terminal cell in pixels (default: 20).
+///
+/// The image is scaled down, keeping its aspect ratio, to fit in the given
+/// number of cells. It's never scaled up.
+///
+/// Supports the PNG, JPEG, GIF, BMP and WebP formats. Returns nil and the
+/// error message if the image can't be read, or if xplr was built without
+/// the image feature.
+///
+/// Type: function( path:string, options:table|nil ) -> escape:string|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// local image, err = xplr.util.image_kitty("/tmp/cat.png", { cols = 40, rows = 20 })
+/// if image then
+///   io.write(image)
+/// end
+/// ```
+///
+/// [1]: https://sw.kovidgoyal.net/kitty/graphics-protocol/
+pub fn image_kitty<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: ImageOptions = from_options(lua, options)?;
+            match render_kitty(Path::new(&path), &options) {
+                Ok(escape) => Ok((Some(escape), None)),
+                Err(e) => Ok((None, Some(format!("{path}: {e}")))),
+            }
+        })?;
+    util.set("image_kitty", func)?;
+    Ok(util)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct ImageOptions {
+    cols: Option<u32>,
+    rows: Option<u32>,
+    cell_width: u32,
+    cell_height: u32,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            cols: None,
+            rows: None,
+            cell_width: 10,
+            cell_height: 20,
+        }
+    }
+}
+
+#[cfg(feature = "image")]
+impl ImageOptions {
+    // The number of cells needed to display the given number of pixels.
+    fn cells(&self, width: u32, height: u32) -> (u32, u32) {
+        (
+            width.div_ceil(self.cell_width.max(1)),
+            height.div_ceil(self.cell_height.max(1)),
+        )
+    }
+}
+
+#[cfg(feature = "image")]
+fn load_image(
+    path: &Path,
+    options: &ImageOptions,
+) -> image::ImageResult<image::DynamicImage> {
+    let image = image::io::Reader::open(path)?
+        .with_guessed_format()?
+        .decode()?;
+
+    let max_width = options
+        .cols
+        .map(|c| c.saturating_mul(options.cell_width).max(1))
+        .unwrap_or(u32::MAX);
+    let max_height = options
+        .rows
+        .map(|r| r.saturating_mul(options.cell_height).max(1))
+        .unwrap_or(u32::MAX);
+
+    if image.width() > max_width || image.height() > max_height {
+        Ok(image.resize(max_width, max_height, image::imageops::FilterType::Triangle))
+    } else {
+        Ok(image)
+    }
+}
+
+#[cfg(feature = "image")]
+fn render_kitty(path: &Path, options: &ImageOptions) -> image::ImageResult<String> {
+    let image = load_image(path, options)?;
+    let mut png = vec![];
+    image.write_to(
+        &mut io::Cursor::new(&mut png),
+        image::ImageOutputFormat::Png,
+    )?;
+    let (cols, rows) = options.cells(image.width(), image.height());
+    Ok(kitty_escape(&png, cols, rows))
+}
+
+#[cfg(not(feature = "image"))]
+fn render_kitty(_: &Path, _: &ImageOptions) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "xplr was built without the image feature",
+    ))
+}
+
+// The PNG data is sent in chunks of 4096 bytes of base64, as required by the
+// protocol. The terminal is asked not to respond, as the response would be
+// read as the user input.
+#[cfg(feature = "image")]
+fn kitty_escape(png: &[u8], cols: u32, rows: u32) -> String {
+    const CHUNK_SIZE: usize = 4096;
+
+    let payload = base64::encode(png);
+    let chunks: Vec<&[u8]> = payload.as_bytes().chunks(CHUNK_SIZE).collect();
+    let mut escape = String::with_capacity(payload.len() + chunks.len() * 16);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+        let chunk = String::from_utf8_lossy(chunk);
+        if i == 0 {
+            escape.push_str(&format!(
+                "\x1b_Ga=T,f=100,q=2,c={cols},r={rows},m={more};{chunk}\x1b\\"
+            ));
+        } else {
+            escape.push_str(&format!("\x1b_Gm={more};{chunk}\x1b\\"));
+        }
+    }
+    escape
+}
+
+/// Render the given image as [Sixel][1] graphics, for previewing it in the
+/// terminals that support Sixel but not the [Kitty graphics protocol][2].
+/// Returns the escape sequence that displays the image at the cursor
+/// position when printed.
+///
+/// The options are the same as [xplr.util.image_kitty][2]. Large images are
+/// also scaled down to at most 1000x1000 pixels, and the colors are reduced
+/// to a palette of 216 colors, to keep the output reasonably small.
+/// Transparent pixels are left blank.
+///
+/// Returns nil and the error message if the image can't be read, or if xplr
+/// was built without the image feature.
+///
+/// Type: function( path:string, options:table|nil ) -> escape:string|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// local image, err = xplr.util.image_sixel("/tmp/cat.png", { cols = 40, rows = 20 })
+/// if image then
+///   io.write(image)
+/// end
+/// ```
+///
+/// [1]: https://en.wikipedia.org/wiki/Sixel
+/// [2]: #xplrutilimage_kitty
+pub fn image_sixel<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, (path, options): (String, Option<Table>)| {
+            let options: ImageOptions = from_options(lua, options)?;
+            match render_sixel(Path::new(&path), &options) {
+                Ok(escape) => Ok((Some(escape), None)),
+                Err(e) => Ok((None, Some(format!("{path}: {e}")))),
+            }
+        })?;
+    util.set("image_sixel", func)?;
+    Ok(util)
+}
+
+#[cfg(feature = "image")]
+fn render_sixel(path: &Path, options: &ImageOptions) -> image::ImageResult<String> {
+    const MAX_SIZE: u32 = 1000;
+
+    let mut image = load_image(path, options)?;
+    if image.width() > MAX_SIZE || image.height() > MAX_SIZE {
+        image = image.resize(MAX_SIZE, MAX_SIZE, image::imageops::FilterType::Triangle);
+    }
+    Ok(sixel_encode(&image.to_rgba8()))
+}
+
+#[cfg(not(feature = "image"))]
+fn render_sixel(_: &Path, _: &ImageOptions) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "xplr was built without the image feature",
+    ))
+}
+
+// Each pixel is mapped to the closest color of a 6x6x6 cube, so that no
+// quantization pass over the image is needed.
+#[cfg(feature = "image")]
+fn sixel_encode(image: &image::RgbaImage) -> String {
+    use std::fmt::Write;
+
+    let (width, height) = image.dimensions();
+    let color = |x: u32, y: u32| -> Option<usize> {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let level = |v: u8| (v as usize * 5 + 127) / 255;
+        (a >= 128).then(|| level(r) * 36 + level(g) * 6 + level(b))
+    };
+
+    let mut escape = format!("\x1bP0;1q\"1;1;{width};{height}");
+
+    let mut used = [false; 216];
+    for y in 0..height {
+        for x in 0..width {
+            if let Some(c) = color(x, y) {
+                used[c] = true;
+            }
+        }
+    }
+    for (c, _) in used.iter().enumerate().filter(|(_, used)| **used) {
+        let percent = |level: usize| level * 100 / 5;
+        write!(
+            escape,
+            "#{c};2;{};{};{}",
+            percent(c / 36),
+            percent(c / 6 % 6),
+            percent(c % 6)
+        )
+        .unwrap_or_default();
+    }
+
+    for band in (0..height).step_by(6) {
+        let rows = band..(band + 6).min(height);
+
+        // The sixels of every color in the band, each a bitmask of the six
+        // rows.
+        let mut sixels: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+        for x in 0..width {
+            for (bit, y) in rows.clone().enumerate() {
+                if let Some(c) = color(x, y) {
+                    sixels.entry(c).or_insert_with(|| vec![0; width as usize])
+                        [x as usize] |= 1 << bit;
+                }
+            }
+        }
+
+        if band > 0 {
+            escape.push('-');
+        }
+        for (i, (c, sixels)) in sixels.iter().enumerate() {
+            if i > 0 {
+                escape.push('$');
+            }
+            write!(escape, "#{c}").unwrap_or_default();
+
+            let len = sixels.iter().rposition(|s| *s != 0).map_or(0, |l| l + 1);
+            let mut x = 0;
+            while x < len {
+                let sixel = sixels[x];
+                let run = sixels[x..len].iter().take_while(|s| **s == sixel).count();
+                let char = (63 + sixel) as char;
+                if run > 3 {
+                    write!(escape, "!{run}{char}").unwrap_or_default();
+                } else {
+                    escape.extend(std::iter::repeat_n(char, run));
+                }
+                x += run;
+            }
+        }
+    }
+
+    escape.push_str("\x1b\\");
+    escape
+}
+
+/// Guess the graphics protocol supported by the terminal, from the
+/// environment variables set by the terminal, so that the image previews can
+/// use [xplr.util.image_kitty][1] or [xplr.util.image_sixel][2] accordingly.
+///
+/// Returns "kitty", "iterm", "sixel" or nil if unknown. The terminal is not
+/// queried, so the protocol may not be detected inside tmux or over SSH.
+///
+/// Type: function() -> "kitty"|"iterm"|"sixel"|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.detect_graphics()
+/// -- "kitty"
+/// ```
+///
+/// [1]: #xplrutilimage_kitty
+/// [2]: #xplrutilimage_sixel
+pub fn detect_graphics<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|_, ()| Ok(graphics_protocol(|var| env::var(var).ok())))?;
+    util.set("detect_graphics", func)?;
+    Ok(util)
+}
+
+fn graphics_protocol(getenv: impl Fn(&str) -> Option<String>) -> Option<&'static str> {
+    let term = getenv("TERM").unwrap_or_default();
+    let term_program = getenv("TERM_PROGRAM").unwrap_or_default();
+
+    if term == "xterm-kitty"
+        || term == "xterm-ghostty"
+        || getenv("KITTY_WINDOW_ID").is_some()
+        || term_program == "ghostty"
+    {
+        Some("kitty")
+    } else if term_program == "iTerm.app" || term_program == "WezTerm" {
+        Some("iterm")
+    } else if term.starts_with("foot")
+        || term.starts_with("mlterm")
+        || term.starts_with("contour")
+        || term.contains("sixel")
+        || getenv("KONSOLE_VERSION").is_some()
+    {
+        Some("sixel")
+    } else {
+        None
+    }
+}
+
+/// Render the given Markdown text, or file, for previewing it in the
+/// terminal. Returns the text styled with the ANSI escape codes: the headings
+/// are bold, the emphasis italic, the code colored, the list items bulleted
+/// or numbered, and the links followed by their dimmed URL. Tables are
+/// aligned, and HTML is left out.
+///
+/// Options:
+///
+/// - file: The first argument is the path of the file to render.
+/// - width: Wrap the paragraphs to this many columns. Code blocks and tables
+///   are not wrapped.
+///
+/// Returns nil and the error message if the file can't be read, or if xplr
+/// was built without the markdown feature.
+///
+/// Type: function( text_or_path:string, options:table|nil ) -> rendered:string|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.render_markdown("# Title\n\nSome `code`.")
+/// -- "\x1b[1;4mTitle\x1b[0m\n\nSome \x1b[36mcode\x1b[0m.\n"
+///
+/// xplr.util.render_markdown("README.md", { file = true, width = 80 })
+/// ```
+pub fn render_markdown<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        file: bool,
+        width: Option<usize>,
+    }
+
+    let func =
+        lua.create_function(|lua, (text_or_path, options): (String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let text = if options.file {
+                match fs::read(&text_or_path) {
+                    Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+                    Err(e) => return Ok((None, Some(format!("{text_or_path}: {e}")))),
+                }
+            } else {
+                text_or_path
+            };
+
+            match markdown_to_ansi(&text, options.width) {
+                Ok(rendered) => Ok((Some(rendered), None)),
+                Err(e) => Ok((None, Some(e.to_string()))),
+            }
+        })?;
+    util.set("render_markdown", func)?;
+    Ok(util)
+}
+
+#[cfg(feature = "markdown")]
+fn markdown_to_ansi(text: &str, width: Option<usize>) -> io::Result<String> {
+    use pulldown_cmark::{Options, Parser};
+
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+
+    let mut renderer = MarkdownRenderer {
+        width,
+        ..Default::default()
+    };
+    for event in Parser::new_ext(text, options) {
+        renderer.event(event);
+    }
+    Ok(renderer.finish())
+}
+
+#[cfg(not(feature = "markdown"))]
+fn markdown_to_ansi(_: &str, _: Option<usize>) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "xplr was built without the markdown feature",
+    ))
+}
+
+#[cfg(feature = "markdown")]
+#[derive(Debug, Default)]
+struct MarkdownTable {
+    alignments: Vec<pulldown_cmark::Alignment>,
+    rows: Vec<Vec<String>>,
+    row: Vec<String>,
+    cell: String,
+}
+
+#[cfg(feature = "markdown")]
+#[derive(Debug, Default)]
+struct MarkdownRenderer {
+    width: Option<usize>,
+    lines: Vec<String>,
+    line: Option<String>,
+    line_width: usize,
+    space: bool,
+    // Separate the next block with a blank line.
+    blank: bool,
+    // The SGR parameters of the nested inline styles.
+    styles: Vec<&'static str>,
+    // The prefixes of every line in the nested blocks, with their widths.
+    indents: Vec<(String, usize)>,
+    // Replaces the indent of a list item on its first line.
+    marker: Option<(usize, String)>,
+    // The next number of the nested lists, if ordered.
+    lists: Vec<Option<u64>>,
+    // The URLs and the texts of the nested links.
+    links: Vec<(String, String)>,
+    table: Option<MarkdownTable>,
+    code_block: bool,
+}
+
+#[cfg(feature = "markdown")]
+impl MarkdownRenderer {
+    fn event(&mut self, event: pulldown_cmark::Event) {
+        use pulldown_cmark::{Event, HeadingLevel, Tag};
+
+        match event {
+            Event::Start(tag) => match tag {
+                Tag::Paragraph => self.start_block(),
+                Tag::Heading(level, _, _) => {
+                    self.start_block();
+                    self.styles.push("1");
+                    if level == HeadingLevel::H1 {
+                        self.styles.push("4");
+                    }
+                }
+                Tag::BlockQuote => {
+                    self.start_block();
+                    self.indents.push(("\x1b[2m│\x1b[0m ".into(), 2));
+                }
+                Tag::CodeBlock(_) => {
+                    self.start_block();
+                    self.indents.push(("  ".into(), 2));
+                    self.code_block = true;
+                }
+                Tag::List(first) => {
+                    if self.lists.is_empty() {
+                        self.start_block();
+                    } else {
+                        self.break_line();
+                    }
+                    self.lists.push(first);
+                }
+                Tag::Item => {
+                    self.break_line();
+                    let marker = match self.lists.last_mut() {
+                        Some(Some(number)) => {
+                            *number += 1;
+                            format!("{}. ", *number - 1)
+                        }
+                        _ => "• ".to_string(),
+                    };
+                    let width = marker.width();
+                    self.marker = Some((self.indents.len(), marker));
+                    self.indents.push((" ".repeat(width), width));
+                }
+                Tag::FootnoteDefinition(name) => {
+                    self.start_block();
+                    self.word(&format!("[^{name}]:"));
+                    self.space = true;
+                }
+                Tag::Table(alignments) => {
+                    self.start_block();
+                    self.table = Some(MarkdownTable {
+                        alignments,
+                        ..Default::default()
+                    });
+                }
+                Tag::TableHead | Tag::TableRow => {}
+                Tag::TableCell => {
+                    if let Some(table) = self.table.as_mut() {
+                        table.cell.clear();
+                    }
+                }
+                Tag::Emphasis => self.styles.push("3"),
+                Tag::Strong => self.styles.push("1"),
+                Tag::Strikethrough => self.styles.push("9"),
+                Tag::Link(_, url, _) | Tag::Image(_, url, _) => {
+                    self.links.push((url.to_string(), String::new()));
+                    self.styles.push("4");
+                }
+            },
+
+            Event::End(tag) => match tag {
+                Tag::Paragraph | Tag::FootnoteDefinition(_) => self.end_block(),
+                Tag::Heading(level, _, _) => {
+                    self.styles.pop();
+                    if level == HeadingLevel::H1 {
+                        self.styles.pop();
+                    }
+                    self.end_block();
+                }
+                Tag::BlockQuote => {
+                    self.break_line();
+                    self.indents.pop();
+                    self.blank = true;
+                }
+                Tag::CodeBlock(_) => {
+                    self.break_line();
+                    self.indents.pop();
+                    self.code_block = false;
+                    self.blank = true;
+                }
+                Tag::List(_) => {
+                    self.break_line();
+                    self.lists.pop();
+                    if self.lists.is_empty() {
+                        self.blank = true;
+                    }
+                }
+                Tag::Item => {
+                    if self.marker.is_some() {
+                        self.start_line();
+                    }
+                    self.break_line();
+                    self.indents.pop();
+                }
+                Tag::TableCell => {
+                    if let Some(table) = self.table.as_mut() {
+                        let cell = std::mem::take(&mut table.cell);
+                        table.row.push(cell.trim().to_string());
+                    }
+                }
+                Tag::TableHead | Tag::TableRow => {
+                    if let Some(table) = self.table.as_mut() {
+                        let row = std::mem::take(&mut table.row);
+                        table.rows.push(row);
+                    }
+                }
+                Tag::Table(_) => {
+                    if let Some(table) = self.table.take() {
+                        self.render_table(table);
+                    }
+                    self.end_block();
+                }
+                Tag::Emphasis | Tag::Strong | Tag::Strikethrough => {
+                    self.styles.pop();
+                }
+                Tag::Link(_, _, _) | Tag::Image(_, _, _) => {
+                    self.styles.pop();
+                    if let Some((url, text)) = self.links.pop() {
+                        if !url.is_empty() && url != text {
+                            self.space = true;
+                            let styles = std::mem::replace(&mut self.styles, vec!["2"]);
+                            self.word(&format!("({url})"));
+                            self.styles = styles;
+                        }
+                    }
+                }
+            },
+
+            Event::Text(text) => {
+                if let Some(table) = self.table.as_mut() {
+                    table.cell.push_str(&text);
+                } else if self.code_block {
+                    self.code_text(&text);
+                } else {
+                    self.text(&text);
+                }
+            }
+            Event::Code(code) => {
+                if let Some(table) = self.table.as_mut() {
+                    table.cell.push_str(&code);
+                } else {
+                    self.styles.push("36");
+                    self.text(&code);
+                    self.styles.pop();
+                }
+            }
+            Event::FootnoteReference(name) => self.text(&format!("[^{name}]")),
+            Event::TaskListMarker(checked) => {
+                self.word(if checked { "[x]" } else { "[ ]" });
+                self.space = true;
+            }
+            Event::SoftBreak => {
+                if let Some(table) = self.table.as_mut() {
+                    table.cell.push(' ');
+                } else {
+                    self.space = true;
+                }
+            }
+            Event::HardBreak => self.break_line(),
+            Event::Rule => {
+                self.start_block();
+                self.start_line();
+                let width = self.width.unwrap_or(40).saturating_sub(self.line_width);
+                self.push_styled(&"─".repeat(width.max(1)), "2", width);
+                self.end_block();
+            }
+            // HTML is not rendered.
+            Event::Html(_) => {}
+        }
+    }
+
+    fn start_block(&mut self) {
+        self.break_line();
+        if self.blank && !self.lines.is_empty() {
+            let prefix: String = self.indents.iter().map(|(p, _)| p.as_str()).collect();
+            self.lines.push(prefix.trim_end().to_string());
+        }
+        self.blank = false;
+    }
+
+    fn end_block(&mut self) {
+        self.break_line();
+        self.blank = true;
+    }
+
+    fn start_line(&mut self) {
+        let mut line = String::new();
+        let mut width = 0;
+        let marker = self.marker.take();
+        for (i, (prefix, prefix_width)) in self.indents.iter().enumerate() {
+            match marker.as_ref() {
+                Some((index, marker)) if *index == i => line.push_str(marker),
+                _ => line.push_str(prefix),
+            }
+            width += prefix_width;
+        }
+        self.line = Some(line);
+        self.line_width = width;
+        self.space = false;
+    }
+
+    fn break_line(&mut self) {
+        if let Some(line) = self.line.take() {
+            self.lines.push(line);
+        }
+        self.line_width = 0;
+        self.space = false;
+    }
+
+    fn push_styled(&mut self, text: &str, styles: &str, width: usize) {
+        let line = self.line.get_or_insert_with(String::new);
+        if styles.is_empty() {
+            line.push_str(text);
+        } else {
+            line.push_str(&format!("\x1b[{styles}m{text}\x1b[0m"));
+        }
+        self.line_width += width;
+    }
+
+    fn word(&mut self, word: &str) {
+        let width = word.width();
+        if self.line.is_none() {
+            self.start_line();
+        } else if self.space {
+            let indent: usize = self.indents.iter().map(|(_, w)| w).sum();
+            let overflows = self
+                .width
+                .map(|max| self.line_width + 1 + width > max)
+                .unwrap_or(false);
+            if overflows && self.line_width > indent {
+                self.break_line();
+                self.start_line();
+            } else {
+                self.push_styled(" ", "", 1);
+            }
+        }
+        self.push_styled(word, &self.styles.join(";"), width);
+        self.space = false;
+    }
+
+    fn text(&mut self, text: &str) {
+        if let Some((_, link_text)) = self.links.last_mut() {
+            link_text.push_str(text);
+        }
+        if text.starts_with(char::is_whitespace) {
+            self.space = true;
+        }
+        for (i, word) in text.split_whitespace().enumerate() {
+            if i > 0 {
+                self.space = true;
+            }
+            self.word(word);
+        }
+        if text.ends_with(char::is_whitespace) {
+            self.space = true;
+        }
+    }
+
+    fn code_text(&mut self, text: &str) {
+        let mut lines = text.split('\n').peekable();
+        while let Some(line) = lines.next() {
+            if self.line.is_none() && (!line.is_empty() || lines.peek().is_some()) {
+                self.start_line();
+            }
+            if !line.is_empty() {
+                self.push_styled(line, "36", line.width());
+            }
+            if lines.peek().is_some() {
+                self.break_line();
+            }
+        }
+    }
+
+    fn render_table(&mut self, table: MarkdownTable) {
+        use pulldown_cmark::Alignment;
+
+        let columns = table.rows.iter().map(Vec::len).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..columns)
+            .map(|c| {
+                table
+                    .rows
+                    .iter()
+                    .filter_map(|row| row.get(c))
+                    .map(|cell| cell.width())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        for (r, row) in table.rows.iter().enumerate() {
+            self.start_line();
+            for (c, width) in widths.iter().enumerate() {
+                if c > 0 {
+                    self.push_styled(" │ ", "2", 3);
+                }
+                let cell = row.get(c).map(String::as_str).unwrap_or_default();
+                let pad = width - cell.width();
+                let (left, right) = match table.alignments.get(c) {
+                    Some(Alignment::Right) => (pad, 0),
+                    Some(Alignment::Center) => (pad / 2, pad - pad / 2),
+                    _ => (0, pad),
+                };
+                let styles = if r == 0 { "1" } else { "" };
+                self.push_styled(&" ".repeat(left), "", left);
+                self.push_styled(
+                    cell,
+                    if cell.is_empty() { "" } else { styles },
+                    cell.width(),
+                );
+                if c + 1 < widths.len() {
+                    self.push_styled(&" ".repeat(right), "", right);
+                }
+            }
+            self.break_line();
+
+            if r == 0 {
+                self.start_line();
+                let rule = widths
+                    .iter()
+                    .map(|w| "─".repeat(*w))
+                    .collect::<Vec<_>>()
+                    .join("─┼─");
+                let width = rule.width();
+                self.push_styled(&rule, "2", width);
+                self.break_line();
+            }
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.break_line();
+        let mut rendered = self.lines.join("\n");
+        if !rendered.is_empty() {
+            rendered.push('\n');
+        }
+        rendered
+    }
+}
+
+/// Remove the ANSI escape sequences, e.g. the colors, from the given text, so
+/// that the output of a command can be displayed with a different style.
+/// Besides the SGR and the other CSI sequences, the OSC sequences (e.g. the
+/// hyperlinks) and the other escape sequences are removed too. Malformed or
+/// truncated sequences are removed up to where they break.
+///
+/// Type: function( text:string ) -> text:string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.ansi_strip("\x1b[01;34mdir\x1b[0m  \x1b[01;32mexec\x1b[0m")
+/// -- "dir  exec"
+/// ```
+pub fn ansi_strip<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, text: String| Ok(strip_ansi(&text)))?;
+    util.set("ansi_strip", func)?;
+    Ok(util)
+}
+
+/// Get the display width of the given text in the terminal, ignoring the
+/// ANSI escape sequences as [xplr.util.ansi_strip][1] does. The wide
+/// characters, e.g. CJK, take two columns.
+///
+/// Type: function( text:string ) -> width:number
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.ansi_width("\x1b[01;34mdir\x1b[0m  \x1b[01;32mexec\x1b[0m")
+/// -- 9
+/// ```
+///
+/// [1]: #xplrutilansi_strip
+pub fn ansi_width<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, text: String| Ok(strip_ansi(&text).width()))?;
+    util.set("ansi_width", func)?;
+    Ok(util)
+}
+
+fn strip_ansi(text: &str) -> String {
+    const ESC: char = '\x1b';
+    const CSI: char = '\u{9b}';
+
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let csi = match c {
+            CSI => true,
+            ESC if chars.peek() == Some(&'[') => {
+                chars.next();
+                true
+            }
+            ESC => false,
+            c => {
+                stripped.push(c);
+                continue;
+            }
+        };
+
+        if csi {
+            // The parameter and the intermediate bytes, then the final byte.
+            while let Some(&c) = chars.peek() {
+                match c {
+                    '\x20'..='\x3f' => {
+                        chars.next();
+                    }
+                    '\x40'..='\x7e' => {
+                        chars.next();
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+            continue;
+        }
+
+        match chars.peek() {
+            // OSC, DCS, SOS, PM and APC strings, terminated by ST or, for
+            // OSC, BEL.
+            Some(']' | 'P' | 'X' | '^' | '_') => {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == ESC {
+                        if chars.peek() == Some(&'\\') {
+                            chars.next();
+                        }
+                        break;
+                    }
+                }
+            }
+            Some('\x20'..='\x7e') => {
+                while let Some('\x20'..='\x2f') = chars.peek() {
+                    chars.next();
+                }
+                if let Some('\x30'..='\x7e') = chars.peek() {
+                    chars.next();
+                }
+            }
+            _ => {}
+        }
+    }
+    stripped
+}
+
+/// Create an empty temporary file in the system temp directory, e.g. for
+/// staging the edits, and return its path.
+///
+/// Options:
+///
+/// - prefix: The start of the file name (default: "xplr-").
+/// - suffix: The end of the file name, e.g. an extension (default: "").
+/// - keep: Keep the file after xplr exits (default: false).
+///
+/// As Lua can't remove the file once it's no longer used, it's kept for the
+/// rest of the session, and removed when xplr exits unless `keep = true`
+/// (remove it with [xplr.util.remove][1] if needed). It's not removed if
+/// xplr is killed.
+///
+/// Returns nil and the error message on failure.
+///
+/// Type: function( options:table|nil ) -> path:string|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.tempfile({ suffix = ".md" })
+/// -- "/tmp/xplr-dlb8S4.md"
+/// ```
+///
+/// [1]: #xplrutilremove
+pub fn tempfile<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, options: Option<Table>| {
+        let options: TempOptions = from_options(lua, options)?;
+        match temp::file(&options.prefix, &options.suffix, options.keep) {
+            Ok(path) => Ok((Some(path.to_string_lossy().to_string()), None)),
+            Err(e) => Ok((None, Some(e.to_string()))),
+        }
+    })?;
+    util.set("tempfile", func)?;
+    Ok(util)
+}
+
+/// Like [xplr.util.tempfile][1], but create an empty temporary directory.
+/// Unless `keep = true`, it's removed with its contents when xplr exits.
+///
+/// Type: function( options:table|nil ) -> path:string|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.tempdir({ prefix = "staging-" })
+/// -- "/tmp/staging-H1mzBa"
+/// ```
+///
+/// [1]: #xplrutiltempfile
+pub fn tempdir<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, options: Option<Table>| {
+        let options: TempOptions = from_options(lua, options)?;
+        match temp::dir(&options.prefix, &options.suffix, options.keep) {
+            Ok(path) => Ok((Some(path.to_string_lossy().to_string()), None)),
+            Err(e) => Ok((None, Some(e.to_string()))),
+        }
+    })?;
+    util.set("tempdir", func)?;
+    Ok(util)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct TempOptions {
+    prefix: String,
+    suffix: String,
+    keep: bool,
+}
+
+impl Default for TempOptions {
+    fn default() -> Self {
+        Self {
+            prefix: "xplr-".into(),
+            suffix: "".into(),
+            keep: false,
+        }
+    }
+}
+
+/// Check if the standard output, or the given stream, is a terminal, e.g. to
+/// fall back to plain text when it's piped.
+///
+/// Note that xplr itself draws on `/dev/tty`, so its standard output isn't a
+/// terminal when it's run as `cd "$(xplr --print-pwd-as-result)"`.
+///
+/// Options:
+///
+/// - stream: "stdout" (default), "stderr" or "stdin".
+///
+/// Type: function( options:table|nil ) -> boolean
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.is_tty()
+/// -- true
+///
+/// xplr.util.is_tty({ stream = "stdin" })
+/// -- false
+/// ```
+pub fn is_tty<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        stream: String,
+    }
+
+    impl Default for Options {
+        fn default() -> Self {
+            Self {
+                stream: "stdout".into(),
+            }
+        }
+    }
+
+    let func = lua.create_function(|lua, options: Option<Table>| {
+        use std::io::IsTerminal;
+
+        let options: Options = from_options(lua, options)?;
+        match options.stream.as_str() {
+            "stdout" => Ok(io::stdout().is_terminal()),
+            "stderr" => Ok(io::stderr().is_terminal()),
+            "stdin" => Ok(io::stdin().is_terminal()),
+            stream => Err(LuaError::RuntimeError(format!("invalid stream: {stream}"))),
+        }
+    })?;
+    util.set("is_tty", func)?;
+    Ok(util)
+}
+
+/// Get the size of the terminal xplr is running in, as `{ cols, rows }`, or
+/// nil if there's no terminal.
+///
+/// Type: function() -> { cols:number, rows:number }|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.terminal_size()
+/// -- { cols = 80, rows = 24 }
+/// ```
+pub fn terminal_size<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, ()| {
+        let size = query_terminal_size(crossterm::terminal::size);
+        lua::serialize(lua, &size).map_err(LuaError::custom)
+    })?;
+    util.set("terminal_size", func)?;
+    Ok(util)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+struct TerminalSize {
+    cols: u16,
+    rows: u16,
+}
+
+// Some platforms report a zero size instead of failing without a terminal.
+fn query_terminal_size(
+    size: impl FnOnce() -> io::Result<(u16, u16)>,
+) -> Option<TerminalSize> {
+    match size() {
+        Ok((cols, rows)) if cols > 0 && rows > 0 => Some(TerminalSize { cols, rows }),
+        _ => None,
+    }
+}
+
+/// Parse the given color into a `{ r, g, b }` table, with the components
+/// between 0 and 255. The color can be a hex string like "#ff8800" or "#f80",
+/// an "rgb(255, 136, 0)" string, one of the named [colors][1] like
+/// "LightRed", or a `{ r, g, b }` table.
+///
+/// The named colors are mapped to the default xterm palette. An invalid color
+/// raises an error, and so does every function taking a color.
+///
+/// Type: function( color:string|table ) -> { r:number, g:number, b:number }
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.color("#ff8800")
+/// -- { r = 255, g = 136, b = 0 }
+///
+/// xplr.util.color("rgb(0, 128, 255)")
+/// -- { r = 0, g = 128, b = 255 }
+///
+/// xplr.util.color("Red")
+/// -- { r = 205, g = 0, b = 0 }
+/// ```
+///
+/// [1]: https://xplr.dev/en/style#color
+pub fn color<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, color: Value| {
+        let color = to_rgb(lua, color)?;
+        lua::serialize(lua, &color).map_err(LuaError::custom)
+    })?;
+    util.set("color", func)?;
+    Ok(util)
+}
+
+/// Mix two [colors][1], going from the first one when `t` is 0 to the second
+/// one when `t` is 1.
+///
+/// Type: function( a:string|table, b:string|table, t:number ) -> { r:number, g:number, b:number }
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.blend("#000000", "#ffffff", 0.5)
+/// -- { r = 128, g = 128, b = 128 }
+/// ```
+///
+/// [1]: #xplrutilcolor
+pub fn blend<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, (a, b, t): (Value, Value, f64)| {
+        let color = to_rgb(lua, a)?.blend(to_rgb(lua, b)?, t);
+        lua::serialize(lua, &color).map_err(LuaError::custom)
+    })?;
+    util.set("blend", func)?;
+    Ok(util)
+}
+
+/// Blend the [color][1] with white by the given amount, between 0 and 1.
+///
+/// Type: function( color:string|table, amount:number ) -> { r:number, g:number, b:number }
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.lighten("#ff0000", 0.5)
+/// -- { r = 255, g = 128, b = 128 }
+/// ```
+///
+/// [1]: #xplrutilcolor
+pub fn lighten<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, (color, amount): (Value, f64)| {
+        let color = to_rgb(lua, color)?.blend(Rgb::WHITE, amount);
+        lua::serialize(lua, &color).map_err(LuaError::custom)
+    })?;
+    util.set("lighten", func)?;
+    Ok(util)
+}
+
+/// Blend the [color][1] with black by the given amount, between 0 and 1.
+///
+/// Type: function( color:string|table, amount:number ) -> { r:number, g:number, b:number }
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.darken("#ff0000", 0.5)
+/// -- { r = 128, g = 0, b = 0 }
+/// ```
+///
+/// [1]: #xplrutilcolor
+pub fn darken<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, (color, amount): (Value, f64)| {
+        let color = to_rgb(lua, color)?.blend(Rgb::BLACK, amount);
+        lua::serialize(lua, &color).map_err(LuaError::custom)
+    })?;
+    util.set("darken", func)?;
+    Ok(util)
+}
+
+/// Convert the [color][1] to a "#rrggbb" hex string.
+///
+/// Type: function( color:string|table ) -> string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.to_hex({ r = 255, g = 136, b = 0 })
+/// -- "#ff8800"
+///
+/// xplr.util.to_hex(xplr.util.lighten("Blue", 0.2))
+/// -- "#3333f1"
+/// ```
+///
+/// [1]: #xplrutilcolor
+pub fn to_hex<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, color: Value| Ok(to_rgb(lua, color)?.hex()))?;
+    util.set("to_hex", func)?;
+    Ok(util)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Rgb {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+impl Rgb {
+    const BLACK: Self = Self::new(0, 0, 0);
+    const WHITE: Self = Self::new(255, 255, 255);
+
+    const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    fn parse(spec: &str) -> Option<Self> {
+        let spec = spec.trim();
+
+        if let Some(hex) = spec.strip_prefix('#') {
+            if !hex.is_ascii() {
+                return None;
+            }
+            let component = |i: usize, len: usize| {
+                let c = u8::from_str_radix(hex.get(i * len..(i + 1) * len)?, 16).ok()?;
+                Some(if len == 1 { c * 17 } else { c })
+            };
+            let len = match hex.len() {
+                3 => 1,
+                6 => 2,
+                _ => return None,
+            };
+            return Some(Self::new(
+                component(0, len)?,
+                component(1, len)?,
+                component(2, len)?,
+            ));
+        }
+
+        if let Some(args) = spec
+            .get(..4)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("rgb("))
+            .and_then(|_| spec[4..].strip_suffix(')'))
+        {
+            let components = args
+                .split(',')
+                .map(|c| c.trim().parse::<u8>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            return match components[..] {
+                [r, g, b] => Some(Self::new(r, g, b)),
+                _ => None,
+            };
+        }
+
+        // The default xterm palette.
+        let rgb = match spec.to_lowercase().as_str() {
+            "black" => Self::new(0, 0, 0),
+            "red" => Self::new(205, 0, 0),
+            "green" => Self::new(0, 205, 0),
+            "yellow" => Self::new(205, 205, 0),
+            "blue" => Self::new(0, 0, 238),
+            "magenta" => Self::new(205, 0, 205),
+            "cyan" => Self::new(0, 205, 205),
+            "gray" => Self::new(229, 229, 229),
+            "darkgray" => Self::new(127, 127, 127),
+            "lightred" => Self::new(255, 0, 0),
+            "lightgreen" => Self::new(0, 255, 0),
+            "lightyellow" => Self::new(255, 255, 0),
+            "lightblue" => Self::new(92, 92, 255),
+            "lightmagenta" => Self::new(255, 0, 255),
+            "lightcyan" => Self::new(0, 255, 255),
+            "white" => Self::new(255, 255, 255),
+            _ => return None,
+        };
+        Some(rgb)
+    }
+
+    fn blend(self, other: Self, t: f64) -> Self {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+        Self::new(
+            mix(self.r, other.r),
+            mix(self.g, other.g),
+            mix(self.b, other.b),
+        )
+    }
+
+    fn hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    // Like xterm and tmux: the nearest of the closest color cube entry and the
+    // closest gray.
+    fn ansi256(&self) -> u8 {
+        const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+        let cube_index = |c: u8| match c {
+            0..=47 => 0,
+            48..=114 => 1,
+            c => (c - 35) / 40,
+        };
+        let (r, g, b) = (cube_index(self.r), cube_index(self.g), cube_index(self.b));
+        let cube = Self::new(CUBE[r as usize], CUBE[g as usize], CUBE[b as usize]);
+        let cube_color = 16 + 36 * r + 6 * g + b;
+        if cube == *self {
+            return cube_color;
+        }
+
+        let average = (self.r as u16 + self.g as u16 + self.b as u16) / 3;
+        let gray_index = if average > 238 {
+            23
+        } else {
+            average.saturating_sub(3) as u8 / 10
+        };
+        let gray_level = 8 + 10 * gray_index;
+        let gray = Self::new(gray_level, gray_level, gray_level);
+
+        if self.distance(&gray) < self.distance(&cube) {
+            232 + gray_index
+        } else {
+            cube_color
+        }
+    }
+
+    fn distance(&self, other: &Self) -> u32 {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+        d(self.r, other.r) + d(self.g, other.g) + d(self.b, other.b)
+    }
+}
+
+// A color argument, either a spec string or a `{ r, g, b }` table.
+fn to_rgb(lua: &Lua, color: Value) -> mlua::Result<Rgb> {
+    let rgb = match &color {
+        Value::String(spec) => spec.to_str().ok().and_then(Rgb::parse),
+        Value::Table(_) => lua.from_value(color.clone()).ok(),
+        _ => None,
+    };
+    rgb.ok_or_else(|| {
+        let color = match color {
+            Value::String(spec) => spec.to_string_lossy().to_string(),
+            color => format!("{color:?}"),
+        };
+        LuaError::RuntimeError(format!("invalid color: {color}"))
+    })
+}
+
+/// Get the index of the xterm 256-color palette entry closest to the given
+/// [color][1], for the terminals without truecolor support. Only the 6x6x6
+/// color cube and the grayscale ramp, i.e. the indices from 16 to 255, are
+/// considered, since the first 16 colors depend on the terminal theme.
+///
+/// Type: function( color:string|table ) -> number
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.to_ansi256("#ff0000")
+/// -- 196
+///
+/// xplr.util.to_ansi256({ r = 128, g = 128, b = 128 })
+/// -- 244
+/// ```
+///
+/// [1]: #xplrutilcolor
+pub fn to_ansi256<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, color: Value| Ok(to_rgb(lua, color)?.ansi256()))?;
+    util.set("to_ansi256", func)?;
+    Ok(util)
+}
+
+/// Check if the terminal supports 24-bit colors, as advertised by the
+/// `COLORTERM` environment variable, or by a `TERM` like "xterm-direct".
+///
+/// Type: function() -> boolean
+///
+/// Example:
+///
+/// ```lua
+/// if xplr.util.supports_truecolor() then
+///   color = { Rgb = { 255, 136, 0 } }
+/// else
+///   color = { Indexed = xplr.util.to_ansi256("#ff8800") }
+/// end
+/// ```
+pub fn supports_truecolor<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|_, ()| Ok(truecolor_supported(|var| env::var(var).ok())))?;
+    util.set("supports_truecolor", func)?;
+    Ok(util)
+}
+
+fn truecolor_supported(getenv: impl Fn(&str) -> Option<String>) -> bool {
+    let colorterm = getenv("COLORTERM").unwrap_or_default().to_lowercase();
+    let term = getenv("TERM").unwrap_or_default();
+
+    colorterm == "truecolor"
+        || colorterm == "24bit"
+        || term.ends_with("-direct")
+        || term.contains("truecolor")
+        || term.contains("24bit")
+}
+
+/// Make the given text, e.g. a file name, safe to display in the TUI, by
+/// replacing the control characters with a visible representation. Escape
+/// sequences can't get through, since the escape character itself is
+/// replaced.
+///
+/// By default, the caret notation is used, e.g. "^M" for a carriage return,
+/// "^[" for an escape, and "M-^[" for the 8-bit controls like in `cat -v`.
+///
+/// Options:
+///
+/// - pictures: Use the Unicode control pictures instead, e.g. "␍" and "␛".
+/// - strip_ansi: Remove the ANSI escape sequences, like [xplr.util.ansi_strip][1],
+///   instead of showing them.
+/// - keep_newlines: Leave the newlines as they are.
+///
+/// Type: function( text:string, options:table|nil ) -> string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.sanitize("foo\x1b[2J\r\nbar")
+/// -- "foo^[[2J^M^Jbar"
+///
+/// xplr.util.sanitize("foo\x1b[2J\r\nbar", { pictures = true, strip_ansi = true })
+/// -- "foo␍␊bar"
+///
+/// xplr.util.sanitize("foo\tbar\nbaz", { keep_newlines = true })
+/// -- "foo^Ibar\nbaz"
+/// ```
+///
+/// [1]: #xplrutilansi_strip
+pub fn sanitize<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, (text, options): (String, Option<Table>)| {
+            let options: SanitizeOptions = from_options(lua, options)?;
+            Ok(sanitize_text(&text, &options))
+        })?;
+    util.set("sanitize", func)?;
+    Ok(util)
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SanitizeOptions {
+    pictures: bool,
+    strip_ansi: bool,
+    keep_newlines: bool,
+}
+
+fn sanitize_text(text: &str, options: &SanitizeOptions) -> String {
+    let stripped;
+    let text = if options.strip_ansi {
+        stripped = strip_ansi(text);
+        &stripped
+    } else {
+        text
+    };
+
+    let mut sanitized = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c as u32 {
+            _ if c == '\n' && options.keep_newlines => sanitized.push(c),
+            // The Unicode control pictures block starts with ␀.
+            n @ (0x00..=0x1f | 0x7f) if options.pictures => {
+                let picture = if n == 0x7f { 0x2421 } else { 0x2400 + n };
+                sanitized.extend(char::from_u32(picture));
+            }
+            n @ (0x00..=0x1f | 0x7f) => {
+                sanitized.push('^');
+                sanitized.extend(char::from_u32(n ^ 0x40));
+            }
+            n @ 0x80..=0x9f => {
+                sanitized.push_str("M-^");
+                sanitized.extend(char::from_u32((n - 0x80) ^ 0x40));
+            }
+            _ => sanitized.push(c),
+        }
+    }
+    sanitized
+}
+
+/// Find the files with the same content in the given directory, recursively.
+/// Returns the groups of duplicates, as a list of `{ hash, size, paths }`,
+/// where the hash is the SHA-256 hex digest of the content, shared by all the
+/// paths of the group.
+///
+/// Only the files of the same size are compared, and hashed by streaming them
+/// from disk, so that the unique files are never read. Symlinks are not
+/// followed, and the files that can't be read are skipped. The hard links to
+/// the same file are only listed once, under the first path found (Unix
+/// only).
+///
+/// Options:
+///
+/// - max_depth: Only look up to this depth, 1 being the direct children, and
+///   0 meaning no limit, like in the explorer config.
+/// - min_size: Skip the files smaller than this many bytes. Defaults to 1,
+///   i.e. the empty files are skipped.
+///
+/// Returns nil and the error message if the directory can't be read.
+///
+/// Type: function( root:string, options:table|nil ) -> { { hash:string, size:integer, paths:{ string... } }... }|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.find_duplicates("/tmp", { max_depth = 2, min_size = 1024 })
+/// -- { { hash = "2c26b4...", size = 2048, paths = { "/tmp/a", "/tmp/b/c" } } }
+/// ```
+pub fn find_duplicates<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func =
+        lua.create_function(|lua, (root, options): (String, Option<Table>)| {
+            let options: DuplicatesOptions = from_options(lua, options)?;
+            match find_duplicate_files(Path::new(&root), &options) {
+                Ok(groups) => {
+                    let groups =
+                        lua::serialize(lua, &groups).map_err(LuaError::custom)?;
+                    Ok((Some(groups), None))
+                }
+                Err(e) => Ok((None, Some(format!("{root}: {e}")))),
+            }
+        })?;
+    util.set("find_duplicates", func)?;
+    Ok(util)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct DuplicatesOptions {
+    max_depth: Option<usize>,
+    min_size: u64,
+}
+
+impl Default for DuplicatesOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            min_size: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct DuplicateGroup {
+    hash: String,
+    size: u64,
+    paths: Vec<String>,
+}
+
+fn find_duplicate_files(
+    root: &Path,
+    options: &DuplicatesOptions,
+) -> io::Result<Vec<DuplicateGroup>> {
+    fs::read_dir(root)?;
+    let root = root.absolutize()?;
+
+    let walker = WalkBuilder::new(&root)
+        .max_depth(explorer::depth_limit(options.max_depth))
+        .standard_filters(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    let mut by_size: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+    let mut inodes: HashSet<(u64, u64)> = HashSet::new();
+    for entry in walker.filter_map(|e| e.ok()) {
+        if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !is_new_inode(&mut inodes, &metadata) {
+            continue;
+        }
+        if metadata.len() >= options.min_size {
+            by_size
+                .entry(metadata.len())
+                .or_default()
+                .push(entry.into_path());
+        }
+    }
+
+    let mut groups = vec![];
+    for (size, paths) in by_size.into_iter().filter(|(_, p)| p.len() > 1) {
+        let mut by_hash: BTreeMap<Vec<u8>, Vec<String>> = BTreeMap::new();
+        for path in paths {
+            let Ok(hash) = fs::File::open(&path).and_then(digest::<Sha256>) else {
+                continue;
+            };
+            by_hash
+                .entry(hash)
+                .or_default()
+                .push(path.to_string_lossy().to_string());
+        }
+        groups.extend(by_hash.into_iter().filter(|(_, p)| p.len() > 1).map(
+            |(hash, paths)| DuplicateGroup {
+                hash: hash.iter().map(|b| format!("{:02x}", b)).collect(),
+                size,
+                paths,
+            },
+        ));
+    }
+
+    // In the order of the walk.
+    groups.sort_by(|a, b| a.paths[0].cmp(&b.paths[0]));
+    Ok(groups)
+}
+
+#[cfg(unix)]
+fn is_new_inode(inodes: &mut HashSet<(u64, u64)>, metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink() <= 1 || inodes.insert((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn is_new_inode(_: &mut HashSet<(u64, u64)>, _: &fs::Metadata) -> bool {
+    true
+}
+
+/// Get the disk usage of the filesystem containing the given path, e.g. to
+/// show the free space in the status bar. Returns a table with the sizes in
+/// bytes:
+///
+/// - total: The size of the filesystem.
+/// - used: The space used.
+/// - available: The space available to unprivileged users, which can be less
+///   than `total - used`.
+/// - mount_point: The directory the filesystem is mounted on.
+/// - fs_type: The type of the filesystem, e.g. "ext4", or nil if unknown. Only
+///   known on Linux for now.
+///
+/// Returns nil and the error message if the path doesn't exist.
+///
+/// Type: function( path:string ) -> { total:integer, used:integer, available:integer, mount_point:string, fs_type:string|nil }|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.disk_usage(app.pwd)
+/// -- { total = 502468108288, used = 120959647744, available = 355892162560, mount_point = "/", fs_type = "ext4" }
+///
+/// local usage = xplr.util.disk_usage(app.pwd)
+/// if usage then
+///   return xplr.util.human_size(usage.available) .. " free"
+/// end
+/// ```
+pub fn disk_usage<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|lua, path: String| {
+        match disk_usage_of(Path::new(&path)) {
+            Ok(usage) => {
+                let usage = lua::serialize(lua, &usage).map_err(LuaError::custom)?;
+                Ok((Some(usage), None))
+            }
+            Err(e) => Ok((None, Some(format!("{path}: {e}")))),
+        }
+    })?;
+    util.set("disk_usage", func)?;
+    Ok(util)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct DiskUsage {
+    total: u64,
+    used: u64,
+    available: u64,
+    mount_point: String,
+    fs_type: Option<String>,
+}
+
+fn disk_usage_of(path: &Path) -> io::Result<DiskUsage> {
+    let mount_point = mount_point(path)?;
+    let stats = fs2::statvfs(&mount_point)?;
+    Ok(DiskUsage {
+        total: stats.total_space(),
+        used: stats.total_space().saturating_sub(stats.free_space()),
+        available: stats.available_space(),
+        fs_type: fs_type(&mount_point),
+        mount_point: mount_point.to_string_lossy().to_string(),
+    })
+}
+
+// The topmost ancestor on the same device.
+#[cfg(unix)]
+fn mount_point(path: &Path) -> io::Result<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = path.canonicalize()?;
+    let dev = fs::metadata(&path)?.dev();
+    let mut mount_point = path.as_path();
+    while let Some(parent) = mount_point.parent() {
+        match fs::metadata(parent) {
+            Ok(metadata) if metadata.dev() == dev => mount_point = parent,
+            _ => break,
+        }
+    }
+    Ok(mount_point.to_path_buf())
+}
+
+// The drive, or the share.
+#[cfg(not(unix))]
+fn mount_point(path: &Path) -> io::Result<PathBuf> {
+    let path = path.canonicalize()?;
+    Ok(path.ancestors().last().unwrap_or(&path).to_path_buf())
+}
+
+#[cfg(target_os = "linux")]
+fn fs_type(mount_point: &Path) -> Option<String> {
+    let mounts = fs::read_to_string("/proc/self/mounts").ok()?;
+    mounts_fs_type(&mounts, mount_point)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fs_type(_: &Path) -> Option<String> {
+    None
+}
+
+// Reads the `/proc/mounts` format, where the spaces and the like in the paths
+// are escaped as octal, e.g. "\040".
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn mounts_fs_type(mounts: &str, mount_point: &Path) -> Option<String> {
+    fn unescape(path: &str) -> String {
+        let bytes = path.as_bytes();
+        let mut unescaped = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let octal = bytes
+                .get(i + 1..i + 4)
+                .filter(|_| bytes[i] == b'\\')
+                .and_then(|o| u8::from_str_radix(std::str::from_utf8(o).ok()?, 8).ok());
+            match octal {
+                Some(byte) => {
+                    unescaped.push(byte);
+                    i += 4;
+                }
+                None => {
+                    unescaped.push(bytes[i]);
+                    i += 1;
+                }
+            }
+        }
+        String::from_utf8_lossy(&unescaped).to_string()
+    }
+
+    // The last mount on the same directory hides the previous ones.
+    mounts
+        .lines()
+        .rev()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let dir = fields.next()?;
+            let fs_type = fields.next()?;
+            Some((unescape(dir), fs_type))
+        })
+        .find(|(dir, _)| Path::new(dir) == mount_point)
+        .map(|(_, fs_type)| fs_type.to_string())
+}
+
+/// Rename the given files or directories, each within its own directory. The
+/// new names are given either by a `transform` function, called with each
+/// current name, or by a regex substitution.
+///
+/// Nothing is renamed if any of the new names is invalid, if two of them are
+/// the same, or if a file not being renamed already exists with one of them.
+/// The files are moved to temporary names first, so that they can swap names
+/// or take the name of another one being renamed, and so that the case only
+/// changes work on the case-insensitive filesystems too. If a rename fails
+/// midway, the ones already done are undone. The files keeping their name
+/// are left out.
+///
+/// Options:
+///
+/// - transform: A function mapping the current name to the new one.
+/// - pattern: A [regex][1] matched against the names.
+/// - replace: What the first match of the `pattern` is replaced with, where
+///   `$1` or `${name}` are the captured groups.
+///
+/// Returns the list of `{ from, to }` paths renamed, or nil and the error
+/// message.
+///
+/// Type: function( paths:{ string... }, options:table ) -> { { from:string, to:string }... }|nil, error:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.batch_rename({ "/tmp/IMG_1.jpg", "/tmp/IMG_2.jpg" }, {
+///   pattern = "^IMG_(\\d+)",
+///   replace = "photo-$1",
+/// })
+/// -- { { from = "/tmp/IMG_1.jpg", to = "/tmp/photo-1.jpg" }, ... }
+///
+/// xplr.util.batch_rename({ "/tmp/Foo.TXT" }, { transform = string.lower })
+/// -- { { from = "/tmp/Foo.TXT", to = "/tmp/foo.txt" } }
+///
+/// xplr.util.batch_rename({ "/tmp/a", "/tmp/b" }, { transform = function() return "c" end })
+/// -- nil, "/tmp/c: both /tmp/a and /tmp/b would be renamed to it"
+/// ```
+///
+/// [1]: https://docs.rs/regex/latest/regex/#syntax
+pub fn batch_rename<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        pattern: Option<String>,
+        replace: Option<String>,
+    }
+
+    enum Rename<'lua> {
+        Transform(mlua::Function<'lua>),
+        Regex(Regex, String),
+    }
+
+    #[derive(Debug, Serialize)]
+    struct Renamed {
+        from: String,
+        to: String,
+    }
+
+    let func = lua.create_function(|lua, (paths, options): (Vec<String>, Table)| {
+        let (options, transform): (Options, _) =
+            from_options_with_callback(lua, Some(options), "transform")?;
+
+        let rename = match (transform, options.pattern, options.replace) {
+            (Some(transform), None, None) => Rename::Transform(transform),
+            (None, Some(pattern), Some(replace)) => {
+                Rename::Regex(Regex::new(&pattern).map_err(LuaError::custom)?, replace)
+            }
+            _ => {
+                return Err(LuaError::RuntimeError(
+                    "either transform, or pattern and replace, is required".into(),
+                ))
+            }
+        };
+
+        let mut renames = vec![];
+        for path in paths {
+            let path = PathBuf::from(path);
+            let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_string())
+            else {
+                let err = format!("{}: not a file name", path.display());
+                return Ok((None, Some(err)));
+            };
+            let new_name = match &rename {
+                Rename::Transform(transform) => transform.call::<_, String>(name)?,
+                Rename::Regex(regex, replace) => {
+                    regex.replace(&name, replace.as_str()).to_string()
+                }
+            };
+            renames.push((path, new_name));
+        }
+
+        match plan_renames(renames).and_then(|plan| apply_renames(&plan).map(|_| plan)) {
+            Ok(plan) => {
+                let renamed: Vec<_> = plan
+                    .into_iter()
+                    .map(|(from, to)| Renamed {
+                        from: from.to_string_lossy().to_string(),
+                        to: to.to_string_lossy().to_string(),
+                    })
+                    .collect();
+                let renamed = lua::serialize(lua, &renamed).map_err(LuaError::custom)?;
+                Ok((Some(renamed), None))
+            }
+            Err(err) => Ok((None, Some(err))),
+        }
+    })?;
+    util.set("batch_rename", func)?;
+    Ok(util)
+}
+
+// Checks everything that can be checked before renaming anything. The paths
+// keeping their name are dropped.
+fn plan_renames(
+    renames: Vec<(PathBuf, String)>,
+) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    let mut plan: Vec<(PathBuf, PathBuf)> = vec![];
+    let mut sources: HashMap<PathBuf, PathBuf> = HashMap::new();
+
+    for (src, new_name) in renames {
+        if new_name.is_empty()
+            || new_name == "."
+            || new_name == ".."
+            || new_name.contains('/')
+            || new_name.contains(std::path::MAIN_SEPARATOR)
+            || new_name.contains('\0')
+        {
+            return Err(format!("{}: invalid new name {new_name:?}", src.display()));
+        }
+        fs::symlink_metadata(&src).map_err(|e| format!("{}: {e}", src.display()))?;
+
+        let dst = src.with_file_name(&new_name);
+        if dst == src {
+            continue;
+        }
+        if let Some(other) = sources.insert(dst.clone(), src.clone()) {
+            return Err(format!(
+                "{}: both {} and {} would be renamed to it",
+                dst.display(),
+                other.display(),
+                src.display()
+            ));
+        }
+        plan.push((src, dst));
+    }
+
+    // The names of the files being renamed are freed first.
+    let moved: HashSet<&PathBuf> = plan.iter().map(|(src, _)| src).collect();
+    for (src, dst) in plan.iter() {
+        if fs::symlink_metadata(dst).is_ok()
+            && !moved.contains(dst)
+            && !is_case_change(src, dst)
+        {
+            return Err(format!(
+                "{}: {}",
+                dst.display(),
+                io::Error::from(io::ErrorKind::AlreadyExists)
+            ));
+        }
+    }
+
+    Ok(plan)
+}
+
+// Whether `dst` only exists because the filesystem is case-insensitive, and
+// names `src` itself.
+fn is_case_change(src: &Path, dst: &Path) -> bool {
+    let same_name = match (src.file_name(), dst.file_name()) {
+        (Some(a), Some(b)) => {
+            a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+        }
+        _ => false,
+    };
+    same_name && is_same_file(src, dst)
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(a), Ok(b)) => (a.dev(), a.ino()) == (b.dev(), b.ino()),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn apply_renames(plan: &[(PathBuf, PathBuf)]) -> Result<(), String> {
+    // Every file is moved to a temporary name first, and then to its new
+    // name, so that the new names are all free by then.
+    let staged: Vec<(&Path, PathBuf, &Path)> = plan
+        .iter()
+        .map(|(src, dst)| (src.as_path(), temp::sibling(src), dst.as_path()))
+        .collect();
+    let moves = staged
+        .iter()
+        .map(|(src, tmp, _)| (*src, tmp.as_path(), *src))
+        .chain(
+            staged
+                .iter()
+                .map(|(_, tmp, dst)| (tmp.as_path(), *dst, *dst)),
+        );
+
+    let mut done: Vec<(&Path, &Path)> = vec![];
+    for (from, to, shown) in moves {
+        if let Err(e) = fs::rename(from, to) {
+            let mut err = format!("{}: {e}", shown.display());
+            for (from, to) in done.into_iter().rev() {
+                if let Err(e) = fs::rename(to, from) {
+                    err.push_str(&format!("; couldn't undo {}: {e}", to.display()));
+                }
+            }
+            return Err(err);
+        }
+        done.push((from, to));
+    }
+    Ok(())
+}
+
+/// Get the prefix of an entry in a tree view, like the `tree` command's. The
+/// `is_last` list tells, for each level from the top down to the entry
+/// itself, whether the ancestor at that level, or the entry, is the last
+/// child of its parent. Only its first `depth` items are used, the missing
+/// ones meaning not last. The prefix of the entries at depth 0, the roots, is
+/// empty.
+///
+/// Options:
+///
+/// - ascii: Use the ASCII characters instead of the box-drawing ones, e.g.
+///   for the terminals without the line-drawing glyphs.
+///
+/// Type: function( depth:integer, is_last:{ boolean... }, options:table|nil ) -> string
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.tree_prefix(1, { false })
+/// -- "├── "
+///
+/// xplr.util.tree_prefix(3, { false, true, true })
+/// -- "│       └── "
+///
+/// xplr.util.tree_prefix(2, { true, false }, { ascii = true })
+/// -- "    |-- "
+/// ```
+pub fn tree_prefix<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        ascii: bool,
+    }
+
+    let func = lua.create_function(
+        |lua, (depth, is_last, options): (usize, Vec<bool>, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            Ok(tree_prefix_of(depth, &is_last, options.ascii))
+        },
+    )?;
+    util.set("tree_prefix", func)?;
+    Ok(util)
+}
+
+fn tree_prefix_of(depth: usize, is_last: &[bool], ascii: bool) -> String {
+    let (branch, last_branch, pipe) = if ascii {
+        ("|-- ", "`-- ", "|   ")
+    } else {
+        ("├── ", "└── ", "│   ")
+    };
+
+    (0..depth)
+        .map(|level| {
+            let last = is_last.get(level).copied().unwrap_or(false);
+            match (level + 1 == depth, last) {
+                (true, true) => last_branch,
+                (true, false) => branch,
+                (false, true) => "    ",
+                (false, false) => pipe,
+            }
+        })
+        .collect()
+}
+
+/// Compile the given [gitignore patterns][1], e.g. `*.log`, `build/` or
+/// `!keep.log`, into a matcher, to hide the entries the same way as with a
+/// `.gitignore` file, without one. Later patterns override the earlier ones,
+/// so a negated pattern re-includes what was ignored before it. Raises an
+/// error if a pattern is invalid.
+///
+/// Options:
+///
+/// - root: The directory the patterns containing a `/`, like `/build` or
+///   `docs/*.pdf`, are relative to. Defaults to the current directory.
+///
+/// The matcher has a `matches(path, is_dir)` method, checking if the path or
+/// node is ignored, i.e. if it or any of its parent directories matches. The
+/// relative paths are relative to the root, and the absolute paths outside
+/// the root are never ignored. The patterns ending with `/` only match the
+/// directories, so pass `is_dir`, which defaults to the node's `is_dir`, or
+/// false for paths.
+///
+/// Type: function( patterns:{ string... }, options:table|nil ) -> matcher
+///
+/// Example:
+///
+/// ```lua
+/// local ignored = xplr.util.gitignore_matcher({ "*.log", "!keep.log", "build/" })
+///
+/// ignored:matches("debug.log")
+/// -- true
+///
+/// ignored:matches("keep.log")
+/// -- false
+///
+/// ignored:matches("build", true)
+/// -- true
+///
+/// ignored:matches("build/out.o")
+/// -- true
+///
+/// ignored:matches(app.focused_node)
+/// ```
+///
+/// [1]: https://git-scm.com/docs/gitignore#_pattern_format
+pub fn gitignore_matcher<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        root: Option<String>,
+    }
+
+    let func = lua.create_function(
+        |lua, (patterns, options): (Vec<String>, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let root = match options.root {
+                Some(root) => PathBuf::from(root).absolutize()?.to_path_buf(),
+                None => env::current_dir()?,
+            };
+
+            let mut builder = GitignoreBuilder::new(&root);
+            for pattern in patterns {
+                builder.add_line(None, &pattern).map_err(LuaError::custom)?;
+            }
+            let gitignore = builder.build().map_err(LuaError::custom)?;
+            Ok(LuaGitignore { root, gitignore })
+        },
+    )?;
+    util.set("gitignore_matcher", func)?;
+    Ok(util)
+}
+
+// Wraps the compiled patterns for Lua.
+struct LuaGitignore {
+    root: PathBuf,
+    gitignore: Gitignore,
+}
+
+impl LuaGitignore {
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        let path = if path.has_root() {
+            match path.strip_prefix(&self.root) {
+                Ok(path) => path,
+                Err(_) => return false,
+            }
+        } else {
+            path
+        };
+        if path.as_os_str().is_empty() {
+            return false;
+        }
+        self.gitignore
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+    }
+}
+
+impl UserData for LuaGitignore {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "matches",
+            |_, this, (target, is_dir): (Value, Option<bool>)| {
+                let is_dir = match (is_dir, &target) {
+                    (Some(is_dir), _) => is_dir,
+                    (None, Value::Table(node)) => node.get("is_dir")?,
+                    (None, _) => false,
+                };
+                Ok(this.matches(Path::new(&target_path(&target)?), is_dir))
+            },
+        );
+    }
+}
+
+/// Find the root of the project containing the given path, i.e. the closest
+/// directory, starting from the path itself, containing one of the marker
+/// files or directories. Returns nil if none is found up to the filesystem
+/// root, or up to the boundary.
+///
+/// Options:
+///
+/// - markers: The names to look for. Defaults to `{ ".git" }`.
+/// - boundary: The last directory to look into, or true for the home
+///   directory, if the path is inside it.
+///
+/// Type: function( start:string, options:table|nil ) -> path:string|nil
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.find_root("/home/user/project/src/main.rs")
+/// -- "/home/user/project"
+///
+/// xplr.util.find_root(app.pwd, { markers = { "Cargo.toml", "package.json" }, boundary = true })
+/// -- "/home/user/project/crate"
+/// ```
+pub fn find_root<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    #[derive(Debug, Deserialize)]
+    #[serde(untagged)]
+    enum Boundary {
+        Home(bool),
+        Dir(String),
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(default)]
+    struct Options {
+        markers: Vec<String>,
+        boundary: Option<Boundary>,
+    }
+
+    impl Default for Options {
+        fn default() -> Self {
+            Self {
+                markers: vec![".git".into()],
+                boundary: None,
+            }
+        }
+    }
+
+    let func =
+        lua.create_function(|lua, (start, options): (String, Option<Table>)| {
+            let options: Options = from_options(lua, options)?;
+            let boundary = match options.boundary {
+                Some(Boundary::Home(true)) => dirs::home_dir(),
+                Some(Boundary::Dir(dir)) => {
+                    Some(PathBuf::from(dir).absolutize()?.to_path_buf())
+                }
+                Some(Boundary::Home(false)) | None => None,
+            };
+            let start = PathBuf::from(start).absolutize()?.to_path_buf();
+            let root = project_root(&start, &options.markers, boundary.as_deref());
+            Ok(root.map(|r| r.to_string_lossy().to_string()))
+        })?;
+    util.set("find_root", func)?;
+    Ok(util)
+}
+
+fn project_root(
+    start: &Path,
+    markers: &[String],
+    boundary: Option<&Path>,
+) -> Option<PathBuf> {
+    let start = if start.is_file() {
+        start.parent()?
+    } else {
+        start
+    };
+
+    for dir in start.ancestors() {
+        if markers
+            .iter()
+            .any(|marker| fs::symlink_metadata(dir.join(marker)).is_ok())
+        {
+            return Some(dir.to_path_buf());
+        }
+        if Some(dir) == boundary {
+            break;
+        }
+    }
+    None
+}
+
+/// Add the given message to the logs, with the given level, to debug the
+/// config without printing, which would break the screen. The logs are shown
+/// with the time and the level's [format and style][1], like the ones added by
+/// the [LogInfo][2] message and the like, once xplr handles the next message.
+///
+/// The level can be "debug", "info", "success", "warn" (or "warning") or
+/// "error". Raises an error for any other level.
+///
+/// Type: function( level:string, message:string )
+///
+/// Example:
+///
+/// ```lua
+/// xplr.util.log("debug", "loaded my plugin")
+/// xplr.util.log("warn", "fzf not found, falling back to find")
+/// ```
+///
+/// [1]: https://xplr.dev/en/general-config#xplrconfiggenerallogsdebugformat
+/// [2]: https://xplr.dev/en/messages#loginfo
+pub fn log<'a>(util: Table<'a>, lua: &Lua) -> Result<Table<'a>> {
+    let func = lua.create_function(|_, (level, message): (String, String)| {
+        let level = match level.as_str() {
+            "debug" => app::LogLevel::Debug,
+            "info" => app::LogLevel::Info,
+            "success" => app::LogLevel::Success,
+            "warn" | "warning" => app::LogLevel::Warning,
+            "error" => app::LogLevel::Error,
+            level => {
+                return Err(LuaError::RuntimeError(format!(
+                    "invalid log level: {level}"
+                )))
+            }
+        };
+        logs::push(level, message);
+        Ok(())
+    })?;
+    util.set("log", func)?;
+    Ok(util)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use tempfile::TempDir;
+
+    fn lua() -> Lua {
+        let lua = Lua::new();
+        let util = create_table(&lua).unwrap();
+        lua.globals().set("util", util).unwrap();
+        lua
+    }
+
+    fn eval<'lua, T: mlua::FromLuaMulti<'lua>>(lua: &'lua Lua, code: &str) -> T {
+        lua.load(code).eval().unwrap()
+    }
+
+    #[test]
+    fn test_relative_to() {
+        let lua = lua();
+
+        let rel: String = eval(&lua, r#"util.relative_to("/a/b/c", "/a/x")"#);
+        assert_eq!(rel, "../b/c");
+
+        let rel: String = eval(&lua, r#"util.relative_to("/a/b/c", "/a")"#);
+        assert_eq!(rel, "b/c");
+
+        let rel: String = eval(&lua, r#"util.relative_to("/a/b", "/a/b")"#);
+        assert_eq!(rel, ".");
+
+        let rel: String = eval(&lua, r#"util.relative_to("/a/b/", "/a/b")"#);
+        assert_eq!(rel, ".");
+
+        let rel: String = eval(&lua, r#"util.relative_to("/a/b/c", "/a/")"#);
+        assert_eq!(rel, "b/c");
+
+        let rel: String = eval(&lua, r#"util.relative_to("/a/b", "/a/b/c/d")"#);
+        assert_eq!(rel, "../..");
+
+        let rel: String = eval(&lua, r#"util.relative_to("/a/x/../b/./c", "/a/y/..")"#);
+        assert_eq!(rel, "b/c");
+
+        let rel: String = eval(&lua, r#"util.relative_to("/", "/a/b")"#);
+        assert_eq!(rel, "../..");
+    }
+
+    #[test]
+    fn test_relative_to_pwd() {
+        let lua = lua();
+        let pwd = std::env::current_dir().unwrap();
+        let path = pwd.join("foo").join("bar");
+
+        let rel: String = eval(
+            &lua,
+            &format!("util.relative_to({:?})", path.to_string_lossy()),
+        );
+        assert_eq!(rel, "foo/bar");
+    }
+
+    #[test]
+    fn test_path_split() {
+        let lua = lua();
+
+        let parts: Vec<String> = eval(&lua, r#"util.path_split("/foo/bar/baz")"#);
+        assert_eq!(parts, vec!["/", "foo", "bar", "baz"]);
+
+        let parts: Vec<String> = eval(&lua, r#"util.path_split("a/b")"#);
+        assert_eq!(parts, vec!["a", "b"]);
+
+        let parts: Vec<String> = eval(&lua, r#"util.path_split("./a//b/./../c/")"#);
+        assert_eq!(parts, vec!["a", "b", "..", "c"]);
+
+        let parts: Vec<String> = eval(&lua, r#"util.path_split("../a")"#);
+        assert_eq!(parts, vec!["..", "a"]);
+
+        let parts: Vec<String> = eval(&lua, r#"util.path_split("/")"#);
+        assert_eq!(parts, vec!["/"]);
+
+        let parts: Vec<String> = eval(&lua, r#"util.path_split("")"#);
+        assert!(parts.is_empty());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_path_split_windows() {
+        assert_eq!(
+            split_path(Path::new(r"C:\foo\bar")),
+            vec!["C:\\", "foo", "bar"]
+        );
+        assert_eq!(
+            split_path(Path::new(r"\\server\share\foo")),
+            vec![r"\\server\share\", "foo"]
+        );
+    }
+
+    #[test]
+    fn test_join() {
+        let lua = lua();
+
+        let path: String = eval(&lua, r#"util.join({ "/foo", "bar", "baz" })"#);
+        assert_eq!(path, "/foo/bar/baz");
+
+        let path: String = eval(&lua, r#"util.join({ "foo/", "", "bar" })"#);
+        assert_eq!(path, "foo/bar");
+
+        let path: String = eval(&lua, r#"util.join({ "foo", "/bar", "baz" })"#);
+        assert_eq!(path, "/bar/baz");
+
+        let path: String = eval(&lua, r#"util.join({})"#);
+        assert_eq!(path, "");
+    }
+
+    #[test]
+    fn test_extension_and_stem() {
+        let lua = lua();
+
+        let ext: Option<String> = eval(&lua, r#"util.extension("foo.tar.gz")"#);
+        assert_eq!(ext.as_deref(), Some("gz"));
+
+        let stem: Option<String> = eval(&lua, r#"util.stem("foo.tar.gz")"#);
+        assert_eq!(stem.as_deref(), Some("foo.tar"));
+
+        let ext: Option<String> = eval(&lua, r#"util.extension(".bashrc")"#);
+        assert_eq!(ext, None);
+
+        let stem: Option<String> = eval(&lua, r#"util.stem(".bashrc")"#);
+        assert_eq!(stem.as_deref(), Some(".bashrc"));
+
+        let ext: Option<String> = eval(&lua, r#"util.extension("/foo/bar")"#);
+        assert_eq!(ext, None);
+
+        let ext: Option<String> = eval(&lua, r#"util.extension("/foo/bar.txt/")"#);
+        assert_eq!(ext.as_deref(), Some("txt"));
+
+        let stem: Option<String> = eval(&lua, r#"util.stem("/foo/bar.txt/")"#);
+        assert_eq!(stem.as_deref(), Some("bar"));
+
+        let stem: Option<String> = eval(&lua, r#"util.stem("/")"#);
+        assert_eq!(stem, None);
+    }
+
+    #[test]
+    fn test_shorten() {
+        let lua = lua();
+
+        let path: String = eval(&lua, r#"util.shorten("/home/user/projects/xplr")"#);
+        assert_eq!(path, "/h/u/p/xplr");
+
+        let path: String = eval(
+            &lua,
+            r#"util.shorten("/home/user/projects/xplr", { length = 2 })"#,
+        );
+        assert_eq!(path, "/ho/us/pr/xplr");
+
+        let path: String = eval(&lua, r#"util.shorten("/a/b/c")"#);
+        assert_eq!(path, "/a/b/c");
+
+        let path: String = eval(&lua, r#"util.shorten("xplr")"#);
+        assert_eq!(path, "xplr");
+
+        let path: String = eval(&lua, r#"util.shorten("/")"#);
+        assert_eq!(path, "/");
+
+        let path: String = eval(&lua, r#"util.shorten("/home/.config/ünïcode/ñ/x")"#);
+        assert_eq!(path, "/h/.c/ü/ñ/x");
+    }
+
+    #[test]
+    fn test_shorten_with_home() {
+        let home = Path::new("/home/user");
+
+        assert_eq!(
+            shorten_path("/home/user/projects/xplr", 1, Some(home)),
+            "~/p/xplr"
+        );
+        assert_eq!(shorten_path("/home/user", 1, Some(home)), "~");
+        assert_eq!(
+            shorten_path("/home/username/xplr", 1, Some(home)),
+            "/h/u/xplr"
+        );
+        assert_eq!(shorten_path("/tmp/foo/xplr", 1, Some(home)), "/t/f/xplr");
+    }
+
+    #[test]
+    fn test_shell_execute() {
+        let lua = lua();
+
+        let (out, code): (String, Option<i32>) = eval(
+            &lua,
+            r#"
+            local res = util.shell_execute("sh", { "-c", "echo foo" })
+            return res.stdout, res.returncode
+            "#,
+        );
+        assert_eq!(out, "foo\n");
+        assert_eq!(code, Some(0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shell_execute_on_output() {
+        let lua = lua();
+
+        let (chunks, out, err, code): (Vec<String>, String, String, Option<i32>) = eval(
+            &lua,
+            r#"
+            local chunks = {}
+            local res = util.shell_execute(
+              "sh",
+              { "-c", "echo foo; sleep 0.2; echo bar >&2; sleep 0.2; echo baz" },
+              {
+                on_output = function(chunk, stream)
+                  table.insert(chunks, stream .. ":" .. chunk)
+                end,
+              }
+            )
+            return chunks, res.stdout, res.stderr, res.returncode
+            "#,
+        );
+        assert_eq!(chunks, vec!["stdout:foo\n", "stderr:bar\n", "stdout:baz\n"]);
+        assert_eq!(
+            (out.as_str(), err.as_str(), code),
+            ("foo\nbaz\n", "bar\n", Some(0))
+        );
+
+        // With a timeout
+        let start = Instant::now();
+        let (chunks, out, err, code): (Vec<String>, String, String, Option<i32>) = eval(
+            &lua,
+            r#"
+            local chunks = {}
+            local res = util.shell_execute(
+              "sh",
+              { "-c", "echo foo; sleep 10; echo bar" },
+              {
+                timeout_ms = 200,
+                on_output = function(chunk) table.insert(chunks, chunk) end,
+              }
+            )
+            return chunks, res.stdout, res.stderr, res.returncode
+            "#,
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(chunks, vec!["foo\n"]);
+        assert_eq!(
+            (out.as_str(), err.as_str(), code),
+            ("foo\n", "timed out after 200ms", None)
+        );
+
+        // Errors in the callback kill the command.
+        let start = Instant::now();
+        let res: mlua::Result<Value> = lua
+            .load(
+                r#"
+                util.shell_execute("sh", { "-c", "echo foo; sleep 10" }, {
+                  on_output = function() error("stop") end,
+                })
+                "#,
+            )
+            .eval();
+        assert!(res.unwrap_err().to_string().contains("stop"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_shell_execute_timeout() {
+        let lua = lua();
+        let start = Instant::now();
+
+        let (out, err, code): (String, String, Option<i32>) = eval(
+            &lua,
+            r#"
+            local res = util.shell_execute(
+              "sh",
+              { "-c", "echo foo; sleep 10; echo bar" },
+              { timeout_ms = 200 }
+            )
+            return res.stdout, res.stderr, res.returncode
+            "#,
+        );
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(out, "foo\n");
+        assert_eq!(err, "timed out after 200ms");
+        assert_eq!(code, None);
+
+        let (out, code): (String, Option<i32>) = eval(
+            &lua,
+            r#"
+            local res = util.shell_execute("echo", { "foo" }, { timeout_ms = 5000 })
+            return res.stdout, res.returncode
+            "#,
+        );
+        assert_eq!(out, "foo\n");
+        assert_eq!(code, Some(0));
+    }
+
+    #[test]
+    fn test_shell_execute_stdin() {
+        let lua = lua();
+
+        let (out, code): (String, Option<i32>) = eval(
+            &lua,
+            r#"
+            local res = util.shell_execute("cat", {}, { stdin = "foo\nbar\n" })
+            return res.stdout, res.returncode
+            "#,
+        );
+        assert_eq!(out, "foo\nbar\n");
+        assert_eq!(code, Some(0));
+
+        let out: String = eval(
+            &lua,
+            r#"
+            local input = string.rep("foo\n", 100000)
+            return util.shell_execute("wc", { "-l" }, { stdin = input }).stdout
+            "#,
+        );
+        assert_eq!(out.trim(), "100000");
+
+        let out: String = eval(
+            &lua,
+            r#"
+            local input = string.rep("foo\n", 100000)
+            return util.shell_execute("cat", {}, { stdin = input }).stdout
+            "#,
+        );
+        assert_eq!(out.len(), 400000);
+    }
+
+    #[test]
+    fn test_shell_execute_cwd_and_env() {
+        let lua = lua();
+        let tmp = std::env::temp_dir().canonicalize().unwrap();
+
+        let out: String = eval(
+            &lua,
+            &format!(
+                "return util.shell_execute('pwd', {{}}, {{ cwd = {:?} }}).stdout",
+                tmp.to_string_lossy()
+            ),
+        );
+        assert_eq!(out.trim(), tmp.to_string_lossy());
+
+        let out: String = eval(
+            &lua,
+            r#"
+            return util.shell_execute(
+              "sh",
+              { "-c", "echo $XPLR_TEST_FOO-$HOME" },
+              { env = { XPLR_TEST_FOO = "foo" } }
+            ).stdout
+            "#,
+        );
+        assert_eq!(
+            out.trim(),
+            format!("foo-{}", std::env::var("HOME").unwrap_or_default())
+        );
+
+        let out: String = eval(
+            &lua,
+            r#"
+            return util.shell_execute(
+              "/bin/sh",
+              { "-c", "echo $XPLR_TEST_FOO-$HOME" },
+              { env = { XPLR_TEST_FOO = "foo" }, clear_env = true }
+            ).stdout
+            "#,
+        );
+        assert_eq!(out.trim(), "foo-");
+    }
+
+    #[test]
+    fn test_shell_spawn() {
+        let lua = lua();
+        let start = Instant::now();
+
+        let pid: u32 = eval(&lua, r#"util.shell_spawn("sleep", { "5" })"#);
+
+        assert!(pid > 0);
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let res: mlua::Result<u32> = lua
+            .load(r#"util.shell_spawn("/there/is/no/program")"#)
+            .eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_stat() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let file = root.join("foo");
+        fs::write(&file, "foo").unwrap();
+
+        let (size, is_file, is_dir, is_symlink, mtime): (u64, bool, bool, bool, u64) =
+            eval(
+                &lua,
+                &format!(
+                    "local s = util.stat({:?}); return s.size, s.is_file, s.is_dir, s.is_symlink, s.mtime",
+                    file.to_string_lossy()
+                ),
+            );
+        assert_eq!(size, 3);
+        assert!(is_file);
+        assert!(!is_dir);
+        assert!(!is_symlink);
+        assert!(mtime > 0);
+
+        #[cfg(unix)]
+        {
+            let link = root.join("link");
+            std::os::unix::fs::symlink(&file, &link).unwrap();
+
+            let (is_file, is_symlink, mode, nlink): (bool, bool, u32, u64) = eval(
+                &lua,
+                &format!(
+                    "local s = util.stat({:?}); return s.is_file, s.is_symlink, s.mode, s.nlink",
+                    link.to_string_lossy()
+                ),
+            );
+            assert!(!is_file);
+            assert!(is_symlink);
+            assert_eq!(mode & 0o170000, 0o120000);
+            assert_eq!(nlink, 1);
+        }
+
+        let (stat, err): (Option<Table>, Option<String>) =
+            eval(&lua, r#"util.stat("/there/is/no/path")"#);
+        assert!(stat.is_none());
+        assert!(err.is_some());
+    }
+
+    #[test]
+    fn test_read_file() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let small = root.join("small");
+        let big = root.join("big");
+        fs::write(&small, "foo\nbar\n").unwrap();
+        fs::write(&big, "x".repeat(2 * 1024 * 1024)).unwrap();
+
+        let (content, truncated): (String, bool) = eval(
+            &lua,
+            &format!("util.read_file({:?})", small.to_string_lossy()),
+        );
+        assert_eq!(content, "foo\nbar\n");
+        assert!(!truncated);
+
+        let (content, truncated): (String, bool) = eval(
+            &lua,
+            &format!("util.read_file({:?}, 8)", small.to_string_lossy()),
+        );
+        assert_eq!(content, "foo\nbar\n");
+        assert!(!truncated);
+
+        let (content, truncated): (String, bool) = eval(
+            &lua,
+            &format!("util.read_file({:?}, 5)", small.to_string_lossy()),
+        );
+        assert_eq!(content, "foo\nb");
+        assert!(truncated);
+
+        let (content, truncated): (String, bool) = eval(
+            &lua,
+            &format!("util.read_file({:?})", big.to_string_lossy()),
+        );
+        assert_eq!(content.len(), 1024 * 1024);
+        assert!(truncated);
+
+        let (content, err): (Option<String>, String) =
+            eval(&lua, r#"util.read_file("/there/is/no/file")"#);
+        assert!(content.is_none());
+        assert!(!err.is_empty());
+    }
+
+    #[test]
+    fn test_write_file() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let file = root.join("foo");
+        let path = file.to_string_lossy();
+
+        let err: Option<String> =
+            eval(&lua, &format!("util.write_file({:?}, 'foo\\n')", path));
+        assert_eq!(err, None);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "foo\n");
+
+        let err: Option<String> =
+            eval(&lua, &format!("util.write_file({:?}, 'bar\\n')", path));
+        assert_eq!(err, None);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "bar\n");
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!("util.write_file({:?}, 'baz\\n', {{ append = true }})", path),
+        );
+        assert_eq!(err, None);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "bar\nbaz\n");
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!("util.write_file({:?}, 'a\\0b', {{ atomic = true }})", path),
+        );
+        assert_eq!(err, None);
+        assert_eq!(fs::read(&file).unwrap(), b"a\0b");
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!(
+                "util.write_file({:?}, 'c', {{ atomic = true, append = true }})",
+                path
+            ),
+        );
+        assert_eq!(err, None);
+        assert_eq!(fs::read(&file).unwrap(), b"a\0bc");
+        assert_eq!(fs::read_dir(root).unwrap().count(), 1);
+
+        let nested = root.join("x/y/z");
+        let err: Option<String> = eval(
+            &lua,
+            &format!("util.write_file({:?}, 'z')", nested.to_string_lossy()),
+        );
+        assert!(err.is_some());
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!(
+                "util.write_file({:?}, 'z', {{ create_dirs = true, mode = tonumber('600', 8) }})",
+                nested.to_string_lossy()
+            ),
+        );
+        assert_eq!(err, None);
+        assert_eq!(fs::read_to_string(&nested).unwrap(), "z");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&nested).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+
+            // The atomic write keeps the permissions of the replaced file.
+            let script = root.join("script.sh");
+            fs::write(&script, "true").unwrap();
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+            let err: Option<String> = eval(
+                &lua,
+                &format!(
+                    "util.write_file({:?}, 'false', {{ atomic = true }})",
+                    script.to_string_lossy()
+                ),
+            );
+            assert_eq!(err, None);
+            assert_eq!(fs::read_to_string(&script).unwrap(), "false");
+            let mode = fs::metadata(&script).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+    }
+
+    #[test]
+    fn test_json() {
+        let lua = lua();
+
+        let json = r#"{"a":[1,2.5,{"b":true,"c":"d"}],"e":{"f":{"g":"h"}}}"#;
+        let code = format!("return util.json_encode(util.json_decode({:?}))", json);
+        let res: String = eval(&lua, &code);
+        assert_eq!(res, json);
+
+        let res: bool = eval(
+            &lua,
+            r#"
+            local v = util.json_decode('{"a": [1, {"b": "c"}]}')
+            return v.a[1] == 1 and v.a[2].b == "c"
+            "#,
+        );
+        assert!(res);
+
+        let res: bool = eval(
+            &lua,
+            r#"
+            local v = util.json_decode('{"a": null, "b": [null]}')
+            return v.a == nil and v.b[1] == nil and util.json_decode("null") == nil
+            "#,
+        );
+        assert!(res);
+
+        // Null leaves a hole in arrays.
+        let res: Vec<String> = eval(
+            &lua,
+            r#"
+            local items = {}
+            for i, v in pairs(util.json_decode('[1, null, 3]')) do
+              table.insert(items, i .. "=" .. v)
+            end
+            table.sort(items)
+            return items
+            "#,
+        );
+        assert_eq!(res, vec!["1=1", "3=3"]);
+
+        let res: String = eval(
+            &lua,
+            "return util.json_encode({ a = { b = 1 } }, { pretty = true })",
+        );
+        assert_eq!(res, "{\n  \"a\": {\n    \"b\": 1\n  }\n}");
+
+        let res: String = eval(&lua, "return util.json_encode({ 'a', 'b' })");
+        assert_eq!(res, r#"["a","b"]"#);
+
+        let res: mlua::Result<Value> = lua.load("util.json_decode('{')").eval();
+        assert!(res.unwrap_err().to_string().contains("EOF"));
+
+        let res: mlua::Result<Value> =
+            lua.load("util.json_encode({ f = function() end })").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_yaml() {
+        let lua = lua();
+
+        let res: bool = eval(
+            &lua,
+            r#"
+            local v = util.yaml_decode([[
+            int: 1
+            float: 1.5
+            bool: true
+            str: foo
+            quoted: "1"
+            nested:
+              key: value
+            ]])
+            return v.int == 1
+              and v.float == 1.5
+              and v.bool == true
+              and v.str == "foo"
+              and v.quoted == "1"
+              and v.nested.key == "value"
+            "#,
+        );
+        assert!(res);
+
+        let res: bool = eval(
+            &lua,
+            r#"
+            local v = util.yaml_decode("- a\n- 2\n- [false]\n---\n- ignored\n")
+            return #v == 3 and v[1] == "a" and v[2] == 2 and v[3][1] == false
+            "#,
+        );
+        assert!(res);
+
+        let res: Value = eval(&lua, "return util.yaml_decode('')");
+        assert_eq!(res, Value::Nil);
+
+        let res: Value = eval(&lua, "return util.yaml_decode('---\\n~\\n')");
+        assert_eq!(res, Value::Nil);
+
+        let res: String = eval(&lua, "return util.yaml_encode({ a = { 1, 2 } })");
+        assert_eq!(res, "a:\n- 1\n- 2\n");
+
+        let res: mlua::Result<Value> = lua.load("util.yaml_decode('foo: [bar')").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_toml() {
+        let lua = lua();
+
+        let toml = r#"
+title = "xplr"
+
+[section]
+key = "value"
+date = 1979-05-27T07:32:00Z
+list = [1, 2]
+
+[section.nested]
+flag = true
+"#;
+
+        let res: bool = eval(
+            &lua,
+            &format!(
+                r#"
+                local v = util.toml_decode({:?})
+                return v.title == "xplr"
+                  and v.section.key == "value"
+                  and v.section.date == "1979-05-27T07:32:00Z"
+                  and v.section.list[2] == 2
+                  and v.section.nested.flag == true
+                "#,
+                toml
+            ),
+        );
+        assert!(res);
+
+        let res: String = eval(
+            &lua,
+            &format!("return util.toml_encode(util.toml_decode({:?}))", toml),
+        );
+        assert_eq!(
+            res,
+            r#"title = "xplr"
+
+[section]
+date = "1979-05-27T07:32:00Z"
+key = "value"
+list = [1, 2]
+
+[section.nested]
+flag = true
+"#
+        );
+
+        let res: mlua::Result<Value> = lua.load("util.toml_decode('[section')").eval();
+        assert!(res.is_err());
+
+        let res: mlua::Result<Value> = lua
+            .load("util.toml_encode({ a = { b = { [1] = 'x', c = 'y' } } })")
+            .eval();
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("TOML keys must be strings, found integer key in a.b"));
+    }
+
+    #[test]
+    fn test_base64() {
+        let lua = lua();
+
+        let res: String = eval(&lua, "return util.base64_encode('foo?>')");
+        assert_eq!(res, "Zm9vPz4=");
+
+        let res: String = eval(
+            &lua,
+            "return util.base64_encode('foo?>', { url_safe = true })",
+        );
+        assert_eq!(res, "Zm9vPz4=");
+
+        let res: String = eval(&lua, "return util.base64_encode('\\255\\254\\253')");
+        assert_eq!(res, "//79");
+
+        let res: String = eval(
+            &lua,
+            "return util.base64_encode('\\255\\254\\253', { url_safe = true })",
+        );
+        assert_eq!(res, "__79");
+
+        let res: mlua::String = eval(
+            &lua,
+            "return util.base64_decode(util.base64_encode('a\\0b\\0'))",
+        );
+        assert_eq!(res.as_bytes(), b"a\0b\0");
+
+        let res: mlua::String = eval(
+            &lua,
+            "return util.base64_decode('__79', { url_safe = true })",
+        );
+        assert_eq!(res.as_bytes(), b"\xff\xfe\xfd");
+
+        let res: mlua::Result<Value> = lua.load("util.base64_decode('__79')").eval();
+        assert!(res.is_err());
+
+        let res: mlua::Result<Value> = lua.load("util.base64_decode('Zm9')").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_hash() {
+        let lua = lua();
+
+        let hash = |code: &str| -> (Option<String>, Option<String>) {
+            eval(&lua, &format!("return util.hash({})", code))
+        };
+
+        assert_eq!(
+            hash("'foo'").0.unwrap(),
+            "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"
+        );
+        assert_eq!(
+            hash("'foo', { algo = 'sha1' }").0.unwrap(),
+            "0beec7b5ea3f0fdbc95d0dd47f3c5bc275da8a33"
+        );
+        assert_eq!(
+            hash("'foo', { algo = 'md5' }").0.unwrap(),
+            "acbd18db4cc2f85cedef654fccc4a4d8"
+        );
+        assert_eq!(
+            hash("'foo', { algo = 'xxhash' }").0.unwrap(),
+            "33bf00a859c4ba3f"
+        );
+        assert_eq!(
+            hash("'', { algo = 'xxhash' }").0.unwrap(),
+            "ef46db3751d8e999"
+        );
+
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let file = root.join("foo");
+        fs::write(&file, "foo".repeat(100_000)).unwrap();
+        let path = file.to_string_lossy();
+
+        let expected = hash("string.rep('foo', 100000), { algo = 'md5' }");
+        let res = hash(&format!("{:?}, {{ algo = 'md5', path = true }}", path));
+        assert_eq!(res, expected);
+        assert!(res.0.is_some());
+
+        let res = hash(&format!(
+            "{:?}, {{ path = true }}",
+            root.join("x").to_string_lossy()
+        ));
+        assert_eq!(res.0, None);
+        assert!(res.1.is_some());
+
+        let res: mlua::Result<Value> =
+            lua.load("util.hash('foo', { algo = 'crc32' })").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        let lua = lua();
+
+        let matches = |args: &str| -> bool {
+            eval(&lua, &format!("return util.glob_match({})", args))
+        };
+
+        assert!(matches("'*.rs', 'main.rs'"));
+        assert!(!matches("'*.rs', 'src/main.rs'"));
+        assert!(matches("'**/*.rs', 'src/main.rs'"));
+        assert!(matches("'src/**/*.rs', 'src/lua/util.rs'"));
+        assert!(matches("'src/**', 'src/a/b/c'"));
+        assert!(!matches("'src/**/*.rs', 'benches/criterion.rs'"));
+
+        assert!(matches("'*.{rs,toml}', 'Cargo.toml'"));
+        assert!(matches("'*.{rs,toml}', 'main.rs'"));
+        assert!(!matches("'*.{rs,toml}', 'Cargo.lock'"));
+
+        assert!(matches("'fo?', 'foo'"));
+        assert!(!matches("'fo?', 'fooo'"));
+        assert!(matches("'[a-c]x', 'bx'"));
+        assert!(!matches("'[!a-c]x', 'bx'"));
+
+        assert!(!matches("'*.md', 'README.MD'"));
+        assert!(matches("'*.md', 'README.MD', { case_insensitive = true }"));
+
+        let res: mlua::Result<bool> = lua.load("util.glob_match('[a', 'a')").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        let lua = lua();
+
+        let score = |query: &str, candidate: &str| -> Option<i64> {
+            let code = format!(
+                "local m = util.fuzzy_match({:?}, {:?}); return m and m.score",
+                query, candidate
+            );
+            eval(&lua, &code)
+        };
+
+        assert_eq!(score("xyz", "source.rs"), None);
+        assert_eq!(score("rs", "sr"), None);
+
+        let exact = score("src", "src").unwrap();
+        let prefix = score("src", "src.rs").unwrap();
+        let scattered = score("src", "source.rs").unwrap();
+        assert!(exact >= prefix);
+        assert!(prefix > scattered);
+
+        assert!(score("src", "SRC").is_some());
+        assert!(score("Src", "Src").is_some());
+        assert!(score("Src", "src").is_none());
+
+        let indices: Vec<usize> =
+            eval(&lua, "return util.fuzzy_match('src', 'source.rs').indices");
+        assert_eq!(indices.len(), 3);
+        assert_eq!(indices[0], 1);
+        assert!(indices.windows(2).all(|w| w[0] < w[1]));
+
+        let indices: Vec<usize> =
+            eval(&lua, "return util.fuzzy_match('éb', 'aébc').indices");
+        assert_eq!(indices, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_clipboard() {
+        let lua = lua();
+
+        let err: Option<String> = eval(&lua, "return util.clipboard_write('xplr')");
+        if let Some(err) = err {
+            // No clipboard in this environment, but it shouldn't panic.
+            let (text, read_err): (Option<String>, Option<String>) =
+                eval(&lua, "return util.clipboard_read()");
+            assert_eq!(text, None);
+            assert!(read_err.is_some(), "{}", err);
+            return;
+        }
+
+        let (text, err): (Option<String>, Option<String>) =
+            eval(&lua, "return util.clipboard_read()");
+        assert_eq!(text.as_deref(), Some("xplr"));
+        assert_eq!(err, None);
+    }
+
+    #[test]
+    fn test_osc52_sequence() {
+        assert_eq!(
+            osc52_sequence("xplr"),
+            ("\x1b]52;c;eHBscg==\x07".into(), false)
+        );
+        assert_eq!(osc52_sequence(""), ("\x1b]52;c;\x07".into(), false));
+
+        let (seq, truncated) = osc52_sequence(&"a".repeat(100_000));
+        assert!(truncated);
+        assert_eq!(seq.len(), "\x1b]52;c;\x07".len() + 74992);
+
+        let text = "é".repeat(50_000);
+        let (seq, truncated) = osc52_sequence(&text);
+        assert!(truncated);
+        let encoded = &seq["\x1b]52;c;".len()..seq.len() - 1];
+        assert!(encoded.len() <= 74994);
+        let decoded = String::from_utf8(base64::decode(encoded).unwrap()).unwrap();
+        assert!(text.starts_with(&decoded));
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let file = root.join("foo");
+        let mut bytes = b"Hello, world!\n".to_vec();
+        bytes.extend(0..=5);
+        fs::write(&file, &bytes).unwrap();
+        let path = file.to_string_lossy();
+
+        let (dump, err): (Option<String>, Option<String>) =
+            eval(&lua, &format!("return util.hexdump({:?})", path));
+        assert_eq!(err, None);
+        assert_eq!(
+            dump.unwrap(),
+            "00000000: 48 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 0a 00 01  Hello, world!...\n\
+             00000010: 02 03 04 05                                      ....\n"
+        );
+
+        let (dump, _): (Option<String>, Option<String>) = eval(
+            &lua,
+            &format!(
+                "return util.hexdump({:?}, {{ offset = 7, length = 9, width = 4 }})",
+                path
+            ),
+        );
+        assert_eq!(
+            dump.unwrap(),
+            "00000007: 77 6f 72 6c  worl\n\
+             0000000b: 64 21 0a 00  d!..\n\
+             0000000f: 01           .\n"
+        );
+
+        let (dump, _): (Option<String>, Option<String>) = eval(
+            &lua,
+            &format!("return util.hexdump({:?}, {{ offset = 100 }})", path),
+        );
+        assert_eq!(dump.unwrap(), "");
+
+        let (dump, err): (Option<String>, Option<String>) = eval(
+            &lua,
+            &format!(
+                "return util.hexdump({:?})",
+                root.join("x").to_string_lossy()
+            ),
+        );
+        assert_eq!(dump, None);
+        assert!(err.is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shell_quote() {
+        let lua = lua();
+        let quote = |string: &str, shell: &str| -> String {
+            eval(
+                &lua,
+                &format!(
+                    "return util.shell_quote({:?}, {{ shell = {:?} }})",
+                    string, shell
+                ),
+            )
+        };
+
+        assert_eq!(quote("a b", "posix"), "'a b'");
+        assert_eq!(quote(r#"it's "x""#, "posix"), r#"'it'"'"'s "x"'"#);
+        assert_eq!(quote("", "posix"), "''");
+
+        assert_eq!(quote("a b", "powershell"), "'a b'");
+        assert_eq!(quote(r#"it's "x" $y"#, "powershell"), r#"'it''s "x" $y'"#);
+        assert_eq!(quote("it\u{2019}s", "powershell"), "'it\u{2019}\u{2019}s'");
+        assert_eq!(quote("", "powershell"), "''");
+
+        assert_eq!(quote("a b", "cmd"), r#"^"a b^""#);
+        assert_eq!(quote(r#"say "hi""#, "cmd"), r#"^"say \^"hi\^"^""#);
+        assert_eq!(quote(r#"a\"b"#, "cmd"), r#"^"a\\\^"b^""#);
+        assert_eq!(quote(r"C:\dir\", "cmd"), r#"^"C:\dir\\^""#);
+        assert_eq!(
+            quote("a^b & c|d %PATH%!", "cmd"),
+            r#"^"a^^b ^& c^|d ^%PATH^%^!^""#
+        );
+        assert_eq!(quote("", "cmd"), r#"^"^""#);
+
+        let default: String = eval(&lua, "return util.shell_quote('a b')");
+        if cfg!(windows) {
+            assert_eq!(default, r#"^"a b^""#);
+        } else {
+            assert_eq!(default, "'a b'");
+        }
+
+        let res: mlua::Result<Value> =
+            lua.load("util.shell_quote('a', { shell = 'fish' })").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_shell_quote_all() {
+        let lua = lua();
+
+        let res: String = eval(
+            &lua,
+            r#"return util.shell_quote_all({ "echo", "a b", "it's", 'say "hi"', "" })"#,
+        );
+        assert_eq!(res, r#"'echo' 'a b' 'it'"'"'s' 'say "hi"' ''"#);
+
+        let res: String = eval(&lua, "return util.shell_quote_all({})");
+        assert_eq!(res, "");
+
+        let res: String = eval(
+            &lua,
+            r#"return util.shell_quote_all({ "a b", "" }, { shell = "powershell" })"#,
+        );
+        assert_eq!(res, "'a b' ''");
+
+        #[cfg(unix)]
+        {
+            let res: String = eval(
+                &lua,
+                r#"
+                local args = { "a b", "it's", 'say "hi"', "", "$HOME" }
+                local cmd = "printf '[%s]' " .. util.shell_quote_all(args)
+                return util.shell_execute("sh", { "-c", cmd }).stdout
+                "#,
+            );
+            assert_eq!(res, r#"[a b][it's][say "hi"][][$HOME]"#);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dirname_basename() {
+        let lua = lua();
+        let dirname = |path: &str| -> Option<String> {
+            eval(&lua, &format!("return util.dirname({:?})", path))
+        };
+        let basename = |path: &str| -> Option<String> {
+            eval(&lua, &format!("return util.basename({:?})", path))
+        };
+
+        let cases = [
+            // path, dirname, basename
+            ("/foo/bar", Some("/foo"), Some("bar")),
+            ("/foo/bar/", Some("/foo"), Some("bar")),
+            ("/foo/bar//", Some("/foo"), Some("bar")),
+            ("/foo", Some("/"), Some("foo")),
+            ("/foo/", Some("/"), Some("foo")),
+            ("foo/bar", Some("foo"), Some("bar")),
+            ("foo", None, Some("foo")),
+            ("foo/", None, Some("foo")),
+            ("/", None, None),
+            ("//", None, None),
+            ("", None, None),
+            (".", None, None),
+            ("./foo", Some("."), Some("foo")),
+            ("..", None, None),
+            ("../foo", Some(".."), Some("foo")),
+            ("/foo/..", Some("/foo"), None),
+            ("/foo/../", Some("/foo"), None),
+            (".hidden", None, Some(".hidden")),
+        ];
+
+        for (path, dir, base) in cases {
+            assert_eq!(dirname(path).as_deref(), dir, "dirname({:?})", path);
+            assert_eq!(basename(path).as_deref(), base, "basename({:?})", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_absolute() {
+        let lua = lua();
+        let pwd = std::env::current_dir().unwrap();
+        let absolute = |path: &str| -> String {
+            eval(&lua, &format!("return util.absolute({:?})", path))
+        };
+
+        assert_eq!(absolute("foo/../bar"), pwd.join("bar").to_string_lossy());
+        assert_eq!(
+            absolute("../foo"),
+            pwd.parent().unwrap().join("foo").to_string_lossy()
+        );
+        assert_eq!(absolute("foo/."), pwd.join("foo").to_string_lossy());
+        assert_eq!(
+            absolute("./foo/./bar/"),
+            pwd.join("foo/bar").to_string_lossy()
+        );
+        assert_eq!(absolute("/a/b/../../.."), "/");
+        assert_eq!(absolute("/a/./b/../c"), "/a/c");
+    }
+
+    #[test]
+    fn test_realpath() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let root = root.canonicalize().unwrap();
+        fs::write(root.join("target"), "").unwrap();
+        std::os::unix::fs::symlink(root.join("target"), root.join("link1")).unwrap();
+        std::os::unix::fs::symlink("link1", root.join("link2")).unwrap();
+
+        let (path, err): (Option<String>, Option<String>) = eval(
+            &lua,
+            &format!(
+                "return util.realpath({:?})",
+                root.join("link2").to_string_lossy()
+            ),
+        );
+        assert_eq!(
+            path,
+            Some(root.join("target").to_string_lossy().to_string())
+        );
+        assert_eq!(err, None);
+
+        let (path, err): (Option<String>, Option<String>) = eval(
+            &lua,
+            &format!(
+                "return util.realpath({:?})",
+                root.join("x/../link1").to_string_lossy()
+            ),
+        );
+        assert_eq!(path, None);
+        assert!(err.is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_predicates() {
+        use std::os::unix::fs::symlink;
+
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::write(root.join("file"), "").unwrap();
+        fs::create_dir(root.join("dir")).unwrap();
+        symlink(root.join("file"), root.join("file-link")).unwrap();
+        symlink(root.join("dir"), root.join("dir-link")).unwrap();
+        symlink(root.join("nothing"), root.join("broken")).unwrap();
+
+        let check = |func: &str, name: &str, follow: bool| -> bool {
+            let path = root.join(name);
+            eval(
+                &lua,
+                &format!(
+                    "return util.{}({:?}, {{ follow = {} }})",
+                    func,
+                    path.to_string_lossy(),
+                    follow
+                ),
+            )
+        };
+
+        // (name, exists, is_dir, is_file) when following, and not following.
+        let cases = [
+            ("file", (true, false, true), (true, false, true)),
+            ("dir", (true, true, false), (true, true, false)),
+            ("file-link", (true, false, true), (true, false, false)),
+            ("dir-link", (true, true, false), (true, false, false)),
+            ("broken", (false, false, false), (true, false, false)),
+            ("nothing", (false, false, false), (false, false, false)),
+        ];
+
+        for (name, followed, not_followed) in cases {
+            for (follow, expected) in [(true, followed), (false, not_followed)] {
+                let actual = (
+                    check("exists", name, follow),
+                    check("is_dir", name, follow),
+                    check("is_file", name, follow),
+                );
+                assert_eq!(actual, expected, "{} follow={}", name, follow);
+            }
+        }
+
+        let is_symlink = |name: &str| -> bool {
+            let path = root.join(name);
+            eval(
+                &lua,
+                &format!("return util.is_symlink({:?})", path.to_string_lossy()),
+            )
+        };
+
+        assert!(!is_symlink("file"));
+        assert!(!is_symlink("dir"));
+        assert!(is_symlink("file-link"));
+        assert!(is_symlink("dir-link"));
+        assert!(is_symlink("broken"));
+        assert!(!is_symlink("nothing"));
+
+        // Follows by default.
+        let res: bool = eval(
+            &lua,
+            &format!(
+                "return util.is_dir({:?})",
+                root.join("dir-link").to_string_lossy()
+            ),
+        );
+        assert!(res);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_uid_gid_to_name() {
+        let lua = lua();
+
+        let name: Option<String> = eval(&lua, "return util.uid_to_name(0)");
+        assert_eq!(name.as_deref(), Some("root"));
+
+        let name: Option<String> = eval(&lua, "return util.gid_to_name(0)");
+        assert!(name.is_some());
+
+        let name: Option<String> = eval(&lua, "return util.uid_to_name(3999999999)");
+        assert_eq!(name, None);
+    }
+
+    #[test]
+    fn test_explore() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::write(root.join("foo"), "").unwrap();
+
+        let (count, errors): (usize, usize) = eval(
+            &lua,
+            &format!(
+                "local nodes, errors = util.explore({:?}); return #nodes, #errors",
+                root.to_string_lossy()
+            ),
+        );
+        assert_eq!((count, errors), (1, 0));
+
+        fs::write(root.join("bar"), "").unwrap();
+        fs::write(root.join("baz"), "").unwrap();
+        let (paths, total_count): (Vec<String>, usize) = eval(
+            &lua,
+            &format!(
+                r#"
+                local config = {{
+                  sorters = {{ {{ sorter = "ByRelativePath", reverse = false }} }},
+                  filters = {{}},
+                  offset = 1,
+                  limit = 1,
+                }}
+                local nodes, _, total_count = util.explore({:?}, config)
+                return {{ nodes[1].relative_path }}, total_count
+                "#,
+                root.to_string_lossy()
+            ),
+        );
+        assert_eq!((paths, total_count), (vec!["baz".to_string()], 3));
+
+        let res: mlua::Result<Value> =
+            lua.load("util.explore('/there/is/no/path')").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_which() {
+        let lua = lua();
+        let program = if cfg!(windows) { "cmd" } else { "sh" };
+
+        let path: Option<String> =
+            eval(&lua, &format!("return util.which({:?})", program));
+        let path = PathBuf::from(path.unwrap());
+        assert!(path.is_absolute());
+        assert!(path.is_file());
+
+        let path: Option<String> =
+            eval(&lua, "return util.which('there-is-no-such-program')");
+        assert_eq!(path, None);
+    }
+
+    #[test]
+    fn test_expand_vars() {
+        let lookup = |name: &str| match name {
+            "FOO" => Some("foo".to_string()),
+            "FOO_2" => Some("foo2".to_string()),
+            _ => None,
+        };
+
+        assert_eq!(expand_vars("$FOO/x", lookup), "foo/x");
+        assert_eq!(expand_vars("${FOO}x", lookup), "foox");
+        assert_eq!(expand_vars("$FOO_2.$FOO", lookup), "foo2.foo");
+        assert_eq!(expand_vars("a$UNSET/b${UNSET}c", lookup), "a/bc");
+        assert_eq!(expand_vars("$", lookup), "$");
+        assert_eq!(expand_vars("5$ and $1", lookup), "5$ and $1");
+        assert_eq!(expand_vars("${FOO", lookup), "${FOO");
+        assert_eq!(expand_vars("${}", lookup), "${}");
+        assert_eq!(expand_vars("é$FOO", lookup), "éfoo");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_expand() {
+        let lua = lua();
+        std::env::set_var("XPLR_TEST_EXPAND", "xplr");
+        let home = dirs::home_dir().unwrap().to_string_lossy().to_string();
+        let expand = |path: &str| -> String {
+            eval(&lua, &format!("return util.expand({:?})", path))
+        };
+
+        assert_eq!(expand("~"), home);
+        assert_eq!(expand("~/foo"), format!("{}/foo", home));
+        assert_eq!(expand("/a/~/b"), "/a/~/b");
+        let root_home = home_dir_of("root").unwrap();
+        assert_eq!(expand("~root/foo"), root_home.join("foo").to_string_lossy());
+        assert_eq!(
+            expand("~there-is-no-such-user/foo"),
+            "~there-is-no-such-user/foo"
+        );
+        assert_eq!(expand("~/$XPLR_TEST_EXPAND"), format!("{}/xplr", home));
+        assert_eq!(expand("/${XPLR_TEST_EXPAND}/x"), "/xplr/x");
+        assert_eq!(expand("/$XPLR_TEST_UNSET/x"), "//x");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        let lua = lua();
+        let parse = |size: &str| -> u64 {
+            eval(&lua, &format!("return util.parse_size({:?})", size))
+        };
+
+        assert_eq!(parse("10MiB"), 10485760);
+        assert_eq!(parse("10"), 10);
+        assert_eq!(parse("10B"), 10);
+        assert_eq!(parse("10M"), 10_000_000);
+        assert_eq!(parse("10 MB"), 10_000_000);
+        assert_eq!(parse("1.5 kb"), 1500);
+        assert_eq!(parse(" 2KiB "), 2048);
+        assert_eq!(parse("1GiB"), 1 << 30);
+        assert_eq!(parse("1TB"), 1_000_000_000_000);
+
+        for garbage in ["", "MB", "10XB", "1.2.3K", "10 i", "-1K"] {
+            let res: mlua::Result<u64> = lua
+                .load(format!("return util.parse_size({:?})", garbage))
+                .eval();
+            assert!(res.is_err(), "{}", garbage);
+        }
+    }
+
+    #[test]
+    fn test_human_size() {
+        let lua = lua();
+
+        let res: String = eval(&lua, "return util.human_size(10485760)");
+        assert_eq!(res, "10.49 MB");
+
+        let res: String =
+            eval(&lua, "return util.human_size(10485760, { binary = true })");
+        assert_eq!(res, "10 MiB");
+
+        for bytes in [0_u64, 999, 1234, 10485760, 123456789, 98765432109] {
+            for binary in [false, true] {
+                let parsed: u64 = eval(
+                    &lua,
+                    &format!(
+                        "return util.parse_size(util.human_size({}, {{ binary = {} }}))",
+                        bytes, binary
+                    ),
+                );
+                let diff = (parsed as f64 - bytes as f64).abs();
+                assert!(diff <= bytes as f64 * 0.005, "{} {}", bytes, parsed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_time() {
+        let lua = lua();
+
+        let res: Option<String> = eval(
+            &lua,
+            "return util.format_time(1669736617, nil, { utc = true })",
+        );
+        assert_eq!(res.as_deref(), Some("2022-11-29T15:43:37+00:00"));
+
+        let res: Option<String> = eval(
+            &lua,
+            "return util.format_time(1669736617.5, '%d %b %Y %H:%M:%S%.3f', { utc = true })",
+        );
+        assert_eq!(res.as_deref(), Some("29 Nov 2022 15:43:37.500"));
+
+        let res: Option<String> = eval(&lua, "return util.format_time(1669736617)");
+        assert!(res.is_some());
+
+        let res: Option<String> =
+            eval(&lua, "return util.format_time(1e300, nil, { utc = true })");
+        assert_eq!(res, None);
+
+        let res: Option<String> =
+            eval(&lua, "return util.format_time(0/0, nil, { utc = true })");
+        assert_eq!(res, None);
+
+        let res: mlua::Result<Option<String>> =
+            lua.load("return util.format_time(0, '%Q')").eval();
+        assert!(res.is_err());
+
+        let offset = chrono::FixedOffset::east_opt(5 * 3600 + 1800).unwrap();
+        let res = format_timestamp(1669736617.0, "%Y-%m-%dT%H:%M:%S%:z", &offset);
+        assert_eq!(res, Ok(Some("2022-11-29T21:13:37+05:30".to_string())));
+
+        let offset = chrono::FixedOffset::west_opt(8 * 3600).unwrap();
+        let res = format_timestamp(-1.0, "%Y-%m-%d %H:%M:%S %z", &offset);
+        assert_eq!(res, Ok(Some("1969-12-31 15:59:59 -0800".to_string())));
+    }
+
+    #[test]
+    fn test_relative_time() {
+        let lua = lua();
+        let relative = |ts: i64, now: i64| -> String {
+            eval(&lua, &format!("return util.relative_time({}, {})", ts, now))
+        };
+
+        let now = 1669736617;
+        assert_eq!(relative(now, now), "just now");
+        assert_eq!(relative(now - 9, now), "just now");
+        assert_eq!(relative(now + 9, now), "just now");
+        assert_eq!(relative(now - 10, now), "10 seconds ago");
+        assert_eq!(relative(now - 59, now), "59 seconds ago");
+        assert_eq!(relative(now - 60, now), "1 minute ago");
+        assert_eq!(relative(now - 179, now), "2 minutes ago");
+        assert_eq!(relative(now - 3600, now), "1 hour ago");
+        assert_eq!(relative(now - 86399, now), "23 hours ago");
+        assert_eq!(relative(now - 5 * 86400, now), "5 days ago");
+        assert_eq!(relative(now - 29 * 86400, now), "29 days ago");
+        assert_eq!(relative(now - 30 * 86400, now), "1 month ago");
+        assert_eq!(relative(now - 364 * 86400, now), "12 months ago");
+        assert_eq!(relative(now - 2 * 365 * 86400, now), "2 years ago");
+
+        assert_eq!(relative(now + 45, now), "in 45 seconds");
+        assert_eq!(relative(now + 7200, now), "in 2 hours");
+        assert_eq!(relative(now + 86400, now), "in 1 day");
+
+        let res: String = eval(&lua, "return util.relative_time(os.time() - 120)");
+        assert_eq!(res, "2 minutes ago");
+    }
+
+    #[test]
+    fn test_regex_match() {
+        let lua = lua();
+
+        let res: Option<Vec<String>> = eval(
+            &lua,
+            r#"return util.regex_match([[(\w+)\.(\w+)$]], "/tmp/foo.txt")"#,
+        );
+        assert_eq!(
+            res,
+            Some(vec!["foo.txt".into(), "foo".into(), "txt".into()])
+        );
+
+        let res: Option<Vec<String>> = eval(
+            &lua,
+            r#"return util.regex_match("(?i)^readme", "README.md")"#,
+        );
+        assert_eq!(res, Some(vec!["README".into()]));
+
+        let res: Option<Vec<String>> =
+            eval(&lua, r#"return util.regex_match("^readme", "README.md")"#);
+        assert_eq!(res, None);
+
+        let res: bool = eval(
+            &lua,
+            r#"
+            local m = util.regex_match("(a)(x)?(b)", "ab")
+            return m[1] == "ab" and m[2] == "a" and m[3] == nil and m[4] == "b"
+            "#,
+        );
+        assert!(res);
+
+        let res: mlua::Result<Value> = lua.load("util.regex_match('(', 'a')").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_regex_replace() {
+        let lua = lua();
+
+        let res: String = eval(
+            &lua,
+            r#"return util.regex_replace([[(\w+)\.txt]], "$1.md", "foo.txt bar.txt")"#,
+        );
+        assert_eq!(res, "foo.md bar.txt");
+
+        let res: String = eval(
+            &lua,
+            r#"return util.regex_replace([[(\w+)\.txt]], "${1}_$$.md", "foo.txt bar.txt", { all = true })"#,
+        );
+        assert_eq!(res, "foo_$.md bar_$.md");
+
+        let res: String = eval(
+            &lua,
+            r#"return util.regex_replace("(?i)X", "y", "xXx", { all = true })"#,
+        );
+        assert_eq!(res, "yyy");
+
+        let res: String = eval(&lua, r#"return util.regex_replace("z", "y", "xXx")"#);
+        assert_eq!(res, "xXx");
+
+        let res: mlua::Result<Value> =
+            lua.load("util.regex_replace('[', 'a', 'a')").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_truncate() {
+        let lua = lua();
+        let truncate = |args: &str| -> String {
+            eval(&lua, &format!("return util.truncate({})", args))
+        };
+
+        assert_eq!(truncate("'foo', 3"), "foo");
+        assert_eq!(truncate("'foobar', 4"), "foo…");
+        assert_eq!(truncate("'foobar', 4, { ellipsis = '...' }"), "f...");
+        assert_eq!(truncate("'foobar', 2, { ellipsis = '...' }"), "..");
+        assert_eq!(truncate("'foobar', 0"), "");
+        assert_eq!(truncate("'foobar', 4, { ellipsis = '' }"), "foob");
+
+        // Wide characters take 2 columns.
+        assert_eq!(truncate("'日本語のテキスト', 7"), "日本語…");
+        assert_eq!(truncate("'日本語のテキスト', 8"), "日本語…");
+        assert_eq!(truncate("'日本語', 6"), "日本語");
+        assert_eq!(truncate("'🦀🦀🦀', 5"), "🦀🦀…");
+        assert_eq!(truncate("'a🦀b', 3"), "a…");
+
+        // Combining characters are kept with their base character.
+        assert_eq!(truncate("'e\\u{301}e\\u{301}e\\u{301}', 2"), "e\u{301}…");
+        assert_eq!(truncate("'a\\u{200b}bc', 2"), "a\u{200b}…");
+
+        assert_eq!(
+            truncate("'/home/user/projects/xplr', 12, { from = 'left' }"),
+            "…ojects/xplr"
+        );
+        assert_eq!(truncate("'/home/日本語', 5, { from = 'left' }"), "…本語");
+
+        for width in 0..12 {
+            let res = truncate(&format!("'a日🦀e\\u{{301}}本語x', {}", width));
+            assert!(res.width() <= width, "{} {}", width, res);
+        }
+    }
+
+    #[test]
+    fn test_wrap() {
+        let lua = lua();
+        let wrap = |args: &str| -> Vec<String> {
+            eval(&lua, &format!("return util.wrap({})", args))
+        };
+
+        assert_eq!(
+            wrap("'The quick brown fox jumps', 10"),
+            vec!["The quick", "brown fox", "jumps"]
+        );
+        assert_eq!(
+            wrap("'Supercalifragilistic word', 10"),
+            vec!["Supercalifragilistic", "word"]
+        );
+        assert_eq!(
+            wrap("'Supercalifragilistic word', 10, { break_words = true }"),
+            vec!["Supercalif", "ragilistic", "word"]
+        );
+        assert_eq!(
+            wrap("'one two\\nthree\\n\\nfour five six', 8"),
+            vec!["one two", "three", "", "four", "five six"]
+        );
+
+        // Wide characters take 2 columns.
+        assert_eq!(wrap("'日本語 テキスト', 8"), vec!["日本語", "テキスト"]);
+
+        assert_eq!(wrap("'', 10"), vec![""]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lscolors() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("foo.tar"), "").unwrap();
+        fs::write(root.join("FOO.TAR"), "").unwrap();
+        fs::write(root.join("foo.txt"), "").unwrap();
+        fs::write(root.join("exe"), "").unwrap();
+        fs::set_permissions(root.join("exe"), fs::Permissions::from_mode(0o755))
+            .unwrap();
+        symlink(root.join("dir"), root.join("link")).unwrap();
+        symlink(root.join("nothing"), root.join("broken")).unwrap();
+
+        lua.load(
+            "lscolors = util.parse_lscolors('di=1;34:ln=4;36:or=31;41:ex=38;5;208:*.tar=38;2;1;2;3')",
+        )
+        .exec()
+        .unwrap();
+
+        let style = |name: &str| -> Style {
+            let path = root.join(name).to_string_lossy().to_string();
+            let style: Value =
+                eval(&lua, &format!("return lscolors:style_for({:?})", path));
+            lua.from_value(style).unwrap()
+        };
+
+        let modifiers =
+            |m: &[Modifier]| Some(m.iter().cloned().collect::<IndexSet<_>>());
+
+        assert_eq!(style("dir").fg, Some(Color::Blue));
+        assert_eq!(style("dir").add_modifiers, modifiers(&[Modifier::Bold]));
+        assert_eq!(style("link").fg, Some(Color::Cyan));
+        assert_eq!(
+            style("link").add_modifiers,
+            modifiers(&[Modifier::Underlined])
+        );
+        assert_eq!(style("broken").fg, Some(Color::Red));
+        assert_eq!(style("broken").bg, Some(Color::Red));
+        assert_eq!(style("exe").fg, Some(Color::Indexed(208)));
+        assert_eq!(style("foo.tar").fg, Some(Color::Rgb(1, 2, 3)));
+        assert_eq!(style("FOO.TAR").fg, Some(Color::Rgb(1, 2, 3)));
+        assert_eq!(style("foo.txt"), Style::default());
+
+        // Nodes work too, and the defaults of dircolors apply.
+        let res: bool = eval(
+            &lua,
+            &format!(
+                r#"
+                local node = {{ absolute_path = {:?} }}
+                local style = util.parse_lscolors(""):style_for(node)
+                return style.fg == "Blue"
+                "#,
+                root.join("dir").to_string_lossy()
+            ),
+        );
+        assert!(res);
+
+        let res: Value = eval(
+            &lua,
+            &format!("return util.lscolor_for({:?})", root.to_string_lossy()),
+        );
+        assert!(matches!(res, Value::Table(_)));
+
+        let res: mlua::Result<Value> = lua.load("util.lscolor_for(1)").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_icon() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("main.rs"), "").unwrap();
+        fs::write(root.join("unknown.xyz"), "").unwrap();
+        let path = |name: &str| root.join(name).to_string_lossy().to_string();
+
+        let icon = |code: String| -> String { eval(&lua, &code) };
+
+        assert_eq!(
+            icon(format!("return util.icon({:?})", path("main.rs"))),
+            "\u{e7a8}"
+        );
+        assert_eq!(
+            icon(format!("return util.icon({:?})", path("MAIN.RS"))),
+            "\u{e7a8}"
+        );
+        assert_eq!(
+            icon(format!("return util.icon({:?})", path("dir"))),
+            "\u{f115}"
+        );
+        assert_eq!(
+            icon(format!("return util.icon({:?})", path("unknown.xyz"))),
+            "\u{f15b}"
+        );
+        assert_eq!(icon("return util.icon('Cargo.toml')".into()), "\u{e7a8}");
+
+        // Nodes are checked using their fields.
+        assert_eq!(
+            icon("return util.icon({ relative_path = 'x', extension = 'rs' })".into()),
+            "\u{e7a8}"
+        );
+        assert_eq!(
+            icon("return util.icon({ relative_path = 'x', is_dir = true })".into()),
+            "\u{f115}"
+        );
+        assert_eq!(
+            icon(
+                "return util.icon({ relative_path = 'x', is_dir = true, is_symlink = true })"
+                    .into()
+            ),
+            "\u{f481}"
+        );
+        assert_eq!(
+            icon(
+                "return util.icon({ relative_path = 'x', permissions = { user_execute = true } })"
+                    .into()
+            ),
+            "\u{f489}"
+        );
+        assert_eq!(
+            icon(
+                "return util.icon({ relative_path = 'x/Cargo.toml', extension = 'toml' })"
+                    .into()
+            ),
+            "\u{e7a8}"
+        );
+
+        // Overrides
+        assert_eq!(
+            icon(format!(
+                "return util.icon({:?}, {{ extensions = {{ RS = 'R' }} }})",
+                path("main.rs")
+            )),
+            "R"
+        );
+        assert_eq!(
+            icon(format!(
+                "return util.icon({:?}, {{ directory = 'D' }})",
+                path("dir")
+            )),
+            "D"
+        );
+        assert_eq!(
+            icon(format!(
+                "return util.icon({:?}, {{ default = '?' }})",
+                path("unknown.xyz")
+            )),
+            "?"
+        );
+        assert_eq!(
+            icon(format!(
+                "return util.icon({:?}, {{ default = '?' }})",
+                path("main.rs")
+            )),
+            "\u{e7a8}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let src = root.join("src");
+        fs::create_dir_all(src.join("sub").join("deeper")).unwrap();
+        fs::write(src.join("a.txt"), "foo").unwrap();
+        fs::write(src.join("sub").join("b.txt"), "hello").unwrap();
+        fs::write(src.join("sub").join("deeper").join("c.sh"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(
+            src.join("sub").join("deeper").join("c.sh"),
+            fs::Permissions::from_mode(0o750),
+        )
+        .unwrap();
+        symlink("a.txt", src.join("link")).unwrap();
+
+        lua.load(format!(
+            r#"
+            progress = {{}}
+            summary, err = util.copy({:?}, {:?}, {{
+              on_progress = function(copied, total)
+                table.insert(progress, {{ copied, total }})
+              end,
+            }})
+            "#,
+            src.to_string_lossy(),
+            root.join("dst").to_string_lossy()
+        ))
+        .exec()
+        .unwrap();
+
+        let (files, bytes, err): (u64, u64, Option<String>) =
+            eval(&lua, "return summary.files, summary.bytes, err");
+        assert_eq!((files, bytes, err), (4, 18, None));
+
+        let (n, copied, total): (usize, u64, u64) = eval(
+            &lua,
+            "return #progress, progress[#progress][1], progress[#progress][2]",
+        );
+        assert_eq!((n, copied, total), (4, 18, 18));
+
+        let dst = root.join("dst");
+        assert_eq!(fs::read_to_string(dst.join("a.txt")).unwrap(), "foo");
+        assert_eq!(
+            fs::read_to_string(dst.join("sub").join("b.txt")).unwrap(),
+            "hello"
+        );
+        let mode = fs::metadata(dst.join("sub").join("deeper").join("c.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o750);
+        assert_eq!(fs::read_link(dst.join("link")).unwrap(), Path::new("a.txt"));
+
+        // Existing files are kept unless overwriting.
+        fs::write(dst.join("a.txt"), "changed").unwrap();
+        let (files, err): (u64, Option<String>) = eval(
+            &lua,
+            &format!(
+                "local s, e = util.copy({:?}, {:?}); return s.files, e",
+                src.join("a.txt").to_string_lossy(),
+                dst.join("a.txt").to_string_lossy()
+            ),
+        );
+        assert_eq!(files, 0);
+        assert!(err.unwrap().contains("a.txt"));
+        assert_eq!(fs::read_to_string(dst.join("a.txt")).unwrap(), "changed");
+
+        let (files, err): (u64, Option<String>) = eval(
+            &lua,
+            &format!(
+                "local s, e = util.copy({:?}, {:?}, {{ overwrite = true }}); return s.files, e",
+                src.to_string_lossy(),
+                dst.to_string_lossy()
+            ),
+        );
+        assert_eq!((files, err), (4, None));
+        assert_eq!(fs::read_to_string(dst.join("a.txt")).unwrap(), "foo");
+
+        // Symlinks can be followed.
+        let (files, err): (u64, Option<String>) = eval(
+            &lua,
+            &format!(
+                "local s, e = util.copy({:?}, {:?}, {{ follow_symlinks = true }}); return s.files, e",
+                src.join("link").to_string_lossy(),
+                root.join("followed").to_string_lossy()
+            ),
+        );
+        assert_eq!((files, err), (1, None));
+        assert!(!fs::symlink_metadata(root.join("followed"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+
+        // Following a symlink loop fails instead of recursing forever.
+        symlink("..", src.join("sub").join("loop")).unwrap();
+        let err: Option<String> = eval(
+            &lua,
+            &format!(
+                r#"
+                local _, e = util.copy({:?}, {:?}, {{
+                  follow_symlinks = true,
+                  on_progress = function() end,
+                }})
+                return e
+                "#,
+                src.to_string_lossy(),
+                root.join("looped").to_string_lossy()
+            ),
+        );
+        assert!(err.unwrap().ends_with("loop: symlink loop"));
+        fs::remove_file(src.join("sub").join("loop")).unwrap();
+
+        // Errors partway report what was copied so far.
+        fs::set_permissions(
+            src.join("sub").join("b.txt"),
+            fs::Permissions::from_mode(0o000),
+        )
+        .unwrap();
+        let readable = fs::File::open(src.join("sub").join("b.txt")).is_ok();
+        if !readable {
+            let (files, err): (u64, Option<String>) = eval(
+                &lua,
+                &format!(
+                    "local s, e = util.copy({:?}, {:?}); return s.files, e",
+                    src.join("sub").to_string_lossy(),
+                    root.join("partial").to_string_lossy()
+                ),
+            );
+            assert!(files <= 1);
+            assert!(err.unwrap().contains("b.txt"));
+        }
+        fs::set_permissions(
+            src.join("sub").join("b.txt"),
+            fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+
+        let res: Option<String> = eval(
+            &lua,
+            &format!(
+                "local _, e = util.copy({:?}, {:?}); return e",
+                src.to_string_lossy(),
+                src.join("inner").to_string_lossy()
+            ),
+        );
+        assert!(res.unwrap().contains("into itself"));
+    }
+
+    #[test]
+    fn test_move() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::write(root.join("foo"), "foo").unwrap();
+        fs::create_dir(root.join("sub")).unwrap();
+        let path = |name: &str| root.join(name).to_string_lossy().to_string();
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!("return util.move({:?}, {:?})", path("foo"), path("bar")),
+        );
+        assert_eq!(err, None);
+        assert!(!root.join("foo").exists());
+        assert_eq!(fs::read_to_string(root.join("bar")).unwrap(), "foo");
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!("return util.move({:?}, {:?})", path("bar"), path("sub/bar")),
+        );
+        assert_eq!(err, None);
+        assert_eq!(
+            fs::read_to_string(root.join("sub").join("bar")).unwrap(),
+            "foo"
+        );
+
+        // Existing destinations are kept unless overwriting.
+        fs::write(root.join("baz"), "baz").unwrap();
+        let err: Option<String> = eval(
+            &lua,
+            &format!("return util.move({:?}, {:?})", path("baz"), path("sub/bar")),
+        );
+        assert!(err.is_some());
+        assert_eq!(
+            fs::read_to_string(root.join("sub").join("bar")).unwrap(),
+            "foo"
+        );
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!(
+                "return util.move({:?}, {:?}, {{ overwrite = true }})",
+                path("baz"),
+                path("sub/bar")
+            ),
+        );
+        assert_eq!(err, None);
+        assert_eq!(
+            fs::read_to_string(root.join("sub").join("bar")).unwrap(),
+            "baz"
+        );
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!("return util.move({:?}, {:?})", path("sub"), path("a/b/sub")),
+        );
+        assert!(err.is_some());
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!(
+                "return util.move({:?}, {:?}, {{ create_dirs = true }})",
+                path("sub"),
+                path("a/b/sub")
+            ),
+        );
+        assert_eq!(err, None);
+        assert!(root.join("a").join("b").join("sub").join("bar").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_move_cross_device() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("src").join("sub")).unwrap();
+        fs::write(root.join("src").join("sub").join("foo"), "foo").unwrap();
+        fs::write(root.join("file"), "file").unwrap();
+
+        let exdev = |_: &Path, _: &Path| Err(io::Error::from_raw_os_error(libc::EXDEV));
+        let options = MoveOptions::default();
+
+        move_path(&root.join("src"), &root.join("dst"), &options, exdev).unwrap();
+        assert!(!root.join("src").exists());
+        assert_eq!(
+            fs::read_to_string(root.join("dst").join("sub").join("foo")).unwrap(),
+            "foo"
+        );
+
+        move_path(&root.join("file"), &root.join("moved"), &options, exdev).unwrap();
+        assert!(!root.join("file").exists());
+        assert_eq!(fs::read_to_string(root.join("moved")).unwrap(), "file");
+
+        // Other errors are not retried.
+        let denied = |_: &Path, _: &Path| Err(io::ErrorKind::PermissionDenied.into());
+        assert!(
+            move_path(&root.join("moved"), &root.join("x"), &options, denied).is_err()
+        );
+        assert!(root.join("moved").exists());
+    }
+
+    #[test]
+    fn test_mkdir_remove() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let path = |name: &str| root.join(name).to_string_lossy().to_string();
+
+        let err: Option<String> =
+            eval(&lua, &format!("return util.mkdir({:?})", path("a/b/c")));
+        assert!(err.is_some());
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!(
+                "return util.mkdir({:?}, {{ parents = true }})",
+                path("a/b/c")
+            ),
+        );
+        assert_eq!(err, None);
+        assert!(root.join("a").join("b").join("c").is_dir());
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!(
+                "return util.mkdir({:?}, {{ parents = true }})",
+                path("a/b/c")
+            ),
+        );
+        assert_eq!(err, None);
+
+        let err: Option<String> =
+            eval(&lua, &format!("return util.mkdir({:?})", path("a/b/c")));
+        assert!(err.is_some());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let err: Option<String> = eval(
+                &lua,
+                &format!(
+                    "return util.mkdir({:?}, {{ mode = 448 }})",
+                    path("a/private")
+                ),
+            );
+            assert_eq!(err, None);
+            let mode = fs::metadata(root.join("a").join("private"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        fs::write(root.join("a").join("b").join("file"), "foo").unwrap();
+
+        let err: Option<String> =
+            eval(&lua, &format!("return util.remove({:?})", path("a")));
+        assert!(err.is_some());
+        assert!(root.join("a").exists());
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!(
+                "return util.remove({:?}, {{ recursive = true }})",
+                path("a")
+            ),
+        );
+        assert_eq!(err, None);
+        assert!(!root.join("a").exists());
+
+        let err: Option<String> =
+            eval(&lua, &format!("return util.remove({:?})", path("a")));
+        assert!(err.is_some());
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!("return util.remove({:?}, {{ force = true }})", path("a")),
+        );
+        assert_eq!(err, None);
+
+        #[cfg(unix)]
+        {
+            fs::create_dir(root.join("target")).unwrap();
+            fs::write(root.join("target").join("file"), "foo").unwrap();
+            std::os::unix::fs::symlink(root.join("target"), root.join("link")).unwrap();
+
+            let err: Option<String> = eval(
+                &lua,
+                &format!(
+                    "return util.remove({:?}, {{ recursive = true }})",
+                    path("link")
+                ),
+            );
+            assert_eq!(err, None);
+            assert!(fs::symlink_metadata(root.join("link")).is_err());
+            assert!(root.join("target").join("file").exists());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_readlink() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::write(root.join("foo"), "foo").unwrap();
+        fs::write(root.join("bar"), "bar").unwrap();
+        let path = |name: &str| root.join(name).to_string_lossy().to_string();
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!("return util.symlink('foo', {:?})", path("link")),
+        );
+        assert_eq!(err, None);
+        assert_eq!(fs::read_to_string(root.join("link")).unwrap(), "foo");
+
+        let target: Option<String> =
+            eval(&lua, &format!("return util.readlink({:?})", path("link")));
+        assert_eq!(target.as_deref(), Some("foo"));
+
+        let target: Option<String> =
+            eval(&lua, &format!("return util.readlink({:?})", path("foo")));
+        assert_eq!(target, None);
+
+        let (target, err): (Option<String>, Option<String>) = eval(
+            &lua,
+            &format!("return util.readlink({:?})", path("nothing")),
+        );
+        assert_eq!(target, None);
+        assert!(err.is_some());
+
+        // Existing links are replaced only when forced.
+        let err: Option<String> = eval(
+            &lua,
+            &format!("return util.symlink('bar', {:?})", path("link")),
+        );
+        assert!(err.is_some());
+        assert_eq!(fs::read_link(root.join("link")).unwrap(), Path::new("foo"));
+
+        let err: Option<String> = eval(
+            &lua,
+            &format!(
+                "return util.symlink('bar', {:?}, {{ force = true }})",
+                path("link")
+            ),
+        );
+        assert_eq!(err, None);
+        assert_eq!(fs::read_link(root.join("link")).unwrap(), Path::new("bar"));
+        assert_eq!(fs::read_to_string(root.join("foo")).unwrap(), "foo");
+
+        fs::create_dir(root.join("dir")).unwrap();
+        let err: Option<String> = eval(
+            &lua,
+            &format!(
+                "return util.symlink('bar', {:?}, {{ force = true }})",
+                path("dir")
+            ),
+        );
+        assert!(err.is_some());
+        assert!(root.join("dir").is_dir());
+
+        let res: mlua::Result<Value> = lua
+            .load("util.symlink('a', 'b', { kind = 'socket' })")
+            .eval();
+        assert!(res.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dir_size() {
+        use std::os::unix::fs::symlink;
+
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let dir = root.join("dir");
+        fs::create_dir_all(dir.join("a").join("b")).unwrap();
+        fs::write(dir.join("foo"), "foo").unwrap();
+        fs::write(dir.join("a").join("bar"), "hello").unwrap();
+        fs::write(dir.join("a").join("b").join("baz"), "1234567890").unwrap();
+        fs::hard_link(dir.join("foo"), dir.join("a").join("foo")).unwrap();
+        symlink(&dir, dir.join("a").join("b").join("loop")).unwrap();
+
+        let size = |options: &str| -> (u64, u64, u64, Option<String>) {
+            eval(
+                &lua,
+                &format!(
+                    "local s, e = util.dir_size({:?}, {}); return s.bytes, s.files, s.dirs, e",
+                    dir.to_string_lossy(),
+                    options
+                ),
+            )
+        };
+
+        // The symlink counts as a file.
+        let link_len = fs::symlink_metadata(dir.join("a").join("b").join("loop"))
+            .unwrap()
+            .len();
+        assert_eq!(size("nil"), (21 + link_len, 5, 2, None));
+        assert_eq!(
+            size("{ dedup_hardlinks = true }"),
+            (18 + link_len, 4, 2, None)
+        );
+        assert_eq!(size("{ max_depth = 1 }"), (3, 1, 1, None));
+        assert_eq!(size("{ max_depth = 2 }"), (11, 3, 2, None));
+        assert_eq!(size("{ max_depth = 0 }"), size("nil"));
+
+        // The loop is only walked once.
+        assert_eq!(size("{ follow_symlinks = true }"), (21, 4, 2, None));
+
+        let (calls, err): (u64, Option<String>) = eval(
+            &lua,
+            &format!(
+                r#"
+                local calls = 0
+                local _, err = util.dir_size({:?}, {{
+                  on_progress = function() calls = calls + 1 end,
+                }})
+                return calls, err
+                "#,
+                dir.to_string_lossy()
+            ),
+        );
+        assert_eq!((calls, err), (0, None));
+
+        let many = root.join("many");
+        fs::create_dir(&many).unwrap();
+        (0..2500).for_each(|i| fs::write(many.join(i.to_string()), "").unwrap());
+        let (calls, files): (u64, u64) = eval(
+            &lua,
+            &format!(
+                r#"
+                local calls, files = 0, 0
+                util.dir_size({:?}, {{
+                  on_progress = function(_, f) calls, files = calls + 1, f end,
+                }})
+                return calls, files
+                "#,
+                many.to_string_lossy()
+            ),
+        );
+        assert_eq!((calls, files), (2, 2000));
+
+        let (files, err): (u64, Option<String>) = eval(
+            &lua,
+            &format!(
+                "local s, e = util.dir_size({:?}); return s.files, e",
+                root.join("nothing").to_string_lossy()
+            ),
+        );
+        assert_eq!(files, 0);
+        assert!(err.is_some());
+    }
+
+    #[test]
+    fn test_find() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("a").join("b")).unwrap();
+        fs::write(root.join("x.txt"), "").unwrap();
+        fs::write(root.join("a").join("b").join("y.txt"), "").unwrap();
+
+        let (n, path): (usize, String) = eval(
+            &lua,
+            &format!(
+                "local nodes = util.find({:?}, {{ pattern = '*.txt' }}); return #nodes, nodes[1].relative_path",
+                root.to_string_lossy()
+            ),
+        );
+        assert_eq!((n, path.as_str()), (2, "a/b/y.txt"));
+
+        let n: usize = eval(
+            &lua,
+            &format!(
+                "return #util.find({:?}, {{ type = 'dir', limit = 1 }})",
+                root.to_string_lossy()
+            ),
+        );
+        assert_eq!(n, 1);
+
+        let res: mlua::Result<Value> = lua
+            .load(format!(
+                "util.find({:?}, {{ type = 'x' }})",
+                root.to_string_lossy()
+            ))
+            .eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_url_encode_decode() {
+        let lua = lua();
+
+        let res: String = eval(&lua, "return util.url_encode('/tmp/a b?c=d&e=ü')");
+        assert_eq!(res, "/tmp/a%20b?c=d&e=%C3%BC");
+
+        let res: String = eval(
+            &lua,
+            "return util.url_encode('/tmp/a b?c=d', { component = true })",
+        );
+        assert_eq!(res, "%2Ftmp%2Fa%20b%3Fc%3Dd");
+
+        let res: String = eval(&lua, "return util.url_encode('a-b_c.d~e')");
+        assert_eq!(res, "a-b_c.d~e");
+
+        let res: String = eval(&lua, "return util.url_decode('/tmp/a%20b%3f%C3%BC')");
+        assert_eq!(res, "/tmp/a b?ü");
+
+        let res: bool = eval(
+            &lua,
+            "local s = 'a b/c?d=é&%' return util.url_decode(util.url_encode(s, { component = true })) == s",
+        );
+        assert!(res);
+
+        for invalid in ["%", "%2", "%zz", "a%g1"] {
+            let res: mlua::Result<Value> =
+                lua.load(format!("util.url_decode({:?})", invalid)).eval();
+            assert!(res.is_err(), "{}", invalid);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_path_to_uri() {
+        let lua = lua();
+
+        let res: String = eval(&lua, "return util.path_to_uri('/tmp/a b/café#1.txt')");
+        assert_eq!(res, "file:///tmp/a%20b/caf%C3%A9%231.txt");
+
+        let res: String = eval(
+            &lua,
+            "return util.uri_to_path(util.path_to_uri('/tmp/a b/café#1.txt'))",
+        );
+        assert_eq!(res, "/tmp/a b/café#1.txt");
+
+        let res: String = eval(
+            &lua,
+            "return util.uri_to_path('file://localhost/tmp/a%20b')",
+        );
+        assert_eq!(res, "/tmp/a b");
+
+        let res: String = eval(&lua, "return util.path_to_uri('foo')");
+        assert!(res.starts_with("file:///"));
+        assert!(res.ends_with("/foo"));
+
+        for invalid in ["/tmp", "http://x/y", "file://host/tmp", "file:///%"] {
+            let res: mlua::Result<Value> =
+                lua.load(format!("util.uri_to_path({:?})", invalid)).eval();
+            assert!(res.is_err(), "{}", invalid);
+        }
+    }
+
+    #[cfg(feature = "notify")]
+    #[test]
+    fn test_notify() {
+        // Only build the notification, without showing it.
+        let lua = lua();
+        let options: NotifyOptions = lua
+            .load("return { summary = 'foo', body = 'bar', icon = 'xplr', timeout_ms = 100, urgency = 'critical' }")
+            .eval::<Table>()
+            .and_then(|t| lua.from_value(Value::Table(t)))
+            .unwrap();
+        let notification = notification(&options);
+        assert_eq!(notification.appname, "xplr");
+        assert_eq!(notification.summary, "foo");
+        assert_eq!(notification.body, "bar");
+        assert_eq!(notification.icon, "xplr");
+        assert_eq!(
+            notification.timeout,
+            notify_rust::Timeout::Milliseconds(100)
+        );
+
+        #[cfg(all(unix, not(target_os = "macos")))]
+        assert!(notification
+            .hints
+            .contains(&notify_rust::Hint::Urgency(notify_rust::Urgency::Critical)));
+
+        let res: mlua::Result<Value> = lua
+            .load("util.notify({ summary = 'foo', urgency = 'x' })")
+            .eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_open_commands() {
+        let path = "/tmp/a b.txt";
+
+        let commands = open::commands(path);
+        assert!(!commands.is_empty());
+        for cmd in commands.iter() {
+            assert!(cmd.get_args().any(|a| a.to_string_lossy().contains(path)));
+        }
+
+        let cmd = open::with_command(path, "myapp");
+        let mut words = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|w| w.to_string_lossy());
+        assert!(words.any(|w| w.contains("myapp")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_spawn_detached() {
+        let missing = || Command::new("xplr-test-missing-launcher");
+
+        assert_eq!(spawn_detached(vec![missing(), Command::new("true")]), None);
+
+        let err = spawn_detached(vec![missing()]).unwrap();
+        assert!(err.starts_with("xplr-test-missing-launcher: "));
+
+        assert_eq!(spawn_detached(vec![]).as_deref(), Some("no launcher found"));
+    }
+
+    #[test]
+    fn test_deep_merge() {
+        let lua = lua();
+
+        let res: bool = eval(
+            &lua,
+            r#"
+            local base = { a = { b = 1, c = { d = 2 } }, list = { 1, 2, 3 }, x = 1 }
+            local over = { a = { c = { e = 3 } }, list = { 4 }, x = { y = 1 } }
+            local res = util.deep_merge(base, over)
+
+            -- The inputs are unchanged.
+            assert(base.a.c.e == nil and over.a.b == nil)
+            assert(res.a ~= base.a and res.a.c ~= over.a.c)
+
+            return res.a.b == 1
+              and res.a.c.d == 2
+              and res.a.c.e == 3
+              and #res.list == 1
+              and res.list[1] == 4
+              and res.x.y == 1
+            "#,
+        );
+        assert!(res);
+
+        // Empty tables replace arrays, but not maps.
+        let res: usize = eval(
+            &lua,
+            "return #util.deep_merge({ list = { 1, 2 } }, { list = {} }).list",
+        );
+        assert_eq!(res, 0);
+
+        let res: i64 = eval(
+            &lua,
+            "return util.deep_merge({ map = { a = 1 } }, { map = {} }).map.a",
+        );
+        assert_eq!(res, 1);
+
+        let res: bool = eval(
+            &lua,
+            "local t = { a = {} }; t.a.self = t.a; local r = util.deep_merge({}, t); return r.a.self == r.a and r.a ~= t.a",
+        );
+        assert!(res);
+
+        let res: mlua::Result<Value> = lua
+            .load("local t = { a = 1 }; t.t = t; return util.deep_merge({ t = { a = 2 } }, t)")
+            .eval();
+        assert!(res.unwrap_err().to_string().contains("containing itself"));
+    }
+
+    #[test]
+    fn test_clone() {
+        let lua = lua();
+
+        let res: bool = eval(
+            &lua,
+            r#"
+            local t = { a = { b = { 1, 2 } }, s = "x" }
+            local c = util.clone(t)
+            c.a.b[1] = 10
+            return t.a.b[1] == 1 and c.a.b[1] == 10 and c.a ~= t.a and c.s == "x"
+            "#,
+        );
+        assert!(res);
+
+        let res: bool = eval(
+            &lua,
+            r#"
+            local t = { shared = {} }
+            t.self = t
+            t.other = t.shared
+            local c = util.clone(t)
+            return c.self == c and c ~= t and c.other == c.shared and c.shared ~= t.shared
+            "#,
+        );
+        assert!(res);
+
+        let res: (i64, Option<String>) =
+            eval(&lua, "return util.clone(1), util.clone(nil)");
+        assert_eq!(res, (1, None));
+    }
+
+    #[test]
+    fn test_version_compare() {
+        let lua = lua();
+        let cmp = |a: &str, b: &str| -> i8 {
+            eval(
+                &lua,
+                &format!("return util.version_compare({:?}, {:?})", a, b),
+            )
+        };
+
+        assert_eq!(cmp("1.2.3", "1.2.3"), 0);
+        assert_eq!(cmp("v1.2.3", "1.2.3"), 0);
+        assert_eq!(cmp("1.2", "1.2.0"), 0);
+        assert_eq!(cmp("1", "1.0.0"), 0);
+        assert_eq!(cmp("1.2.3", "1.10.0"), -1);
+        assert_eq!(cmp("2.0.0", "1.99.99"), 1);
+        assert_eq!(cmp("1.0.0+build.1", "1.0.0+build.2"), 0);
+
+        // Pre-release ordering, as in the semver specification.
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(cmp(pair[0], pair[1]), -1, "{:?}", pair);
+            assert_eq!(cmp(pair[1], pair[0]), 1, "{:?}", pair);
+        }
+        assert_eq!(cmp("v1.2-rc.1", "1.2.0-rc.1"), 0);
+
+        let res: mlua::Result<Value> =
+            lua.load("util.version_compare('1.2.3', 'foo')").eval();
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("invalid version: foo"));
+    }
+
+    #[test]
+    fn test_version_matches() {
+        let lua = lua();
+        let matches = |v: &str, req: &str| -> bool {
+            eval(
+                &lua,
+                &format!("return util.version_matches({:?}, {:?})", v, req),
+            )
+        };
+
+        assert!(matches("1.4.2", ">=1.2, <2.0"));
+        assert!(matches("v1.2", ">=1.2, <2.0"));
+        assert!(!matches("2.0.0", ">=1.2, <2.0"));
+        assert!(!matches("1.1.9", ">=1.2, <2.0"));
+        assert!(matches("1.9.0", "^1.2"));
+        assert!(!matches("1.3.0-rc.1", ">=1.2"));
+        assert!(matches("1.3.0-rc.1", ">=1.3.0-rc.0"));
+
+        let res: mlua::Result<Value> =
+            lua.load("util.version_matches('1.2.3', '>>1')").eval();
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("invalid version requirement: >>1"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_diff_dirs() {
+        use std::os::unix::fs::symlink;
+        use std::time::{Duration, SystemTime};
+
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let (left, right) = (root.join("left"), root.join("right"));
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        for dir in [&left, &right] {
+            fs::create_dir_all(dir.join("dir")).unwrap();
+            fs::write(dir.join("same"), "same").unwrap();
+            fs::write(dir.join("target-a"), "target").unwrap();
+            fs::write(dir.join("target-b"), "target").unwrap();
+        }
+        fs::write(left.join("removed"), "").unwrap();
+        fs::write(right.join("added"), "").unwrap();
+        fs::write(left.join("changed"), "foo").unwrap();
+        fs::write(right.join("changed"), "bar").unwrap();
+        fs::write(left.join("resized"), "foo").unwrap();
+        fs::write(right.join("resized"), "foobar").unwrap();
+        fs::write(left.join("touched"), "foo").unwrap();
+        fs::write(right.join("touched"), "foo").unwrap();
+        fs::write(left.join("kind"), "").unwrap();
+        fs::create_dir(right.join("kind")).unwrap();
+        symlink("target-a", left.join("link")).unwrap();
+        symlink("target-b", right.join("link")).unwrap();
+
+        for dir in [&left, &right] {
+            for name in ["same", "changed", "target-a", "target-b"] {
+                let file = fs::File::options()
+                    .write(true)
+                    .open(dir.join(name))
+                    .unwrap();
+                file.set_modified(mtime).unwrap();
+            }
+        }
+        fs::File::options()
+            .write(true)
+            .open(left.join("touched"))
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        let diff = |options: &str| -> (Vec<String>, Vec<String>, Vec<String>) {
+            eval(
+                &lua,
+                &format!(
+                    r#"
+                    local diff = util.diff_dirs({:?}, {:?}, {})
+                    local differing = {{}}
+                    for _, d in ipairs(diff.differing) do
+                      table.insert(differing, d.name .. ":" .. table.concat(d.reasons, ","))
+                    end
+                    return diff.only_left, diff.only_right, differing
+                    "#,
+                    left.to_string_lossy(),
+                    right.to_string_lossy(),
+                    options
+                ),
+            )
+        };
+
+        let (only_left, only_right, differing) = diff("nil");
+        assert_eq!(only_left, vec!["removed"]);
+        assert_eq!(only_right, vec!["added"]);
+        assert_eq!(
+            differing,
+            vec!["kind:type", "resized:size", "touched:mtime"]
+        );
+
+        let (_, _, differing) = diff("{ by_content = true }");
+        assert_eq!(
+            differing,
+            vec!["changed:content", "kind:type", "resized:size"]
+        );
+
+        let (_, _, differing) = diff("{ compare_links = true }");
+        assert_eq!(
+            differing,
+            vec!["kind:type", "link:target", "resized:size", "touched:mtime"]
+        );
+
+        let res: mlua::Result<Value> = lua
+            .load(format!(
+                "util.diff_dirs({:?}, {:?})",
+                left.to_string_lossy(),
+                root.join("nothing").to_string_lossy()
+            ))
+            .eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_read_lines() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let file = root.join("foo.log");
+        let mut content = (1..=30)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>()
+            .into_bytes();
+        content.extend_from_slice(b"crlf\r\ninvalid \xff");
+        fs::write(&file, content).unwrap();
+
+        let read = |options: &str| -> (Vec<String>, usize, bool) {
+            eval(
+                &lua,
+                &format!(
+                    "local res = util.read_lines({:?}, {}); return res.lines, res.total_read, res.reached_eof",
+                    file.to_string_lossy(),
+                    options
+                ),
+            )
+        };
+
+        let (lines, total_read, eof) = read("{ start = 10, count = 11 }");
+        assert_eq!(
+            lines,
+            (10..=20).map(|i| format!("line {}", i)).collect::<Vec<_>>()
+        );
+        assert_eq!((total_read, eof), (20, false));
+
+        let (lines, total_read, eof) = read("{ count = 2 }");
+        assert_eq!(lines, vec!["line 1", "line 2"]);
+        assert_eq!((total_read, eof), (2, false));
+
+        // Until the end of the file.
+        let (lines, total_read, eof) = read("{ start = 30 }");
+        assert_eq!(lines, vec!["line 30", "crlf", "invalid \u{fffd}"]);
+        assert_eq!((total_read, eof), (32, true));
+
+        let (lines, total_read, eof) = read("{ start = 31, count = 2 }");
+        assert_eq!(lines, vec!["crlf", "invalid \u{fffd}"]);
+        assert_eq!((total_read, eof), (32, true));
+
+        // Past the end of the file.
+        let (lines, total_read, eof) = read("{ start = 40, count = 5 }");
+        assert!(lines.is_empty());
+        assert_eq!((total_read, eof), (32, true));
+
+        let (lines, total_read, eof) = read("{ count = 0 }");
+        assert!(lines.is_empty());
+        assert_eq!((total_read, eof), (0, false));
+
+        let (res, err): (Value, Option<String>) = eval(
+            &lua,
+            &format!(
+                "return util.read_lines({:?})",
+                root.join("nothing").to_string_lossy()
+            ),
+        );
+        assert!(res.is_nil());
+        assert!(err.is_some());
+    }
+
+    #[test]
+    fn test_watch() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+
+        let (handle, err): (Option<u64>, Option<String>) = eval(
+            &lua,
+            &format!(
+                "return util.watch({:?}, {{ recursive = true, debounce_ms = 50, messages = {{ 'Refresh' }} }})",
+                root.to_string_lossy()
+            ),
+        );
+        assert_eq!(err, None);
+        let handle = handle.unwrap();
+        assert_eq!(
+            fs_watcher::messages(handle),
+            Some(vec![ExternalMsg::Refresh])
+        );
+
+        let unwatched: bool = eval(&lua, &format!("return util.unwatch({})", handle));
+        assert!(unwatched);
+        assert_eq!(fs_watcher::messages(handle), None);
+
+        let unwatched: bool = eval(&lua, &format!("return util.unwatch({})", handle));
+        assert!(!unwatched);
+
+        let (handle, err): (Option<u64>, Option<String>) = eval(
+            &lua,
+            &format!(
+                "return util.watch({:?})",
+                root.join("nothing").to_string_lossy()
+            ),
+        );
+        assert_eq!(handle, None);
+        assert!(err.is_some());
+    }
+
+    #[test]
+    fn test_watch_app_context() {
+        // Creating the app changes the current directory.
+        let pwd = env::current_dir().unwrap();
+        let xplr = mlua::Lua::new();
+        let app =
+            app::App::create("xplr".into(), None, pwd, &xplr, None, [].into()).unwrap();
+        let tmp = TempDir::new().unwrap();
+        let fs_event = |handle, paths: &[&str]| {
+            let event = fs_watcher::FsEvent {
+                handle,
+                paths: paths.iter().map(|p| p.to_string()).collect(),
+            };
+            app::Task::new(app::MsgIn::Internal(app::InternalMsg::FsEvent(event)), None)
+        };
+
+        let handle = fs_watcher::watch(
+            tmp.path(),
+            false,
+            Duration::from_millis(50),
+            vec![ExternalMsg::CallLuaSilently("custom.on_change".into())],
+        )
+        .unwrap();
+
+        let mut app = app.handle_task(fs_event(handle, &["/a", "/b"])).unwrap();
+        let event = app.to_lua_ctx_heavy().fs_event.unwrap();
+        assert_eq!(
+            (event.handle, event.paths),
+            (handle, vec!["/a".into(), "/b".into()])
+        );
+        assert!(app
+            .msg_out
+            .drain(..)
+            .any(|m| m == app::MsgOut::CallLuaSilently("custom.on_change".into())));
+
+        // The events of the stopped watchers are ignored.
+        fs_watcher::unwatch(handle);
+        let app = app.handle_task(fs_event(handle, &["/c"])).unwrap();
+        assert_eq!(app.fs_event.unwrap().paths, vec!["/a", "/b"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_env() {
+        let lua = lua();
+        let name = format!("XPLR_UTIL_TEST_ENV_{}", std::process::id());
+
+        let value: Option<String> =
+            eval(&lua, &format!("return util.getenv({:?})", name));
+        assert_eq!(value, None);
+
+        lua.load(format!("util.setenv({:?}, 'foo bar')", name))
+            .exec()
+            .unwrap();
+        let value: Option<String> =
+            eval(&lua, &format!("return util.getenv({:?})", name));
+        assert_eq!(value.as_deref(), Some("foo bar"));
+
+        let value: Option<String> =
+            eval(&lua, &format!("return util.environ()[{:?}]", name));
+        assert_eq!(value.as_deref(), Some("foo bar"));
+
+        // Inherited by the spawned commands.
+        let out: String = eval(
+            &lua,
+            &format!(
+                "return util.shell_execute('sh', {{ '-c', 'printf %s \"${}\"' }}).stdout",
+                name
+            ),
+        );
+        assert_eq!(out, "foo bar");
+
+        lua.load(format!("util.setenv({:?}, nil)", name))
+            .exec()
+            .unwrap();
+        let value: Option<String> =
+            eval(&lua, &format!("return util.getenv({:?})", name));
+        assert_eq!(value, None);
+
+        let out: String = eval(
+            &lua,
+            &format!(
+                "return util.shell_execute('sh', {{ '-c', 'printf %s \"${{{}-unset}}\"' }}).stdout",
+                name
+            ),
+        );
+        assert_eq!(out, "unset");
+
+        for code in ["util.setenv('', 'x')", "util.setenv('A=B', 'x')"] {
+            assert!(lua.load(code).exec().is_err());
+        }
+    }
+
+    #[test]
+    fn test_lines() {
+        let lua = lua();
+
+        let lines = |text: &str| -> Vec<String> {
+            eval(&lua, &format!("return util.lines({:?})", text))
+        };
+
+        assert_eq!(lines("foo\nbar"), vec!["foo", "bar"]);
+        assert_eq!(lines("foo\r\nbar\r\n"), vec!["foo", "bar"]);
+        assert_eq!(lines("foo\r\nbar\nbaz\r\n"), vec!["foo", "bar", "baz"]);
+        assert_eq!(lines("foo\n"), vec!["foo"]);
+        assert_eq!(lines("foo\n\n"), vec!["foo", ""]);
+        assert_eq!(lines("\nfoo"), vec!["", "foo"]);
+        assert_eq!(lines("foo\rbar"), vec!["foo\rbar"]);
+        assert!(lines("").is_empty());
+    }
+
+    #[test]
+    fn test_unlines() {
+        let lua = lua();
+
+        let text: String = eval(&lua, "return util.unlines({ 'foo', 'bar' })");
+        assert_eq!(text, "foo\nbar");
+
+        let text: String = eval(
+            &lua,
+            "return util.unlines({ 'foo', 'bar' }, { separator = '\\r\\n' })",
+        );
+        assert_eq!(text, "foo\r\nbar");
+
+        let text: String = eval(&lua, "return util.unlines({})");
+        assert_eq!(text, "");
+
+        let text: String =
+            eval(&lua, "return util.unlines(util.lines('foo\\r\\nbar\\n'))");
+        assert_eq!(text, "foo\nbar");
+    }
+
+    #[test]
+    fn test_sniff() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+
+        let png = [
+            0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0, 0, 0, 0x0d, b'I', b'H',
+            b'D', b'R',
+        ];
+        fs::write(root.join("picture"), png).unwrap();
+        fs::write(root.join("archive"), [0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0]).unwrap();
+        fs::write(root.join("notes"), "héllo\nworld\n").unwrap();
+        fs::write(root.join("script"), "#!/bin/sh\necho foo\n").unwrap();
+        fs::write(root.join("binary"), [b'a', 0, b'b']).unwrap();
+
+        let sniff = |name: &str, options: &str| -> (String, Option<String>, bool) {
+            eval(
+                &lua,
+                &format!(
+                    "local res = util.sniff({:?}, {}); return res.mime, res.extension, res.is_text",
+                    root.join(name).to_string_lossy(),
+                    options
+                ),
+            )
+        };
+
+        assert_eq!(
+            sniff("picture", "nil"),
+            ("image/png".into(), Some("png".into()), false)
+        );
+        assert_eq!(
+            sniff("archive", "nil"),
+            ("application/gzip".into(), Some("gz".into()), false)
+        );
+        assert_eq!(sniff("notes", "nil"), ("text/plain".into(), None, true));
+        assert_eq!(
+            sniff("script", "nil"),
+            ("text/x-shellscript".into(), Some("sh".into()), true)
+        );
+        assert_eq!(
+            sniff("binary", "nil"),
+            ("application/octet-stream".into(), None, false)
+        );
+
+        // The sample may end in the middle of a character.
+        assert_eq!(
+            sniff("notes", "{ max_bytes = 2 }"),
+            ("text/plain".into(), None, true)
+        );
+
+        let (res, err): (Value, Option<String>) = eval(
+            &lua,
+            &format!(
+                "return util.sniff({:?})",
+                root.join("nothing").to_string_lossy()
+            ),
+        );
+        assert!(res.is_nil());
+        assert!(err.is_some());
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn test_archive_list() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+
+        // No entry for the "docs" and "src/lua" directories.
+        let mut zip =
+            zip::ZipWriter::new(fs::File::create(root.join("foo.zip")).unwrap());
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        zip.add_directory("src/", options).unwrap();
+        zip.start_file("src/lua/util.rs", options).unwrap();
+        zip.write_all(b"foo").unwrap();
+        zip.start_file("docs/README.md", options).unwrap();
+        zip.write_all(b"hello").unwrap();
+        zip.finish().unwrap();
+
+        let gz = flate2::write::GzEncoder::new(
+            fs::File::create(root.join("bar.tar.gz")).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut tar = tar::Builder::new(gz);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header.clone(), "./a/b/c.txt", &b"abc"[..])
+            .unwrap();
+        tar.append_data(&mut header, "./d.txt", &b"def"[..])
+            .unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        // Detected from the content.
+        fs::copy(root.join("bar.tar.gz"), root.join("bar")).unwrap();
+
+        let list = |name: &str| -> Vec<(String, bool, u64)> {
+            let entries: Vec<Table> = eval(
+                &lua,
+                &format!(
+                    "return util.archive_list({:?})",
+                    root.join(name).to_string_lossy()
+                ),
+            );
+            entries
+                .into_iter()
+                .map(|e| {
+                    (
+                        e.get("relative_path").unwrap(),
+                        e.get("is_dir").unwrap(),
+                        e.get("size").unwrap(),
+                    )
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            list("foo.zip"),
+            vec![
+                ("docs".into(), true, 0),
+                ("docs/README.md".into(), false, 5),
+                ("src".into(), true, 0),
+                ("src/lua".into(), true, 0),
+                ("src/lua/util.rs".into(), false, 3),
+            ]
+        );
+
+        let expected: Vec<(String, bool, u64)> = vec![
+            ("a".into(), true, 0),
+            ("a/b".into(), true, 0),
+            ("a/b/c.txt".into(), false, 3),
+            ("d.txt".into(), false, 3),
+        ];
+        assert_eq!(list("bar.tar.gz"), expected);
+        assert_eq!(list("bar"), expected);
+
+        let (entries, err): (Value, Option<String>) = eval(
+            &lua,
+            &format!(
+                "return util.archive_list({:?}, {{ format = 'zip' }})",
+                root.join("bar.tar.gz").to_string_lossy()
+            ),
+        );
+        assert!(entries.is_nil());
+        assert!(err.is_some());
+
+        fs::write(root.join("text"), "foo").unwrap();
+        let (entries, err): (Value, Option<String>) = eval(
+            &lua,
+            &format!(
+                "return util.archive_list({:?})",
+                root.join("text").to_string_lossy()
+            ),
+        );
+        assert!(entries.is_nil());
+        assert!(err.unwrap().ends_with("unsupported archive format"));
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn test_archive_extract() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let archive = root.join("foo.zip");
+
+        let mut zip = zip::ZipWriter::new(fs::File::create(&archive).unwrap());
+        let options = zip::write::FileOptions::default();
+        zip.start_file("docs/README.md", options).unwrap();
+        zip.write_all(b"hello world").unwrap();
+        zip.add_directory("empty/", options).unwrap();
+        zip.start_file("../evil.txt", options).unwrap();
+        zip.write_all(b"evil").unwrap();
+        zip.finish().unwrap();
+
+        let extract =
+            |member: &str, options: &str| -> (Option<mlua::String>, Option<String>) {
+                eval(
+                    &lua,
+                    &format!(
+                        "return util.archive_extract({:?}, {:?}, {})",
+                        archive.to_string_lossy(),
+                        member,
+                        options
+                    ),
+                )
+            };
+
+        // To the memory.
+        let (content, err) = extract("docs/README.md", "nil");
+        assert_eq!(err, None);
+        assert_eq!(content.unwrap().as_bytes(), b"hello world");
+
+        let (content, _) = extract("./docs/README.md", "{ max_bytes = 5 }");
+        assert_eq!(content.unwrap().as_bytes(), b"hello");
+
+        // To the disk.
+        let dest = root.join("out");
+        let dest_opt = format!("{{ dest = {:?} }}", dest.to_string_lossy());
+        let (path, err) = extract("docs/README.md", &dest_opt);
+        assert_eq!(err, None);
+        let path = path.unwrap().to_str().unwrap().to_string();
+        assert_eq!(path, dest.join("docs/README.md").to_string_lossy());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello world");
+
+        let (_, err) = extract("docs/README.md", &dest_opt);
+        assert!(err.is_some());
+        let overwrite = format!(
+            "{{ dest = {:?}, overwrite = true }}",
+            dest.to_string_lossy()
+        );
+        let (_, err) = extract("docs/README.md", &overwrite);
+        assert_eq!(err, None);
+
+        let (_, err) = extract("empty", &dest_opt);
+        assert_eq!(err, None);
+        assert!(dest.join("empty").is_dir());
+
+        // Zip slip.
+        let (path, err) = extract("../evil.txt", &dest_opt);
+        assert!(path.is_none());
+        assert!(err.unwrap().ends_with("unsafe member path"));
+        assert!(!root.join("evil.txt").exists());
+
+        // Symlinks inside dest aren't written through.
+        #[cfg(unix)]
+        {
+            let elsewhere = root.join("elsewhere");
+            fs::create_dir(&elsewhere).unwrap();
+            fs::remove_dir_all(dest.join("docs")).unwrap();
+            std::os::unix::fs::symlink(&elsewhere, dest.join("docs")).unwrap();
+            let (path, err) = extract("docs/README.md", &overwrite);
+            assert!(path.is_none());
+            assert!(err.unwrap().ends_with("symlink in the destination path"));
+            assert!(!elsewhere.join("README.md").exists());
+        }
+
+        let (content, err) = extract("nothing", "nil");
+        assert!(content.is_none());
+        assert!(err.unwrap().ends_with("no such member: nothing"));
+    }
+
+    #[test]
+    fn test_throttle() {
+        let lua = lua();
+        let key = format!("test-throttle-{}", std::process::id());
+        let code = format!("return util.throttle({:?}, 200)", key);
+
+        let allowed: bool = eval(&lua, &code);
+        assert!(allowed);
+        let allowed: bool = eval(&lua, &code);
+        assert!(!allowed);
+
+        std::thread::sleep(Duration::from_millis(250));
+        let allowed: bool = eval(&lua, &code);
+        assert!(allowed);
+    }
+
+    #[test]
+    fn test_tabulate() {
+        let lua = lua();
+
+        let tabulate = |code: &str| -> Vec<String> {
+            eval(&lua, &format!("return util.tabulate({})", code))
+        };
+
+        assert_eq!(
+            tabulate(
+                r#"{ { "name", "size" }, { "foo", 1 }, { "日本語", 1024 }, { "😀", 12.5 } }, {
+                  separator = " | ",
+                  columns = { {}, { align = "right" } },
+                }"#
+            ),
+            vec![
+                "name   | size",
+                "foo    |    1",
+                "日本語 | 1024",
+                "😀     | 12.5",
+            ]
+        );
+
+        // No trailing spaces, and missing cells are empty.
+        assert_eq!(
+            tabulate(r#"{ { "a", "bb", "c" }, { "aaa" }, { nil, "b", "cc" } }"#),
+            vec!["a   bb c", "aaa", "    b  cc"]
+        );
+
+        // Truncated.
+        assert_eq!(
+            tabulate(
+                r#"{ { "日本語のテキスト", "x" }, { "ab", "y" } }, { columns = { { max_width = 7 } } }"#
+            ),
+            vec!["日本語… x", "ab      y"]
+        );
+
+        assert!(tabulate("{}").is_empty());
+
+        let res: mlua::Result<Value> = lua.load("util.tabulate({ { {} } })").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_set_progress() {
+        let lua = lua();
+
+        lua.load("util.set_progress('copying', 0.5)")
+            .exec()
+            .unwrap();
+        let current = progress::current().unwrap();
+        assert_eq!(current.label, "copying");
+        assert_eq!(current.fraction, Some(0.5));
+
+        lua.load("util.set_progress('searching')").exec().unwrap();
+        assert_eq!(progress::current().unwrap().fraction, None);
+
+        lua.load("util.clear_progress()").exec().unwrap();
+        assert_eq!(progress::current(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_program() {
+        let env = |editor: &'static str| {
+            move |var: &str| (var == "EDITOR").then(|| editor.to_string())
+        };
+        let cmd = |command: &str, args: &[&str]| app::Command {
+            command: command.into(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        };
+
+        assert_eq!(
+            resolve_program(env("sh"), &["VISUAL", "EDITOR"], &[]),
+            Some(cmd("sh", &[]))
+        );
+        assert_eq!(
+            resolve_program(env("sh -c 'echo \"$1\"' --"), &["VISUAL", "EDITOR"], &[]),
+            Some(cmd("sh", &["-c", "echo \"$1\"", "--"]))
+        );
+
+        // Falls back when the program isn't found, or the variable is empty.
+        let fallbacks = ["there-is-no-such-program", "sh"];
+        assert_eq!(
+            resolve_program(env("there-is-no-such-editor -w"), &["EDITOR"], &fallbacks),
+            Some(cmd("sh", &[]))
+        );
+        assert_eq!(
+            resolve_program(env(""), &["EDITOR"], &fallbacks),
+            Some(cmd("sh", &[]))
+        );
+        assert_eq!(
+            resolve_program(env("there-is-no-such-editor"), &["EDITOR"], &[]),
+            None
+        );
+    }
+
+    #[cfg(feature = "trash")]
+    #[test]
+    fn test_trash_missing() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+
+        let (ok, error): (bool, Option<String>) = eval(
+            &lua,
+            &format!(
+                "local res = util.trash({{ {:?} }}); return res[1].ok, res[1].error",
+                tmp.path().join("nothing").to_string_lossy()
+            ),
+        );
+        assert!(!ok);
+        assert!(error.is_some());
+    }
+
+    #[cfg(feature = "trash")]
+    #[test]
+    #[ignore = "needs a usable trash, which not every environment has"]
+    fn test_trash() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let file = tmp.path().join("foo");
+        fs::write(&file, "foo").unwrap();
+
+        let (ok, error): (bool, Option<String>) = eval(
+            &lua,
+            &format!(
+                "local res = util.trash({{ {:?} }}); return res[1].ok, res[1].error",
+                file.to_string_lossy()
+            ),
+        );
+        assert_eq!((ok, error), (true, None));
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn test_store() {
+        let other = lua();
+        let lua = lua();
+
+        let res: Option<String> = eval(&lua, "return util.store_get('test_store')");
+        assert_eq!(res, None);
+
+        lua.load(
+            r#"util.store_set("test_store", { a = { b = { 1, 2, 3 } }, c = "d" })"#,
+        )
+        .exec()
+        .unwrap();
+        let res: String = eval(
+            &lua,
+            "return util.json_encode(util.store_get('test_store'))",
+        );
+        assert_eq!(res, r#"{"a":{"b":[1,2,3]},"c":"d"}"#);
+
+        // It's a copy.
+        let res: String = eval(
+            &lua,
+            r#"
+            local value = util.store_get("test_store")
+            value.c = "changed"
+            return util.store_get("test_store").c
+            "#,
+        );
+        assert_eq!(res, "d");
+
+        // Visible from another Lua state in the same session.
+        let res: String = eval(&other, "return util.store_get('test_store').c");
+        assert_eq!(res, "d");
+
+        lua.load("util.store_set('test_store', 42)").exec().unwrap();
+        let res: i64 = eval(&lua, "return util.store_get('test_store')");
+        assert_eq!(res, 42);
+
+        let res: bool = eval(&lua, "return util.store_delete('test_store')");
+        assert!(res);
+        let res: bool = eval(&lua, "return util.store_delete('test_store')");
+        assert!(!res);
+        let res: Option<i64> = eval(&lua, "return util.store_get('test_store')");
+        assert_eq!(res, None);
+
+        lua.load("util.store_set('test_store', 1); util.store_set('test_store', nil)")
+            .exec()
+            .unwrap();
+        let res: Option<i64> = eval(&lua, "return util.store_get('test_store')");
+        assert_eq!(res, None);
+
+        let res: mlua::Result<Value> = lua
+            .load("return util.store_set('test_store', print)")
+            .eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_store_app_state() {
+        // Creating the app changes the current directory.
+        let pwd = env::current_dir().unwrap();
+        let xplr = mlua::Lua::new();
+        let app =
+            app::App::create("xplr".into(), None, pwd, &xplr, None, [].into()).unwrap();
+        let refresh =
+            || app::Task::new(app::MsgIn::External(ExternalMsg::Refresh), None);
+
+        xplr.load(r#"xplr.util.store_set("test_store_app_state", { a = { 1, 2 } })"#)
+            .exec()
+            .unwrap();
+        let app = app.handle_task(refresh()).unwrap();
+        assert_eq!(
+            app.store.get("test_store_app_state").cloned(),
+            Some(serde_json::json!({ "a": [1, 2] }))
+        );
+
+        xplr.load(r#"xplr.util.store_delete("test_store_app_state")"#)
+            .exec()
+            .unwrap();
+        let app = app.handle_task(refresh()).unwrap();
+        assert_eq!(app.store.get("test_store_app_state"), None);
+
+        // The throttled times are carried with it.
+        xplr.load(r#"assert(xplr.util.throttle("test_store_app_state", 60000))"#)
+            .exec()
+            .unwrap();
+        let app = app.handle_task(refresh()).unwrap();
+        assert!(app.throttled.contains_key("test_store_app_state"));
+    }
+
+    #[test]
+    fn test_common_path() {
+        let lua = lua();
+
+        let res: Option<String> = eval(
+            &lua,
+            "return util.common_path({ '/a/b/c', '/a/b/d/e', '/a/b/c/f' })",
+        );
+        assert_eq!(res.as_deref(), Some("/a/b"));
+
+        // Components, not strings.
+        let res: Option<String> =
+            eval(&lua, "return util.common_path({ '/a/b', '/a/bc' })");
+        assert_eq!(res.as_deref(), Some("/a"));
+
+        // Normalized.
+        let res: Option<String> = eval(
+            &lua,
+            "return util.common_path({ '/a//b/./c', '/a/b/x/../d' })",
+        );
+        assert_eq!(res.as_deref(), Some("/a/b"));
+
+        let res: Option<String> = eval(&lua, "return util.common_path({ '/a', '/b' })");
+        assert_eq!(res.as_deref(), Some("/"));
+
+        let res: Option<String> = eval(&lua, "return util.common_path({})");
+        assert_eq!(res, None);
+
+        // Relative paths are resolved from $PWD.
+        let pwd = env::current_dir().unwrap();
+        let res: Option<String> = eval(
+            &lua,
+            &format!(
+                "return util.common_path({{ 'foo/bar', '{}' }})",
+                pwd.join("foo/baz").display()
+            ),
+        );
+        assert_eq!(res, Some(pwd.join("foo").to_string_lossy().to_string()));
+
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("dir/file"), "").unwrap();
+        let common_path = |paths: &[&str]| -> Option<String> {
+            let paths = paths
+                .iter()
+                .map(|p| format!("{:?}", root.join(p).to_string_lossy()))
+                .collect::<Vec<_>>()
+                .join(", ");
+            eval(&lua, &format!("return util.common_path({{ {} }})", paths))
+        };
+        let path = |p: &str| Some(root.join(p).to_string_lossy().to_string());
+
+        // A single path, or a path and its descendants.
+        assert_eq!(common_path(&["dir"]), path("dir"));
+        assert_eq!(common_path(&["dir/file"]), path("dir"));
+        assert_eq!(common_path(&["dir/file", "dir/file"]), path("dir"));
+        assert_eq!(common_path(&["dir", "dir/file"]), path("dir"));
+    }
+
+    #[test]
+    fn test_common_ancestor() {
+        let paths = |paths: &[&str]| -> Vec<PathBuf> {
+            paths.iter().map(PathBuf::from).collect()
+        };
+
+        assert_eq!(
+            common_ancestor(&paths(&["/x/y/z", "/x/y/w"])),
+            Some(PathBuf::from("/x/y"))
+        );
+        assert_eq!(common_ancestor(&paths(&[])), None);
+
+        // Disjoint roots.
+        assert_eq!(common_ancestor(&paths(&["/x", "y"])), None);
+
+        #[cfg(windows)]
+        assert_eq!(common_ancestor(&paths(&["C:\\x", "D:\\x"])), None);
+    }
+
+    #[cfg(feature = "highlight")]
+    #[test]
+    fn test_highlight() {
+        let lua = lua();
+
+        let code = "fn main() {\n    println!(\"hello\");\n}\n";
+        lua.globals().set("code", code).unwrap();
+
+        for language in ["rs", "Rust", "rust"] {
+            let res: String = eval(
+                &lua,
+                &format!("return util.highlight(code, {{ language = '{language}' }})"),
+            );
+            assert!(res.contains("\x1b[38;2;"), "{language}: {res:?}");
+            assert!(res.ends_with("\x1b[0m"));
+            assert_eq!(strip_ansi(&res), code);
+        }
+
+        let other: String = eval(
+            &lua,
+            "return util.highlight(code, { language = 'rs', theme = 'InspiredGitHub' })",
+        );
+        let default: String =
+            eval(&lua, "return util.highlight(code, { language = 'rs' })");
+        assert_ne!(other, default);
+
+        // Unknown languages are passed through.
+        let res: String = eval(
+            &lua,
+            "return util.highlight(code, { language = 'no-such-language' })",
+        );
+        assert_eq!(res, code);
+        let res: String = eval(&lua, "return util.highlight(code)");
+        assert_eq!(res, code);
+
+        // Detected from the first line.
+        let res: String = eval(&lua, "return util.highlight('#!/bin/sh\\necho hi\\n')");
+        assert!(res.contains("\x1b[38;2;"));
+
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let path = root.join("main.rs");
+        fs::write(&path, code).unwrap();
+        lua.globals()
+            .set("path", path.to_string_lossy().to_string())
+            .unwrap();
+        let res: String = eval(&lua, "return util.highlight(path, { file = true })");
+        assert!(res.contains("\x1b[38;2;"));
+        assert_eq!(strip_ansi(&res), code);
+
+        let (res, err): (Option<String>, Option<String>) = eval(
+            &lua,
+            "return util.highlight(path .. '.missing', { file = true })",
+        );
+        assert_eq!(res, None);
+        assert!(err.unwrap().contains("main.rs.missing"));
+
+        let res: mlua::Result<Value> = lua
+            .load("return util.highlight(code, { theme = 'no-such-theme' })")
+            .eval();
+        assert!(res.is_err());
+    }
+
+    #[cfg(feature = "image")]
+    fn parse_kitty(escape: &str) -> (Vec<String>, Vec<u8>) {
+        let mut controls = vec![];
+        let mut payload = String::new();
+        for command in escape.split_terminator("\x1b\\") {
+            let command = command.strip_prefix("\x1b_G").unwrap();
+            let (control, chunk) = command.split_once(';').unwrap();
+            assert!(chunk.len() <= 4096);
+            controls.push(control.to_string());
+            payload.push_str(chunk);
+        }
+        (controls, base64::decode(payload).unwrap())
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_kitty_escape() {
+        let (controls, payload) = parse_kitty(&kitty_escape(b"tiny", 2, 1));
+        assert_eq!(controls, vec!["a=T,f=100,q=2,c=2,r=1,m=0"]);
+        assert_eq!(payload, b"tiny");
+
+        // 10000 bytes are 13336 bytes of base64.
+        let data: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+        let (controls, payload) = parse_kitty(&kitty_escape(&data, 40, 20));
+        assert_eq!(
+            controls,
+            vec!["a=T,f=100,q=2,c=40,r=20,m=1", "m=1", "m=1", "m=0"]
+        );
+        assert_eq!(payload, data);
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_image_kitty() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+
+        let path = root.join("tiny.png");
+        image::RgbImage::from_pixel(4, 2, image::Rgb([255, 0, 0]))
+            .save(&path)
+            .unwrap();
+        // The format is detected from the content.
+        fs::copy(&path, root.join("tiny")).unwrap();
+        let wide = root.join("wide.png");
+        image::RgbImage::new(100, 40).save(&wide).unwrap();
+        fs::write(root.join("text.png"), "not an image").unwrap();
+
+        let image_kitty =
+            |path: &Path, options: &str| -> (Option<String>, Option<String>) {
+                eval(
+                    &lua,
+                    &format!(
+                        "return util.image_kitty({:?}, {options})",
+                        path.to_string_lossy()
+                    ),
+                )
+            };
+
+        for path in [path.clone(), root.join("tiny")] {
+            let (escape, err) = image_kitty(&path, "nil");
+            assert_eq!(err, None);
+            let (controls, png) = parse_kitty(&escape.unwrap());
+            assert_eq!(controls, vec!["a=T,f=100,q=2,c=1,r=1,m=0"]);
+            let image = image::load_from_memory(&png).unwrap().to_rgb8();
+            assert_eq!(image.dimensions(), (4, 2));
+            assert_eq!(image.get_pixel(0, 0), &image::Rgb([255, 0, 0]));
+        }
+
+        // Scaled down to fit, keeping the aspect ratio.
+        let (escape, _) = image_kitty(&wide, "{ cols = 5, rows = 5 }");
+        let (controls, png) = parse_kitty(&escape.unwrap());
+        assert_eq!(controls, vec!["a=T,f=100,q=2,c=5,r=1,m=0"]);
+        let image = image::load_from_memory(&png).unwrap();
+        assert_eq!((image.width(), image.height()), (50, 20));
+
+        let (escape, _) = image_kitty(&wide, "{ rows = 1, cell_height = 10 }");
+        let (controls, _) = parse_kitty(&escape.unwrap());
+        assert_eq!(controls, vec!["a=T,f=100,q=2,c=3,r=1,m=0"]);
+
+        // Never scaled up.
+        let (escape, _) = image_kitty(&path, "{ cols = 100, rows = 100 }");
+        let (controls, _) = parse_kitty(&escape.unwrap());
+        assert_eq!(controls, vec!["a=T,f=100,q=2,c=1,r=1,m=0"]);
+
+        for path in [root.join("text.png"), root.join("missing.png")] {
+            let (escape, err) = image_kitty(&path, "nil");
+            assert_eq!(escape, None);
+            assert!(err
+                .unwrap()
+                .starts_with(&path.to_string_lossy().to_string()));
+        }
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_sixel_encode() {
+        let red = image::Rgba([255, 0, 0, 255]);
+        let blue = image::Rgba([0, 0, 255, 255]);
+        let clear = image::Rgba([0, 0, 0, 0]);
+
+        let image =
+            image::RgbaImage::from_fn(2, 1, |x, _| if x == 0 { red } else { blue });
+        assert_eq!(
+            sixel_encode(&image),
+            "\x1bP0;1q\"1;1;2;1#5;2;0;0;100#180;2;100;0;0#5?@$#180@\x1b\\"
+        );
+
+        // Runs are compressed, and the six rows of a band share the sixels.
+        let image = image::RgbaImage::from_pixel(10, 6, red);
+        assert_eq!(
+            sixel_encode(&image),
+            "\x1bP0;1q\"1;1;10;6#180;2;100;0;0#180!10~\x1b\\"
+        );
+
+        // Multiple bands, with transparency.
+        let image =
+            image::RgbaImage::from_fn(
+                3,
+                7,
+                |x, y| if x == 1 && y != 6 { clear } else { red },
+            );
+        assert_eq!(
+            sixel_encode(&image),
+            "\x1bP0;1q\"1;1;3;7#180;2;100;0;0#180~?~-#180@@@\x1b\\"
+        );
+
+        // The closest colors of the palette.
+        let image = image::RgbaImage::from_pixel(1, 1, image::Rgba([120, 200, 30, 255]));
+        assert_eq!(
+            sixel_encode(&image),
+            "\x1bP0;1q\"1;1;1;1#97;2;40;80;20#97@\x1b\\"
+        );
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_image_sixel() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+
+        let path = root.join("tiny.png");
+        image::RgbImage::from_pixel(4, 2, image::Rgb([255, 0, 0]))
+            .save(&path)
+            .unwrap();
+        let big = root.join("big.png");
+        image::RgbImage::new(2000, 500).save(&big).unwrap();
+        fs::write(root.join("text.png"), "not an image").unwrap();
+
+        let image_sixel =
+            |path: &Path, options: &str| -> (Option<String>, Option<String>) {
+                eval(
+                    &lua,
+                    &format!(
+                        "return util.image_sixel({:?}, {options})",
+                        path.to_string_lossy()
+                    ),
+                )
+            };
+
+        let (escape, err) = image_sixel(&path, "nil");
+        assert_eq!(err, None);
+        assert_eq!(
+            escape.unwrap(),
+            "\x1bP0;1q\"1;1;4;2#180;2;100;0;0#180!4B\x1b\\"
+        );
+
+        // Scaled down to fit.
+        let (escape, _) = image_sixel(&big, "{ cols = 10 }");
+        assert!(escape.unwrap().starts_with("\x1bP0;1q\"1;1;100;25#"));
+        let (escape, _) = image_sixel(&big, "nil");
+        assert!(escape.unwrap().starts_with("\x1bP0;1q\"1;1;1000;250#"));
+
+        for path in [root.join("text.png"), root.join("missing.png")] {
+            let (escape, err) = image_sixel(&path, "nil");
+            assert_eq!(escape, None);
+            assert!(err
+                .unwrap()
+                .starts_with(&path.to_string_lossy().to_string()));
+        }
+    }
+
+    #[test]
+    fn test_graphics_protocol() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |var: &str| {
+                vars.iter()
+                    .find(|(name, _)| *name == var)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        assert_eq!(
+            graphics_protocol(env(&[("TERM", "xterm-kitty")])),
+            Some("kitty")
+        );
+        assert_eq!(
+            graphics_protocol(env(&[
+                ("TERM", "xterm-256color"),
+                ("KITTY_WINDOW_ID", "1")
+            ])),
+            Some("kitty")
+        );
+        assert_eq!(
+            graphics_protocol(env(&[("TERM_PROGRAM", "iTerm.app")])),
+            Some("iterm")
+        );
+        assert_eq!(graphics_protocol(env(&[("TERM", "foot")])), Some("sixel"));
+        assert_eq!(
+            graphics_protocol(env(&[
+                ("TERM", "xterm-256color"),
+                ("KONSOLE_VERSION", "230401")
+            ])),
+            Some("sixel")
+        );
+        assert_eq!(graphics_protocol(env(&[("TERM", "xterm-256color")])), None);
+        assert_eq!(graphics_protocol(env(&[])), None);
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn test_render_markdown() {
+        let lua = lua();
+        let render = |markdown: &str, options: &str| -> String {
+            lua.globals().set("markdown", markdown).unwrap();
+            eval(
+                &lua,
+                &format!("return util.render_markdown(markdown, {options})"),
+            )
+        };
+        let res = render(
+            "# Title\n\n## Sub title\n\nSome *emphasis* and `code`.\n",
+            "nil",
+        );
+        assert_eq!(
+            res,
+            "\x1b[1;4mTitle\x1b[0m\n\n\x1b[1mSub\x1b[0m \x1b[1mtitle\x1b[0m\n\nSome \x1b[3memphasis\x1b[0m and \x1b[36mcode\x1b[0m.\n"
+        );
+
+        let res = render("```rust\nfn main() {\n\n}\n```\n", "nil");
+        assert_eq!(
+            res,
+            "  \x1b[36mfn main() {\x1b[0m\n  \n  \x1b[36m}\x1b[0m\n"
+        );
+
+        let res = render("See [xplr](https://xplr.dev) or <https://xplr.dev>.", "nil");
+        assert_eq!(
+            strip_ansi(&res),
+            "See xplr (https://xplr.dev) or https://xplr.dev.\n"
+        );
+        assert!(res.contains("\x1b[2m(https://xplr.dev)\x1b[0m"));
+
+        let res = render(
+            "- one\n- two\n  1. nested\n  2. [x] done\n- > quoted\n\ntext\n",
+            "nil",
+        );
+        assert_eq!(
+            strip_ansi(&res),
+            "• one\n• two\n  1. nested\n  2. [x] done\n• │ quoted\n\ntext\n"
+        );
+
+        let res = render(
+            "| Name | Size |\n|:-----|-----:|\n| foo | 1 |\n| barbaz | 100 |\n",
+            "nil",
+        );
+        assert_eq!(
+            strip_ansi(&res),
+            "Name   │ Size\n───────┼─────\nfoo    │    1\nbarbaz │  100\n"
+        );
+
+        // Wrapped, keeping the indentation.
+        let res = render(
+            "The quick brown fox jumps over the lazy dog.\n\n- The quick brown fox jumps over the lazy dog.\n",
+            "{ width = 16 }",
+        );
+        assert_eq!(
+            strip_ansi(&res),
+            "The quick brown\nfox jumps over\nthe lazy dog.\n\n• The quick\n  brown fox\n  jumps over the\n  lazy dog.\n"
+        );
+
+        assert_eq!(render("", "nil"), "");
+
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let path = root.join("README.md");
+        fs::write(&path, "# Title\n").unwrap();
+        lua.globals()
+            .set("path", path.to_string_lossy().to_string())
+            .unwrap();
+        let res: String =
+            eval(&lua, "return util.render_markdown(path, { file = true })");
+        assert_eq!(res, "\x1b[1;4mTitle\x1b[0m\n");
+
+        let (res, err): (Option<String>, Option<String>) = eval(
+            &lua,
+            "return util.render_markdown(path .. '.missing', { file = true })",
+        );
+        assert_eq!(res, None);
+        assert!(err.unwrap().contains("README.md.missing"));
+    }
+
+    #[test]
+    fn test_ansi_strip() {
+        let lua = lua();
+        let ls = "\x1b[0m\x1b[01;34mdir\x1b[0m  \x1b[01;32mexec\x1b[0m  file\n";
+        lua.globals().set("ls", ls).unwrap();
+
+        let res: String = eval(&lua, "return util.ansi_strip(ls)");
+        assert_eq!(res, "dir  exec  file\n");
+
+        let res: usize = eval(&lua, "return util.ansi_width(ls)");
+        assert_eq!(res, 15);
+        let res: usize = eval(&lua, "return util.ansi_width('\\x1b[1m日本\\x1b[0m')");
+        assert_eq!(res, 4);
+        let res: usize = eval(&lua, "return util.ansi_width('')");
+        assert_eq!(res, 0);
+
+        // Other sequences.
+        assert_eq!(strip_ansi("\x1b[38;2;1;2;3mrgb\x1b[0m"), "rgb");
+        assert_eq!(strip_ansi("\x1b[2K\x1b[1Aup"), "up");
+        assert_eq!(strip_ansi("\u{9b}31mc1\u{9b}0m"), "c1");
+        assert_eq!(
+            strip_ansi("\x1b]8;;https://xplr.dev\x1b\\link\x1b]8;;\x1b\\"),
+            "link"
+        );
+        assert_eq!(strip_ansi("\x1b]0;title\x07text"), "text");
+        assert_eq!(strip_ansi("\x1b(Bcharset\x1b7"), "charset");
+
+        // Malformed.
+        assert_eq!(strip_ansi("red\x1b[31"), "red");
+        assert_eq!(strip_ansi("\x1b[3\u{e9}t\u{e9}"), "\u{e9}t\u{e9}");
+        assert_eq!(strip_ansi("\x1b[31m\x1b"), "");
+        assert_eq!(strip_ansi("\x1b]unterminated"), "");
+        assert_eq!(strip_ansi("\x1b\x1b[0mx"), "x");
+    }
+
+    #[test]
+    fn test_tempfile() {
+        let lua = lua();
+        let tmp = env::temp_dir();
+
+        let file: String = eval(&lua, "return util.tempfile()");
+        let file = PathBuf::from(file);
+        assert!(file.is_file());
+        assert_eq!(file.parent(), Some(tmp.as_path()));
+        assert!(file
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("xplr-"));
+
+        let named: String = eval(
+            &lua,
+            "return util.tempfile({ prefix = 'edit-', suffix = '.md' })",
+        );
+        let named = PathBuf::from(named);
+        let name = named.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with("edit-") && name.ends_with(".md"), "{name}");
+
+        let dir: String = eval(&lua, "return util.tempdir({ suffix = '.d' })");
+        let dir = PathBuf::from(dir);
+        assert!(dir.is_dir());
+        assert_eq!(dir.parent(), Some(tmp.as_path()));
+        assert!(dir.to_string_lossy().ends_with(".d"));
+        fs::write(dir.join("file"), "").unwrap();
+
+        let kept_file: String = eval(&lua, "return util.tempfile({ keep = true })");
+        let kept_dir: String = eval(&lua, "return util.tempdir({ keep = true })");
+
+        let (res, err): (Option<String>, Option<String>) =
+            eval(&lua, "return util.tempfile({ prefix = 'no/such/dir/' })");
+        assert_eq!(res, None);
+        assert!(err.is_some());
+
+        // When the session ends.
+        temp::cleanup();
+        assert!(!file.exists());
+        assert!(!named.exists());
+        assert!(!dir.exists());
+        assert!(Path::new(&kept_file).is_file());
+        assert!(Path::new(&kept_dir).is_dir());
+
+        fs::remove_file(kept_file).unwrap();
+        fs::remove_dir(kept_dir).unwrap();
+    }
+
+    #[test]
+    fn test_apply_symbolic_mode() {
+        let apply = |mode, spec| apply_symbolic_mode(mode, spec, false).unwrap();
+
+        assert_eq!(apply(0o644, "+x"), 0o755);
+        assert_eq!(apply(0o644, "u+x"), 0o744);
+        assert_eq!(apply(0o777, "go-w"), 0o755);
+        assert_eq!(apply(0o777, "a-rwx"), 0o000);
+        assert_eq!(apply(0o640, "o=r"), 0o644);
+        assert_eq!(apply(0o777, "u=rw,g=r,o="), 0o640);
+        assert_eq!(apply(0o600, "g=u"), 0o660);
+        assert_eq!(apply(0o640, "o+g-w"), 0o644);
+        assert_eq!(apply(0o644, "u+s,g+s"), 0o6644);
+        assert_eq!(apply(0o644, "+t"), 0o1644);
+        assert_eq!(apply(0o755, "o-s"), 0o755);
+
+        // Execute only for the directories or the executables.
+        assert_eq!(apply(0o600, "a+X"), 0o600);
+        assert_eq!(apply(0o700, "a+X"), 0o711);
+        assert_eq!(apply_symbolic_mode(0o600, "a+X", true).unwrap(), 0o711);
+
+        for spec in ["", "u", "u+y", "x+u", "u+x,", "+x,o"] {
+            assert!(apply_symbolic_mode(0, spec, false).is_err(), "{spec}");
+        }
+
+        assert_eq!(FileMode::parse("755"), Ok(FileMode::Absolute(0o755)));
+        assert_eq!(FileMode::parse("0644"), Ok(FileMode::Absolute(0o644)));
+        assert_eq!(FileMode::parse("u+x"), Ok(FileMode::Symbolic("u+x".into())));
+        assert!(FileMode::parse("789").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_chmod() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let mode = |path: &str| {
+            fs::symlink_metadata(root.join(path))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o7777
+        };
+        let chmod = |path: &str, args: &str| -> Option<String> {
+            eval(
+                &lua,
+                &format!(
+                    "return util.chmod({:?}, {args})",
+                    root.join(path).to_string_lossy()
+                ),
+            )
+        };
+
+        fs::create_dir_all(root.join("dir/sub")).unwrap();
+        fs::write(root.join("file"), "").unwrap();
+        fs::write(root.join("dir/file"), "").unwrap();
+        fs::write(root.join("dir/sub/file"), "").unwrap();
+        for path in ["file", "dir/file", "dir/sub/file"] {
+            fs::set_permissions(root.join(path), fs::Permissions::from_mode(0o644))
+                .unwrap();
+        }
+        std::os::unix::fs::symlink(root.join("file"), root.join("dir/link")).unwrap();
+
+        assert_eq!(chmod("file", "tonumber('600', 8)"), None);
+        assert_eq!(mode("file"), 0o600);
+        assert_eq!(chmod("file", "'640'"), None);
+        assert_eq!(mode("file"), 0o640);
+        assert_eq!(chmod("file", "'+x'"), None);
+        assert_eq!(mode("file"), 0o751);
+        assert_eq!(chmod("file", "'u=rw,go=r'"), None);
+        assert_eq!(mode("file"), 0o644);
+
+        // Not recursive.
+        assert_eq!(chmod("dir", "'700'"), None);
+        assert_eq!(mode("dir"), 0o700);
+        assert_eq!(mode("dir/file"), 0o644);
+
+        assert_eq!(chmod("dir", "'go-r,u+x', { recursive = true }"), None);
+        assert_eq!(mode("dir"), 0o700);
+        assert_eq!(mode("dir/sub"), 0o711);
+        assert_eq!(mode("dir/file"), 0o700);
+        assert_eq!(mode("dir/sub/file"), 0o700);
+        // The symlinks inside are skipped.
+        assert_eq!(mode("file"), 0o644);
+
+        assert_eq!(chmod("dir", "'a+rX', { recursive = true }"), None);
+        assert_eq!(mode("dir/sub"), 0o755);
+        assert_eq!(mode("dir/file"), 0o755);
+
+        let err = chmod("missing", "'+x'").unwrap();
+        assert!(err.contains("missing"), "{err}");
+
+        let res: mlua::Result<Value> = lua
+            .load(format!(
+                "return util.chmod({:?}, 'u+y')",
+                root.join("file").to_string_lossy()
+            ))
+            .eval();
+        assert!(res.is_err());
+
+        for bits in ["-1", "tonumber('10000', 8)", "1.5", "2^40"] {
+            let res: mlua::Result<Value> = lua
+                .load(format!(
+                    "return util.chmod({:?}, {bits})",
+                    root.join("file").to_string_lossy()
+                ))
+                .eval();
+            assert!(res.is_err(), "{bits}");
+            assert_eq!(mode("file"), 0o644);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_chown() {
+        use std::os::unix::fs::MetadataExt;
+
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::write(root.join("dir/file"), "").unwrap();
+        std::os::unix::fs::symlink("file", root.join("dir/link")).unwrap();
+        lua.globals()
+            .set("dir", root.join("dir").to_string_lossy().to_string())
+            .unwrap();
+
+        // Changing to the current owner is always allowed.
+        let metadata = fs::metadata(root.join("dir/file")).unwrap();
+        let (uid, gid) = (metadata.uid(), metadata.gid());
+        let res: Option<String> = eval(
+            &lua,
+            &format!("return util.chown(dir, {{ uid = {uid}, gid = {gid}, recursive = true }})"),
+        );
+        assert_eq!(res, None);
+
+        if let Some(user) = users::get_user_by_uid(uid) {
+            let res: Option<String> = eval(
+                &lua,
+                &format!(
+                    "return util.chown(dir, {{ user = {:?} }})",
+                    user.name().to_string_lossy()
+                ),
+            );
+            assert_eq!(res, None);
+        }
+        let res: Option<String> = eval(&lua, "return util.chown(dir, {})");
+        assert_eq!(res, None);
+
+        let res: Option<String> = eval(
+            &lua,
+            "return util.chown(dir, { user = 'no-such-user-xplr' })",
+        );
+        assert_eq!(res.as_deref(), Some("no such user: no-such-user-xplr"));
+        let res: Option<String> = eval(
+            &lua,
+            "return util.chown(dir, { group = 'no-such-group-xplr' })",
+        );
+        assert_eq!(res.as_deref(), Some("no such group: no-such-group-xplr"));
+
+        let res: Option<String> = eval(&lua, "return util.chown(dir .. '/missing', {})");
+        assert!(res.unwrap().contains("missing"));
+
+        let res: mlua::Result<Value> = lua
+            .load("return util.chown(dir, { uid = 0, user = 'root' })")
+            .eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_sort_nodes() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir(root.join("b_dir")).unwrap();
+        fs::create_dir(root.join("D_dir")).unwrap();
+        fs::write(root.join("a10"), "12345").unwrap();
+        fs::write(root.join("a9"), "1").unwrap();
+        fs::write(root.join("C"), "123").unwrap();
+        lua.globals()
+            .set("root", root.to_string_lossy().to_string())
+            .unwrap();
+
+        let sort = |sorters: &str| -> Vec<String> {
+            eval(
+                &lua,
+                &format!(
+                    r#"
+                    local names = {{}}
+                    local nodes = util.sort_nodes(util.explore(root), {sorters})
+                    for _, node in ipairs(nodes) do
+                      table.insert(names, node.relative_path)
+                    end
+                    return names
+                    "#
+                ),
+            )
+        };
+
+        assert_eq!(
+            sort("{ { sorter = 'ByRelativePath', reverse = true } }"),
+            vec!["b_dir", "a10", "a9", "D_dir", "C"]
+        );
+        assert_eq!(
+            sort("{ { sorter = 'ByIRelativePath' } }"),
+            vec!["a9", "a10", "b_dir", "C", "D_dir"]
+        );
+
+        // Stable, so the directories keep the order given by explore.
+        let dirs = sort("{ { sorter = 'BySize' } }");
+        let files: Vec<_> = dirs.iter().filter(|n| !n.ends_with("_dir")).collect();
+        assert_eq!(files, vec!["a9", "C", "a10"]);
+
+        assert_eq!(
+            sort(
+                "{ { sorter = 'ByIsDir', reverse = true }, { sorter = 'ByIRelativePath' } }"
+            ),
+            vec!["b_dir", "D_dir", "a9", "a10", "C"]
+        );
+
+        // The nodes are returned as given.
+        let res: String = eval(
+            &lua,
+            r#"
+            local nodes = util.explore(root)
+            nodes[1].custom = "kept"
+            local sorted = util.sort_nodes(nodes, {})
+            return sorted[1].custom .. #sorted
+            "#,
+        );
+        assert_eq!(res, "kept5");
+
+        let res: mlua::Result<Value> = lua
+            .load("return util.sort_nodes(util.explore(root), { { sorter = 'ByNothing' } })")
+            .eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_filter_nodes() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        for name in [
+            "README.md",
+            "notes.MD",
+            ".hidden.md",
+            "main.rs",
+            "Cargo.toml",
+        ] {
+            fs::write(root.join(name), "").unwrap();
+        }
+        lua.globals()
+            .set("root", root.to_string_lossy().to_string())
+            .unwrap();
+
+        let filter = |filters: &str| -> Vec<String> {
+            eval(
+                &lua,
+                &format!(
+                    r#"
+                    local names = {{}}
+                    local nodes = util.sort_nodes(
+                      util.explore(root),
+                      {{ {{ sorter = "ByRelativePath" }} }}
+                    )
+                    for _, node in ipairs(util.filter_nodes(nodes, {filters})) do
+                      table.insert(names, node.relative_path)
+                    end
+                    return names
+                    "#
+                ),
+            )
+        };
+
+        assert_eq!(
+            filter("{ { filter = 'RelativePathDoesStartWith', input = 'READ' } }"),
+            vec!["README.md"]
+        );
+        assert_eq!(
+            filter("{ { filter = 'IRelativePathDoesMatchRegex', input = '\\\\.md$' } }"),
+            vec![".hidden.md", "README.md", "notes.MD"]
+        );
+        assert_eq!(
+            filter("{ { filter = 'RelativePathDoesNotEndWith', input = '.md' } }"),
+            vec!["Cargo.toml", "main.rs", "notes.MD"]
+        );
+
+        // All must pass.
+        assert_eq!(
+            filter(
+                r#"{
+                  { filter = "IRelativePathDoesEndWith", input = ".md" },
+                  { filter = "RelativePathDoesNotStartWith", input = "." },
+                }"#
+            ),
+            vec!["README.md", "notes.MD"]
+        );
+        assert_eq!(filter("{}").len(), 5);
+
+        let res: mlua::Result<Value> = lua
+            .load("return util.filter_nodes(util.explore(root), { { filter = 'Nope', input = '' } })")
+            .eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_index_of() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        for name in ["b", "d", "f"] {
+            fs::write(root.join(name), "").unwrap();
+        }
+        lua.globals()
+            .set("root", root.to_string_lossy().to_string())
+            .unwrap();
+
+        let index_of = |path: &str| -> Option<usize> {
+            eval(
+                &lua,
+                &format!(
+                    r#"
+                    local nodes = util.sort_nodes(
+                      util.explore(root),
+                      {{ {{ sorter = "ByRelativePath" }} }}
+                    )
+                    return util.index_of(nodes, {path:?})
+                    "#
+                ),
+            )
+        };
+
+        assert_eq!(index_of("d"), Some(2));
+        assert_eq!(index_of("b"), Some(1));
+        assert_eq!(index_of("x"), None);
+
+        // Inserted before.
+        fs::write(root.join("a"), "").unwrap();
+        fs::write(root.join("c"), "").unwrap();
+        assert_eq!(index_of("d"), Some(4));
+
+        // Removed before, inserted after.
+        fs::remove_file(root.join("a")).unwrap();
+        fs::remove_file(root.join("b")).unwrap();
+        fs::write(root.join("e"), "").unwrap();
+        assert_eq!(index_of("d"), Some(2));
+        assert_eq!(index_of("b"), None);
+
+        // Sorted differently.
+        let res: Option<usize> = eval(
+            &lua,
+            r#"
+            local nodes = util.sort_nodes(
+              util.explore(root),
+              { { sorter = "ByRelativePath", reverse = true } }
+            )
+            return util.index_of(nodes, "d")
+            "#,
+        );
+        assert_eq!(res, Some(3));
+
+        let res: Option<usize> = eval(&lua, "return util.index_of({}, 'd')");
+        assert_eq!(res, None);
+        let res: mlua::Result<Value> =
+            lua.load("return util.index_of({ 1 }, 'd')").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_is_tty() {
+        use std::io::IsTerminal;
+
+        let lua = lua();
+
+        let res: bool = eval(&lua, "return util.is_tty()");
+        assert_eq!(res, io::stdout().is_terminal());
+        let res: bool = eval(&lua, "return util.is_tty({ stream = 'stderr' })");
+        assert_eq!(res, io::stderr().is_terminal());
+        let res: bool = eval(&lua, "return util.is_tty({ stream = 'stdin' })");
+        assert_eq!(res, io::stdin().is_terminal());
+
+        let res: mlua::Result<Value> =
+            lua.load("return util.is_tty({ stream = 'stdfoo' })").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_terminal_size() {
+        assert_eq!(
+            query_terminal_size(|| Ok((80, 24))),
+            Some(TerminalSize { cols: 80, rows: 24 })
+        );
+        assert_eq!(query_terminal_size(|| Ok((0, 0))), None);
+        assert_eq!(
+            query_terminal_size(|| Err(io::Error::other("no tty"))),
+            None
+        );
+
+        let lua = lua();
+        let res: Value = eval(&lua, "return util.terminal_size()");
+        match crossterm::terminal::size() {
+            Ok((cols, rows)) if cols > 0 && rows > 0 => {
+                let (c, r): (u16, u16) = eval(
+                    &lua,
+                    "local size = util.terminal_size(); return size.cols, size.rows",
+                );
+                assert_eq!((c, r), (cols, rows));
+            }
+            _ => assert!(res.is_nil()),
+        }
+    }
+
+    #[test]
+    fn test_color() {
+        assert_eq!(Rgb::parse("#ff8800"), Some(Rgb::new(255, 136, 0)));
+        assert_eq!(Rgb::parse("#FF8800"), Some(Rgb::new(255, 136, 0)));
+        assert_eq!(Rgb::parse("#f80"), Some(Rgb::new(255, 136, 0)));
+        assert_eq!(Rgb::parse("rgb(0, 128, 255)"), Some(Rgb::new(0, 128, 255)));
+        assert_eq!(Rgb::parse("RGB(0,128,255)"), Some(Rgb::new(0, 128, 255)));
+        assert_eq!(Rgb::parse("Red"), Some(Rgb::new(205, 0, 0)));
+        assert_eq!(Rgb::parse("lightblue"), Some(Rgb::new(92, 92, 255)));
+
+        for spec in [
+            "",
+            "#",
+            "#ff88",
+            "#ff880g",
+            "#ff88000",
+            "#ffé",
+            "rgb(0, 128)",
+            "rgb(0, 128, 256)",
+            "rgb(0, 128, 255, 1)",
+            "rgb(0, 128, 255",
+            "Reset",
+            "foo",
+        ] {
+            assert_eq!(Rgb::parse(spec), None, "{spec}");
+        }
+
+        let lua = lua();
+
+        let res: (u8, u8, u8) = eval(
+            &lua,
+            "local c = util.color('#ff8800'); return c.r, c.g, c.b",
+        );
+        assert_eq!(res, (255, 136, 0));
+        let res: (u8, u8, u8) = eval(
+            &lua,
+            "local c = util.color('rgb(0, 128, 255)'); return c.r, c.g, c.b",
+        );
+        assert_eq!(res, (0, 128, 255));
+        let res: (u8, u8, u8) =
+            eval(&lua, "local c = util.color('Green'); return c.r, c.g, c.b");
+        assert_eq!(res, (0, 205, 0));
+        let res: (u8, u8, u8) = eval(
+            &lua,
+            "local c = util.color({ r = 1, g = 2, b = 3 }); return c.r, c.g, c.b",
+        );
+        assert_eq!(res, (1, 2, 3));
+
+        for code in [
+            "util.color('#ff88')",
+            "util.color('rgb(0, 0, 300)')",
+            "util.color('Reset')",
+            "util.color({ r = 1, g = 2 })",
+            "util.color({ r = 1, g = 2, b = 256 })",
+            "util.color(42)",
+            "util.blend('#000000', 'foo', 0.5)",
+            "util.to_hex('foo')",
+        ] {
+            let res: mlua::Result<Value> = lua.load(code).eval();
+            assert!(
+                res.unwrap_err().to_string().contains("invalid color"),
+                "{code}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_blend() {
+        assert_eq!(Rgb::BLACK.blend(Rgb::WHITE, 0.5), Rgb::new(128, 128, 128));
+        assert_eq!(Rgb::BLACK.blend(Rgb::WHITE, 0.0), Rgb::BLACK);
+        assert_eq!(Rgb::BLACK.blend(Rgb::WHITE, 1.0), Rgb::WHITE);
+        assert_eq!(Rgb::BLACK.blend(Rgb::WHITE, 2.0), Rgb::WHITE);
+        assert_eq!(Rgb::BLACK.blend(Rgb::WHITE, -1.0), Rgb::BLACK);
+
+        let lua = lua();
+
+        let res: String = eval(
+            &lua,
+            "return util.to_hex(util.blend('#ff0000', '#0000ff', 0.5))",
+        );
+        assert_eq!(res, "#800080");
+        let res: String = eval(&lua, "return util.to_hex(util.lighten('#ff0000', 0.5))");
+        assert_eq!(res, "#ff8080");
+        let res: String = eval(&lua, "return util.to_hex(util.darken('#ff0000', 0.5))");
+        assert_eq!(res, "#800000");
+        let res: String = eval(&lua, "return util.to_hex(util.lighten('Blue', 0.2))");
+        assert_eq!(res, "#3333f1");
+    }
+
+    #[test]
+    fn test_to_hex() {
+        let lua = lua();
+
+        for hex in ["#000000", "#ffffff", "#ff8800", "#0a1b2c"] {
+            let res: String = eval(&lua, &format!("return util.to_hex('{hex}')"));
+            assert_eq!(res, hex);
+            let res: String = eval(
+                &lua,
+                &format!("return util.to_hex(util.color(util.to_hex('{hex}')))"),
+            );
+            assert_eq!(res, hex);
+        }
+
+        let res: String = eval(&lua, "return util.to_hex('#F80')");
+        assert_eq!(res, "#ff8800");
+        let res: String = eval(&lua, "return util.to_hex({ r = 255, g = 136, b = 0 })");
+        assert_eq!(res, "#ff8800");
+    }
+
+    #[test]
+    fn test_to_ansi256() {
+        for (rgb, index) in [
+            (Rgb::new(0, 0, 0), 16),
+            (Rgb::new(255, 255, 255), 231),
+            (Rgb::new(255, 0, 0), 196),
+            (Rgb::new(0, 255, 0), 46),
+            (Rgb::new(0, 0, 255), 21),
+            (Rgb::new(255, 135, 0), 208),
+            (Rgb::new(95, 135, 175), 67),
+            (Rgb::new(8, 8, 8), 232),
+            (Rgb::new(128, 128, 128), 244),
+            (Rgb::new(238, 238, 238), 255),
+            (Rgb::new(250, 250, 250), 231),
+            (Rgb::new(3, 3, 3), 16),
+            (Rgb::new(1, 2, 3), 16),
+            (Rgb::new(100, 100, 100), 241),
+            (Rgb::new(254, 1, 1), 196),
+        ] {
+            assert_eq!(rgb.ansi256(), index, "{rgb:?}");
+        }
+
+        let lua = lua();
+        let res: u8 = eval(&lua, "return util.to_ansi256('#ff8800')");
+        assert_eq!(res, 208);
+        let res: u8 = eval(
+            &lua,
+            "return util.to_ansi256({ r = 128, g = 128, b = 128 })",
+        );
+        assert_eq!(res, 244);
+        let res: mlua::Result<Value> = lua.load("util.to_ansi256('foo')").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_supports_truecolor() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |var: &str| {
+                vars.iter()
+                    .find(|(k, _)| *k == var)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+
+        assert!(truecolor_supported(env(&[("COLORTERM", "truecolor")])));
+        assert!(truecolor_supported(env(&[("COLORTERM", "24bit")])));
+        assert!(truecolor_supported(env(&[("COLORTERM", "TrueColor")])));
+        assert!(truecolor_supported(env(&[("TERM", "xterm-direct")])));
+        assert!(truecolor_supported(env(&[("TERM", "xterm-truecolor")])));
+        assert!(!truecolor_supported(env(&[])));
+        assert!(!truecolor_supported(env(&[("COLORTERM", "yes")])));
+        assert!(!truecolor_supported(env(&[("TERM", "xterm-256color")])));
+
+        let lua = lua();
+        let res: bool = eval(&lua, "return util.supports_truecolor()");
+        assert_eq!(res, truecolor_supported(|var| env::var(var).ok()));
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let name = "foo\x1b[2Jbar\tbaz\r\nqux\x7f\u{9b}31m.txt";
+        let options = |pictures, strip_ansi, keep_newlines| SanitizeOptions {
+            pictures,
+            strip_ansi,
+            keep_newlines,
+        };
+
+        assert_eq!(
+            sanitize_text(name, &SanitizeOptions::default()),
+            "foo^[[2Jbar^Ibaz^M^Jqux^?M-^[31m.txt"
+        );
+        assert_eq!(
+            sanitize_text(name, &options(true, false, false)),
+            "foo␛[2Jbar␉baz␍␊qux␡M-^[31m.txt"
+        );
+        assert_eq!(
+            sanitize_text(name, &options(false, true, false)),
+            "foobar^Ibaz^M^Jqux^?.txt"
+        );
+        assert_eq!(
+            sanitize_text(name, &options(false, false, true)),
+            "foo^[[2Jbar^Ibaz^M\nqux^?M-^[31m.txt"
+        );
+        assert_eq!(
+            sanitize_text(name, &options(true, true, true)),
+            "foobar␉baz␍\nqux␡.txt"
+        );
+
+        assert_eq!(sanitize_text("", &SanitizeOptions::default()), "");
+        assert_eq!(sanitize_text("\0", &SanitizeOptions::default()), "^@");
+        assert_eq!(sanitize_text("\0", &options(true, false, false)), "␀");
+        assert_eq!(
+            sanitize_text("naïve 日本.md", &SanitizeOptions::default()),
+            "naïve 日本.md"
+        );
+
+        // Nothing left to be interpreted by the terminal.
+        let sanitized = sanitize_text(name, &SanitizeOptions::default());
+        assert!(!sanitized.chars().any(char::is_control));
+
+        let lua = lua();
+        let res: String = eval(&lua, r#"return util.sanitize("foo\27[2J\r\nbar")"#);
+        assert_eq!(res, "foo^[[2J^M^Jbar");
+        let res: String = eval(
+            &lua,
+            r#"return util.sanitize("foo\27[2J\r\nbar", { pictures = true, strip_ansi = true })"#,
+        );
+        assert_eq!(res, "foo␍␊bar");
+        let res: String = eval(
+            &lua,
+            r#"return util.sanitize("foo\tbar\nbaz", { keep_newlines = true })"#,
+        );
+        assert_eq!(res, "foo^Ibar\nbaz");
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("sub/deep")).unwrap();
+        fs::write(root.join("a.txt"), "same content").unwrap();
+        fs::write(root.join("sub/b.txt"), "same content").unwrap();
+        fs::write(root.join("sub/deep/c.txt"), "same content").unwrap();
+        fs::write(root.join("unique.txt"), "other thing!").unwrap();
+        fs::write(root.join("small1"), "x").unwrap();
+        fs::write(root.join("small2"), "x").unwrap();
+        fs::write(root.join("empty1"), "").unwrap();
+        fs::write(root.join("empty2"), "").unwrap();
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("a.txt"), root.join("link")).unwrap();
+            fs::hard_link(root.join("sub/b.txt"), root.join("sub/hard.txt")).unwrap();
+        }
+
+        let path = |p: &str| root.join(p).to_string_lossy().to_string();
+        let same_hash =
+            "a636bd7cd42060a4d07fa1bfbcc010eb7794c2ba721e1e3e4c20335a15b66eaf";
+
+        let groups = find_duplicate_files(root, &DuplicatesOptions::default()).unwrap();
+        assert_eq!(
+            groups,
+            vec![
+                DuplicateGroup {
+                    hash: same_hash.into(),
+                    size: 12,
+                    paths: vec![path("a.txt"), path("sub/b.txt"), path("sub/deep/c.txt")],
+                },
+                DuplicateGroup {
+                    hash: "2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881"
+                        .into(),
+                    size: 1,
+                    paths: vec![path("small1"), path("small2")],
+                },
+            ]
+        );
+
+        let options = DuplicatesOptions {
+            max_depth: Some(2),
+            min_size: 2,
+        };
+        let groups = find_duplicate_files(root, &options).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].paths, vec![path("a.txt"), path("sub/b.txt")]);
+
+        let options = DuplicatesOptions {
+            max_depth: None,
+            min_size: 0,
+        };
+        let groups = find_duplicate_files(root, &options).unwrap();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[1].paths, vec![path("empty1"), path("empty2")]);
+
+        let options = DuplicatesOptions {
+            max_depth: Some(0),
+            min_size: 0,
+        };
+        assert_eq!(find_duplicate_files(root, &options).unwrap(), groups);
+
+        let lua = lua();
+        lua.globals()
+            .set("root", root.to_string_lossy().to_string())
+            .unwrap();
+
+        let (hash, size, count): (String, u64, usize) = eval(
+            &lua,
+            r#"
+            local groups = util.find_duplicates(root, { min_size = 2 })
+            assert(#groups == 1)
+            return groups[1].hash, groups[1].size, #groups[1].paths
+            "#,
+        );
+        assert_eq!((hash.as_str(), size, count), (same_hash, 12, 3));
+
+        let (groups, err): (Value, String) =
+            eval(&lua, "return util.find_duplicates(root .. '/nope')");
+        assert!(groups.is_nil());
+        assert!(err.contains("nope"), "{err}");
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let cwd = env::current_dir().unwrap();
+        let usage = disk_usage_of(&cwd).unwrap();
+        assert!(usage.total > 0);
+        assert!(usage.used <= usage.total);
+        assert!(usage.available <= usage.total);
+        assert!(cwd.canonicalize().unwrap().starts_with(&usage.mount_point));
+
+        // Same filesystem.
+        let sub = disk_usage_of(&cwd.join("src")).unwrap();
+        assert_eq!(sub.mount_point, usage.mount_point);
+        assert_eq!(sub.total, usage.total);
+
+        #[cfg(target_os = "linux")]
+        {
+            assert!(usage.fs_type.is_some());
+            let proc = disk_usage_of(Path::new("/proc/self")).unwrap();
+            assert_eq!(proc.mount_point, "/proc");
+            assert_eq!(proc.fs_type.as_deref(), Some("proc"));
+        }
+
+        let lua = lua();
+
+        let (total, available): (u64, u64) = eval(
+            &lua,
+            "local usage = util.disk_usage('.'); return usage.total, usage.available",
+        );
+        assert!(total > 0);
+        assert!(available <= total);
+
+        let (usage, err): (Value, String) =
+            eval(&lua, "return util.disk_usage('/nonexistent/xplr/path')");
+        assert!(usage.is_nil());
+        assert!(err.starts_with("/nonexistent/xplr/path: "), "{err}");
+    }
+
+    #[test]
+    fn test_mounts_fs_type() {
+        let mounts = "\
+sysfs /sys sysfs rw,nosuid 0 0
+/dev/sda1 / ext4 rw,relatime 0 0
+tmpfs /mnt/my\\040disk tmpfs rw 0 0
+/dev/sdb1 /mnt/data ext4 rw 0 0
+/dev/sdc1 /mnt/data btrfs rw 0 0
+";
+        assert_eq!(
+            mounts_fs_type(mounts, Path::new("/")),
+            Some("ext4".to_string())
+        );
+        assert_eq!(
+            mounts_fs_type(mounts, Path::new("/mnt/my disk")),
+            Some("tmpfs".to_string())
+        );
+        assert_eq!(
+            mounts_fs_type(mounts, Path::new("/mnt/data")),
+            Some("btrfs".to_string())
+        );
+        assert_eq!(mounts_fs_type(mounts, Path::new("/mnt")), None);
+        assert_eq!(mounts_fs_type("", Path::new("/")), None);
+    }
+
+    #[test]
+    fn test_batch_rename() {
+        let lua = lua();
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        for name in ["IMG_1.jpg", "IMG_2.jpg", "notes.txt"] {
+            fs::write(root.join(name), name).unwrap();
+        }
+        lua.globals()
+            .set("root", root.to_string_lossy().to_string())
+            .unwrap();
+        let names = || {
+            let mut names: Vec<_> = fs::read_dir(root)
+                .unwrap()
+                .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        // Regex.
+        let renamed: Vec<String> = eval(
+            &lua,
+            r#"
+            local renamed = {}
+            local paths = { root .. "/IMG_1.jpg", root .. "/IMG_2.jpg", root .. "/notes.txt" }
+            local res = assert(util.batch_rename(paths, {
+              pattern = "^IMG_(\\d+)",
+              replace = "photo-$1",
+            }))
+            for _, r in ipairs(res) do
+              table.insert(renamed, r.from:sub(#root + 2) .. " -> " .. r.to:sub(#root + 2))
+            end
+            return renamed
+            "#,
+        );
+        assert_eq!(
+            renamed,
+            vec!["IMG_1.jpg -> photo-1.jpg", "IMG_2.jpg -> photo-2.jpg"]
+        );
+        assert_eq!(names(), vec!["notes.txt", "photo-1.jpg", "photo-2.jpg"]);
+        assert_eq!(
+            fs::read_to_string(root.join("photo-1.jpg")).unwrap(),
+            "IMG_1.jpg"
+        );
+
+        // Transform.
+        let count: usize = eval(
+            &lua,
+            r#"
+            local res = util.batch_rename({ root .. "/notes.txt" }, { transform = string.upper })
+            return #res
+            "#,
+        );
+        assert_eq!(count, 1);
+        assert_eq!(names(), vec!["NOTES.TXT", "photo-1.jpg", "photo-2.jpg"]);
+
+        // No-op.
+        let count: usize = eval(
+            &lua,
+            r#"
+            local paths = { root .. "/NOTES.TXT", root .. "/photo-1.jpg" }
+            local res = util.batch_rename(paths, { transform = function(name) return name end })
+            return #res
+            "#,
+        );
+        assert_eq!(count, 0);
+        let count: usize =
+            eval(&lua, "return #util.batch_rename({}, { transform = print })");
+        assert_eq!(count, 0);
+
+        // Collisions, nothing is renamed.
+        let (res, err): (Value, String) = eval(
+            &lua,
+            r#"
+            local paths = { root .. "/NOTES.TXT", root .. "/photo-1.jpg", root .. "/photo-2.jpg" }
+            return util.batch_rename(paths, { pattern = "-\\d", replace = "" })
+            "#,
+        );
+        assert!(res.is_nil());
+        assert_eq!(
+            err,
+            format!(
+                "{0}/photo.jpg: both {0}/photo-1.jpg and {0}/photo-2.jpg would be renamed to it",
+                root.display()
+            )
+        );
+        assert_eq!(names(), vec!["NOTES.TXT", "photo-1.jpg", "photo-2.jpg"]);
+
+        let (res, err): (Value, String) = eval(
+            &lua,
+            r#"
+            local paths = { root .. "/NOTES.TXT", root .. "/photo-1.jpg" }
+            return util.batch_rename(paths, { pattern = "1", replace = "2" })
+            "#,
+        );
+        assert!(res.is_nil());
+        assert!(
+            err.starts_with(&format!("{}/photo-2.jpg: ", root.display())),
+            "{err}"
+        );
+        assert_eq!(names(), vec!["NOTES.TXT", "photo-1.jpg", "photo-2.jpg"]);
+
+        // Swaps and chains, through the names being freed.
+        let count: usize = eval(
+            &lua,
+            r#"
+            local paths = { root .. "/photo-1.jpg", root .. "/photo-2.jpg" }
+            local swapped = { ["photo-1.jpg"] = "photo-2.jpg", ["photo-2.jpg"] = "photo-1.jpg" }
+            local res = util.batch_rename(paths, { transform = function(name) return swapped[name] end })
+            return #res
+            "#,
+        );
+        assert_eq!(count, 2);
+        assert_eq!(names(), vec!["NOTES.TXT", "photo-1.jpg", "photo-2.jpg"]);
+        assert_eq!(
+            fs::read_to_string(root.join("photo-1.jpg")).unwrap(),
+            "IMG_2.jpg"
+        );
+
+        let count: usize = eval(
+            &lua,
+            r#"
+            local paths = { root .. "/photo-1.jpg", root .. "/photo-2.jpg" }
+            local res = util.batch_rename(paths, { pattern = "(\\d)", replace = "${1}0" })
+            return #res
+            "#,
+        );
+        assert_eq!(count, 2);
+        let count: usize = eval(
+            &lua,
+            r#"
+            local paths = { root .. "/photo-10.jpg", root .. "/photo-20.jpg" }
+            local next = { ["photo-10.jpg"] = "photo-20.jpg", ["photo-20.jpg"] = "photo-30.jpg" }
+            local res = util.batch_rename(paths, { transform = function(name) return next[name] end })
+            return #res
+            "#,
+        );
+        assert_eq!(count, 2);
+        assert_eq!(names(), vec!["NOTES.TXT", "photo-20.jpg", "photo-30.jpg"]);
+        assert_eq!(
+            fs::read_to_string(root.join("photo-30.jpg")).unwrap(),
+            "IMG_1.jpg"
+        );
+
+        for transform in ["''", "'..'", "'a/b'"] {
+            let (res, err): (Value, String) = eval(
+                &lua,
+                &format!(
+                    "return util.batch_rename({{ root .. '/NOTES.TXT' }}, {{ transform = function() return {transform} end }})"
+                ),
+            );
+            assert!(res.is_nil());
+            assert!(err.contains("invalid new name"), "{err}");
+        }
+
+        let (res, err): (Value, String) = eval(
+            &lua,
+            "return util.batch_rename({ root .. '/nope' }, { transform = string.upper })",
+        );
+        assert!(res.is_nil());
+        assert!(
+            err.starts_with(&format!("{}/nope: ", root.display())),
+            "{err}"
+        );
+        assert_eq!(names(), vec!["NOTES.TXT", "photo-20.jpg", "photo-30.jpg"]);
+
+        for code in [
+            "util.batch_rename({}, {})",
+            "util.batch_rename({}, { pattern = 'a' })",
+            "util.batch_rename({}, { pattern = '(', replace = '' })",
+            "util.batch_rename({}, { transform = print, pattern = 'a', replace = 'b' })",
+        ] {
+            let res: mlua::Result<Value> = lua.load(code).eval();
+            assert!(res.is_err(), "{code}");
+        }
+    }
+
+    #[test]
+    fn test_apply_renames_undo() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::write(root.join("a"), "").unwrap();
+        fs::write(root.join("b"), "").unwrap();
+
+        // The second source went missing after the checks.
+        let plan = vec![
+            (root.join("a"), root.join("c")),
+            (root.join("nope"), root.join("d")),
+        ];
+        let err = apply_renames(&plan).unwrap_err();
+        assert!(err.starts_with(&format!("{}: ", root.join("nope").display())));
+        assert!(root.join("a").exists());
+        assert!(!root.join("c").exists());
+        assert_eq!(fs::read_dir(root).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_is_case_change() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::write(root.join("Foo"), "").unwrap();
+        fs::hard_link(root.join("Foo"), root.join("bar")).unwrap();
+
+        assert!(is_case_change(&root.join("Foo"), &root.join("Foo")));
+        assert!(!is_case_change(&root.join("Foo"), &root.join("bar")));
+        assert!(!is_case_change(&root.join("Foo"), &root.join("foo2")));
+    }
+
+    #[test]
+    fn test_tree_prefix() {
+        for (depth, is_last, ascii, prefix) in [
+            (0, &[][..], false, ""),
+            (0, &[true][..], false, ""),
+            (1, &[false][..], false, "├── "),
+            (1, &[true][..], false, "└── "),
+            (2, &[false, false][..], false, "│   ├── "),
+            (2, &[false, true][..], false, "│   └── "),
+            (2, &[true, false][..], false, "    ├── "),
+            (3, &[false, true, true][..], false, "│       └── "),
+            (3, &[true, true, false][..], false, "        ├── "),
+            (3, &[false, false, false, true][..], false, "│   │   ├── "),
+            (2, &[][..], false, "│   ├── "),
+            (2, &[true][..], false, "    ├── "),
+            (1, &[false][..], true, "|-- "),
+            (1, &[true][..], true, "`-- "),
+            (3, &[false, true, false][..], true, "|       |-- "),
+        ] {
+            assert_eq!(
+                tree_prefix_of(depth, is_last, ascii),
+                prefix,
+                "{depth} {is_last:?} {ascii}"
+            );
+        }
+
+        let lua = lua();
+        let res: String =
+            eval(&lua, "return util.tree_prefix(3, { false, true, true })");
+        assert_eq!(res, "│       └── ");
+        let res: String = eval(
+            &lua,
+            "return util.tree_prefix(2, { true, false }, { ascii = true })",
+        );
+        assert_eq!(res, "    |-- ");
+    }
+
+    #[test]
+    fn test_gitignore_matcher() {
+        let lua = lua();
+
+        let matches = |patterns: &str, path: &str, is_dir: bool| -> bool {
+            eval(
+                &lua,
+                &format!(
+                    "return util.gitignore_matcher({patterns}, {{ root = '/project' }}):matches({path:?}, {is_dir})"
+                ),
+            )
+        };
+
+        // Negated override.
+        let patterns = "{ '*.log', '!keep.log' }";
+        assert!(matches(patterns, "debug.log", false));
+        assert!(matches(patterns, "logs/debug.log", false));
+        assert!(!matches(patterns, "keep.log", false));
+        assert!(!matches(patterns, "logs/keep.log", false));
+        assert!(!matches(patterns, "debug.txt", false));
+
+        // Order matters.
+        assert!(matches("{ '!keep.log', '*.log' }", "keep.log", false));
+
+        // Directory only.
+        let patterns = "{ 'build/' }";
+        assert!(matches(patterns, "build", true));
+        assert!(!matches(patterns, "build", false));
+        assert!(matches(patterns, "build/out.o", false));
+        assert!(matches(patterns, "src/build/out.o", false));
+        assert!(!matches(patterns, "builder", true));
+
+        // Anchored.
+        let patterns = "{ '/target', 'docs/*.pdf' }";
+        assert!(matches(patterns, "target", true));
+        assert!(!matches(patterns, "sub/target", true));
+        assert!(matches(patterns, "docs/a.pdf", false));
+        assert!(!matches(patterns, "sub/docs/a.pdf", false));
+
+        // Absolute paths.
+        let patterns = "{ '*.log' }";
+        assert!(matches(patterns, "/project/debug.log", false));
+        assert!(!matches(patterns, "/elsewhere/debug.log", false));
+        assert!(!matches(patterns, "/project", true));
+
+        assert!(!matches("{}", "debug.log", false));
+
+        // Nodes.
+        let res: (bool, bool) = eval(
+            &lua,
+            r#"
+            local ignored = util.gitignore_matcher({ "build/" }, { root = "/project" })
+            return ignored:matches({ absolute_path = "/project/build", is_dir = true }),
+              ignored:matches({ absolute_path = "/project/build", is_dir = false })
+            "#,
+        );
+        assert_eq!(res, (true, false));
+
+        let res: mlua::Result<Value> =
+            lua.load("util.gitignore_matcher({ 'a[' })").eval();
+        assert!(res.is_err());
+        let res: mlua::Result<Value> =
+            lua.load("util.gitignore_matcher({}):matches(42)").eval();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_find_root() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let project = root.join("project");
+        fs::create_dir_all(project.join(".git")).unwrap();
+        fs::create_dir_all(project.join("crate/src/bin")).unwrap();
+        fs::write(project.join("crate/Cargo.toml"), "").unwrap();
+        fs::write(project.join("crate/src/main.rs"), "").unwrap();
+        fs::create_dir_all(root.join("other/deep")).unwrap();
+
+        let git = vec![".git".to_string()];
+        let cargo = vec!["Cargo.toml".to_string(), "package.json".to_string()];
+
+        let bin = project.join("crate/src/bin");
+        assert_eq!(project_root(&bin, &git, None), Some(project.clone()));
+        assert_eq!(
+            project_root(&bin, &cargo, None),
+            Some(project.join("crate"))
+        );
+        assert_eq!(project_root(&project, &git, None), Some(project.clone()));
+
+        // Files and nonexistent paths.
+        let main = project.join("crate/src/main.rs");
+        assert_eq!(project_root(&main, &git, None), Some(project.clone()));
+        let missing = project.join("crate/src/missing/file");
+        assert_eq!(project_root(&missing, &git, None), Some(project.clone()));
+
+        // No markers.
+        let deep = root.join("other/deep");
+        assert_eq!(project_root(&deep, &git, Some(root)), None);
+        assert_eq!(project_root(&deep, &[], None), None);
+
+        // The boundary is looked into, but not above.
+        assert_eq!(
+            project_root(&bin, &git, Some(&project)),
+            Some(project.clone())
+        );
+        assert_eq!(project_root(&bin, &git, Some(&project.join("crate"))), None);
+
+        let lua = lua();
+        lua.globals()
+            .set("root", root.to_string_lossy().to_string())
+            .unwrap();
+
+        let res: String =
+            eval(&lua, "return util.find_root(root .. '/project/crate/src')");
+        assert_eq!(res, project.to_string_lossy());
+        let res: String = eval(
+            &lua,
+            "return util.find_root(root .. '/project/crate/src', { markers = { 'Cargo.toml' } })",
+        );
+        assert_eq!(res, project.join("crate").to_string_lossy());
+        let res: Option<String> = eval(
+            &lua,
+            "return util.find_root(root .. '/other/deep', { boundary = root })",
+        );
+        assert_eq!(res, None);
+        let res: Option<String> = eval(
+            &lua,
+            "return util.find_root(root .. '/other/deep', { markers = {}, boundary = true })",
+        );
+        assert_eq!(res, None);
+    }
+
+    #[test]
+    fn test_log() {
+        // Creating the app changes the current directory.
+        let pwd = env::current_dir().unwrap();
+        let xplr = mlua::Lua::new();
+        let app =
+            app::App::create("xplr".into(), None, pwd, &xplr, None, [].into()).unwrap();
+
+        xplr.load(
+            r#"
+            xplr.util.log("warn", "xplr-util-test-log: careful")
+            xplr.util.log("debug", "xplr-util-test-log: details")
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let app = app
+            .handle_task(app::Task::new(
+                app::MsgIn::External(ExternalMsg::Refresh),
+                None,
+            ))
+            .unwrap();
+        let logs: Vec<_> = app
+            .logs
+            .iter()
+            .filter(|l| l.message.starts_with("xplr-util-test-log: "))
+            .map(|l| (l.level, l.message.as_str()))
+            .collect();
+        assert_eq!(
+            logs,
+            vec![
+                (app::LogLevel::Warning, "xplr-util-test-log: careful"),
+                (app::LogLevel::Debug, "xplr-util-test-log: details"),
+            ]
+        );
+        assert!(!app.logs_hidden);
+
+        let lua = lua();
+        for level in ["debug", "info", "success", "warn", "warning", "error"] {
+            let res: mlua::Result<Value> = lua
+                .load(format!(
+                    "util.log({level:?}, 'xplr-util-test-log: {level}')"
+                ))
+                .eval();
+            assert!(res.is_ok(), "{level}");
+        }
+        let res: mlua::Result<Value> = lua.load("util.log('fatal', 'foo')").eval();
+        assert!(res.is_err());
+    }
+}
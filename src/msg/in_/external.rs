@@ -1655,6 +1655,62 @@ pub struct ExplorerConfig {
     pub filters: IndexSet<NodeFilterApplicable>,
     pub sorters: IndexSet<NodeSorterApplicable>,
     pub searcher: Option<NodeSearcher>,
+
+    /// How deep to descend into the sub directories. `1` (default) lists only
+    /// the entries of the given directory, and `0` means no limit.
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+
+    /// Hide the entries ignored by git (`.gitignore`, `.git/info/exclude`,
+    /// and the global gitignore). Has no effect outside git repositories.
+    #[serde(default)]
+    pub respect_gitignore: bool,
+
+    /// Populate the `mime` field of the nodes. It's guessed from the
+    /// extension, so it's cheap, but still not free.
+    #[serde(default)]
+    pub add_mime: bool,
+
+    /// Populate the `owner` and `group` fields of the nodes. It requires
+    /// looking up the user and group databases for every node.
+    #[serde(default)]
+    pub add_owner: bool,
+
+    /// Populate the `binary_human_size` field of the nodes, e.g. `1.2 MiB`.
+    #[serde(default)]
+    pub add_binary_human_size: bool,
+
+    /// How many threads to use for reading the metadata of the entries. `1`
+    /// reads them one by one, and null (default) or `0` uses one thread per
+    /// CPU. The order of the nodes isn't affected.
+    #[serde(default)]
+    pub parallelism: Option<usize>,
+
+    /// Report the symlinks to directories as directories, and descend into
+    /// them if `max_depth` allows it. Symlinks to the explored directory or
+    /// any of its parents are reported as is, to avoid loops.
+    #[serde(default)]
+    pub follow_symlinks: bool,
+
+    /// Only list the entries whose file names match any of these glob
+    /// patterns, e.g. `*.{rs,toml}`. Applies to the directories too.
+    #[serde(default)]
+    pub include_patterns: Vec<String>,
+
+    /// Hide the entries whose file names match any of these glob patterns,
+    /// even if they match `include_patterns`.
+    #[serde(default)]
+    pub exclude_patterns: Vec<String>,
+
+    /// Skip this many nodes. Like `limit`, it applies after filtering,
+    /// sorting and searching the full listing, so the pages are stable.
+    #[serde(default)]
+    pub offset: usize,
+
+    /// Return at most this many nodes. The `mime`, `owner`, `group` and
+    /// `binary_human_size` fields are only populated for the returned nodes.
+    #[serde(default)]
+    pub limit: Option<usize>,
 }
 
 impl ExplorerConfig {
@@ -1,4 +1,5 @@
 use crate::app::DirectoryBuffer;
+use crate::fs_watcher::FsEvent;
 use crate::input::Key;
 use serde::{Deserialize, Serialize};
 
@@ -7,4 +8,5 @@ pub enum InternalMsg {
     AddLastFocus(String, Option<String>),
     SetDirectory(DirectoryBuffer),
     HandleKey(Key),
+    FsEvent(FsEvent),
 }
@@ -1,26 +1,74 @@
 use crate::permissions::Permissions;
-use humansize::{format_size, DECIMAL};
+use humansize::{format_size, BINARY, DECIMAL};
+#[cfg(unix)]
+use lazy_static::lazy_static;
+use path_absolutize::Absolutize;
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
+use std::fs::Metadata;
+use std::io;
 use std::os::unix::prelude::MetadataExt;
 use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::sync::Mutex;
 use std::time::UNIX_EPOCH;
+#[cfg(unix)]
+use users::{Groups, Users, UsersCache};
+
+#[cfg(unix)]
+lazy_static! {
+    /// Shared by all the nodes, so that each user and group is only looked up
+    /// once.
+    static ref USERS_CACHE: Mutex<UsersCache> = Mutex::new(UsersCache::new());
+}
 
 fn to_human_size(size: u64) -> String {
     format_size(size, DECIMAL)
 }
 
+fn to_binary_human_size(size: u64) -> String {
+    format_size(size, BINARY.decimal_places(1))
+}
+
 fn mime_essence(path: &Path, is_dir: bool) -> String {
     if is_dir {
         String::from("inode/directory")
     } else {
-        mime_guess::from_path(&path)
+        mime_guess::from_path(path)
             .first()
             .map(|m| m.essence_str().to_string())
             .unwrap_or_default()
     }
 }
 
+/// Get the name of the user with the given ID, if any.
+#[cfg(unix)]
+pub fn uid_to_name(uid: u32) -> Option<String> {
+    let cache = USERS_CACHE.lock().ok()?;
+    cache
+        .get_user_by_uid(uid)
+        .map(|u| u.name().to_string_lossy().to_string())
+}
+
+#[cfg(not(unix))]
+pub fn uid_to_name(_uid: u32) -> Option<String> {
+    None
+}
+
+/// Get the name of the group with the given ID, if any.
+#[cfg(unix)]
+pub fn gid_to_name(gid: u32) -> Option<String> {
+    let cache = USERS_CACHE.lock().ok()?;
+    cache
+        .get_group_by_gid(gid)
+        .map(|g| g.name().to_string_lossy().to_string())
+}
+
+#[cfg(not(unix))]
+pub fn gid_to_name(_gid: u32) -> Option<String> {
+    None
+}
+
 #[derive(Debug, Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct ResolvedNode {
     pub absolute_path: String,
@@ -35,6 +83,10 @@ pub struct ResolvedNode {
     pub last_modified: Option<u128>,
     pub uid: u32,
     pub gid: u32,
+
+    /// Set when a symlink points to a path that doesn't exist.
+    #[serde(default)]
+    pub is_broken: bool,
 }
 
 impl ResolvedNode {
@@ -51,7 +103,7 @@ impl ResolvedNode {
                         m.is_dir(),
                         m.is_file(),
                         m.permissions().readonly(),
-                        m.len(),
+                        if m.is_dir() { 0 } else { m.len() },
                         m.created()
                             .ok()
                             .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
@@ -82,6 +134,32 @@ impl ResolvedNode {
             last_modified,
             uid,
             gid,
+            is_broken: false,
+        }
+    }
+
+    /// Metadata for the missing target of a broken symlink. The target has no
+    /// owner, so it takes the one of the symlink.
+    fn broken(path: PathBuf, uid: u32, gid: u32) -> Self {
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        Self {
+            absolute_path: path.to_string_lossy().to_string(),
+            mime_essence: mime_essence(&path, false),
+            extension,
+            is_dir: false,
+            is_file: false,
+            is_readonly: false,
+            size: 0,
+            human_size: to_human_size(0),
+            created: None,
+            last_modified: None,
+            uid,
+            gid,
+            is_broken: true,
         }
     }
 }
@@ -106,12 +184,40 @@ pub struct Node {
     pub uid: u32,
     pub gid: u32,
 
+    /// Same as `mime_essence`, but it's `None` for directories and unknown
+    /// types. Only populated when exploring with `add_mime`.
+    pub mime: Option<String>,
+
+    /// The names of the owner user and group. Only populated when exploring
+    /// with `add_owner`.
+    pub owner: Option<String>,
+    pub group: Option<String>,
+
+    /// Like `human_size`, but in binary units with one decimal, e.g.
+    /// `1.2 MiB`. Only populated when exploring with `add_binary_human_size`.
+    pub binary_human_size: Option<String>,
+
     pub canonical: Option<ResolvedNode>,
     pub symlink: Option<ResolvedNode>,
 }
 
 impl Node {
     pub fn new(parent: String, relative_path: String) -> Self {
+        let metadata = Path::new(&parent).join(&relative_path).symlink_metadata();
+        Self::from_metadata(parent, relative_path, metadata.ok())
+    }
+
+    /// Same as [`Node::new`], but fails if the metadata can't be read.
+    pub fn try_new(parent: String, relative_path: String) -> io::Result<Self> {
+        let metadata = Path::new(&parent).join(&relative_path).symlink_metadata()?;
+        Ok(Self::from_metadata(parent, relative_path, Some(metadata)))
+    }
+
+    fn from_metadata(
+        parent: String,
+        relative_path: String,
+        metadata: Option<Metadata>,
+    ) -> Self {
         let absolute_path = PathBuf::from(&parent)
             .join(&relative_path)
             .to_string_lossy()
@@ -140,15 +246,14 @@ impl Node {
             last_modified,
             uid,
             gid,
-        ) = path
-            .symlink_metadata()
+        ) = metadata
             .map(|m| {
                 (
                     m.file_type().is_symlink(),
                     m.is_dir(),
                     m.is_file(),
                     m.permissions().readonly(),
-                    m.len(),
+                    if m.is_dir() { 0 } else { m.len() },
                     Permissions::from(&m),
                     m.created()
                         .ok()
@@ -162,7 +267,7 @@ impl Node {
                     m.gid(),
                 )
             })
-            .unwrap_or_else(|_| {
+            .unwrap_or_else(|| {
                 (
                     false,
                     false,
@@ -198,16 +303,72 @@ impl Node {
             last_modified,
             uid,
             gid,
+            mime: None,
+            owner: None,
+            group: None,
+            binary_human_size: None,
             canonical: maybe_canonical_meta.clone(),
-            symlink: if is_symlink {
-                maybe_canonical_meta
-            } else {
-                None
+            symlink: match (is_symlink, maybe_canonical_meta) {
+                (true, Some(meta)) => Some(meta),
+                (true, None) => broken_symlink_target(&path)
+                    .map(|target| ResolvedNode::broken(target, uid, gid)),
+                (false, _) => None,
             },
         }
     }
 }
 
+/// Where the symlink points to, relative to its directory if not absolute.
+fn broken_symlink_target(path: &Path) -> Option<PathBuf> {
+    let target = std::fs::read_link(path).ok()?;
+    let target = match path.parent() {
+        Some(parent) if target.is_relative() => parent.join(target),
+        _ => target,
+    };
+    Some(
+        target
+            .absolutize()
+            .map(|p| p.to_path_buf())
+            .unwrap_or(target),
+    )
+}
+
+impl Node {
+    /// Populate the `mime` field from the extension based `mime_essence`.
+    pub fn with_mime(mut self) -> Self {
+        self.mime =
+            Some(self.mime_essence.clone()).filter(|m| !self.is_dir && !m.is_empty());
+        self
+    }
+
+    /// Populate the `owner` and `group` fields from `uid` and `gid`.
+    pub fn with_owner(mut self) -> Self {
+        self.owner = uid_to_name(self.uid);
+        self.group = gid_to_name(self.gid);
+        self
+    }
+
+    /// Populate the `binary_human_size` field from `size`.
+    pub fn with_binary_human_size(mut self) -> Self {
+        self.binary_human_size = Some(to_binary_human_size(self.size));
+        self
+    }
+
+    /// Report a symlink to a directory as a directory. The node still has
+    /// `is_symlink` set.
+    pub fn followed(mut self) -> Self {
+        if self.is_symlink && self.canonical.as_ref().map(|c| c.is_dir).unwrap_or(false)
+        {
+            self.is_dir = true;
+            self.is_file = false;
+            self.size = 0;
+            self.human_size = to_human_size(0);
+            self.mime_essence = mime_essence(Path::new(&self.absolute_path), true);
+        }
+        self
+    }
+}
+
 impl Ord for Node {
     fn cmp(&self, other: &Self) -> Ordering {
         // Notice that the we flip the ordering on costs.
@@ -0,0 +1,177 @@
+use crate::app::{ExternalMsg, MsgIn, Task};
+use lazy_static::lazy_static;
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The progress is cleared if it's not updated within this duration.
+pub const PROGRESS_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref PROGRESS: Mutex<ProgressState> = Mutex::new(ProgressState::default());
+}
+
+/// The progress of a long operation, as reported with `xplr.util.set_progress`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    pub label: String,
+    /// Between 0 and 1, or None if unknown.
+    pub fraction: Option<f64>,
+    pub updated_at: Instant,
+}
+
+impl Progress {
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.updated_at) >= PROGRESS_TIMEOUT
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ProgressState {
+    progress: Option<Progress>,
+    tx_msg_in: Option<Sender<Task>>,
+    expiring: bool,
+}
+
+impl ProgressState {
+    pub fn set(&mut self, label: String, fraction: Option<f64>, now: Instant) {
+        self.progress = Some(Progress {
+            label,
+            fraction: fraction.map(|f| f.clamp(0.0, 1.0)),
+            updated_at: now,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.progress = None;
+    }
+
+    /// The current progress, clearing it if it has expired.
+    pub fn current(&mut self, now: Instant) -> Option<Progress> {
+        if self
+            .progress
+            .as_ref()
+            .map(|p| p.is_expired(now))
+            .unwrap_or(false)
+        {
+            self.progress = None;
+        }
+        self.progress.clone()
+    }
+}
+
+/// Refresh the screen once the progress expires, so that it's cleared even if
+/// nothing else happens.
+fn expire_later() {
+    thread::spawn(|| loop {
+        let deadline = match PROGRESS.lock() {
+            Ok(state) => state
+                .progress
+                .as_ref()
+                .map(|p| p.updated_at + PROGRESS_TIMEOUT),
+            Err(_) => return,
+        };
+
+        if let Some(deadline) = deadline {
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        }
+
+        let Ok(mut state) = PROGRESS.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        match state.progress.as_ref() {
+            Some(p) if !p.is_expired(now) => continue,
+            Some(_) => {
+                state.progress = None;
+                if let Some(tx) = state.tx_msg_in.as_ref() {
+                    let msg = MsgIn::External(ExternalMsg::Refresh);
+                    tx.send(Task::new(msg, None)).unwrap_or_default();
+                }
+            }
+            None => {}
+        }
+        state.expiring = false;
+        return;
+    });
+}
+
+pub fn connect(tx_msg_in: Sender<Task>) {
+    if let Ok(mut state) = PROGRESS.lock() {
+        state.tx_msg_in = Some(tx_msg_in);
+    }
+}
+
+pub fn set(label: String, fraction: Option<f64>) {
+    if let Ok(mut state) = PROGRESS.lock() {
+        state.set(label, fraction, Instant::now());
+        if !state.expiring {
+            state.expiring = true;
+            expire_later();
+        }
+    }
+}
+
+pub fn clear() {
+    if let Ok(mut state) = PROGRESS.lock() {
+        state.clear();
+    }
+}
+
+pub fn current() -> Option<Progress> {
+    PROGRESS
+        .lock()
+        .ok()
+        .and_then(|mut state| state.current(Instant::now()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_state() {
+        let mut state = ProgressState::default();
+        let start = Instant::now();
+        assert_eq!(state.current(start), None);
+
+        state.set("copying".into(), Some(0.5), start);
+        let progress = state.current(start).unwrap();
+        assert_eq!(progress.label, "copying");
+        assert_eq!(progress.fraction, Some(0.5));
+
+        // Clamped.
+        state.set("copying".into(), Some(1.5), start);
+        assert_eq!(state.current(start).unwrap().fraction, Some(1.0));
+        state.set("copying".into(), Some(-1.0), start);
+        assert_eq!(state.current(start).unwrap().fraction, Some(0.0));
+
+        state.set("searching".into(), None, start);
+        assert_eq!(state.current(start).unwrap().fraction, None);
+
+        state.clear();
+        assert_eq!(state.current(start), None);
+    }
+
+    #[test]
+    fn test_progress_auto_clear() {
+        let mut state = ProgressState::default();
+        let start = Instant::now();
+        let almost = start + PROGRESS_TIMEOUT - Duration::from_millis(1);
+
+        state.set("copying".into(), Some(0.1), start);
+        assert!(state.current(almost).is_some());
+        assert!(state.current(start + PROGRESS_TIMEOUT).is_none());
+
+        // Stays cleared.
+        assert!(state.current(start).is_none());
+
+        // Updating it keeps it alive.
+        state.set("copying".into(), Some(0.1), start);
+        state.set("copying".into(), Some(0.2), almost);
+        let progress = state.current(start + PROGRESS_TIMEOUT).unwrap();
+        assert_eq!(progress.fraction, Some(0.2));
+        assert!(state.current(almost + PROGRESS_TIMEOUT).is_none());
+    }
+}
@@ -5,9 +5,13 @@ use crate::app::ExternalMsg;
 use crate::cli::Cli;
 use crate::event_reader::EventReader;
 use crate::explorer;
+use crate::fs_watcher;
+use crate::logs;
 use crate::lua;
 use crate::pipe;
+use crate::progress;
 use crate::pwd_watcher;
+use crate::temp;
 use crate::ui;
 use crate::yaml;
 use anyhow::{bail, Error, Result};
@@ -28,7 +32,7 @@ use tui_input::Input;
 
 pub fn get_tty() -> Result<fs::File> {
     let tty = "/dev/tty";
-    match fs::OpenOptions::new().read(true).write(true).open(&tty) {
+    match fs::OpenOptions::new().read(true).write(true).open(tty) {
         Ok(f) => Ok(f),
         Err(e) => {
             bail!(format!("Failed to open {}. {}", tty, e))
@@ -109,9 +113,9 @@ fn call(
 
     let status = Command::new(cmd.command.clone())
         .env("XPLR", &app.bin)
-        .env("XPLR_VROOT", &app.vroot.clone().unwrap_or_default())
+        .env("XPLR_VROOT", app.vroot.clone().unwrap_or_default())
         .env("XPLR_APP_VERSION", &app.version)
-        .env("XPLR_PID", &app.pid.to_string())
+        .env("XPLR_PID", app.pid.to_string())
         .env("XPLR_INPUT_BUFFER", input_buffer)
         .env("XPLR_INITIAL_PWD", &app.initial_pwd)
         .env("XPLR_FOCUS_PATH", app.focused_node_str())
@@ -326,6 +330,9 @@ impl Runner {
 
         // Threads
         pwd_watcher::keep_watching(app.pwd.as_ref(), tx_msg_in.clone(), rx_pwd_watcher)?;
+        fs_watcher::connect(tx_msg_in.clone());
+        progress::connect(tx_msg_in.clone());
+        logs::connect(tx_msg_in.clone());
         let mut event_reader = EventReader::new(tx_msg_in.clone());
         event_reader.start();
 
@@ -814,6 +821,7 @@ impl Runner {
         term::disable_raw_mode()?;
         terminal.show_cursor()?;
 
+        temp::cleanup();
         fs::remove_dir_all(session_path)?;
 
         result
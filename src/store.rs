@@ -0,0 +1,374 @@
+use crate::temp;
+use anyhow::Result;
+use fs2::FileExt;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    static ref SESSION_STORE: Mutex<SessionStore> = Mutex::new(SessionStore::default());
+    static ref PERSISTENT_STORE: Mutex<Option<PersistentStore>> =
+        Mutex::new(default_persistent_path().map(PersistentStore::new));
+}
+
+/// Values stashed by plugins with `xplr.util.store_set`, keyed by name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Store {
+    values: BTreeMap<String, serde_json::Value>,
+}
+
+impl Store {
+    pub fn get(&self, key: &str) -> Option<&serde_json::Value> {
+        self.values.get(key)
+    }
+
+    pub fn set(&mut self, key: String, value: serde_json::Value) {
+        self.values.insert(key, value);
+    }
+
+    /// Returns the removed value, if any.
+    pub fn delete(&mut self, key: &str) -> Option<serde_json::Value> {
+        self.values.remove(key)
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.values.keys().cloned().collect()
+    }
+}
+
+/// The session store as changed from Lua, until it's copied into the app
+/// state with [`sync`].
+#[derive(Debug, Default)]
+struct SessionStore {
+    store: Store,
+    changed: bool,
+
+    /// The last times allowed by `xplr.util.throttle`, by key.
+    throttled: HashMap<String, Instant>,
+    throttled_changed: bool,
+}
+
+pub fn get(key: &str) -> Option<serde_json::Value> {
+    SESSION_STORE
+        .lock()
+        .ok()
+        .and_then(|session| session.store.get(key).cloned())
+}
+
+pub fn set(key: String, value: serde_json::Value) {
+    if let Ok(mut session) = SESSION_STORE.lock() {
+        session.store.set(key, value);
+        session.changed = true;
+    }
+}
+
+pub fn delete(key: &str) -> Option<serde_json::Value> {
+    let mut session = SESSION_STORE.lock().ok()?;
+    let value = session.store.delete(key);
+    session.changed |= value.is_some();
+    value
+}
+
+/// Whether the work identified by the key can run again, i.e. if at least the
+/// interval has passed since the last time it was allowed at.
+pub fn throttle(key: String, interval: Duration, now: Instant) -> bool {
+    let Ok(mut session) = SESSION_STORE.lock() else {
+        return true;
+    };
+    let allowed = throttle_at(&mut session.throttled, key, interval, now);
+    session.throttled_changed |= allowed;
+    allowed
+}
+
+fn throttle_at(
+    last: &mut HashMap<String, Instant>,
+    key: String,
+    interval: Duration,
+    now: Instant,
+) -> bool {
+    match last.get(&key) {
+        Some(t) if now.saturating_duration_since(*t) < interval => false,
+        _ => {
+            last.insert(key, now);
+            true
+        }
+    }
+}
+
+/// Copy the session store, and the throttled times, into the app state, if
+/// they were changed since the last call.
+pub fn sync(store: &mut Store, throttled: &mut HashMap<String, Instant>) {
+    if let Ok(mut session) = SESSION_STORE.lock() {
+        if session.changed {
+            store.clone_from(&session.store);
+            session.changed = false;
+        }
+        if session.throttled_changed {
+            throttled.clone_from(&session.throttled);
+            session.throttled_changed = false;
+        }
+    }
+}
+
+/// The file backing `xplr.util.persist_set`, if there's a data directory.
+/// There's no fallback to the shared temp directory, where other users could
+/// read, replace or lock the file.
+pub fn default_persistent_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("xplr").join("store.json"))
+}
+
+/// A [Store] saved to a JSON file, shared by all the xplr instances.
+#[derive(Debug)]
+pub struct PersistentStore {
+    path: PathBuf,
+    store: Option<Store>,
+}
+
+impl PersistentStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, store: None }
+    }
+
+    fn read(&self) -> Result<Store> {
+        match fs::read_to_string(&self.path) {
+            Ok(json) => Ok(serde_json::from_str(&json)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Store::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The file is read on first access only.
+    fn load(&mut self) -> Result<&Store> {
+        let store = match self.store.take() {
+            Some(store) => store,
+            None => self.read()?,
+        };
+        Ok(self.store.insert(store))
+    }
+
+    // Held while the file is read, modified and written, so that the instances
+    // writing at the same time don't overwrite each other's changes. Released
+    // when dropped.
+    fn lock(&self) -> Result<fs::File> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_file_name(format!(
+                ".{}.lock",
+                self.path.file_name().unwrap_or_default().to_string_lossy()
+            )))?;
+        lock.lock_exclusive()?;
+        Ok(lock)
+    }
+
+    fn write(&self, store: &Store) -> Result<()> {
+        // Readers, including other instances, never see a partially written
+        // file.
+        let tmp = temp::sibling(&self.path);
+        let res = (|| {
+            let mut file = fs::File::create(&tmp)?;
+            file.write_all(serde_json::to_string_pretty(store)?.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&tmp, &self.path)?;
+            Ok(())
+        })();
+        if res.is_err() {
+            fs::remove_file(&tmp).ok();
+        }
+        res
+    }
+
+    pub fn get(&mut self, key: &str) -> Result<Option<serde_json::Value>> {
+        Ok(self.load()?.get(key).cloned())
+    }
+
+    pub fn keys(&mut self) -> Result<Vec<String>> {
+        Ok(self.load()?.keys())
+    }
+
+    /// Sets or, if the value is None, deletes the key. The file is locked and
+    /// read again first, so that the changes made by other instances aren't
+    /// lost.
+    pub fn set(&mut self, key: String, value: Option<serde_json::Value>) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut store = self.read()?;
+        match value {
+            Some(value) => store.set(key, value),
+            None => {
+                store.delete(&key);
+            }
+        }
+        self.write(&store)?;
+        self.store = Some(store);
+        Ok(())
+    }
+}
+
+fn with_persistent_store<T>(
+    f: impl FnOnce(&mut PersistentStore) -> Result<T>,
+) -> Result<T> {
+    let mut store = PERSISTENT_STORE
+        .lock()
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+    match store.as_mut() {
+        Some(store) => f(store),
+        None => Err(anyhow::anyhow!("no data directory to save the store in")),
+    }
+}
+
+pub fn persistent_get(key: &str) -> Result<Option<serde_json::Value>> {
+    with_persistent_store(|store| store.get(key))
+}
+
+pub fn persistent_set(key: String, value: Option<serde_json::Value>) -> Result<()> {
+    with_persistent_store(|store| store.set(key, value))
+}
+
+pub fn persistent_keys() -> Result<Vec<String>> {
+    with_persistent_store(|store| store.keys())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_throttle_at() {
+        let mut last = HashMap::new();
+        let start = Instant::now();
+        let interval = Duration::from_millis(100);
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // Rapid calls only pass once per interval.
+        let passed: Vec<u64> = (0..=350)
+            .step_by(10)
+            .filter(|&ms| throttle_at(&mut last, "foo".into(), interval, at(ms)))
+            .collect();
+        assert_eq!(passed, vec![0, 100, 200, 300]);
+
+        // The keys are independent.
+        assert!(throttle_at(&mut last, "bar".into(), interval, at(350)));
+        assert!(!throttle_at(&mut last, "bar".into(), interval, at(360)));
+    }
+
+    fn fixture() -> (TempDir, PathBuf) {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("xplr").join("store.json");
+        (tmp, path)
+    }
+
+    #[test]
+    fn test_store() {
+        let mut store = Store::default();
+        assert_eq!(store.get("foo"), None);
+
+        store.set("foo".into(), json!({ "bar": [1, 2] }));
+        store.set("baz".into(), json!("qux"));
+        assert_eq!(store.get("foo"), Some(&json!({ "bar": [1, 2] })));
+        assert_eq!(store.keys(), vec!["baz".to_string(), "foo".to_string()]);
+
+        store.set("foo".into(), json!(true));
+        assert_eq!(store.get("foo"), Some(&json!(true)));
+
+        assert_eq!(store.delete("foo"), Some(json!(true)));
+        assert_eq!(store.delete("foo"), None);
+        assert_eq!(store.keys(), vec!["baz".to_string()]);
+    }
+
+    #[test]
+    fn test_persistent_store() {
+        let (_tmp, path) = fixture();
+
+        let mut store = PersistentStore::new(path.clone());
+        assert_eq!(store.get("foo").unwrap(), None);
+        assert!(store.keys().unwrap().is_empty());
+        assert!(!path.exists());
+
+        store
+            .set("foo".into(), Some(json!({ "bar": [1, 2] })))
+            .unwrap();
+        store.set("baz".into(), Some(json!("qux"))).unwrap();
+        assert_eq!(store.get("baz").unwrap(), Some(json!("qux")));
+
+        // Restarted.
+        let mut store = PersistentStore::new(path.clone());
+        assert_eq!(store.get("foo").unwrap(), Some(json!({ "bar": [1, 2] })));
+        assert_eq!(store.keys().unwrap(), vec!["baz", "foo"]);
+
+        store.set("foo".into(), None).unwrap();
+        let mut store = PersistentStore::new(path.clone());
+        assert_eq!(store.get("foo").unwrap(), None);
+        assert_eq!(store.keys().unwrap(), vec!["baz"]);
+
+        // No temporary file is left behind, only the lock file.
+        let files = fs::read_dir(path.parent().unwrap()).unwrap().count();
+        assert_eq!(files, 2);
+    }
+
+    #[test]
+    fn test_persistent_store_instances() {
+        let (_tmp, path) = fixture();
+
+        let mut one = PersistentStore::new(path.clone());
+        let mut two = PersistentStore::new(path.clone());
+        assert_eq!(one.get("foo").unwrap(), None);
+        assert_eq!(two.get("foo").unwrap(), None);
+
+        one.set("foo".into(), Some(json!(1))).unwrap();
+        two.set("bar".into(), Some(json!(2))).unwrap();
+
+        // Neither write is lost.
+        let mut three = PersistentStore::new(path.clone());
+        assert_eq!(three.keys().unwrap(), vec!["bar", "foo"]);
+        assert_eq!(two.get("foo").unwrap(), Some(json!(1)));
+    }
+
+    #[test]
+    fn test_persistent_store_concurrent() {
+        let (_tmp, path) = fixture();
+
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let mut store = PersistentStore::new(path);
+                    for j in 0..20 {
+                        store.set(format!("{i}-{j}"), Some(json!(j))).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // No write is lost, even when interleaved.
+        let mut store = PersistentStore::new(path.clone());
+        assert_eq!(store.keys().unwrap().len(), 80);
+    }
+
+    #[test]
+    fn test_persistent_store_corrupt() {
+        let (_tmp, path) = fixture();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "{ not json").unwrap();
+
+        let mut store = PersistentStore::new(path.clone());
+        assert!(store.get("foo").is_err());
+
+        // The file isn't overwritten.
+        assert!(store.set("foo".into(), Some(json!(1))).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{ not json");
+    }
+}
@@ -0,0 +1,73 @@
+use lazy_static::lazy_static;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use tempfile::{Builder, TempDir, TempPath};
+
+lazy_static! {
+    static ref TEMP_PATHS: Mutex<TempPaths> = Mutex::new(TempPaths::default());
+}
+
+// The paths are removed when dropped.
+#[derive(Debug, Default)]
+struct TempPaths {
+    files: Vec<TempPath>,
+    dirs: Vec<TempDir>,
+}
+
+// Even if poisoned, so that the paths aren't removed while in use.
+fn temp_paths() -> MutexGuard<'static, TempPaths> {
+    TEMP_PATHS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A path in the same directory as the given one, unique within the process,
+/// to write to before renaming it to the given path. It's not created.
+pub fn sibling(path: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = format!(
+        ".{}.xplr-{}-{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+    );
+    path.with_file_name(name)
+}
+
+/// Create a temporary file in the system temp directory. Unless `keep` is
+/// true, it's removed by [cleanup].
+pub fn file(prefix: &str, suffix: &str, keep: bool) -> io::Result<PathBuf> {
+    let path = Builder::new()
+        .prefix(prefix)
+        .suffix(suffix)
+        .tempfile()?
+        .into_temp_path();
+    if keep {
+        path.keep().map_err(|e| e.error)
+    } else {
+        let path_buf = path.to_path_buf();
+        temp_paths().files.push(path);
+        Ok(path_buf)
+    }
+}
+
+/// Create a temporary directory in the system temp directory. Unless `keep`
+/// is true, it's removed with its contents by [cleanup].
+pub fn dir(prefix: &str, suffix: &str, keep: bool) -> io::Result<PathBuf> {
+    let dir = Builder::new().prefix(prefix).suffix(suffix).tempdir()?;
+    if keep {
+        Ok(dir.keep())
+    } else {
+        let path = dir.path().to_path_buf();
+        temp_paths().dirs.push(dir);
+        Ok(path)
+    }
+}
+
+/// Remove the temporary files and directories that aren't kept. Called when
+/// the session ends.
+pub fn cleanup() {
+    let mut paths = temp_paths();
+    paths.files.clear();
+    paths.dirs.clear();
+}
@@ -37,6 +37,16 @@ fn read_only_indicator(app: &app::App) -> &str {
     }
 }
 
+fn progress_indicator(app: &app::App) -> String {
+    match app.progress.as_ref() {
+        Some(p) => match p.fraction {
+            Some(f) => format!(" {} {:.0}%", p.label, f * 100.0),
+            None => format!(" {}…", p.label),
+        },
+        None => "".into(),
+    }
+}
+
 fn string_to_text<'a>(string: String) -> Text<'a> {
     if *NO_COLOR {
         Text::raw(string)
@@ -104,9 +114,10 @@ pub enum ContentBody {
     },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub enum Layout {
+    #[default]
     Nothing,
     Table,
     InputAndLogs,
@@ -127,12 +138,6 @@ pub enum Layout {
     },
 }
 
-impl Default for Layout {
-    fn default() -> Self {
-        Self::Nothing
-    }
-}
-
 impl Layout {
     pub fn extend(self, other: &Self) -> Self {
         match (self, other) {
@@ -192,22 +197,17 @@ impl Border {
 }
 
 #[derive(
-    Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Serialize, Deserialize,
+    Debug, Clone, Copy, Default, Hash, PartialEq, Eq, PartialOrd, Serialize, Deserialize,
 )]
 #[serde(deny_unknown_fields)]
 pub enum BorderType {
+    #[default]
     Plain,
     Rounded,
     Double,
     Thick,
 }
 
-impl Default for BorderType {
-    fn default() -> Self {
-        Self::Plain
-    }
-}
-
 impl Into<TuiBorderType> for BorderType {
     fn into(self) -> TuiBorderType {
         match self {
@@ -374,6 +374,7 @@ pub struct ResolvedNodeUiMetadata {
     pub human_size: String,
     pub created: Option<u128>,
     pub last_modified: Option<u128>,
+    pub is_broken: bool,
 }
 
 impl From<ResolvedNode> for ResolvedNodeUiMetadata {
@@ -389,6 +390,7 @@ impl From<ResolvedNode> for ResolvedNodeUiMetadata {
             human_size: node.human_size,
             created: node.created,
             last_modified: node.last_modified,
+            is_broken: node.is_broken,
         }
     }
 }
@@ -489,7 +491,7 @@ fn block<'a>(config: PanelUiConfig, default_title: String) -> Block<'a> {
                 .unwrap_or_default()
                 .iter()
                 .map(|b| b.bits())
-                .fold(0, |a, b| (a ^ b)),
+                .fold(0, |a, b| a ^ b),
         ))
         .title(Span::styled(
             config.title.format.unwrap_or(default_title),
@@ -988,6 +990,7 @@ fn draw_logs<B: Backend>(
                     app::LogLevel::Warning => &logs_config.warning,
                     app::LogLevel::Success => &logs_config.success,
                     app::LogLevel::Error => &logs_config.error,
+                    app::LogLevel::Debug => &logs_config.debug,
                 };
 
                 let prefix =
@@ -1018,10 +1021,11 @@ fn draw_logs<B: Backend>(
     let logs_list = List::new(logs).block(block(
         config,
         format!(
-            " Logs ({}) [{}{}] ",
+            " Logs ({}) [{}{}]{} ",
             app.logs.len(),
             app.mode.name,
             read_only_indicator(app),
+            progress_indicator(app),
         ),
     ));
 
@@ -1278,12 +1282,9 @@ pub fn draw_layout<B: Backend>(
                 )
                 .split(layout_size);
 
-            splits
-                .into_iter()
-                .zip(chunks.into_iter())
-                .for_each(|(split, chunk)| {
-                    draw_layout(split, f, screen_size, chunk, app, lua)
-                });
+            splits.into_iter().zip(chunks).for_each(|(split, chunk)| {
+                draw_layout(split, f, screen_size, chunk, app, lua)
+            });
         }
 
         Layout::Vertical { config, splits } => {
@@ -1309,12 +1310,9 @@ pub fn draw_layout<B: Backend>(
                 )
                 .split(layout_size);
 
-            splits
-                .into_iter()
-                .zip(chunks.into_iter())
-                .for_each(|(split, chunk)| {
-                    draw_layout(split, f, screen_size, chunk, app, lua)
-                });
+            splits.into_iter().zip(chunks).for_each(|(split, chunk)| {
+                draw_layout(split, f, screen_size, chunk, app, lua)
+            });
         }
     }
 }